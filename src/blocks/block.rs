@@ -6,26 +6,31 @@
     The block should also know which range of bytes it contains.
  */
 
-use std::{ops::Range, sync::Arc};
+use std::{collections::HashSet, ops::Range, sync::Arc};
 
 use async_trait::async_trait;
 use futures::stream::BoxStream;
 use serde::{Serialize, Deserialize};
 
-use crate::global::Global;
-use super::{direct_block::DirectBlock, indirect_block::IndirectBlock, stored_block::StoredBlock};
+use crate::{global::{Global, Descriptor}, scrub::{ScrubReport, Repairs}};
+use super::{direct_block::DirectBlock, indirect_block::IndirectBlock, stored_block::StoredBlock, replicated_block::ReplicatedBlock, erasure_block::ErasureBlock};
 
 #[async_trait]
 pub trait Block {
     async fn range(&self, global: Arc<Global>) -> Result<Range<usize>, String>;
-    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<Result<Vec<u8>, String>>;
+    fn known_range(&self) -> Option<Range<usize>>; // the range, if it is known without downloading anything
+    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<'_, Result<Vec<u8>, String>>;
+    #[allow(dead_code)] // files are replaced as a whole for now, nothing rewrites a block in place yet
     async fn put(&mut self, global: Arc<Global>, data: Vec<u8>, range: Range<usize>) -> Result<(), String>;
     async fn delete(&self, global: Arc<Global>) -> Result<(), String>;
     async fn create(global: Arc<Global>, data: Vec<u8>, start: usize) -> Result<BlockType, String>;
+    async fn scrub(&mut self, global: Arc<Global>, report: &mut ScrubReport, repair: bool) -> Repairs; // the block changed and has to be saved if anything was repaired
+    async fn reachable(&self, global: Arc<Global>, found: &mut HashSet<Descriptor>) -> Result<(), String>; // adds every descriptor used by the block
+    async fn shift(&mut self, global: Arc<Global>, offset: usize) -> Result<(), String>; // moves the data offset bytes further, so blocks uploaded on their own can be joined
     fn to_enum(self) -> BlockType;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum BlockType {
     #[serde(rename = "d")]
     Direct(DirectBlock),
@@ -33,6 +38,10 @@ pub enum BlockType {
     Indirect(IndirectBlock),
     #[serde(rename = "s")]
     Stored(StoredBlock),
+    #[serde(rename = "r")]
+    Replicated(ReplicatedBlock),
+    #[serde(rename = "e")]
+    Erasure(ErasureBlock),
 } // we use short names to reduce the size of the serialized data while allowing backwards compatibility
 
 macro_rules! match_method {
@@ -41,17 +50,43 @@ macro_rules! match_method {
             BlockType::Direct(block) => block.$method($($arg),*),
             BlockType::Indirect(block) => block.$method($($arg),*),
             BlockType::Stored(block) => block.$method($($arg),*),
+            BlockType::Replicated(block) => block.$method($($arg),*),
+            BlockType::Erasure(block) => block.$method($($arg),*),
         }
     };
 }
 
+impl BlockType {
+    // The buckets a single chunk block reads from, stored and indirect blocks read from many so they return none
+    pub fn buckets(&self) -> Vec<&str> {
+        match self {
+            BlockType::Direct(block) => block.buckets(),
+            BlockType::Replicated(block) => block.buckets(),
+            BlockType::Erasure(block) => block.buckets(),
+            BlockType::Stored(_) | BlockType::Indirect(_) => Vec::new(),
+        }
+    }
+}
+
+// Returns false only if the block is known to lie outside of the range, so it can be skipped without downloading it
+pub fn may_overlap(block: &impl Block, range: &Range<usize>) -> bool {
+    match block.known_range() {
+        Some(known) => known.start < range.end && range.start < known.end,
+        None => true,
+    }
+}
+
 #[async_trait]
 impl Block for BlockType {
     async fn range(&self, global: Arc<Global>) -> Result<Range<usize>, String> {
         match_method!(self, range, global).await
     }
 
-    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<Result<Vec<u8>, String>> {
+    fn known_range(&self) -> Option<Range<usize>> {
+        match_method!(self, known_range,)
+    }
+
+    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<'_, Result<Vec<u8>, String>> {
         match_method!(self, get, global, range)
     }
 
@@ -67,6 +102,18 @@ impl Block for BlockType {
         IndirectBlock::create(global, data, start).await // we use indirect blocks, because they will fit any data size
     }
 
+    async fn scrub(&mut self, global: Arc<Global>, report: &mut ScrubReport, repair: bool) -> Repairs {
+        match_method!(self, scrub, global, report, repair).await
+    }
+
+    async fn reachable(&self, global: Arc<Global>, found: &mut HashSet<Descriptor>) -> Result<(), String> {
+        match_method!(self, reachable, global, found).await
+    }
+
+    async fn shift(&mut self, global: Arc<Global>, offset: usize) -> Result<(), String> {
+        match_method!(self, shift, global, offset).await
+    }
+
     fn to_enum(self) -> BlockType {
         self
     }
@@ -3,15 +3,15 @@
     It does not split the data into chunks.
  */
 
-use std::{ops::Range, sync::Arc};
+use std::{collections::HashSet, ops::Range, sync::Arc};
 use async_trait::async_trait;
 use futures::stream::BoxStream;
 use serde::{Serialize, Deserialize};
 
-use crate::global::{Global, Descriptor};
+use crate::{global::{Global, Descriptor}, scrub::{ScrubReport, Repairs, check_chunk}};
 use super::block::{Block, BlockType};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DirectBlock {
     #[serde(rename = "b")]
     bucket: String,
@@ -21,13 +21,62 @@ pub struct DirectBlock {
     range: Range<usize>,
 } // we use short names to reduce the size of the serialized data while allowing backwards compatibility
 
+impl DirectBlock {
+    // The buckets the chunk is read from
+    pub fn buckets(&self) -> Vec<&str> {
+        vec![&self.bucket]
+    }
+
+    #[cfg(test)]
+    pub fn chunk(&self) -> (&String, &Descriptor) {
+        (&self.bucket, &self.descriptor)
+    }
+
+    // Picks the bucket and decides how many bytes of the data the block will take
+    pub fn plan(global: &Global, len: usize) -> Result<(String, usize), String> {
+        let bucket_name = global.random_bucket().ok_or("No buckets found".to_string())?;
+        let bucket = global.get_bucket(bucket_name).ok_or("Bucket not found".to_string())?;
+        let len = std::cmp::min(len, bucket.max_size());
+        if len == 0 {
+            return Err("Data is empty".to_string())
+        }
+        Ok((bucket_name.clone(), len))
+    }
+
+    // Uploads the data, which has to be already sliced to the planned size
+    pub async fn upload(global: Arc<Global>, bucket_name: String, data: Vec<u8>, start: usize) -> Result<BlockType, String> {
+        let bucket = global.get_bucket(&bucket_name).ok_or("Bucket not found".to_string())?;
+        let descriptor = match bucket.create().await {
+            Ok(descriptor) => descriptor,
+            Err(e) => return Err(format!("Could not create the descriptor: {}", e))
+        };
+        // the descriptor is of no use without the data, so we delete it again
+        if let Err(err) = bucket.put(&descriptor, data.clone()).await {
+            return match bucket.delete(&descriptor).await {
+                Ok(_) => Err(err),
+                Err(e) => Err(format!("{}, {}", err, e))
+            };
+        }
+
+        Ok(BlockType::Direct(DirectBlock {
+            range: start..start + data.len(),
+            bucket: bucket_name,
+            descriptor
+        }))
+    }
+}
+
 #[async_trait]
 impl Block for DirectBlock {
     async fn range(&self, _global: Arc<Global>) -> Result<Range<usize>, String> {
         Ok(self.range.clone())
     }
 
-    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<Result<Vec<u8>, String>> {
+    fn known_range(&self) -> Option<Range<usize>> {
+        Some(self.range.clone())
+    }
+
+    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<'_, Result<Vec<u8>, String>> {
         Box::pin(async_stream::stream! {
             if range.end <= self.range.start || range.start >= self.range.end {
                 return // the range is outside of the block, so we return an empty stream
@@ -71,41 +120,27 @@ impl Block for DirectBlock {
     }
 
     async fn create(global: Arc<Global>, data: Vec<u8>, start: usize) -> Result<BlockType, String> {
-        // finding the buckets
-        let bucket_name = global.random_bucket().ok_or("No buckets found".to_string())?;
-        let bucket = match global.get_bucket(bucket_name) {
-            Some(bucket) => bucket,
-            None => Err("Bucket not found".to_string())?
-        };
+        let (bucket_name, len) = Self::plan(&global, data.len())?;
+        Self::upload(global, bucket_name, data[..len].to_vec(), start).await
+    }
 
-        // slice the data
-        let data = data[..std::cmp::min(data.len(), bucket.max_size())].to_vec();
-        if data.is_empty() {
-            return Err("Data is empty".to_string())
+    async fn scrub(&mut self, global: Arc<Global>, report: &mut ScrubReport, _repair: bool) -> Repairs {
+        // there is only one copy, so a missing chunk can not be repaired
+        report.checked += 1;
+        if let Err(e) = check_chunk(&global, &self.bucket, &self.descriptor, self.range.len()).await {
+            report.lost(format!("Chunk in {}: {}", self.bucket, e));
         }
+        Repairs::default()
+    }
 
-        // create descriptors
-        let bucket = match global.get_bucket(bucket_name) {
-            Some(bucket) => bucket,
-            None => Err("Bucket not found".to_string())?
-        };
-        let descriptor = match bucket.create().await {
-            Ok(descriptor) => descriptor,
-            Err(e) => return Err(format!("Could not create the descriptor: {}", e))
-        };
-
-        // put the data
-        let bucket = match global.get_bucket(bucket_name) {
-            Some(bucket) => bucket,
-            None => Err("Bucket not found".to_string())?
-        };
-        bucket.put(&descriptor, data.clone()).await?;
+    async fn reachable(&self, _global: Arc<Global>, found: &mut HashSet<Descriptor>) -> Result<(), String> {
+        found.insert(self.descriptor.clone());
+        Ok(())
+    }
 
-        Ok(BlockType::Direct(DirectBlock {
-            range: start..start + data.len(),
-            bucket: bucket_name.clone(),
-            descriptor
-        }))
+    async fn shift(&mut self, _global: Arc<Global>, offset: usize) -> Result<(), String> {
+        self.range = self.range.start + offset..self.range.end + offset;
+        Ok(())
     }
 
     fn to_enum(self) -> BlockType {
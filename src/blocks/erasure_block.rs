@@ -0,0 +1,360 @@
+/*
+    This block splits the data into data shards and computes additional parity shards with Reed-Solomon coding.
+    Every shard is stored in a different bucket and any `data_shards` of them are enough to rebuild the data.
+ */
+
+use std::{collections::HashSet, ops::Range, sync::Arc};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Serialize, Deserialize};
+
+use crate::{global::{Global, Descriptor}, scrub::{ScrubReport, Repairs, check_chunk, rewrite_chunk}};
+use super::{block::{Block, BlockType}, redundancy::{Redundancy, default_data_shards, default_parity_shards}};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Shard {
+    #[serde(rename = "b")]
+    pub bucket: String,
+    #[serde(rename = "d")]
+    pub descriptor: Descriptor,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErasureBlock {
+    #[serde(rename = "s")]
+    shards: Vec<Shard>, // data shards first, then parity shards
+    #[serde(rename = "k")]
+    data_shards: usize,
+    #[serde(rename = "r")]
+    range: Range<usize>,
+} // we use short names to reduce the size of the serialized data while allowing backwards compatibility
+
+fn shard_size(len: usize, data_shards: usize) -> usize {
+    len.div_ceil(data_shards)
+}
+
+// splits the data into equally sized data shards and appends the parity shards
+fn encode(data: &[u8], data_shards: usize, parity_shards: usize) -> Result<Vec<Vec<u8>>, String> {
+    let size = shard_size(data.len(), data_shards);
+    let mut shards = data.chunks(size)
+        .map(|chunk| {
+            let mut shard = chunk.to_vec();
+            shard.resize(size, 0);
+            shard
+        })
+        .collect::<Vec<Vec<u8>>>();
+    shards.resize(data_shards + parity_shards, vec![0; size]);
+
+    let rs = ReedSolomon::new(data_shards, parity_shards).map_err(|e| format!("Invalid erasure coding parameters: {:?}", e))?;
+    rs.encode(&mut shards).map_err(|e| format!("Could not encode the data: {:?}", e))?;
+    Ok(shards)
+}
+
+impl ErasureBlock {
+    // The buckets the chunk is read from
+    pub fn buckets(&self) -> Vec<&str> {
+        self.shards.iter().map(|shard| shard.bucket.as_str()).collect()
+    }
+
+    #[cfg(test)]
+    pub fn shards(&self) -> &[Shard] {
+        &self.shards
+    }
+
+    pub async fn create_erasure(global: Arc<Global>, data: Vec<u8>, start: usize, data_shards: usize, parity_shards: usize) -> Result<BlockType, String> {
+        let (buckets, len) = Self::plan(&global, data.len(), data_shards, parity_shards)?;
+        Self::upload(global, buckets, data[..len].to_vec(), start, data_shards).await
+    }
+
+    // Picks the buckets and decides how many bytes of the data the block will take
+    pub fn plan(global: &Global, len: usize, data_shards: usize, parity_shards: usize) -> Result<(Vec<String>, usize), String> {
+        if data_shards == 0 || parity_shards == 0 {
+            return Err("Erasure coding needs at least one data and one parity shard".to_string())
+        }
+        if len == 0 {
+            return Err("Data is empty".to_string())
+        }
+
+        // every shard goes to a different bucket
+        let mut buckets: Vec<String> = Vec::new();
+        while buckets.len() < data_shards + parity_shards {
+            match global.next_bucket(1, &buckets) {
+                Some(bucket) => buckets.push(bucket.clone()),
+                None => return Err(format!("Not enough buckets for {} data and {} parity shards", data_shards, parity_shards))
+            }
+        }
+
+        // the smallest bucket decides the size of the shards
+        let mut max_shard = usize::MAX;
+        for bucket_name in buckets.iter() {
+            let bucket = global.get_bucket(bucket_name).ok_or("Bucket not found".to_string())?;
+            max_shard = std::cmp::min(max_shard, bucket.max_size());
+        }
+        Ok((buckets, std::cmp::min(len, max_shard.saturating_mul(data_shards))))
+    }
+
+    // Encodes the data and uploads every shard to its bucket, the data has to be already sliced to the planned size
+    pub async fn upload(global: Arc<Global>, buckets: Vec<String>, data: Vec<u8>, start: usize, data_shards: usize) -> Result<BlockType, String> {
+        let parity_shards = buckets.len().saturating_sub(data_shards);
+        let encoded = encode(&data, data_shards, parity_shards)?;
+
+        let mut shards: Vec<Shard> = Vec::new();
+        let mut error = None;
+        for (bucket_name, shard) in buckets.into_iter().zip(encoded) {
+            let bucket = match global.get_bucket(&bucket_name) {
+                Some(bucket) => bucket,
+                None => {
+                    error = Some("Bucket not found".to_string());
+                    break;
+                }
+            };
+            let descriptor = match bucket.create().await {
+                Ok(descriptor) => descriptor,
+                Err(e) => {
+                    error = Some(format!("Could not create the descriptor: {}", e));
+                    break;
+                }
+            };
+            let result = bucket.put(&descriptor, shard).await;
+            shards.push(Shard { bucket: bucket_name, descriptor });
+            if let Err(e) = result {
+                error = Some(e);
+                break;
+            }
+        }
+
+        let block = ErasureBlock {
+            shards,
+            data_shards,
+            range: start..start + data.len(),
+        };
+
+        // if we encountered an error, we delete all the shards we created
+        if let Some(err) = error {
+            return match block.delete(global).await {
+                Ok(_) => Err(err),
+                Err(e) => Err(format!("{}, {}", err, e))
+            };
+        }
+
+        Ok(BlockType::Erasure(block))
+    }
+
+    // fetches the shards until enough of them are available to rebuild the data
+    async fn fetch(&self, global: Arc<Global>) -> Result<Vec<u8>, String> {
+        let size = shard_size(self.range.len(), self.data_shards);
+        let mut shards: Vec<Option<Vec<u8>>> = vec![None; self.shards.len()];
+        let mut found = 0;
+        let mut errors = Vec::new();
+        for (i, shard) in self.shards.iter().enumerate() {
+            if found == self.data_shards {
+                break;
+            }
+            let bucket = match global.get_bucket(&shard.bucket) {
+                Some(bucket) => bucket,
+                None => {
+                    errors.push(format!("Bucket {} not found", shard.bucket));
+                    continue;
+                }
+            };
+            match bucket.get(&shard.descriptor).await {
+                Ok(data) if data.len() == size => {
+                    shards[i] = Some(data);
+                    found += 1;
+                },
+                Ok(_) => errors.push(format!("Shard in {} has wrong size", shard.bucket)),
+                Err(e) => errors.push(format!("Shard in {}: {}", shard.bucket, e)),
+            }
+        }
+        if found < self.data_shards {
+            return Err(format!("Could not get enough shards ({} of {}): {}", found, self.data_shards, errors.join(", ")));
+        }
+
+        // the parity shards are only needed if some of the data shards are missing
+        if shards[..self.data_shards].iter().any(|shard| shard.is_none()) {
+            let rs = ReedSolomon::new(self.data_shards, self.shards.len() - self.data_shards)
+                .map_err(|e| format!("Invalid erasure coding parameters: {:?}", e))?;
+            rs.reconstruct_data(&mut shards).map_err(|e| format!("Could not rebuild the data: {:?}", e))?;
+        }
+
+        let mut data = shards.into_iter()
+            .take(self.data_shards)
+            .flat_map(|shard| shard.unwrap_or_default())
+            .collect::<Vec<u8>>();
+        data.truncate(self.range.len());
+        Ok(data)
+    }
+}
+
+#[async_trait]
+impl Block for ErasureBlock {
+    async fn range(&self, _global: Arc<Global>) -> Result<Range<usize>, String> {
+        Ok(self.range.clone())
+    }
+
+    fn known_range(&self) -> Option<Range<usize>> {
+        Some(self.range.clone())
+    }
+
+    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<'_, Result<Vec<u8>, String>> {
+        Box::pin(async_stream::stream! {
+            if range.end <= self.range.start || range.start >= self.range.end {
+                return // the range is outside of the block, so we return an empty stream
+            }
+            let data = self.fetch(global).await?;
+
+            // calculate the data slice
+            let start = std::cmp::max(range.start, self.range.start) - self.range.start;
+            let end = std::cmp::min(range.end, self.range.end) - self.range.start;
+            yield Ok(data[start..end].to_vec());
+        })
+    }
+
+    // indirect blocks ensure that the data.length == range.length && data[0] corresponds to range.start
+    async fn put(&mut self, global: Arc<Global>, data: Vec<u8>, _range: Range<usize>) -> Result<(), String> {
+        let encoded = encode(&data, self.data_shards, self.shards.len() - self.data_shards)?;
+
+        let mut failed = Vec::new();
+        for (i, (shard, data)) in self.shards.iter().zip(encoded).enumerate() {
+            let result = match global.get_bucket(&shard.bucket) {
+                Some(bucket) => bucket.put(&shard.descriptor, data.clone()).await,
+                None => Err(format!("Bucket {} not found", shard.bucket))
+            };
+            if let Err(e) = result {
+                failed.push((i, data, e));
+            }
+        }
+
+        // a shard that could not be overwritten must not be mixed with the new ones, so it is written to a new place just like scrubbing does
+        let mut errors = Vec::new();
+        for (i, data, error) in failed {
+            let exclude = self.shards.iter().enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, shard)| shard.bucket.clone())
+                .collect::<Vec<String>>();
+            match rewrite_chunk(&global, data, &exclude, &self.shards[i].bucket).await {
+                Ok((bucket, descriptor)) => {
+                    let old = std::mem::replace(&mut self.shards[i], Shard { bucket, descriptor });
+                    if let Some(bucket) = global.get_bucket(&old.bucket) {
+                        let _ = bucket.delete(&old.descriptor).await;
+                    }
+                },
+                Err(e) => errors.push(format!("Could not put the shard in {}: {}, {}", self.shards[i].bucket, error, e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    async fn delete(&self, global: Arc<Global>) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for shard in self.shards.iter() {
+            let bucket = match global.get_bucket(&shard.bucket) {
+                Some(bucket) => bucket,
+                None => {
+                    errors.push(format!("Bucket {} not found", shard.bucket));
+                    continue;
+                }
+            };
+            if let Err(e) = bucket.delete(&shard.descriptor).await {
+                errors.push(e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    async fn create(global: Arc<Global>, data: Vec<u8>, start: usize) -> Result<BlockType, String> {
+        let (data_shards, parity_shards) = match global.redundancy {
+            Redundancy::Erasure { data, parity } => (data, parity),
+            _ => (default_data_shards(), default_parity_shards())
+        };
+        Self::create_erasure(global, data, start, data_shards, parity_shards).await
+    }
+
+    async fn scrub(&mut self, global: Arc<Global>, report: &mut ScrubReport, repair: bool) -> Repairs {
+        let size = shard_size(self.range.len(), self.data_shards);
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::new();
+        for shard in self.shards.iter() {
+            report.checked += 1;
+            match check_chunk(&global, &shard.bucket, &shard.descriptor, size).await {
+                Ok(data) => shards.push(Some(data)),
+                Err(e) => {
+                    report.missing(format!("Shard in {}: {}", shard.bucket, e));
+                    shards.push(None);
+                }
+            }
+        }
+        let missing = shards.iter().enumerate()
+            .filter(|(_, shard)| shard.is_none())
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+        if missing.is_empty() {
+            return Repairs::default();
+        }
+        if shards.len() - missing.len() < self.data_shards {
+            report.lost += 1;
+            return Repairs::default();
+        }
+        if !repair {
+            return Repairs::default();
+        }
+
+        // rebuild the parity shards too, they have to be rewritten as well
+        let rs = match ReedSolomon::new(self.data_shards, self.shards.len() - self.data_shards) {
+            Ok(rs) => rs,
+            Err(e) => {
+                report.errors.push(format!("Invalid erasure coding parameters: {:?}", e));
+                return Repairs::default();
+            }
+        };
+        if let Err(e) = rs.reconstruct(&mut shards) {
+            report.errors.push(format!("Could not rebuild the shards: {:?}", e));
+            return Repairs::default();
+        }
+
+        let mut repairs = Repairs::default();
+        for i in missing {
+            // the new shard can not share a bucket with the other shards
+            let exclude = self.shards.iter().enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, shard)| shard.bucket.clone())
+                .collect::<Vec<String>>();
+            let data = shards[i].take().unwrap_or_default();
+            match rewrite_chunk(&global, data, &exclude, &self.shards[i].bucket).await {
+                Ok((bucket, descriptor)) => {
+                    // the old shard is deleted by the caller once the block is saved
+                    let old = std::mem::replace(&mut self.shards[i], Shard { bucket: bucket.clone(), descriptor: descriptor.clone() });
+                    repairs.old.push((old.bucket, old.descriptor));
+                    repairs.new.push((bucket, descriptor));
+                    report.repaired += 1;
+                },
+                Err(e) => report.errors.push(format!("Could not repair the shard in {}: {}", self.shards[i].bucket, e)),
+            }
+        }
+        repairs
+    }
+
+    async fn reachable(&self, _global: Arc<Global>, found: &mut HashSet<Descriptor>) -> Result<(), String> {
+        for shard in self.shards.iter() {
+            found.insert(shard.descriptor.clone());
+        }
+        Ok(())
+    }
+
+    async fn shift(&mut self, _global: Arc<Global>, offset: usize) -> Result<(), String> {
+        self.range = self.range.start + offset..self.range.end + offset;
+        Ok(())
+    }
+
+    fn to_enum(self) -> BlockType {
+        BlockType::Erasure(self)
+    }
+}
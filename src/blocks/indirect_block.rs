@@ -1,18 +1,154 @@
-use std::{ops::Range, sync::Arc};
+use std::{collections::HashSet, ops::Range, sync::Arc};
 
 use async_trait::async_trait;
-use futures::stream::{BoxStream, StreamExt};
+use futures::{future::BoxFuture, stream::{BoxStream, FuturesOrdered, StreamExt}};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
 use serde::{Serialize, Deserialize};
 
-use crate::global::Global;
-use super::{block::{Block, BlockType}, direct_block::DirectBlock, stored_block::StoredBlock};
+use crate::{global::{Global, Descriptor}, scrub::{ScrubReport, Repairs}};
+use super::{block::{Block, BlockType, may_overlap}, redundancy::Redundancy, stored_block::StoredBlock};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct IndirectBlock {
     #[serde(rename = "b")]
     blocks: Vec<BlockType>,  // we will make sure that these are in order
 }
 
+async fn delete_all(global: Arc<Global>, blocks: &[BlockType]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for block in blocks.iter() {
+        if let Err(err) = block.delete(global.clone()).await {
+            errors.push(err);
+        }
+    }
+    errors
+}
+
+impl IndirectBlock {
+    // Creates the block from data read from the reader as it is uploaded, so only the chunks in flight are kept in memory.
+    // The chunks use the given redundancy instead of the global one. Returns the block and where its data ends.
+    pub fn create_from_reader<'a, R: AsyncBufRead + Unpin + Send>(global: Arc<Global>, reader: &'a mut R, start: usize, redundancy: Redundancy) -> BoxFuture<'a, Result<(BlockType, usize), String>> {
+        Box::pin(async move {
+            let mut uploads = FuturesOrdered::new();
+            let mut blocks = Vec::new(); // we will make sure that these are in order
+            let mut errors = Vec::new();
+            let mut start = start;
+            let mut count = 0;
+            let mut ended = false;
+
+            while !ended && errors.is_empty() && count < global.direct_block_count {
+                // we do not know how much data is left, so every chunk is planned as big as the buckets allow
+                let mut plan = match redundancy.plan(&global, usize::MAX) {
+                    Ok(plan) => plan,
+                    Err(err) => {
+                        errors.push(err);
+                        break;
+                    }
+                };
+                let mut chunk = Vec::new();
+                if let Err(err) = (&mut *reader).take(plan.len as u64).read_to_end(&mut chunk).await {
+                    errors.push(format!("Could not read the data: {}", err));
+                    break;
+                }
+                if chunk.is_empty() {
+                    break;
+                }
+                ended = chunk.len() < plan.len;
+                plan.len = chunk.len();
+
+                let (upload_global, upload_redundancy, chunk_start) = (global.clone(), redundancy.clone(), start);
+                start += chunk.len();
+                count += 1;
+                uploads.push_back(async move { upload_redundancy.upload(upload_global, plan, chunk, chunk_start).await });
+
+                // wait for a slot before reading the next chunk
+                if uploads.len() >= std::cmp::max(global.parallel_uploads, 1) {
+                    match uploads.next().await {
+                        Some(Ok(block)) => blocks.push(block.to_enum()),
+                        Some(Err(err)) => errors.push(err),
+                        None => (),
+                    }
+                }
+            }
+            while let Some(result) = uploads.next().await {
+                match result {
+                    Ok(block) => blocks.push(block.to_enum()),
+                    Err(err) => errors.push(err),
+                }
+            }
+
+            // if there is still data left, we create a stored block
+            if !ended && errors.is_empty() && count == global.direct_block_count {
+                match reader.fill_buf().await {
+                    Ok([]) => (),
+                    Ok(_) => match StoredBlock::create_from_reader(global.clone(), reader, start, redundancy).await {
+                        Ok((block, end)) => {
+                            blocks.push(block.to_enum());
+                            start = end;
+                        },
+                        Err(err) => errors.push(err),
+                    },
+                    Err(err) => errors.push(format!("Could not read the data: {}", err)),
+                }
+            }
+
+            // if we encountered an error, we delete all the blocks we created
+            if !errors.is_empty() {
+                errors.extend(delete_all(global, &blocks).await);
+                return Err(errors.join(", "));
+            }
+
+            Ok((BlockType::Indirect(IndirectBlock {
+                blocks,
+            }), start))
+        })
+    }
+
+    // The chunks, in order
+    pub fn into_blocks(self) -> Vec<BlockType> {
+        self.blocks
+    }
+
+    // Builds the block from chunks that are already uploaded and in order, the chunks over direct_block_count go into stored blocks like in create_from_reader.
+    // The chunks are not deleted on failure, they still belong to the caller.
+    pub fn from_blocks(global: Arc<Global>, mut blocks: Vec<BlockType>) -> BoxFuture<'static, Result<BlockType, String>> {
+        Box::pin(async move {
+            if blocks.len() > global.direct_block_count {
+                let rest = blocks.split_off(global.direct_block_count);
+                let start = rest[0].range(global.clone()).await?.start;
+                let end = rest[rest.len() - 1].range(global.clone()).await?.end;
+                let inner = Self::from_blocks(global.clone(), rest).await?;
+                match StoredBlock::store(global.clone(), &inner, start..end).await {
+                    Ok(stored) => blocks.push(stored),
+                    Err(err) => return match Self::release(global, inner).await {
+                        Ok(_) => Err(err),
+                        Err(e) => Err(format!("{}, {}", err, e)),
+                    },
+                }
+            }
+            Ok(BlockType::Indirect(IndirectBlock {
+                blocks,
+            }))
+        })
+    }
+
+    // Undoes from_blocks, the descriptors it made are deleted and the chunks it was given are left alone
+    pub fn release(global: Arc<Global>, block: BlockType) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async move {
+            let indirect = match block {
+                BlockType::Indirect(indirect) if indirect.blocks.len() > global.direct_block_count => indirect,
+                _ => return Ok(()),
+            };
+            if let Some(BlockType::Stored(stored)) = indirect.blocks.last() {
+                let inner = stored.stored.get::<BlockType>(global.clone()).await?;
+                Self::release(global.clone(), inner).await?;
+                stored.stored.delete(global).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
 #[async_trait]
 impl Block for IndirectBlock {
     async fn range(&self, global: Arc<Global>) -> Result<Range<usize>, String> {
@@ -31,15 +167,57 @@ impl Block for IndirectBlock {
         Ok(first_range.start..last_range.end)
     }
 
-    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<Result<Vec<u8>, String>> {
+    fn known_range(&self) -> Option<Range<usize>> {
+        match (self.blocks.first(), self.blocks.last()) {
+            (Some(first), Some(last)) => Some(first.known_range()?.start..last.known_range()?.end),
+            _ => Some(0..0),
+        }
+    }
+
+    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<'_, Result<Vec<u8>, String>> {
         Box::pin(async_stream::stream! {
-            for block in self.blocks.iter() {
-                let global_clone = global.clone();
-                let range_clone = range.clone();
-                let mut stream = block.get(global_clone, range_clone);
-                while let Some(data) = stream.next().await {
-                    yield data;
+            // blocks that are known to be outside of the range are skipped without touching them
+            let blocks = self.blocks.iter()
+                .filter(|block| may_overlap(*block, &range))
+                .collect::<Vec<&BlockType>>();
+            let mut i = 0;
+            while i < blocks.len() {
+                // stored and indirect blocks can hold a lot of data, so they are streamed one by one and prefetch on their own
+                if matches!(blocks[i], BlockType::Stored(_) | BlockType::Indirect(_)) {
+                    let mut stream = blocks[i].get(global.clone(), range.clone());
+                    while let Some(data) = stream.next().await {
+                        yield data;
+                    }
+                    i += 1;
+                    continue;
+                }
+
+                // the other blocks hold a single chunk, so we fetch a few of them at once and still yield them in order.
+                // a chunk is only started while fewer chunks are in flight than the prefetch depth of its source
+                let end = blocks[i..].iter()
+                    .position(|block| matches!(block, BlockType::Stored(_) | BlockType::Indirect(_)))
+                    .map_or(blocks.len(), |offset| i + offset);
+                let mut pending = blocks[i..end].iter().peekable();
+                let mut fetches = FuturesOrdered::new();
+                loop {
+                    while let Some(block) = pending.peek() {
+                        let source_types = block.buckets().into_iter()
+                            .filter_map(|bucket| global.get_bucket(bucket))
+                            .map(|bucket| bucket.type_name());
+                        if fetches.len() >= global.prefetch.depth(source_types) {
+                            break;
+                        }
+                        fetches.push_back(block.get(global.clone(), range.clone()).collect::<Vec<Result<Vec<u8>, String>>>());
+                        pending.next();
+                    }
+                    match fetches.next().await {
+                        Some(chunks) => for data in chunks {
+                            yield data;
+                        },
+                        None => break,
+                    }
                 }
+                i = end;
             }
         })
     }
@@ -63,9 +241,10 @@ impl Block for IndirectBlock {
 
         // if data is left, we create new blocks just like we did in the create function
         while start < range.end && self.blocks.len() < global.direct_block_count {
-            let block = DirectBlock::create(global.clone(), data[(start-start_offset)..].to_vec(), start).await?;
-            let range = block.range(global.clone()).await?;
-            start = range.end;
+            let plan = global.redundancy.plan(&global, range.end - start)?;
+            let slice = data[start-start_offset..start-start_offset+plan.len].to_vec();
+            let block = global.redundancy.upload(global.clone(), plan, slice, start).await?;
+            start = block.range(global.clone()).await?.end;
             self.blocks.push(block.to_enum());
         }
 
@@ -95,50 +274,30 @@ impl Block for IndirectBlock {
     }
 
     async fn create(global: Arc<Global>, data: Vec<u8>, start: usize) -> Result<BlockType, String> {
+        let (block, _) = Self::create_from_reader(global.clone(), &mut data.as_slice(), start, global.redundancy.clone()).await?;
+        Ok(block)
+    }
 
-        let mut blocks = Vec::new(); // we will make sure that these are in order
-        let slice_offset = start;
-        let mut start = start;
-        let end = start + data.len();
-
-        let mut error: Option<String> = None;
-        while start < end && blocks.len() < global.direct_block_count {
-            let block = DirectBlock::create(global.clone(), data[(start-slice_offset)..].to_vec(), start).await?;
-            let range = match block.range(global.clone()).await {
-                Ok(range) => range,
-                Err(err) => {
-                    error = Some(err);
-                    break;
-                }
-            };
-            start = range.end;
-            blocks.push(block.to_enum());
+    async fn scrub(&mut self, global: Arc<Global>, report: &mut ScrubReport, repair: bool) -> Repairs {
+        let mut repairs = Repairs::default();
+        for block in self.blocks.iter_mut() {
+            repairs.extend(block.scrub(global.clone(), report, repair).await);
         }
+        repairs
+    }
 
-        // if we encountered an error, we delete all the blocks we created
-        if let Some(err) = error {
-            let mut errors = vec![err];
-            for block in blocks.iter() {
-                match block.delete(global.clone()).await {
-                    Ok(_) => (),
-                    Err(err) => errors.push(err),
-                }
-            }
-
-            return Err(errors.join(", "));
+    async fn reachable(&self, global: Arc<Global>, found: &mut HashSet<Descriptor>) -> Result<(), String> {
+        for block in self.blocks.iter() {
+            block.reachable(global.clone(), found).await?;
         }
+        Ok(())
+    }
 
-
-        // if there is still data left, we create a stored block
-        if start < end {
-            let slice = data[start-slice_offset..].to_vec();
-            let block = StoredBlock::create(global, slice, start).await?;
-            blocks.push(block.to_enum());
+    async fn shift(&mut self, global: Arc<Global>, offset: usize) -> Result<(), String> {
+        for block in self.blocks.iter_mut() {
+            block.shift(global.clone(), offset).await?;
         }
-        
-        Ok(BlockType::Indirect(IndirectBlock {
-            blocks,
-        }))
+        Ok(())
     }
 
     fn to_enum(self) -> BlockType {
@@ -1,4 +1,7 @@
 pub mod block;
 pub mod direct_block;
+pub mod erasure_block;
 pub mod indirect_block;
+pub mod redundancy;
+pub mod replicated_block;
 pub mod stored_block;
\ No newline at end of file
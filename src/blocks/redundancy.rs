@@ -0,0 +1,91 @@
+/*
+    Redundancy decides which kind of block is used to store a single chunk of data.
+    It can be set globally in the config, or chosen for a single file when it is created.
+ */
+
+use std::{str::FromStr, sync::Arc};
+use serde::Deserialize;
+
+use crate::global::Global;
+use super::{block::BlockType, direct_block::DirectBlock, replicated_block::ReplicatedBlock, erasure_block::ErasureBlock};
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type")]
+pub enum Redundancy {
+    #[serde(rename = "none")]
+    #[default]
+    None,
+    #[serde(rename = "replicate")]
+    Replicate {
+        #[serde(default = "default_copies")]
+        copies: usize
+    },
+    #[serde(rename = "erasure")]
+    Erasure {
+        #[serde(default = "default_data_shards")]
+        data: usize,
+        #[serde(default = "default_parity_shards")]
+        parity: usize
+    },
+}
+
+// The buckets chosen for a block and how many bytes of the data it takes
+#[derive(Debug)]
+pub struct Plan {
+    buckets: Vec<String>,
+    pub len: usize,
+}
+
+const fn default_copies() -> usize { 2 }
+pub const fn default_data_shards() -> usize { 4 }
+pub const fn default_parity_shards() -> usize { 2 }
+
+impl Redundancy {
+    // Chooses the buckets of the next block without uploading anything, so the chunks can be split up front
+    pub fn plan(&self, global: &Global, len: usize) -> Result<Plan, String> {
+        let (buckets, len) = match self {
+            Redundancy::None => DirectBlock::plan(global, len).map(|(bucket, len)| (vec![bucket], len))?,
+            Redundancy::Replicate { copies } => ReplicatedBlock::plan(global, len, *copies)?,
+            Redundancy::Erasure { data, parity } => ErasureBlock::plan(global, len, *data, *parity)?,
+        };
+        Ok(Plan { buckets, len })
+    }
+
+    // Uploads a planned block, the data has to be exactly plan.len bytes long
+    pub async fn upload(&self, global: Arc<Global>, plan: Plan, data: Vec<u8>, start: usize) -> Result<BlockType, String> {
+        match self {
+            Redundancy::None => {
+                let bucket = plan.buckets.into_iter().next().ok_or("No buckets found".to_string())?;
+                DirectBlock::upload(global, bucket, data, start).await
+            },
+            Redundancy::Replicate { .. } => ReplicatedBlock::upload(global, plan.buckets, data, start).await,
+            Redundancy::Erasure { data: data_shards, .. } => ErasureBlock::upload(global, plan.buckets, data, start, *data_shards).await,
+        }
+    }
+
+    pub fn human_readable(&self) -> String {
+        match self {
+            Redundancy::None => "none".to_string(),
+            Redundancy::Replicate { copies } => format!("{} copies", copies),
+            Redundancy::Erasure { data, parity } => format!("{} data + {} parity shards", data, parity),
+        }
+    }
+}
+
+// Parses the short form used by the shell and the upload form: none, replicate[:copies], erasure[:data:parity]
+impl FromStr for Redundancy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<&str>>();
+        let number = |part: &str| part.parse::<usize>().map_err(|_| format!("Invalid number: {}", part));
+        match parts.as_slice() {
+            ["none"] => Ok(Redundancy::None),
+            ["replicate"] => Ok(Redundancy::Replicate { copies: default_copies() }),
+            ["replicate", copies] => Ok(Redundancy::Replicate { copies: number(copies)? }),
+            ["erasure"] => Ok(Redundancy::Erasure { data: default_data_shards(), parity: default_parity_shards() }),
+            ["erasure", data, parity] => Ok(Redundancy::Erasure { data: number(data)?, parity: number(parity)? }),
+            _ => Err(format!("Invalid redundancy: {}. Use none, replicate[:copies] or erasure[:data:parity]", s)),
+        }
+    }
+}
@@ -0,0 +1,291 @@
+/*
+    This block stores the same data in multiple buckets.
+    If one of the copies is missing, the next one is used.
+ */
+
+use std::{collections::HashSet, ops::Range, sync::Arc};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use serde::{Serialize, Deserialize};
+
+use crate::{global::{Global, Descriptor}, scrub::{ScrubReport, Repairs, check_chunk, rewrite_chunk}};
+use super::{block::{Block, BlockType}, redundancy::Redundancy};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Replica {
+    #[serde(rename = "b")]
+    pub bucket: String,
+    #[serde(rename = "d")]
+    pub descriptor: Descriptor,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReplicatedBlock {
+    #[serde(rename = "c")]
+    copies: Vec<Replica>,
+    #[serde(rename = "r")]
+    range: Range<usize>,
+} // we use short names to reduce the size of the serialized data while allowing backwards compatibility
+
+impl ReplicatedBlock {
+    // The buckets the chunk is read from
+    pub fn buckets(&self) -> Vec<&str> {
+        self.copies.iter().map(|replica| replica.bucket.as_str()).collect()
+    }
+
+    #[cfg(test)]
+    pub fn copies(&self) -> &[Replica] {
+        &self.copies
+    }
+
+    pub async fn create_replicated(global: Arc<Global>, data: Vec<u8>, start: usize, copies: usize) -> Result<BlockType, String> {
+        let (buckets, len) = Self::plan(&global, data.len(), copies)?;
+        Self::upload(global, buckets, data[..len].to_vec(), start).await
+    }
+
+    // Picks the buckets and decides how many bytes of the data the block will take
+    pub fn plan(global: &Global, len: usize, copies: usize) -> Result<(Vec<String>, usize), String> {
+        if copies == 0 {
+            return Err("Replication factor must be at least 1".to_string())
+        }
+
+        // the first bucket decides the size of the chunk, the rest has to fit it
+        let first = global.random_bucket().ok_or("No buckets found".to_string())?;
+        let bucket = global.get_bucket(first).ok_or("Bucket not found".to_string())?;
+        let len = std::cmp::min(len, bucket.max_size());
+        if len == 0 {
+            return Err("Data is empty".to_string())
+        }
+
+        let mut buckets = vec![first.clone()];
+        while buckets.len() < copies {
+            match global.next_bucket(len, &buckets) {
+                Some(bucket) => buckets.push(bucket.clone()),
+                None => return Err(format!("Not enough buckets for {} copies of {} bytes", copies, len))
+            }
+        }
+        Ok((buckets, len))
+    }
+
+    // Uploads a copy of the data to every bucket, the data has to be already sliced to the planned size
+    pub async fn upload(global: Arc<Global>, buckets: Vec<String>, data: Vec<u8>, start: usize) -> Result<BlockType, String> {
+        let mut replicas: Vec<Replica> = Vec::new();
+        let mut error = None;
+        for bucket_name in buckets {
+            let bucket = match global.get_bucket(&bucket_name) {
+                Some(bucket) => bucket,
+                None => {
+                    error = Some("Bucket not found".to_string());
+                    break;
+                }
+            };
+            let descriptor = match bucket.create().await {
+                Ok(descriptor) => descriptor,
+                Err(e) => {
+                    error = Some(format!("Could not create the descriptor: {}", e));
+                    break;
+                }
+            };
+            let result = bucket.put(&descriptor, data.clone()).await;
+            replicas.push(Replica { bucket: bucket_name, descriptor });
+            if let Err(e) = result {
+                error = Some(e);
+                break;
+            }
+        }
+
+        // if we encountered an error, we delete all the copies we created
+        if let Some(err) = error {
+            let block = ReplicatedBlock { copies: replicas, range: start..start + data.len() };
+            return match block.delete(global).await {
+                Ok(_) => Err(err),
+                Err(e) => Err(format!("{}, {}", err, e))
+            };
+        }
+
+        Ok(BlockType::Replicated(ReplicatedBlock {
+            copies: replicas,
+            range: start..start + data.len(),
+        }))
+    }
+}
+
+#[async_trait]
+impl Block for ReplicatedBlock {
+    async fn range(&self, _global: Arc<Global>) -> Result<Range<usize>, String> {
+        Ok(self.range.clone())
+    }
+
+    fn known_range(&self) -> Option<Range<usize>> {
+        Some(self.range.clone())
+    }
+
+    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<'_, Result<Vec<u8>, String>> {
+        Box::pin(async_stream::stream! {
+            if range.end <= self.range.start || range.start >= self.range.end {
+                return // the range is outside of the block, so we return an empty stream
+            }
+
+            // try every copy until one of them works
+            let mut errors = Vec::new();
+            let mut data = None;
+            for replica in self.copies.iter() {
+                let bucket = match global.get_bucket(&replica.bucket) {
+                    Some(bucket) => bucket,
+                    None => {
+                        errors.push(format!("Bucket {} not found", replica.bucket));
+                        continue;
+                    }
+                };
+                match bucket.get(&replica.descriptor).await {
+                    Ok(got) if got.len() == self.range.len() => {
+                        data = Some(got);
+                        break;
+                    },
+                    Ok(_) => errors.push(format!("Copy in {} has wrong size", replica.bucket)),
+                    Err(e) => errors.push(format!("Copy in {}: {}", replica.bucket, e)),
+                }
+            }
+            let data = match data {
+                Some(data) => data,
+                None => Err(format!("Could not get the data: {}", errors.join(", ")))?
+            };
+
+            // calculate the data slice
+            let start = std::cmp::max(range.start, self.range.start) - self.range.start;
+            let end = std::cmp::min(range.end, self.range.end) - self.range.start;
+            yield Ok(data[start..end].to_vec());
+        })
+    }
+
+    // indirect blocks ensure that the data.length == range.length && data[0] corresponds to range.start
+    async fn put(&mut self, global: Arc<Global>, data: Vec<u8>, _range: Range<usize>) -> Result<(), String> {
+        let mut failed = Vec::new();
+        for (i, replica) in self.copies.iter().enumerate() {
+            let result = match global.get_bucket(&replica.bucket) {
+                Some(bucket) => bucket.put(&replica.descriptor, data.clone()).await,
+                None => Err(format!("Bucket {} not found", replica.bucket))
+            };
+            if let Err(e) = result {
+                failed.push((i, e));
+            }
+        }
+
+        // a missing copy should not make the whole block read-only, but a copy with the old data must not be read either,
+        // so the failed copies are written to new places just like scrubbing does
+        let mut errors = Vec::new();
+        for (i, error) in failed {
+            let exclude = self.copies.iter().enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, replica)| replica.bucket.clone())
+                .collect::<Vec<String>>();
+            match rewrite_chunk(&global, data.clone(), &exclude, &self.copies[i].bucket).await {
+                Ok((bucket, descriptor)) => {
+                    let old = std::mem::replace(&mut self.copies[i], Replica { bucket, descriptor });
+                    if let Some(bucket) = global.get_bucket(&old.bucket) {
+                        let _ = bucket.delete(&old.descriptor).await;
+                    }
+                },
+                Err(e) => errors.push(format!("Could not put the copy in {}: {}, {}", self.copies[i].bucket, error, e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    async fn delete(&self, global: Arc<Global>) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for replica in self.copies.iter() {
+            let bucket = match global.get_bucket(&replica.bucket) {
+                Some(bucket) => bucket,
+                None => {
+                    errors.push(format!("Bucket {} not found", replica.bucket));
+                    continue;
+                }
+            };
+            if let Err(e) = bucket.delete(&replica.descriptor).await {
+                errors.push(e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    async fn create(global: Arc<Global>, data: Vec<u8>, start: usize) -> Result<BlockType, String> {
+        let copies = match global.redundancy {
+            Redundancy::Replicate { copies } => copies,
+            _ => 1
+        };
+        Self::create_replicated(global, data, start, copies).await
+    }
+
+    async fn scrub(&mut self, global: Arc<Global>, report: &mut ScrubReport, repair: bool) -> Repairs {
+        let mut healthy = None;
+        let mut missing = Vec::new();
+        for (i, replica) in self.copies.iter().enumerate() {
+            report.checked += 1;
+            match check_chunk(&global, &replica.bucket, &replica.descriptor, self.range.len()).await {
+                Ok(data) => healthy = Some(data),
+                Err(e) => {
+                    report.missing(format!("Copy in {}: {}", replica.bucket, e));
+                    missing.push(i);
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Repairs::default();
+        }
+        let data = match healthy {
+            Some(data) => data,
+            None => {
+                report.lost += 1;
+                return Repairs::default();
+            }
+        };
+        if !repair {
+            return Repairs::default();
+        }
+
+        let mut repairs = Repairs::default();
+        for i in missing {
+            // the new copy can not share a bucket with the other copies
+            let exclude = self.copies.iter().enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, replica)| replica.bucket.clone())
+                .collect::<Vec<String>>();
+            match rewrite_chunk(&global, data.clone(), &exclude, &self.copies[i].bucket).await {
+                Ok((bucket, descriptor)) => {
+                    // the old copy is deleted by the caller once the block is saved
+                    let old = std::mem::replace(&mut self.copies[i], Replica { bucket: bucket.clone(), descriptor: descriptor.clone() });
+                    repairs.old.push((old.bucket, old.descriptor));
+                    repairs.new.push((bucket, descriptor));
+                    report.repaired += 1;
+                },
+                Err(e) => report.errors.push(format!("Could not repair the copy in {}: {}", self.copies[i].bucket, e)),
+            }
+        }
+        repairs
+    }
+
+    async fn reachable(&self, _global: Arc<Global>, found: &mut HashSet<Descriptor>) -> Result<(), String> {
+        for replica in self.copies.iter() {
+            found.insert(replica.descriptor.clone());
+        }
+        Ok(())
+    }
+
+    async fn shift(&mut self, _global: Arc<Global>, offset: usize) -> Result<(), String> {
+        self.range = self.range.start + offset..self.range.end + offset;
+        Ok(())
+    }
+
+    fn to_enum(self) -> BlockType {
+        BlockType::Replicated(self)
+    }
+}
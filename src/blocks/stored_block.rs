@@ -2,33 +2,70 @@
     This block type uses Stored to store the description of a block it wraps.
  */
 
-use std::{sync::Arc, ops::Range};
+use std::{collections::HashSet, sync::Arc, ops::Range};
 use async_trait::async_trait;
 use futures::{StreamExt, stream::BoxStream};
 use serde::{Serialize, Deserialize};
+use tokio::io::AsyncBufRead;
 
-use crate::{global::Global, blocks::block::{Block, BlockType}, stored::Stored};
+use crate::{global::{Global, Descriptor}, blocks::{block::{Block, BlockType, may_overlap}, indirect_block::IndirectBlock, redundancy::Redundancy}, scrub::{ScrubReport, Repairs}, stored::Stored};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StoredBlock {
     #[serde(rename = "s")]
-    pub stored: Stored
+    pub stored: Stored,
+    #[serde(rename = "r", default, skip_serializing_if = "Option::is_none")]
+    range: Option<Range<usize>>, // cached, so reads can skip the block without downloading it. Older blocks do not have it
+}
+
+impl StoredBlock {
+    // Creates the block from data read from the reader, with the given redundancy. Returns the block and where its data ends
+    pub async fn create_from_reader<R: AsyncBufRead + Unpin + Send>(global: Arc<Global>, reader: &mut R, start: usize, redundancy: Redundancy) -> Result<(BlockType, usize), String> {
+        let (block, end) = IndirectBlock::create_from_reader(global.clone(), reader, start, redundancy).await?;
+        match Self::store(global.clone(), &block, start..end).await {
+            Ok(stored) => Ok((stored, end)),
+            Err(err) => match block.delete(global).await {
+                Ok(_) => Err(err),
+                Err(e) => Err(format!("{}, {}", err, e))
+            },
+        }
+    }
+
+    // Wraps a block that is already uploaded, the range is the one the wrapped block covers
+    pub async fn store(global: Arc<Global>, block: &BlockType, range: Range<usize>) -> Result<BlockType, String> {
+        let stored = Stored::create(global, block).await?;
+        Ok(BlockType::Stored(StoredBlock {
+            stored,
+            range: Some(range),
+        }))
+    }
 }
 
 #[async_trait]
 impl Block for StoredBlock {
     async fn range(&self, global: Arc<Global>) -> Result<Range<usize>, String> {
-        self.stored.get::<BlockType>(global.clone()).await?.range(global).await
+        match &self.range {
+            Some(range) => Ok(range.clone()),
+            None => self.stored.get::<BlockType>(global.clone()).await?.range(global).await
+        }
+    }
+
+    fn known_range(&self) -> Option<Range<usize>> {
+        self.range.clone()
     }
 
     async fn put(&mut self, global: Arc<Global>, data: Vec<u8>, range: Range<usize>) -> Result<(), String> {
         let mut block = self.stored.get::<BlockType>(global.clone()).await?;
         block.put(global.clone(), data, range).await?;
+        self.range = Some(block.range(global.clone()).await?);
         self.stored.put(global, block).await
     }
 
-    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<Result<Vec<u8>, String>> {
+    fn get(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<'_, Result<Vec<u8>, String>> {
         Box::pin(async_stream::stream! {
+            if !may_overlap(self, &range) {
+                return // the range is outside of the block, so we do not even download the wrapped block
+            }
             let global = global.clone();
             let block = self.stored.get::<BlockType>(global.clone()).await?;
             let mut stream = block.get(global, range.clone());
@@ -56,11 +93,45 @@ impl Block for StoredBlock {
     }
 
     async fn create(global: Arc<Global>, data: Vec<u8>, start: usize) -> Result<BlockType, String> {
-        let block = BlockType::create(global.clone(), data, start).await?;
-        let stored = Stored::create(global.clone(), block).await?;
-        Ok(BlockType::Stored(StoredBlock {
-            stored
-        }))
+        let (block, _) = Self::create_from_reader(global.clone(), &mut data.as_slice(), start, global.redundancy.clone()).await?;
+        Ok(block)
+    }
+
+    async fn scrub(&mut self, global: Arc<Global>, report: &mut ScrubReport, repair: bool) -> Repairs {
+        report.checked += 1;
+        let mut block = match self.stored.get::<BlockType>(global.clone()).await {
+            Ok(block) => block,
+            Err(e) => {
+                report.lost(format!("Stored block: {}", e));
+                return Repairs::default();
+            }
+        };
+        // the wrapped block is saved here, so the stored block itself never changes
+        let repairs = block.scrub(global.clone(), report, repair).await;
+        if !repairs.is_empty() {
+            match self.stored.put(global.clone(), block).await {
+                Ok(_) => repairs.commit(&global).await,
+                Err(e) => {
+                    report.errors.push(format!("Could not save the stored block: {}", e));
+                    repairs.discard(&global, report).await;
+                }
+            }
+        }
+        Repairs::default()
+    }
+
+    async fn reachable(&self, global: Arc<Global>, found: &mut HashSet<Descriptor>) -> Result<(), String> {
+        found.insert(self.stored.descriptor().clone());
+        let block = self.stored.get::<BlockType>(global.clone()).await?;
+        block.reachable(global, found).await
+    }
+
+    // the wrapped block is rewritten in place, so the block should not be referenced from anywhere else
+    async fn shift(&mut self, global: Arc<Global>, offset: usize) -> Result<(), String> {
+        let mut block = self.stored.get::<BlockType>(global.clone()).await?;
+        block.shift(global.clone(), offset).await?;
+        self.range = Some(block.range(global.clone()).await?);
+        self.stored.put(global, block).await
     }
 
     fn to_enum(self) -> BlockType {
@@ -3,51 +3,121 @@
     Each bucket has a maximum size, which is the maximum size of a single data chunk that can be stored in the bucket.
 */
 
+use std::sync::{Arc, OnceLock};
 use serde::Deserialize;
+use tokio::sync::Semaphore;
 
-use crate::{sources::source::{SourceType, Source}, encryption::encryption::{EncryptionType, Encryption}, global::Descriptor};
+use crate::{cache::{BucketCache, CacheConfig}, prefetch::Prefetch, sources::source::{SourceType, Source}, compression::compression::{CompressionType, Compression}, encryption::encryption::{EncryptionType, Encryption}, global::Descriptor};
 
 #[derive(Deserialize, Debug)]
 pub struct Bucket {
     source: SourceType,
     #[serde(default)]
     encryption: EncryptionType,
+    #[serde(default)]
+    compression: CompressionType,
+    #[serde(default)]
+    cache: Option<CacheConfig>,
+    #[serde(skip)]
+    cached: OnceLock<Option<BucketCache>>,
+    #[serde(skip)]
+    limit: OnceLock<Option<Arc<Semaphore>>>,
 }
 
 impl Bucket {
+    // The bucket does not know its name or the global cache config, so Global::get_bucket attaches the cache on first use
+    pub fn attach_cache(&self, name: &str, global: Option<&CacheConfig>) {
+        self.cached.get_or_init(|| {
+            let config = match (&self.cache, &self.source) {
+                (Some(config), _) => Some(config),
+                (None, SourceType::LocalSource(_)) => None, // a local folder is as fast as the cache
+                (None, _) => global,
+            };
+            config.map(|config| BucketCache::new(name, config.open()))
+        });
+    }
+
+    // Same as attach_cache, the reads of the source are limited by the prefetch config of its type
+    pub fn attach_limit(&self, prefetch: &Prefetch) {
+        self.limit.get_or_init(|| prefetch.limit(self.source.type_name()));
+    }
+
+    pub fn type_name(&self) -> &str {
+        self.source.type_name()
+    }
+
+    pub fn cache(&self) -> Option<&BucketCache> {
+        self.cached.get().and_then(|cache| cache.as_ref())
+    }
+
     // Returns the maximum size of data that can be stored in a single descriptor
     pub fn max_size(&self) -> usize {
-        self.encryption.max_size(
-            self.source.max_size()
+        self.compression.max_size(
+            self.encryption.max_size(
+                self.source.max_size()
+            )
         )
     }
 
     pub fn human_readable(&self) -> String {
-        format!("{:<20} {:<20} {}", self.source.human_readable(), self.encryption.human_readable(), self.max_size())
+        format!("{:<20} {:<20} {:<20} {}", self.source.human_readable(), self.encryption.human_readable(), self.compression.human_readable(), self.max_size())
     }
 
     // Takes a descriptor and returns a stream of data or an error (String)
     pub async fn get(&self, descriptor: &Descriptor) -> Result<Vec<u8>, String> {
         let iv = descriptor.to_vec();
-        let data = self.source.get(descriptor).await?;
+        let data = match self.cache() {
+            Some(cache) => match cache.get(descriptor).await {
+                Some(data) => data,
+                None => {
+                    let reservation = cache.reserve(descriptor);
+                    let data = self.source_get(descriptor).await?;
+                    cache.insert(reservation, &data).await;
+                    data
+                }
+            },
+            None => self.source_get(descriptor).await?
+        };
         let decrypted = self.encryption.decrypt(data, iv)?;
-        Ok(decrypted)
+        self.compression.decompress(decrypted, self.max_size())
     }
     
+    async fn source_get(&self, descriptor: &Descriptor) -> Result<Vec<u8>, String> {
+        let _permit = match self.limit.get().and_then(|limit| limit.as_ref()) {
+            Some(limit) => Some(limit.acquire().await.map_err(|e| e.to_string())?),
+            None => None
+        };
+        self.source.get(descriptor).await
+    }
+
     // Takes a descriptor and data and uploads the data to the descriptor or returns an error (String)
     pub async fn put(&self, descriptor: &Descriptor, data: Vec<u8>) -> Result<(), String> {
         let iv = descriptor.to_vec();
-        let encrypted = self.encryption.encrypt(data, iv)?;
-        self.source.put(descriptor, encrypted).await
+        let compressed = self.compression.compress(data)?;
+        let encrypted = self.encryption.encrypt(compressed, iv)?;
+        let result = self.source.put(descriptor, encrypted).await;
+        if let Some(cache) = self.cache() {
+            cache.invalidate(descriptor).await;
+        }
+        result
     }
     
     // Takes a descriptor and deletes the data at the descriptor or returns an error (String)
     pub async fn delete(&self, descriptor: &Descriptor) -> Result<(), String> {
-        self.source.delete(descriptor).await
+        let result = self.source.delete(descriptor).await;
+        if let Some(cache) = self.cache() {
+            cache.invalidate(descriptor).await;
+        }
+        result
     }
 
     // Creates a new descriptor and returns it or returns an error (String)
     pub async fn create(&self) -> Result<Descriptor, String> {
         self.source.create().await
     }
+
+    // Returns all descriptors stored in the source or returns an error (String) if the source can not list them
+    pub async fn list(&self) -> Result<Vec<Descriptor>, String> {
+        self.source.list().await
+    }
 }
\ No newline at end of file
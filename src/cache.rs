@@ -0,0 +1,293 @@
+/*
+    The cache keeps the raw chunks of remote buckets in a local folder, so reading them again does not go over the network.
+    Chunks are stored exactly as the source returned them (still encrypted), keyed by the bucket name and the descriptor.
+    Buckets configured with the same folder share one cache and one size limit, the least recently used chunks are evicted first.
+ */
+
+use std::{collections::{BTreeMap, HashMap}, path::PathBuf, sync::{Arc, Mutex, OnceLock, atomic::{AtomicU64, AtomicUsize, Ordering}}};
+use serde::Deserialize;
+
+use crate::global::Descriptor;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CacheConfig {
+    folder: String,
+    #[serde(default = "default_max_size")]
+    max_size: usize, // in bytes
+}
+
+const fn default_max_size() -> usize { 256 * 1024 * 1024 }
+
+impl CacheConfig {
+    // Returns the cache of the folder, creating it on first use
+    pub fn open(&self) -> Arc<Cache> {
+        static CACHES: OnceLock<Mutex<HashMap<String, Arc<Cache>>>> = OnceLock::new();
+        let mut caches = CACHES.get_or_init(Default::default).lock().unwrap();
+        caches.entry(self.folder.clone())
+            .or_insert_with(|| Arc::new(Cache::new(PathBuf::from(&self.folder), self.max_size)))
+            .clone()
+    }
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, (u64, usize)>, // file name -> (last use, size)
+    order: BTreeMap<u64, String>,           // last use -> file name
+    size: usize,
+    clock: u64,
+    pending: HashMap<String, (usize, u64)>, // file name -> (reads from the source in flight, generation)
+}
+
+impl Index {
+    fn touch(&mut self, name: &str) -> bool {
+        self.clock += 1;
+        match self.entries.get_mut(name) {
+            Some((used, _)) => {
+                self.order.remove(used);
+                *used = self.clock;
+                self.order.insert(self.clock, name.to_string());
+                true
+            },
+            None => false
+        }
+    }
+
+    fn insert(&mut self, name: String, size: usize) {
+        self.remove(&name);
+        self.clock += 1;
+        self.order.insert(self.clock, name.clone());
+        self.entries.insert(name, (self.clock, size));
+        self.size += size;
+    }
+
+    fn remove(&mut self, name: &str) -> bool {
+        match self.entries.remove(name) {
+            Some((used, size)) => {
+                self.order.remove(&used);
+                self.size -= size;
+                true
+            },
+            None => false
+        }
+    }
+
+    // a read from the source is starting, returns the generation it has to match when inserting
+    fn reserve(&mut self, name: &str) -> u64 {
+        let (readers, generation) = self.pending.entry(name.to_string()).or_insert((0, 0));
+        *readers += 1;
+        *generation
+    }
+
+    fn release(&mut self, name: &str) {
+        if let Some((readers, _)) = self.pending.get_mut(name) {
+            *readers -= 1;
+            if *readers == 0 {
+                self.pending.remove(name);
+            }
+        }
+    }
+
+    // the reads in flight might have returned the old data
+    fn invalidate(&mut self, name: &str) -> bool {
+        if let Some((_, generation)) = self.pending.get_mut(name) {
+            *generation += 1;
+        }
+        self.remove(name)
+    }
+
+    // removes the least recently used entries until the size fits and returns their names
+    fn evict(&mut self, max_size: usize) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.size > max_size {
+            let name = match self.order.values().next() {
+                Some(name) => name.clone(),
+                None => break
+            };
+            self.remove(&name);
+            evicted.push(name);
+        }
+        evicted
+    }
+}
+
+pub struct Cache {
+    folder: PathBuf,
+    max_size: usize,
+    index: Mutex<Index>,
+    temp: AtomicU64,
+}
+
+// Taken before reading a chunk from the source, the data is only cached if the chunk was not changed meanwhile
+pub struct Reservation<'a> {
+    cache: &'a Cache,
+    name: String,
+    generation: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.cache.index.lock().unwrap().release(&self.name);
+    }
+}
+
+impl std::fmt::Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cache({})", self.folder.display())
+    }
+}
+
+fn file_name(bucket: &str, descriptor: &Descriptor) -> String {
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    format!("{}_{}", hex(bucket.as_bytes()), hex(descriptor))
+}
+
+impl Cache {
+    fn new(folder: PathBuf, max_size: usize) -> Self {
+        let _ = std::fs::create_dir_all(&folder);
+
+        // pick up the chunks cached by the previous run, oldest first
+        let mut found = Vec::new();
+        if let Ok(entries) = std::fs::read_dir(&folder) {
+            for entry in entries.flatten() {
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue
+                };
+                // the folder might contain other files, which are not ours to touch
+                if !name.contains('_') || !name.chars().all(|c| c.is_ascii_hexdigit() || c == '_') {
+                    continue;
+                }
+                if let Ok(metadata) = entry.metadata() {
+                    found.push((metadata.modified().ok(), name, metadata.len() as usize));
+                }
+            }
+        }
+        found.sort();
+
+        let mut index = Index::default();
+        for (_, name, size) in found {
+            index.insert(name, size);
+        }
+        for name in index.evict(max_size) {
+            let _ = std::fs::remove_file(folder.join(name));
+        }
+
+        Self { folder, max_size, index: Mutex::new(index), temp: AtomicU64::new(0) }
+    }
+
+    pub async fn get(&self, bucket: &str, descriptor: &Descriptor) -> Option<Vec<u8>> {
+        let name = file_name(bucket, descriptor);
+        if !self.index.lock().unwrap().touch(&name) {
+            return None;
+        }
+        match tokio::fs::read(self.folder.join(&name)).await {
+            Ok(data) => Some(data),
+            Err(_) => {
+                self.index.lock().unwrap().remove(&name);
+                None
+            }
+        }
+    }
+
+    pub fn reserve(&self, bucket: &str, descriptor: &Descriptor) -> Reservation<'_> {
+        let name = file_name(bucket, descriptor);
+        let generation = self.index.lock().unwrap().reserve(&name);
+        Reservation { cache: self, name, generation }
+    }
+
+    pub async fn insert(&self, reservation: Reservation<'_>, data: &[u8]) {
+        if data.len() > self.max_size {
+            return;
+        }
+        // written next to the chunk first, so an invalidation can not be overwritten by the old data
+        let temp = self.folder.join(format!("{}.{}", reservation.name, self.temp.fetch_add(1, Ordering::Relaxed)));
+        if tokio::fs::write(&temp, data).await.is_err() {
+            let _ = tokio::fs::remove_file(temp).await;
+            return;
+        }
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            let current = index.pending.get(&reservation.name).map(|(_, generation)| *generation);
+            if current == Some(reservation.generation) && std::fs::rename(&temp, self.folder.join(&reservation.name)).is_ok() {
+                index.insert(reservation.name.clone(), data.len());
+                Some(index.evict(self.max_size))
+            } else {
+                None
+            }
+        };
+        let evicted = match evicted {
+            Some(evicted) => evicted,
+            None => {
+                let _ = tokio::fs::remove_file(temp).await;
+                return;
+            }
+        };
+        for name in evicted {
+            let _ = tokio::fs::remove_file(self.folder.join(name)).await;
+        }
+    }
+
+    pub async fn invalidate(&self, bucket: &str, descriptor: &Descriptor) {
+        let name = file_name(bucket, descriptor);
+        let removed = self.index.lock().unwrap().invalidate(&name);
+        if removed {
+            let _ = tokio::fs::remove_file(self.folder.join(name)).await;
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.index.lock().unwrap().size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+// The cache as seen by a single bucket, which also counts its own hits and misses
+#[derive(Debug)]
+pub struct BucketCache {
+    name: String,
+    cache: Arc<Cache>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl BucketCache {
+    pub fn new(name: &str, cache: Arc<Cache>) -> Self {
+        Self { name: name.to_string(), cache, hits: AtomicUsize::new(0), misses: AtomicUsize::new(0) }
+    }
+
+    pub async fn get(&self, descriptor: &Descriptor) -> Option<Vec<u8>> {
+        let data = self.cache.get(&self.name, descriptor).await;
+        match data {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        data
+    }
+
+    pub fn reserve(&self, descriptor: &Descriptor) -> Reservation<'_> {
+        self.cache.reserve(&self.name, descriptor)
+    }
+
+    pub async fn insert(&self, reservation: Reservation<'_>, data: &[u8]) {
+        self.cache.insert(reservation, data).await
+    }
+
+    pub async fn invalidate(&self, descriptor: &Descriptor) {
+        self.cache.invalidate(&self.name, descriptor).await
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+}
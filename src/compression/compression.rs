@@ -0,0 +1,85 @@
+use serde::Deserialize;
+
+use super::{lz4::Lz4, none::None, zstd::Zstd};
+
+pub trait Compression {
+    fn max_size(&self, encrypted_size: usize) -> usize;
+    fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, String>;
+    // max_size is the largest chunk the bucket accepts, decompressed data can never be bigger
+    fn decompress(&self, data: Vec<u8>, max_size: usize) -> Result<Vec<u8>, String>;
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum CompressionType {
+    #[serde(rename = "none")]
+    None(None),
+    #[serde(rename = "zstd")]
+    Zstd(Zstd),
+    #[serde(rename = "lz4")]
+    Lz4(Lz4),
+}
+
+impl Default for CompressionType {
+    fn default() -> Self { CompressionType::None(None) }
+}
+
+// This macro removes the need to write out the match statement for each method in the enum
+macro_rules! match_method {
+    ($self:ident, $method:ident, $($arg:expr),*) => {
+        match $self {
+            CompressionType::None(compression) => compression.$method($($arg),*),
+            CompressionType::Zstd(compression) => compression.$method($($arg),*),
+            CompressionType::Lz4(compression) => compression.$method($($arg),*),
+        }
+    };
+}
+
+impl CompressionType {
+    pub fn human_readable(&self) -> &str {
+        match self {
+            CompressionType::None(_) => "none",
+            CompressionType::Zstd(_) => "zstd",
+            CompressionType::Lz4(_) => "lz4",
+        }
+    }
+}
+
+impl Compression for CompressionType {
+    fn max_size(&self, encrypted_size: usize) -> usize {
+        match_method!(self, max_size, encrypted_size)
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        match_method!(self, compress, data)
+    }
+
+    fn decompress(&self, data: Vec<u8>, max_size: usize) -> Result<Vec<u8>, String> {
+        match_method!(self, decompress, data, max_size)
+    }
+}
+
+/* #region header */
+// every compressed chunk starts with one byte telling if the rest is compressed, so data that does not compress is stored raw
+const RAW: u8 = 0;
+const COMPRESSED: u8 = 1;
+pub const HEADER_SIZE: usize = 1;
+
+pub fn add_header(data: Vec<u8>, compressed: Vec<u8>) -> Vec<u8> {
+    let (header, body) = if compressed.len() < data.len() { (COMPRESSED, compressed) } else { (RAW, data) };
+    let mut result = Vec::with_capacity(body.len() + HEADER_SIZE);
+    result.push(header);
+    result.extend(body);
+    result
+}
+
+// returns whether the chunk is compressed and its body
+pub fn split_header(data: &[u8]) -> Result<(bool, &[u8]), String> {
+    match data.split_first() {
+        Some((&RAW, body)) => Ok((false, body)),
+        Some((&COMPRESSED, body)) => Ok((true, body)),
+        Some((header, _)) => Err(format!("Unknown compression header: {}", header)),
+        _ => Err("Chunk is empty".to_string()),
+    }
+}
+/* #endregion */
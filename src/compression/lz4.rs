@@ -0,0 +1,65 @@
+use serde::Deserialize;
+
+use super::compression::{Compression, add_header, split_header, HEADER_SIZE};
+
+#[derive(Deserialize, Debug)]
+pub struct Lz4;
+
+impl Compression for Lz4 {
+    fn max_size(&self, encrypted_size: usize) -> usize {
+        encrypted_size.saturating_sub(HEADER_SIZE)
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let compressed = lz4_flex::compress_prepend_size(&data);
+        Ok(add_header(data, compressed))
+    }
+
+    fn decompress(&self, data: Vec<u8>, max_size: usize) -> Result<Vec<u8>, String> {
+        match split_header(&data)? {
+            (true, body) => {
+                // check the prepended size before lz4 allocates it
+                let (size, body) = match body.split_first_chunk::<4>() {
+                    Some((size, body)) => (u32::from_le_bytes(*size) as usize, body),
+                    None => return Err("Decompression failed: missing size".to_string()),
+                };
+                if size > max_size {
+                    return Err(format!("Decompression failed: chunk claims {} bytes, the limit is {}", size, max_size));
+                }
+                lz4_flex::decompress(body, size).map_err(|e| format!("Decompression failed: {}", e))
+            },
+            (false, body) => Ok(body.to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod lz4_tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let text = "Perferendis nihil quidem neque sed blanditiis. ".repeat(50).into_bytes();
+        let compressed = Lz4.compress(text.clone()).unwrap();
+        assert!(compressed.len() < text.len() / 4);
+        assert_eq!(Lz4.decompress(compressed, text.len()).unwrap(), text);
+
+        let short = b"abc".to_vec();
+        let stored = Lz4.compress(short.clone()).unwrap();
+        assert_eq!(stored, [&[0u8][..], &short[..]].concat());
+        assert_eq!(Lz4.decompress(stored, short.len()).unwrap(), short);
+        assert!(Lz4.decompress(vec![7, 1, 2], 3).is_err());
+    }
+
+    #[test]
+    fn huge_size_is_rejected() {
+        let text = "Perferendis nihil quidem neque sed blanditiis. ".repeat(50).into_bytes();
+        let compressed = Lz4.compress(text.clone()).unwrap();
+        assert!(Lz4.decompress(compressed.clone(), text.len() - 1).is_err());
+
+        // a chunk claiming 4 GiB must fail before anything is allocated
+        let mut huge = compressed;
+        huge[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Lz4.decompress(huge, text.len()).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+#[allow(clippy::module_inception)]
+pub mod compression;
+pub mod lz4;
+pub mod none;
+pub mod zstd;
@@ -0,0 +1,20 @@
+use serde::Deserialize;
+
+use super::compression::Compression;
+
+#[derive(Deserialize, Debug)]
+pub struct None;
+
+impl Compression for None {
+    fn max_size(&self, encrypted_size: usize) -> usize {
+        encrypted_size
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok(data)
+    }
+
+    fn decompress(&self, data: Vec<u8>, _max_size: usize) -> Result<Vec<u8>, String> {
+        Ok(data)
+    }
+}
@@ -0,0 +1,59 @@
+use serde::Deserialize;
+
+use super::compression::{Compression, add_header, split_header, HEADER_SIZE};
+
+#[derive(Deserialize, Debug)]
+pub struct Zstd {
+    #[serde(default = "default_level")]
+    level: i32,
+}
+
+const fn default_level() -> i32 { 3 }
+
+impl Compression for Zstd {
+    fn max_size(&self, encrypted_size: usize) -> usize {
+        encrypted_size.saturating_sub(HEADER_SIZE)
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let compressed = ::zstd::bulk::compress(&data, self.level).map_err(|e| format!("Compression failed: {}", e))?;
+        Ok(add_header(data, compressed))
+    }
+
+    fn decompress(&self, data: Vec<u8>, max_size: usize) -> Result<Vec<u8>, String> {
+        match split_header(&data)? {
+            (true, body) => ::zstd::bulk::decompress(body, max_size).map_err(|e| format!("Decompression failed: {}", e)),
+            (false, body) => Ok(body.to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod zstd_tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let zstd = Zstd { level: default_level() };
+        let text = "Perferendis nihil quidem neque sed blanditiis. ".repeat(50).into_bytes();
+        let compressed = zstd.compress(text.clone()).unwrap();
+        assert!(compressed.len() < text.len() / 4);
+        assert_eq!(zstd.decompress(compressed, text.len()).unwrap(), text);
+
+        // data that does not compress is stored raw
+        let random = (0..200u32).map(|x| (x.wrapping_mul(2654435761) >> 13) as u8).collect::<Vec<u8>>();
+        let stored = zstd.compress(random.clone()).unwrap();
+        assert_eq!(stored.len(), random.len() + HEADER_SIZE);
+        assert_eq!(zstd.decompress(stored, random.len()).unwrap(), random);
+    }
+
+    #[test]
+    fn huge_size_is_rejected() {
+        let zstd = Zstd { level: default_level() };
+        // a few bytes of zeroes that expand far beyond the chunk limit
+        let bomb = zstd.compress(vec![0; 1 << 24]).unwrap();
+        assert!(bomb.len() < 4096);
+        assert!(zstd.decompress(bomb.clone(), 4096).is_err());
+        assert_eq!(zstd.decompress(bomb, 1 << 24).unwrap().len(), 1 << 24);
+    }
+}
@@ -0,0 +1,148 @@
+/*
+    Authenticated encryption, which detects tampered or truncated chunks instead of returning garbage.
+    Every chunk gets a random nonce, which is stored in front of the ciphertext.
+    The descriptor is used as associated data, so a chunk can not be swapped with another one.
+ */
+
+use aes_gcm::{Aes128Gcm, Aes256Gcm, aead::{Aead, AeadCore, KeyInit, Payload, OsRng, generic_array::GenericArray}};
+use chacha20poly1305::ChaCha20Poly1305;
+use serde::Deserialize;
+
+use super::{encryption::Encryption, kdf::{Kdf, DerivedKey}};
+
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+fn seal<C: Aead + AeadCore + KeyInit>(key: &[u8], data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+    let cipher = C::new_from_slice(key).map_err(|_| "Invalid key length")?;
+    let nonce = C::generate_nonce(&mut OsRng);
+    let encrypted = cipher.encrypt(&nonce, Payload { msg: &data, aad: &iv })
+        .map_err(|_| "Authenticated encryption failed")?;
+    let mut result = nonce.to_vec();
+    result.extend(encrypted);
+    Ok(result)
+}
+
+fn open<C: Aead + AeadCore + KeyInit>(key: &[u8], data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+    if data.len() < NONCE_SIZE + TAG_SIZE {
+        return Err("Authentication failed: the chunk is truncated".to_string());
+    }
+    let cipher = C::new_from_slice(key).map_err(|_| "Invalid key length")?;
+    let (nonce, encrypted) = data.split_at(NONCE_SIZE);
+    cipher.decrypt(GenericArray::from_slice(nonce), Payload { msg: encrypted, aad: &iv })
+        .map_err(|_| "Authentication failed: the chunk was modified or the key is wrong".to_string())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AesGcm {
+    key: String,
+    #[serde(default)]
+    variant: AesGcmType,
+    #[serde(default)]
+    kdf: Kdf,
+    #[serde(skip)]
+    derived: DerivedKey,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub enum AesGcmType {
+    #[serde(rename = "aes128")]
+    Aes128,
+    #[serde(rename = "aes256")]
+    #[default]
+    Aes256,
+}
+
+impl AesGcmType {
+    fn key_size(&self) -> usize {
+        match self {
+            AesGcmType::Aes128 => 16,
+            AesGcmType::Aes256 => 32,
+        }
+    }
+}
+
+impl Encryption for AesGcm {
+    fn max_size(&self, source_size: usize) -> usize {
+        source_size.saturating_sub(NONCE_SIZE + TAG_SIZE)
+    }
+
+    fn encrypt(&self, data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+        let key = self.derived.get(&self.kdf, &self.key, self.variant.key_size())?;
+        match self.variant {
+            AesGcmType::Aes128 => seal::<Aes128Gcm>(key, data, iv),
+            AesGcmType::Aes256 => seal::<Aes256Gcm>(key, data, iv),
+        }
+    }
+
+    fn decrypt(&self, data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+        let key = self.derived.get(&self.kdf, &self.key, self.variant.key_size())?;
+        match self.variant {
+            AesGcmType::Aes128 => open::<Aes128Gcm>(key, data, iv),
+            AesGcmType::Aes256 => open::<Aes256Gcm>(key, data, iv),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChaCha {
+    key: String,
+    #[serde(default)]
+    kdf: Kdf,
+    #[serde(skip)]
+    derived: DerivedKey,
+}
+
+impl Encryption for ChaCha {
+    fn max_size(&self, source_size: usize) -> usize {
+        source_size.saturating_sub(NONCE_SIZE + TAG_SIZE)
+    }
+
+    fn encrypt(&self, data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+        seal::<ChaCha20Poly1305>(self.derived.get(&self.kdf, &self.key, 32)?, data, iv)
+    }
+
+    fn decrypt(&self, data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+        open::<ChaCha20Poly1305>(self.derived.get(&self.kdf, &self.key, 32)?, data, iv)
+    }
+}
+
+#[cfg(test)]
+mod aead_tests {
+    use super::*;
+
+    fn roundtrip(encryption: &dyn Encryption) {
+        let data = "Perferendis nihil quidem neque sed blanditiis.".as_bytes().to_vec();
+        let iv = "0123f9abcdef".as_bytes().to_vec();
+        let encrypted = encryption.encrypt(data.clone(), iv.clone()).unwrap();
+        assert_eq!(encrypted.len(), data.len() + NONCE_SIZE + TAG_SIZE);
+        assert_eq!(encryption.decrypt(encrypted.clone(), iv.clone()).unwrap(), data);
+
+        // a flipped bit, a truncated chunk and a chunk moved to another descriptor are all rejected
+        let mut tampered = encrypted.clone();
+        tampered[NONCE_SIZE + 3] ^= 1;
+        assert!(encryption.decrypt(tampered, iv.clone()).unwrap_err().starts_with("Authentication failed"));
+        assert!(encryption.decrypt(encrypted[..encrypted.len() - 1].to_vec(), iv.clone()).is_err());
+        assert!(encryption.decrypt(encrypted[..10].to_vec(), iv).is_err());
+        assert!(encryption.decrypt(encrypted, "0123f9abcdeg".as_bytes().to_vec()).is_err());
+    }
+
+    #[test]
+    fn aes_gcm() {
+        roundtrip(&AesGcm { key: "c3VwZXJzZWNyZXQ=".to_string(), variant: AesGcmType::Aes128, kdf: Kdf::Legacy, derived: DerivedKey::default() });
+        roundtrip(&AesGcm { key: "c3VwZXJzZWNyZXQ=".to_string(), variant: AesGcmType::Aes256, kdf: Kdf::Pbkdf2 { salt: "somesalt".to_string(), iterations: 10 }, derived: DerivedKey::default() });
+    }
+
+    #[test]
+    fn chacha() {
+        roundtrip(&ChaCha { key: "c3VwZXJzZWNyZXQ=".to_string(), kdf: Kdf::Legacy, derived: DerivedKey::default() });
+    }
+
+    #[test]
+    fn max_size() {
+        let chacha = ChaCha { key: "key".to_string(), kdf: Kdf::Legacy, derived: DerivedKey::default() };
+        let data = vec![7u8; chacha.max_size(100)];
+        assert_eq!(chacha.encrypt(data, vec![1]).unwrap().len(), 100);
+        assert_eq!(chacha.max_size(10), 0);
+    }
+}
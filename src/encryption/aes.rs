@@ -3,9 +3,10 @@ use crypto::{
     blockmodes,
     buffer::{self, ReadBuffer, WriteBuffer},
 };
+use rand::{thread_rng, Rng};
 use serde::Deserialize;
 
-use super::encryption::Encryption;
+use super::{encryption::Encryption, kdf::{Kdf, DerivedKey}};
 
 #[derive(Deserialize, Debug)]
 pub struct Aes {
@@ -13,8 +14,15 @@ pub struct Aes {
     #[serde(default)]
     #[serde(rename = "variant")]
     size: AesType,
+    #[serde(default)]
+    kdf: Kdf,
+    #[serde(skip)]
+    derived: DerivedKey,
 }
 
+const BLOCK_SIZE: usize = 16; // aes uses 16 byte blocks, no matter the key size
+const FORMAT_RANDOM_IV: u8 = 1; // header of the chunks that store their own iv
+
 /* #region AesType */
 #[derive(Deserialize, Debug, Default)]
 pub enum AesType {
@@ -52,19 +60,11 @@ impl AesType {
         }
     }
 
-    fn block_size(&self) -> usize {
-        match self {
-            AesType::Aes128 => 16,
-            AesType::Aes192 => 24,
-            AesType::Aes256 => 32,
-        }
-    }
-
 }
 /* #endregion */
 
 // generate a key from a string by repeating it (if key was shorter, we also do some bit shifting in the repetions to make it more random)
-fn to_size(init_key: &Vec<u8>, size: usize) -> Vec<u8> {
+pub fn to_size(init_key: &Vec<u8>, size: usize) -> Vec<u8> {
     let mut key = init_key.iter().cycle().take(size).cloned().collect::<Vec<u8>>();
     for i in init_key.len()..key.len() {
         let mut tmp = key[i] as u16;
@@ -76,22 +76,17 @@ fn to_size(init_key: &Vec<u8>, size: usize) -> Vec<u8> {
     key
 }
 
-impl Encryption for Aes {
-    fn max_size(&self, source_size: usize) -> usize {
-        // how many full blocks fit into the source size
-        (source_size / self.size.block_size()) * self.size.block_size()
-    }
-
-    fn encrypt(&self, data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+impl Aes {
+    fn encrypt_cbc(&self, data: &[u8], iv: &[u8]) -> Result<Vec<u8>, String> {
         let mut encryptor = aes::cbc_encryptor(
             self.size.to_enum(),
-            &to_size(&self.key.as_bytes().to_vec(), self.size.key_size()),
-            &to_size(&iv, self.size.iv_size()),
+            self.derived.get(&self.kdf, &self.key, self.size.key_size())?,
+            iv,
             blockmodes::PkcsPadding,
         );
 
         let mut final_result = Vec::<u8>::new();
-        let mut read_buffer = buffer::RefReadBuffer::new(&data[..]);
+        let mut read_buffer = buffer::RefReadBuffer::new(data);
         let mut buffer = [0; 4096];
         let mut write_buffer = buffer::RefWriteBuffer::new(&mut buffer);
 
@@ -115,16 +110,16 @@ impl Encryption for Aes {
         Ok(final_result)
     }
 
-    fn decrypt(&self, data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+    fn decrypt_cbc(&self, data: &[u8], iv: &[u8]) -> Result<Vec<u8>, String> {
         let mut decryptor = aes::cbc_decryptor(
             self.size.to_enum(),
-            &to_size(&self.key.as_bytes().to_vec(), self.size.key_size()),
-            &to_size(&iv, self.size.iv_size()),
+            self.derived.get(&self.kdf, &self.key, self.size.key_size())?,
+            iv,
             blockmodes::PkcsPadding,
         );
 
         let mut final_result = Vec::<u8>::new();
-        let mut read_buffer = buffer::RefReadBuffer::new(&data[..]);
+        let mut read_buffer = buffer::RefReadBuffer::new(data);
         let mut buffer = [0; 4096];
         let mut write_buffer = buffer::RefWriteBuffer::new(&mut buffer);
 
@@ -149,6 +144,35 @@ impl Encryption for Aes {
     }
 }
 
+impl Encryption for Aes {
+    fn max_size(&self, source_size: usize) -> usize {
+        // pkcs padding always adds at least one byte, so only full blocks minus one byte fit after the header
+        let blocks = source_size.saturating_sub(1 + BLOCK_SIZE) / BLOCK_SIZE;
+        (blocks * BLOCK_SIZE).saturating_sub(1)
+    }
+
+    fn encrypt(&self, data: Vec<u8>, _iv: Vec<u8>) -> Result<Vec<u8>, String> {
+        // a fresh iv for every write, so rewriting a descriptor never reuses it
+        let mut iv = vec![0u8; BLOCK_SIZE];
+        thread_rng().fill(&mut iv[..]);
+        let encrypted = self.encrypt_cbc(&data, &iv)?;
+
+        let mut result = vec![FORMAT_RANDOM_IV];
+        result.extend(iv);
+        result.extend(encrypted);
+        Ok(result)
+    }
+
+    fn decrypt(&self, data: Vec<u8>, iv: Vec<u8>) -> Result<Vec<u8>, String> {
+        // the ciphertext is made of full blocks, so the one byte header tells the formats apart
+        match data.len() % BLOCK_SIZE {
+            0 => self.decrypt_cbc(&data, &to_size(&iv, self.size.iv_size())), // legacy chunks use the descriptor as the iv
+            1 if data.len() > BLOCK_SIZE && data[0] == FORMAT_RANDOM_IV => self.decrypt_cbc(&data[1 + BLOCK_SIZE..], &data[1..1 + BLOCK_SIZE]),
+            _ => Err("Unknown chunk format".to_string()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod aes_tests {
     use super::*;
@@ -158,6 +182,8 @@ mod aes_tests {
         let aes = Aes {
             key: "c3VwZXJzZWNyZXQ=".to_string(),
             size: AesType::Aes128,
+            kdf: Kdf::Legacy,
+            derived: DerivedKey::default(),
         };
         let data = "Perferendis nihil quidem neque sed blanditiis."
             .as_bytes()
@@ -175,6 +201,48 @@ mod aes_tests {
         assert_ne!(encrypted2, encrypted_copy);
     }
 
+    #[test]
+    fn random_iv() {
+        let aes = Aes {
+            key: "c3VwZXJzZWNyZXQ=".to_string(),
+            size: AesType::Aes256,
+            kdf: Kdf::Legacy,
+            derived: DerivedKey::default(),
+        };
+        let data = vec![42u8; aes.max_size(100)];
+        let iv = "0123f9abcdef".as_bytes().to_vec();
+        let encrypted = aes.encrypt(data.clone(), iv.clone()).unwrap();
+        assert!(encrypted.len() <= 100);
+        assert_ne!(aes.encrypt(data.clone(), iv.clone()).unwrap(), encrypted); // same descriptor, different iv
+        assert_eq!(aes.decrypt(encrypted, iv).unwrap(), data);
+    }
+
+    #[test]
+    fn legacy_format() {
+        let aes = Aes {
+            key: "c3VwZXJzZWNyZXQ=".to_string(),
+            size: AesType::Aes128,
+            kdf: Kdf::Legacy,
+            derived: DerivedKey::default(),
+        };
+        let data = "Perferendis nihil quidem neque sed blanditiis.".as_bytes().to_vec();
+        let iv = "0123f9abcdef".as_bytes().to_vec();
+        // chunks written before the iv was stored next to them
+        let legacy = aes.encrypt_cbc(&data, &to_size(&iv, 16)).unwrap();
+        assert_eq!(aes.decrypt(legacy, iv).unwrap(), data);
+    }
+
+    #[test]
+    fn truncated_chunk() {
+        let aes = Aes {
+            key: "c3VwZXJzZWNyZXQ=".to_string(),
+            size: AesType::Aes128,
+            kdf: Kdf::Legacy,
+            derived: DerivedKey::default(),
+        };
+        assert!(aes.decrypt(vec![FORMAT_RANDOM_IV], "0123f9abcdef".as_bytes().to_vec()).is_err());
+    }
+
     #[test]
     fn key_extender() {
         let input_key = '0';
@@ -1,6 +1,6 @@
 use serde::Deserialize;
 
-use super::{aes::Aes, none::None};
+use super::{aead::{AesGcm, ChaCha}, aes::Aes, none::None};
 
 pub trait Encryption {
     fn max_size(&self, source_size: usize) -> usize;
@@ -14,7 +14,11 @@ pub enum EncryptionType {
     #[serde(rename = "none")]
     None(None),
     #[serde(rename = "aes")]
-    Aes(Aes)
+    Aes(Aes),
+    #[serde(rename = "aes_gcm")]
+    AesGcm(AesGcm),
+    #[serde(rename = "chacha20poly1305")]
+    ChaCha(ChaCha),
 }
 
 impl Default for EncryptionType {
@@ -26,7 +30,9 @@ macro_rules! match_method {
     ($self:ident, $method:ident, $($arg:expr),*) => {
         match $self {
             EncryptionType::None(encryption) => encryption.$method($($arg),*),
-            EncryptionType::Aes(encryption) => encryption.$method($($arg),*)
+            EncryptionType::Aes(encryption) => encryption.$method($($arg),*),
+            EncryptionType::AesGcm(encryption) => encryption.$method($($arg),*),
+            EncryptionType::ChaCha(encryption) => encryption.$method($($arg),*),
         }
     };
 }
@@ -35,7 +41,9 @@ impl EncryptionType {
     pub fn human_readable(&self) -> &str {
         match self {
             EncryptionType::None(_) => "none",
-            EncryptionType::Aes(_) => "aes"
+            EncryptionType::Aes(_) => "aes",
+            EncryptionType::AesGcm(_) => "aes-gcm",
+            EncryptionType::ChaCha(_) => "chacha20-poly1305",
         }
     }
 }
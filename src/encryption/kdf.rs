@@ -0,0 +1,117 @@
+/*
+    Key derivation turns the configured passphrase into a key of the size the cipher needs.
+    The legacy mode stretches the passphrase itself and is only kept so that existing buckets stay readable.
+ */
+
+use std::sync::OnceLock;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use super::aes::to_size;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(tag = "type")]
+pub enum Kdf {
+    #[serde(rename = "legacy")]
+    #[default]
+    Legacy,
+    #[serde(rename = "argon2id")]
+    Argon2id {
+        salt: String,
+        #[serde(default = "default_argon2_memory")]
+        memory: u32, // in KiB
+        #[serde(default = "default_argon2_iterations")]
+        iterations: u32,
+        #[serde(default = "default_argon2_parallelism")]
+        parallelism: u32,
+    },
+    #[serde(rename = "pbkdf2")]
+    Pbkdf2 {
+        salt: String,
+        #[serde(default = "default_pbkdf2_iterations")]
+        iterations: u32,
+    },
+}
+
+// the defaults follow the OWASP recommendations
+const fn default_argon2_memory() -> u32 { 19 * 1024 }
+const fn default_argon2_iterations() -> u32 { 2 }
+const fn default_argon2_parallelism() -> u32 { 1 }
+const fn default_pbkdf2_iterations() -> u32 { 600_000 }
+
+impl Kdf {
+    pub fn derive(&self, passphrase: &str, size: usize) -> Result<Vec<u8>, String> {
+        match self {
+            Kdf::Legacy => Ok(to_size(&passphrase.as_bytes().to_vec(), size)),
+            Kdf::Argon2id { salt, memory, iterations, parallelism } => {
+                let params = argon2::Params::new(*memory, *iterations, *parallelism, Some(size))
+                    .map_err(|e| format!("Invalid argon2 parameters: {}", e))?;
+                let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+                let mut key = vec![0u8; size];
+                argon2.hash_password_into(passphrase.as_bytes(), salt.as_bytes(), &mut key)
+                    .map_err(|e| format!("Key derivation failed: {}", e))?;
+                Ok(key)
+            },
+            Kdf::Pbkdf2 { salt, iterations } => {
+                if *iterations == 0 {
+                    return Err("Invalid pbkdf2 parameters: iterations must be at least 1".to_string());
+                }
+                let mut key = vec![0u8; size];
+                pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt.as_bytes(), *iterations, &mut key);
+                Ok(key)
+            },
+        }
+    }
+}
+
+// Derives the key on first use and keeps it, because a real kdf is deliberately slow
+#[derive(Default)]
+pub struct DerivedKey(OnceLock<Result<Vec<u8>, String>>);
+
+impl DerivedKey {
+    pub fn get(&self, kdf: &Kdf, passphrase: &str, size: usize) -> Result<&[u8], String> {
+        match self.0.get_or_init(|| kdf.derive(passphrase, size)) {
+            Ok(key) => Ok(key),
+            Err(e) => Err(e.clone()),
+        }
+    }
+}
+
+// the key must not end up in the debug output
+impl std::fmt::Debug for DerivedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DerivedKey")
+    }
+}
+
+#[cfg(test)]
+mod kdf_tests {
+    use super::*;
+
+    #[test]
+    fn derives_stable_keys() {
+        let argon2 = Kdf::Argon2id { salt: "somesalt".to_string(), memory: 64, iterations: 1, parallelism: 1 };
+        let pbkdf2 = Kdf::Pbkdf2 { salt: "somesalt".to_string(), iterations: 10 };
+        for kdf in [argon2, pbkdf2] {
+            let key = kdf.derive("passphrase", 32).unwrap();
+            assert_eq!(key.len(), 32);
+            assert_eq!(key, kdf.derive("passphrase", 32).unwrap());
+            assert_ne!(key, kdf.derive("passphrasf", 32).unwrap());
+            assert_ne!(key, Kdf::Legacy.derive("passphrase", 32).unwrap());
+        }
+    }
+
+    #[test]
+    fn salt_matters() {
+        let a = Kdf::Pbkdf2 { salt: "somesalt".to_string(), iterations: 10 };
+        let b = Kdf::Pbkdf2 { salt: "othersalt".to_string(), iterations: 10 };
+        assert_ne!(a.derive("passphrase", 16).unwrap(), b.derive("passphrase", 16).unwrap());
+        // argon2 needs at least 8 bytes of salt
+        assert!(Kdf::Argon2id { salt: "short".to_string(), memory: 64, iterations: 1, parallelism: 1 }.derive("passphrase", 16).is_err());
+    }
+
+    #[test]
+    fn legacy_is_unchanged() {
+        assert_eq!(Kdf::Legacy.derive("key", 16).unwrap(), to_size(&b"key".to_vec(), 16));
+    }
+}
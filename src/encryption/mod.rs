@@ -1,3 +1,5 @@
+pub mod aead;
 pub mod aes;
 pub mod encryption;
+pub mod kdf;
 pub mod none;
\ No newline at end of file
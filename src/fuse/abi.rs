@@ -0,0 +1,267 @@
+/*
+    The messages the kernel and the filesystem exchange through /dev/fuse, see include/uapi/linux/fuse.h.
+    Everything is in the byte order of the machine. Only the parts of the protocol the filesystem uses are here.
+ */
+
+pub const KERNEL_VERSION: u32 = 7;
+pub const KERNEL_MINOR_VERSION: u32 = 31;
+pub const ROOT_ID: u64 = 1;
+
+// opcodes
+pub const LOOKUP: u32 = 1;
+pub const FORGET: u32 = 2;
+pub const GETATTR: u32 = 3;
+pub const SETATTR: u32 = 4;
+pub const MKDIR: u32 = 9;
+pub const UNLINK: u32 = 10;
+pub const RMDIR: u32 = 11;
+pub const RENAME: u32 = 12;
+pub const OPEN: u32 = 14;
+pub const READ: u32 = 15;
+pub const WRITE: u32 = 16;
+pub const STATFS: u32 = 17;
+pub const RELEASE: u32 = 18;
+pub const FSYNC: u32 = 20;
+pub const FLUSH: u32 = 25;
+pub const INIT: u32 = 26;
+pub const OPENDIR: u32 = 27;
+pub const READDIR: u32 = 28;
+pub const RELEASEDIR: u32 = 29;
+pub const FSYNCDIR: u32 = 30;
+pub const CREATE: u32 = 35;
+pub const INTERRUPT: u32 = 36;
+pub const DESTROY: u32 = 38;
+pub const BATCH_FORGET: u32 = 42;
+pub const RENAME2: u32 = 45;
+
+// init flags
+pub const ASYNC_READ: u32 = 1 << 0;
+pub const ATOMIC_O_TRUNC: u32 = 1 << 3;
+pub const BIG_WRITES: u32 = 1 << 5;
+
+// setattr valid bits
+pub const FATTR_SIZE: u32 = 1 << 3;
+pub const FATTR_FH: u32 = 1 << 6;
+
+// rename2 flags
+pub const RENAME_NOREPLACE: u32 = 1 << 0;
+
+pub const IN_HEADER_SIZE: usize = 40;
+pub const OUT_HEADER_SIZE: usize = 16;
+pub const MAX_WRITE: u32 = 128 * 1024;
+pub const BUFFER_SIZE: usize = MAX_WRITE as usize + 4096;  // the largest request is a write with its header
+
+// Reads the fields of a request one after the other
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(taken)
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|bytes| u64::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    // A name ends with a nul byte, names that are not utf-8 are not supported
+    pub fn name(&mut self) -> Option<String> {
+        let end = self.data.iter().position(|byte| *byte == 0)?;
+        let name = std::str::from_utf8(&self.data[..end]).ok()?.to_string();
+        self.data = &self.data[end + 1..];
+        Some(name)
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+}
+
+// Builds the body of a reply
+#[derive(Default)]
+pub struct Writer {
+    pub data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.data.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.data.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn zeros(&mut self, len: usize) -> &mut Self {
+        self.data.resize(self.data.len() + len, 0);
+        self
+    }
+}
+
+pub struct Header {
+    pub opcode: u32,
+    pub unique: u64,
+    pub nodeid: u64,
+}
+
+// None if the request is not as long as it says
+pub fn header(reader: &mut Reader) -> Option<Header> {
+    let len = reader.data.len();
+    if reader.u32()? as usize != len {
+        return None;
+    }
+    let header = Header {
+        opcode: reader.u32()?,
+        unique: reader.u64()?,
+        nodeid: reader.u64()?,
+    };
+    reader.skip(IN_HEADER_SIZE - 24)?;  // uid, gid, pid and padding
+    Some(header)
+}
+
+// The whole message, an error is sent without a body
+pub fn reply(unique: u64, result: Result<Vec<u8>, i32>) -> Vec<u8> {
+    let (error, body) = match result {
+        Ok(body) => (0, body),
+        Err(errno) => (-errno, Vec::new()),
+    };
+    let mut message = Writer::default();
+    message.u32((OUT_HEADER_SIZE + body.len()) as u32).u32(error as u32).u64(unique).bytes(&body);
+    message.data
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attr {
+    pub ino: u64,
+    pub size: u64,
+    pub modified: u64,
+    pub kind: Kind,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Attr {
+    pub fn mode(&self) -> u32 {
+        match self.kind {
+            Kind::File => libc::S_IFREG | 0o644,
+            Kind::Directory => libc::S_IFDIR | 0o755,
+        }
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        let links = match self.kind {
+            Kind::File => 1,
+            Kind::Directory => 2,
+        };
+        writer.u64(self.ino).u64(self.size).u64(self.size.div_ceil(512))
+            .u64(self.modified).u64(self.modified).u64(self.modified)  // atime, mtime, ctime
+            .u32(0).u32(0).u32(0)
+            .u32(self.mode()).u32(links).u32(self.uid).u32(self.gid)
+            .u32(0)  // rdev
+            .u32(4096)  // blksize
+            .u32(0);  // flags
+    }
+}
+
+const VALID: u64 = 1;  // how long the kernel may cache names and attributes, in seconds
+
+pub fn entry_out(attr: &Attr, writer: &mut Writer) {
+    writer.u64(attr.ino).u64(0).u64(VALID).u64(VALID).u32(0).u32(0);
+    attr.write(writer);
+}
+
+pub fn attr_out(attr: &Attr) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.u64(VALID).u32(0).u32(0);
+    attr.write(&mut writer);
+    writer.data
+}
+
+pub fn open_out(fh: u64, writer: &mut Writer) {
+    writer.u64(fh).u32(0).u32(0);
+}
+
+// Adds a directory entry if it fits, entries are padded to 8 bytes. Without a kind the kernel looks the entry up when it needs it
+pub fn dirent(writer: &mut Writer, max: usize, ino: u64, offset: u64, kind: Option<Kind>, name: &str) -> bool {
+    let len = 24 + name.len();
+    let padded = (len + 7) & !7;
+    if writer.data.len() + padded > max {
+        return false;
+    }
+    let kind = match kind {
+        Some(Kind::File) => libc::DT_REG,
+        Some(Kind::Directory) => libc::DT_DIR,
+        None => libc::DT_UNKNOWN,
+    };
+    writer.u64(ino).u64(offset).u32(name.len() as u32).u32(kind as u32).bytes(name.as_bytes()).zeros(padded - len);
+    true
+}
+
+#[cfg(test)]
+mod abi_tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_messages() {
+        let mut request = Writer::default();
+        request.u32(52).u32(LOOKUP).u64(7).u64(ROOT_ID).zeros(16).bytes(b"cat.jpg\0rest");
+        let mut reader = Reader::new(&request.data);
+        let header = header(&mut reader).unwrap();
+        assert_eq!((header.opcode, header.unique, header.nodeid), (LOOKUP, 7, ROOT_ID));
+        assert_eq!(reader.name().unwrap(), "cat.jpg");
+        assert_eq!(reader.rest(), b"rest");
+        assert!(reader.u32().is_none());
+        assert!(super::header(&mut Reader::new(&request.data[..51])).is_none());
+
+        let message = reply(7, Err(libc::ENOENT));
+        assert_eq!(message.len(), OUT_HEADER_SIZE);
+        assert_eq!(i32::from_ne_bytes(message[4..8].try_into().unwrap()), -libc::ENOENT);
+
+        let attr = Attr { ino: 2, size: 1000, modified: 0, kind: Kind::File, uid: 0, gid: 0 };
+        assert_eq!(attr_out(&attr).len(), 104);
+        let mut entry = Writer::default();
+        entry_out(&attr, &mut entry);
+        assert_eq!(entry.data.len(), 128);
+
+        let mut entries = Writer::default();
+        assert!(dirent(&mut entries, 64, 2, 1, Some(Kind::File), "a"));
+        assert_eq!(entries.data.len(), 32);
+        assert!(!dirent(&mut entries, 64, 3, 2, None, "a longer name"));
+    }
+}
@@ -0,0 +1,604 @@
+/*
+    The operations of the mounted filesystem. The kernel refers to files by inode numbers, which are given out the first time a Stored is seen and kept for as long as the filesystem is mounted.
+    Files opened for writing are copied into an unlinked temporary file, and uploaded again when they are flushed or closed. The new data is put under the same Stored, so the inode number stays the same.
+ */
+
+use std::{collections::HashMap, os::unix::fs::FileExt, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex}};
+
+use futures::StreamExt;
+use tokio::io::{AsyncSeekExt, BufReader};
+
+use crate::{global::Global, inodes::{directory::Directory, file::File, inode::{Inode, InodeType}, metadata::Size, path::{delete_inode, OpenDirectory}}, stored::Stored};
+use super::abi::{self, Attr, Header, Kind, Reader, Writer};
+
+type Reply = Result<Vec<u8>, i32>;
+
+struct Node {
+    stored: Option<Stored>,  // the root has none
+    parent: u64,
+    name: String,
+}
+
+// Nodes are never forgotten, the kernel may ask about any of them until it is unmounted
+struct Nodes {
+    nodes: HashMap<u64, Node>,
+    inos: HashMap<Stored, u64>,
+    next: u64,
+}
+
+impl Nodes {
+    // The inode number of the Stored, remembering where it was seen last
+    fn ino(&mut self, stored: Stored, parent: u64, name: &str) -> u64 {
+        let ino = match self.inos.get(&stored) {
+            Some(ino) => *ino,
+            None => {
+                let ino = self.next;
+                self.next += 1;
+                self.inos.insert(stored.clone(), ino);
+                ino
+            },
+        };
+        self.nodes.insert(ino, Node { stored: Some(stored), parent, name: name.to_string() });
+        ino
+    }
+
+    fn remove(&mut self, stored: &Stored) {
+        if let Some(ino) = self.inos.remove(stored) {
+            self.nodes.remove(&ino);
+        }
+    }
+}
+
+struct Upload {
+    ino: u64,
+    temp: std::fs::File,
+    dirty: AtomicBool,
+}
+
+enum Handle {
+    Directory(Vec<(u64, Option<Kind>, String)>),  // listed when the directory is opened
+    Read(Arc<File>),
+    Write(Arc<Upload>),
+}
+
+pub struct Filesystem {
+    global: Arc<Global>,
+    nodes: Mutex<Nodes>,
+    handles: Mutex<HashMap<u64, Handle>>,
+    next_handle: AtomicU64,
+    uid: u32,
+    gid: u32,
+}
+
+fn eio(e: String) -> i32 {
+    println!("FUSE request failed: {}", e);
+    libc::EIO
+}
+
+fn size(inode: &InodeType) -> u64 {
+    match inode {
+        InodeType::File(file) => match file.metadata.size {
+            Size::Bytes(size) => size as u64,
+            _ => 0,
+        },
+        InodeType::Directory(_) => 0,
+    }
+}
+
+fn kind(inode: &InodeType) -> Kind {
+    match inode {
+        InodeType::File(_) => Kind::File,
+        InodeType::Directory(_) => Kind::Directory,
+    }
+}
+
+impl Filesystem {
+    pub fn new(global: Arc<Global>) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(abi::ROOT_ID, Node { stored: None, parent: abi::ROOT_ID, name: String::new() });
+        Self {
+            global,
+            nodes: Mutex::new(Nodes { nodes, inos: HashMap::new(), next: abi::ROOT_ID + 1 }),
+            handles: Mutex::new(HashMap::new()),
+            next_handle: AtomicU64::new(1),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    // None if the request does not get a reply
+    pub async fn handle(&self, header: &Header, mut reader: Reader<'_>) -> Option<Reply> {
+        let ino = header.nodeid;
+        let reply = match header.opcode {
+            abi::FORGET | abi::BATCH_FORGET | abi::INTERRUPT => return None,
+            abi::INIT => self.init(&mut reader),
+            abi::DESTROY => Ok(Vec::new()),
+            abi::LOOKUP => match reader.name() {
+                Some(name) => self.lookup(ino, &name).await,
+                None => Err(libc::EINVAL),
+            },
+            abi::GETATTR => self.getattr(ino).await,
+            abi::SETATTR => self.setattr(ino, &mut reader).await,
+            abi::MKDIR => self.mkdir(ino, &mut reader).await,
+            abi::CREATE => self.create(ino, &mut reader).await,
+            abi::UNLINK | abi::RMDIR => match reader.name() {
+                Some(name) => self.remove(ino, &name, header.opcode == abi::RMDIR).await,
+                None => Err(libc::EINVAL),
+            },
+            abi::RENAME | abi::RENAME2 => self.rename(ino, &mut reader, header.opcode == abi::RENAME2).await,
+            abi::OPEN => self.open(ino, &mut reader).await,
+            abi::READ => self.read(&mut reader).await,
+            abi::WRITE => self.write(&mut reader),
+            abi::FLUSH | abi::FSYNC => match reader.u64() {
+                Some(fh) => self.flush(fh).await.map(|_| Vec::new()),
+                None => Err(libc::EINVAL),
+            },
+            abi::RELEASE => self.release(&mut reader).await,
+            abi::OPENDIR => self.opendir(ino).await,
+            abi::READDIR => self.readdir(&mut reader),
+            abi::RELEASEDIR => match reader.u64() {
+                Some(fh) => {
+                    self.handles.lock().unwrap().remove(&fh);
+                    Ok(Vec::new())
+                },
+                None => Err(libc::EINVAL),
+            },
+            abi::FSYNCDIR => Ok(Vec::new()),
+            abi::STATFS => Ok(self.statfs()),
+            _ => Err(libc::ENOSYS),
+        };
+        Some(reply)
+    }
+
+    fn init(&self, reader: &mut Reader) -> Reply {
+        let (major, minor, max_readahead, flags) = match (reader.u32(), reader.u32(), reader.u32(), reader.u32()) {
+            (Some(major), Some(minor), Some(max_readahead), Some(flags)) => (major, minor, max_readahead, flags),
+            _ => return Err(libc::EINVAL),
+        };
+        if major < abi::KERNEL_VERSION || (major == abi::KERNEL_VERSION && minor < 12) {
+            return Err(libc::EPROTO);
+        }
+        let mut writer = Writer::default();
+        writer.u32(abi::KERNEL_VERSION)
+            .u32(std::cmp::min(minor, abi::KERNEL_MINOR_VERSION))
+            .u32(max_readahead)
+            .u32(flags & (abi::ASYNC_READ | abi::ATOMIC_O_TRUNC | abi::BIG_WRITES))
+            .u16(16)  // max_background
+            .u16(12)  // congestion_threshold
+            .u32(abi::MAX_WRITE)
+            .u32(1)  // time_gran
+            .zeros(28);
+        Ok(writer.data)
+    }
+
+    fn stored(&self, ino: u64) -> Result<Option<Stored>, i32> {
+        match self.nodes.lock().unwrap().nodes.get(&ino) {
+            Some(node) => Ok(node.stored.clone()),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    async fn inode(&self, ino: u64) -> Result<InodeType, i32> {
+        match self.stored(ino)? {
+            Some(stored) => stored.get::<InodeType>(self.global.clone()).await.map_err(eio),
+            None => Ok(self.global.get_root().map_err(eio)?.to_enum()),
+        }
+    }
+
+    async fn directory(&self, ino: u64) -> Result<OpenDirectory, i32> {
+        let stored = self.stored(ino)?;
+        match &stored {
+            Some(stored) => match stored.get::<InodeType>(self.global.clone()).await.map_err(eio)? {
+                InodeType::Directory(directory) => Ok(OpenDirectory { stored: Some(stored.clone()), directory }),
+                InodeType::File(_) => Err(libc::ENOTDIR),
+            },
+            None => Ok(OpenDirectory { stored: None, directory: self.global.get_root().map_err(eio)? }),
+        }
+    }
+
+    // Files that are being written have the size of their temporary file
+    fn attr(&self, ino: u64, inode: &InodeType) -> Attr {
+        let pending = self.handles.lock().unwrap().values().find_map(|handle| match handle {
+            Handle::Write(upload) if upload.ino == ino => upload.temp.metadata().ok().map(|metadata| metadata.len()),
+            _ => None,
+        });
+        let modified = match inode {
+            InodeType::File(file) => file.metadata.modified,
+            InodeType::Directory(directory) => directory.metadata.modified,
+        };
+        Attr { ino, size: pending.unwrap_or_else(|| size(inode)), modified, kind: kind(inode), uid: self.uid, gid: self.gid }
+    }
+
+    fn entry(&self, parent: u64, name: &str, stored: Stored, inode: &InodeType) -> (u64, Writer) {
+        let ino = self.nodes.lock().unwrap().ino(stored, parent, name);
+        let mut writer = Writer::default();
+        abi::entry_out(&self.attr(ino, inode), &mut writer);
+        (ino, writer)
+    }
+
+    fn new_handle(&self, handle: Handle) -> u64 {
+        let fh = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(fh, handle);
+        fh
+    }
+
+    async fn lookup(&self, parent: u64, name: &str) -> Reply {
+        let directory = self.directory(parent).await?;
+        let stored = directory.directory.get(&name.to_string()).map_err(|_| libc::ENOENT)?.clone();
+        let inode = stored.get::<InodeType>(self.global.clone()).await.map_err(eio)?;
+        Ok(self.entry(parent, name, stored, &inode).1.data)
+    }
+
+    async fn getattr(&self, ino: u64) -> Reply {
+        let inode = self.inode(ino).await?;
+        Ok(abi::attr_out(&self.attr(ino, &inode)))
+    }
+
+    // Only the size can be changed, the other attributes are not stored
+    async fn setattr(&self, ino: u64, reader: &mut Reader<'_>) -> Reply {
+        let (valid, fh, size) = match (reader.u32(), reader.u32(), reader.u64(), reader.u64()) {
+            (Some(valid), Some(_), Some(fh), Some(size)) => (valid, fh, size),
+            _ => return Err(libc::EINVAL),
+        };
+        if valid & abi::FATTR_SIZE != 0 {
+            let upload = {
+                let handles = self.handles.lock().unwrap();
+                let by_fh = match valid & abi::FATTR_FH != 0 {
+                    true => handles.get(&fh),
+                    false => None,
+                };
+                by_fh.into_iter().chain(handles.values()).find_map(|handle| match handle {
+                    Handle::Write(upload) if upload.ino == ino => Some(upload.clone()),
+                    _ => None,
+                })
+            };
+            match upload {
+                Some(upload) => {
+                    upload.temp.set_len(size).map_err(|e| eio(e.to_string()))?;
+                    upload.dirty.store(true, Ordering::SeqCst);
+                },
+                None => {
+                    let file = match self.inode(ino).await? {
+                        InodeType::File(file) => file,
+                        InodeType::Directory(_) => return Err(libc::EISDIR),
+                    };
+                    let upload = self.load(ino, &file, size == 0).await?;
+                    upload.temp.set_len(size).map_err(|e| eio(e.to_string()))?;
+                    upload.dirty.store(true, Ordering::SeqCst);
+                    self.upload(&upload).await?;
+                },
+            }
+        }
+        self.getattr(ino).await
+    }
+
+    // Copies the file into a temporary file, which is removed as soon as it is closed
+    async fn load(&self, ino: u64, file: &File, empty: bool) -> Result<Upload, i32> {
+        let path = std::env::temp_dir().join(format!("chunkdrive-fuse-{}-{}", std::process::id(), rand::random::<u64>()));
+        let temp = std::fs::OpenOptions::new().read(true).write(true).create_new(true).open(&path).map_err(|e| eio(e.to_string()))?;
+        std::fs::remove_file(&path).map_err(|e| eio(e.to_string()))?;
+        if !empty {
+            let mut offset = 0;
+            let mut stream = file.get(self.global.clone());
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk.map_err(eio)?;
+                temp.write_all_at(&chunk, offset).map_err(|e| eio(e.to_string()))?;
+                offset += chunk.len() as u64;
+            }
+        }
+        Ok(Upload { ino, temp, dirty: AtomicBool::new(empty) })
+    }
+
+    // Uploads the temporary file and puts it in place of the old data
+    async fn upload(&self, upload: &Upload) -> Result<(), i32> {
+        if !upload.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let name = match self.nodes.lock().unwrap().nodes.get(&upload.ino) {
+            Some(node) => node.name.clone(),
+            None => return Ok(()),  // unlinked while open, the data goes away with it
+        };
+        let temp = upload.temp.try_clone().map_err(|e| eio(e.to_string()))?;
+        let mut temp = tokio::fs::File::from_std(temp);
+        temp.rewind().await.map_err(|e| eio(e.to_string()))?;
+        let mut file = match File::create_named(self.global.clone(), &name, &mut BufReader::new(temp), self.global.redundancy.clone()).await {
+            Ok(file) => file,
+            Err(e) => {
+                upload.dirty.store(true, Ordering::SeqCst);
+                return Err(eio(e));
+            },
+        };
+
+        let _tree = self.global.lock_tree().await;
+        let stored = match self.stored(upload.ino) {
+            Ok(Some(stored)) => stored,
+            _ => return file.delete(self.global.clone()).await.map_err(eio),
+        };
+        let mut old = match stored.get::<InodeType>(self.global.clone()).await.map_err(eio)? {
+            InodeType::File(old) => old,
+            InodeType::Directory(_) => return Err(libc::EISDIR),
+        };
+        file.metadata.created = old.metadata.created;
+        stored.put(self.global.clone(), file.to_enum()).await.map_err(eio)?;
+        old.delete(self.global.clone()).await.map_err(eio)
+    }
+
+    // Adds a new inode to the directory, unless the name is taken
+    async fn add(&self, parent: u64, name: &str, inode: InodeType) -> Result<(Stored, InodeType), i32> {
+        let name = name.to_string();
+        let _tree = self.global.lock_tree().await;
+        let mut directory = self.directory(parent).await?;
+        if directory.directory.get(&name).is_ok() {
+            return Err(libc::EEXIST);
+        }
+        directory.directory.add(self.global.clone(), &name, inode).await.map_err(eio)?;
+        let stored = directory.directory.get(&name).map_err(eio)?.clone();
+        directory.save(self.global.clone()).await.map_err(eio)?;
+        let inode = stored.get::<InodeType>(self.global.clone()).await.map_err(eio)?;
+        Ok((stored, inode))
+    }
+
+    async fn mkdir(&self, parent: u64, reader: &mut Reader<'_>) -> Reply {
+        let name = match (reader.u32(), reader.u32(), reader.name()) {
+            (Some(_), Some(_), Some(name)) => name,  // the mode and umask are ignored
+            _ => return Err(libc::EINVAL),
+        };
+        let (stored, inode) = self.add(parent, &name, Directory::new().to_enum()).await?;
+        Ok(self.entry(parent, &name, stored, &inode).1.data)
+    }
+
+    async fn create(&self, parent: u64, reader: &mut Reader<'_>) -> Reply {
+        let name = match (reader.skip(16), reader.name()) {
+            (Some(_), Some(name)) => name,  // flags, mode, umask and padding
+            _ => return Err(libc::EINVAL),
+        };
+        let file = File::create(self.global.clone(), Vec::new()).await.map_err(eio)?;
+        let (stored, inode) = self.add(parent, &name, file.to_enum()).await?;
+        let (ino, mut writer) = self.entry(parent, &name, stored, &inode);
+        let file = match inode {
+            InodeType::File(file) => file,
+            InodeType::Directory(_) => return Err(libc::EISDIR),
+        };
+        let upload = self.load(ino, &file, true).await?;
+        upload.dirty.store(false, Ordering::SeqCst);  // the empty file is already stored
+        let fh = self.new_handle(Handle::Write(Arc::new(upload)));
+        abi::open_out(fh, &mut writer);
+        Ok(writer.data)
+    }
+
+    async fn remove(&self, parent: u64, name: &str, directory: bool) -> Reply {
+        let name = name.to_string();
+        let _tree = self.global.lock_tree().await;
+        let mut open = self.directory(parent).await?;
+        let stored = open.directory.get(&name).map_err(|_| libc::ENOENT)?.clone();
+        match (stored.get::<InodeType>(self.global.clone()).await.map_err(eio)?, directory) {
+            (InodeType::File(_), true) => return Err(libc::ENOTDIR),
+            (InodeType::Directory(_), false) => return Err(libc::EISDIR),
+            (InodeType::Directory(removed), true) if !removed.list().is_empty() => return Err(libc::ENOTEMPTY),
+            _ => (),
+        }
+        open.directory.unlink(&name).map_err(eio)?;
+        open.save(self.global.clone()).await.map_err(eio)?;
+        self.nodes.lock().unwrap().remove(&stored);
+        delete_inode(self.global.clone(), stored).await.map_err(eio)?;
+        Ok(Vec::new())
+    }
+
+    // True if the directory is the ancestor or the same as the inode
+    fn contains(&self, directory: u64, mut ino: u64) -> bool {
+        let nodes = self.nodes.lock().unwrap();
+        loop {
+            if ino == directory {
+                return true;
+            }
+            match nodes.nodes.get(&ino) {
+                Some(node) if ino != abi::ROOT_ID => ino = node.parent,
+                _ => return false,
+            }
+        }
+    }
+
+    async fn rename(&self, parent: u64, reader: &mut Reader<'_>, flagged: bool) -> Reply {
+        let target_parent = reader.u64().ok_or(libc::EINVAL)?;
+        let flags = match flagged {
+            true => reader.u32().zip(reader.u32()).ok_or(libc::EINVAL)?.0,
+            false => 0,
+        };
+        let (name, target_name) = reader.name().zip(reader.name()).ok_or(libc::EINVAL)?;
+        if flags & !abi::RENAME_NOREPLACE != 0 {
+            return Err(libc::EINVAL);
+        }
+
+        let _tree = self.global.lock_tree().await;
+        let mut source = self.directory(parent).await?;
+        let stored = source.directory.get(&name).map_err(|_| libc::ENOENT)?.clone();
+        let moved = stored.get::<InodeType>(self.global.clone()).await.map_err(eio)?;
+        let ino = self.nodes.lock().unwrap().ino(stored.clone(), parent, &name);
+        if matches!(moved, InodeType::Directory(_)) && self.contains(ino, target_parent) {
+            return Err(libc::EINVAL);
+        }
+        if parent == target_parent && name == target_name {
+            return Ok(Vec::new());
+        }
+
+        let mut target = match parent == target_parent {
+            true => None,
+            false => Some(self.directory(target_parent).await?),
+        };
+        let target_directory = match &mut target {
+            Some(target) => &mut target.directory,
+            None => &mut source.directory,
+        };
+        let replaced = match target_directory.get(&target_name) {
+            Ok(replaced) => Some(replaced.clone()),
+            Err(_) => None,
+        };
+        if let Some(replaced) = &replaced {
+            if flags & abi::RENAME_NOREPLACE != 0 {
+                return Err(libc::EEXIST);
+            }
+            match (&moved, replaced.get::<InodeType>(self.global.clone()).await.map_err(eio)?) {
+                (InodeType::Directory(_), InodeType::File(_)) => return Err(libc::ENOTDIR),
+                (InodeType::File(_), InodeType::Directory(_)) => return Err(libc::EISDIR),
+                (_, InodeType::Directory(directory)) if !directory.list().is_empty() => return Err(libc::ENOTEMPTY),
+                _ => (),
+            }
+            target_directory.unlink(&target_name).map_err(eio)?;
+        }
+        target_directory.put(&target_name, stored.clone()).map_err(eio)?;
+        source.directory.unlink(&name).map_err(eio)?;
+
+        if let Some(target) = target {
+            target.save(self.global.clone()).await.map_err(eio)?;
+        }
+        source.save(self.global.clone()).await.map_err(eio)?;
+        {
+            let mut nodes = self.nodes.lock().unwrap();
+            nodes.ino(stored, target_parent, &target_name);
+            if let Some(replaced) = &replaced {
+                nodes.remove(replaced);
+            }
+        }
+        if let Some(replaced) = replaced {
+            delete_inode(self.global.clone(), replaced).await.map_err(eio)?;
+        }
+        Ok(Vec::new())
+    }
+
+    async fn open(&self, ino: u64, reader: &mut Reader<'_>) -> Reply {
+        let flags = reader.u32().ok_or(libc::EINVAL)? as i32;
+        let file = match self.inode(ino).await? {
+            InodeType::File(file) => file,
+            InodeType::Directory(_) => return Err(libc::EISDIR),
+        };
+        let handle = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => Handle::Read(Arc::new(file)),
+            _ => Handle::Write(Arc::new(self.load(ino, &file, flags & libc::O_TRUNC != 0).await?)),
+        };
+        let mut writer = Writer::default();
+        abi::open_out(self.new_handle(handle), &mut writer);
+        Ok(writer.data)
+    }
+
+    fn handle_of(&self, fh: u64) -> Result<Handle, i32> {
+        match self.handles.lock().unwrap().get(&fh) {
+            Some(Handle::Read(file)) => Ok(Handle::Read(file.clone())),
+            Some(Handle::Write(upload)) => Ok(Handle::Write(upload.clone())),
+            Some(Handle::Directory(_)) => Err(libc::EISDIR),
+            None => Err(libc::EBADF),
+        }
+    }
+
+    async fn read(&self, reader: &mut Reader<'_>) -> Reply {
+        let (fh, offset, size) = match (reader.u64(), reader.u64(), reader.u32()) {
+            (Some(fh), Some(offset), Some(size)) => (fh, offset, size as usize),
+            _ => return Err(libc::EINVAL),
+        };
+        match self.handle_of(fh)? {
+            Handle::Read(file) => {
+                let offset = offset as usize;
+                let mut data = Vec::with_capacity(size);
+                let mut stream = file.get_range(self.global.clone(), offset..offset.saturating_add(size));
+                while let Some(chunk) = stream.next().await {
+                    data.extend_from_slice(&chunk.map_err(eio)?);
+                }
+                Ok(data)
+            },
+            Handle::Write(upload) => {
+                let mut data = vec![0; size];
+                let mut read = 0;
+                while read < size {
+                    match upload.temp.read_at(&mut data[read..], offset + read as u64) {
+                        Ok(0) => break,
+                        Ok(len) => read += len,
+                        Err(e) => return Err(eio(e.to_string())),
+                    }
+                }
+                data.truncate(read);
+                Ok(data)
+            },
+            Handle::Directory(_) => Err(libc::EISDIR),
+        }
+    }
+
+    fn write(&self, reader: &mut Reader) -> Reply {
+        let (fh, offset, size) = match (reader.u64(), reader.u64(), reader.u32(), reader.skip(20)) {
+            (Some(fh), Some(offset), Some(size), Some(_)) => (fh, offset, size),  // skips the flags and the lock owner
+            _ => return Err(libc::EINVAL),
+        };
+        let data = reader.rest();
+        if data.len() != size as usize {
+            return Err(libc::EINVAL);
+        }
+        let upload = match self.handle_of(fh)? {
+            Handle::Write(upload) => upload,
+            _ => return Err(libc::EBADF),
+        };
+        upload.temp.write_all_at(data, offset).map_err(|e| eio(e.to_string()))?;
+        upload.dirty.store(true, Ordering::SeqCst);
+        let mut writer = Writer::default();
+        writer.u32(size).u32(0);
+        Ok(writer.data)
+    }
+
+    async fn flush(&self, fh: u64) -> Result<(), i32> {
+        match self.handle_of(fh) {
+            Ok(Handle::Write(upload)) => self.upload(&upload).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn release(&self, reader: &mut Reader<'_>) -> Reply {
+        let fh = reader.u64().ok_or(libc::EINVAL)?;
+        let result = self.flush(fh).await;
+        self.handles.lock().unwrap().remove(&fh);
+        result.map(|_| Vec::new())
+    }
+
+    async fn opendir(&self, ino: u64) -> Reply {
+        let directory = self.directory(ino).await?;
+        let parent = self.nodes.lock().unwrap().nodes.get(&ino).map(|node| node.parent).unwrap_or(abi::ROOT_ID);
+        let mut children = directory.directory.list_tuples();
+        children.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut entries = vec![(ino, Some(Kind::Directory), ".".to_string()), (parent, Some(Kind::Directory), "..".to_string())];
+        {
+            let mut nodes = self.nodes.lock().unwrap();
+            for (name, stored) in children {
+                entries.push((nodes.ino(stored, ino, &name), None, name));
+            }
+        }
+        let mut writer = Writer::default();
+        abi::open_out(self.new_handle(Handle::Directory(entries)), &mut writer);
+        Ok(writer.data)
+    }
+
+    fn readdir(&self, reader: &mut Reader) -> Reply {
+        let (fh, offset, size) = match (reader.u64(), reader.u64(), reader.u32()) {
+            (Some(fh), Some(offset), Some(size)) => (fh, offset as usize, size as usize),
+            _ => return Err(libc::EINVAL),
+        };
+        let handles = self.handles.lock().unwrap();
+        let entries = match handles.get(&fh) {
+            Some(Handle::Directory(entries)) => entries,
+            _ => return Err(libc::EBADF),
+        };
+        let mut writer = Writer::default();
+        for (index, (ino, kind, name)) in entries.iter().enumerate().skip(offset) {
+            if !abi::dirent(&mut writer, size, *ino, index as u64 + 1, *kind, name) {
+                break;
+            }
+        }
+        Ok(writer.data)
+    }
+
+    // The free space of the buckets is not known, so the sizes are left empty
+    fn statfs(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.zeros(5 * 8)  // blocks, bfree, bavail, files, ffree
+            .u32(4096)  // bsize
+            .u32(255)  // namelen
+            .u32(4096)  // frsize
+            .zeros(4 + 6 * 4);
+        writer.data
+    }
+}
@@ -0,0 +1,99 @@
+/*
+    Mounts the tree as a regular filesystem on Linux. The kernel protocol is spoken directly over /dev/fuse, so no FUSE library is needed, but mounting needs root.
+    Requests are read one after the other and answered in tasks, so a slow download does not hold up the others.
+ */
+
+use std::{ffi::CString, io::{Read, Write}, os::fd::AsRawFd, panic::AssertUnwindSafe, sync::Arc};
+use futures::FutureExt;
+use tokio::runtime::Runtime;
+
+use crate::global::Global;
+use self::{abi::Reader, filesystem::Filesystem};
+
+pub mod abi;
+pub mod filesystem;
+
+pub struct Session {
+    device: Arc<std::fs::File>,
+}
+
+fn c_path(path: &str) -> Result<CString, String> {
+    CString::new(path).map_err(|_| format!("Invalid path {}", path))
+}
+
+impl Session {
+    pub fn mount(mountpoint: &str) -> Result<Self, String> {
+        let device = std::fs::OpenOptions::new().read(true).write(true).open("/dev/fuse")
+            .map_err(|e| format!("Could not open /dev/fuse: {}", e))?;
+        let options = format!("fd={},rootmode=40000,user_id={},group_id={}", device.as_raw_fd(), unsafe { libc::getuid() }, unsafe { libc::getgid() });
+        let (source, target, kind, options) = (c_path("chunkdrive")?, c_path(mountpoint)?, c_path("fuse.chunkdrive")?, c_path(&options)?);
+        let result = unsafe { libc::mount(source.as_ptr(), target.as_ptr(), kind.as_ptr(), libc::MS_NOSUID | libc::MS_NODEV, options.as_ptr() as *const libc::c_void) };
+        if result != 0 {
+            return Err(format!("Could not mount at {}: {}", mountpoint, std::io::Error::last_os_error()));
+        }
+        Ok(Self { device: Arc::new(device) })
+    }
+
+    // Answers requests until the filesystem is unmounted
+    pub fn run(&self, global: Arc<Global>, runtime: &Runtime) -> Result<(), String> {
+        let filesystem = Arc::new(Filesystem::new(global));
+        let mut buffer = vec![0; abi::BUFFER_SIZE];
+        loop {
+            let len = match (&*self.device).read(&mut buffer) {
+                Ok(len) => len,
+                Err(e) => match e.raw_os_error() {
+                    Some(libc::ENOENT) | Some(libc::EINTR) | Some(libc::EAGAIN) => continue,  // the request was interrupted
+                    Some(libc::ENODEV) => return Ok(()),
+                    _ => return Err(format!("Could not read from /dev/fuse: {}", e)),
+                },
+            };
+            let request = buffer[..len].to_vec();
+            let destroy = matches!(abi::header(&mut Reader::new(&request)), Some(header) if header.opcode == abi::DESTROY);
+
+            let filesystem = filesystem.clone();
+            let device = self.device.clone();
+            let task = runtime.spawn(async move {
+                let mut reader = Reader::new(&request);
+                let header = match abi::header(&mut reader) {
+                    Some(header) => header,
+                    None => return,
+                };
+                // a request that panics still gets an answer, otherwise the process waiting for it hangs
+                let result = match AssertUnwindSafe(filesystem.handle(&header, reader)).catch_unwind().await {
+                    Ok(Some(result)) => result,
+                    Ok(None) => return,
+                    Err(_) => Err(libc::EIO),
+                };
+                let _ = (&*device).write(&abi::reply(header.unique, result));  // fails if the request was interrupted
+            });
+            if destroy {
+                let _ = runtime.block_on(task);
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub fn unmount(mountpoint: &str) -> Result<(), String> {
+    let target = c_path(mountpoint)?;
+    match unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } {
+        0 => Ok(()),
+        _ => Err(format!("Could not unmount {}: {}", mountpoint, std::io::Error::last_os_error())),
+    }
+}
+
+// Mounts the tree and blocks until it is unmounted, Ctrl+C unmounts it
+pub fn mount(global: Arc<Global>, mountpoint: &str) -> Result<(), String> {
+    let session = Session::mount(mountpoint)?;
+    let runtime = Runtime::new().map_err(|e| e.to_string())?;
+    let path = mountpoint.to_string();
+    runtime.spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            if let Err(e) = unmount(&path) {
+                println!("{}", e);
+            }
+        }
+    });
+    println!("Mounted at {}, press Ctrl+C or run umount to unmount", mountpoint);
+    session.run(global, &runtime)
+}
@@ -0,0 +1,89 @@
+/*
+    Garbage collection finds descriptors in the buckets that are not reachable from the root and deletes them.
+    They are left behind by failed uploads, interrupted deletes or descriptors that were created but never written.
+    Unfinished uploads that services keep on disk count as reachable. Uploads that are still being written are not, so make sure nothing is being uploaded while collecting.
+ */
+
+use std::{collections::HashSet, sync::Arc};
+use futures::future::BoxFuture;
+
+use crate::{blocks::block::Block, global::{Global, Descriptor}, inodes::{directory::Directory, inode::InodeType}};
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub reachable: usize,
+    pub orphans: Vec<(String, Descriptor)>, // (bucket, descriptor)
+    pub deleted: usize,
+    pub errors: Vec<String>,
+}
+
+impl GcReport {
+    pub fn human_readable(&self) -> String {
+        format!("{} reachable descriptors, {} orphans, {} deleted", self.reachable, self.orphans.len(), self.deleted)
+    }
+}
+
+// Finds the orphaned descriptors and deletes them if `delete` is set
+pub async fn collect(global: Arc<Global>, delete: bool) -> Result<GcReport, String> {
+    let mut report = GcReport::default();
+
+    // if anything can not be read, we do not know what it references, so deleting would be unsafe
+    let mut found = HashSet::new();
+    let root = global.get_root()?;
+    reachable_directory(global.clone(), &root, &mut found).await
+        .map_err(|e| format!("Could not walk the tree, nothing was deleted: {}", e))?;
+    for block in global.unfinished_blocks().map_err(|e| format!("{}, nothing was deleted", e))? {
+        block.reachable(global.clone(), &mut found).await
+            .map_err(|e| format!("Could not walk an unfinished upload, nothing was deleted: {}", e))?;
+    }
+    report.reachable = found.len();
+
+    // buckets might share the same storage, so a descriptor is an orphan only if no bucket references it
+    let mut seen = HashSet::new();
+    let mut buckets = global.list_buckets();
+    buckets.sort();
+    for name in buckets {
+        let bucket = global.get_bucket(name).ok_or("Bucket not found".to_string())?;
+        let descriptors = match bucket.list().await {
+            Ok(descriptors) => descriptors,
+            Err(e) => {
+                report.errors.push(format!("Skipping bucket {}: {}", name, e));
+                continue;
+            }
+        };
+        for descriptor in descriptors {
+            if found.contains(&descriptor) || !seen.insert(descriptor.clone()) {
+                continue;
+            }
+            report.orphans.push((name.clone(), descriptor));
+        }
+    }
+
+    if delete {
+        for (name, descriptor) in report.orphans.iter() {
+            let bucket = global.get_bucket(name).ok_or("Bucket not found".to_string())?;
+            match bucket.delete(descriptor).await {
+                Ok(_) => report.deleted += 1,
+                Err(e) => report.errors.push(format!("Could not delete {} from {}: {}", String::from_utf8_lossy(descriptor), name, e)),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn reachable_directory<'a>(global: Arc<Global>, dir: &'a Directory, found: &'a mut HashSet<Descriptor>) -> BoxFuture<'a, Result<(), String>> {
+    Box::pin(async move {
+        for (name, stored) in dir.list_tuples() {
+            found.insert(stored.descriptor().clone());
+            let inode = stored.get::<InodeType>(global.clone()).await
+                .map_err(|e| format!("{}: {}", name, e))?;
+            match inode {
+                InodeType::Directory(dir) => reachable_directory(global.clone(), &dir, found).await?,
+                InodeType::File(file) => file.data.reachable(global.clone(), found).await
+                    .map_err(|e| format!("{}: {}", name, e))?,
+            }
+        }
+        Ok(())
+    })
+}
@@ -4,7 +4,7 @@ use rand::seq::IteratorRandom;
 use serde::{Deserialize, Serialize};
 use rmp_serde::{Deserializer, Serializer};
 
-use crate::{bucket::Bucket, inodes::directory::Directory, services::service::{ServiceType, Service}};
+use crate::{blocks::{block::BlockType, redundancy::Redundancy}, bucket::Bucket, cache::CacheConfig, inodes::directory::Directory, prefetch::Prefetch, persist::write_atomic, scrub::ScrubConfig, services::service::{ServiceType, Service}};
 
 pub type Descriptor = Vec<u8>;
 
@@ -14,26 +14,69 @@ pub struct Global {
 
     #[serde(default = "default_direct_block_count")]
     pub direct_block_count: usize,
+
+    #[serde(default)]
+    pub redundancy: Redundancy,
+
+    #[serde(default)]
+    pub prefetch: Prefetch,
+
+    #[serde(default = "default_parallel_uploads")]
+    pub parallel_uploads: usize,
     
     #[serde(default = "default_root_path")]
     root_path: String,
 
+    #[serde(default = "default_root_backups")]
+    pub root_backups: usize, // older versions of the root kept as root.dat.1, root.dat.2, ...
+
     #[serde(default)]
     services: Vec<ServiceType>,
+
+    #[serde(default)]
+    scrub: Option<ScrubConfig>,
+
+    #[serde(default)]
+    cache: Option<CacheConfig>,
+
+    #[serde(skip)]
+    tree: tokio::sync::Mutex<()>,
 }
 
 const fn default_direct_block_count() -> usize { 10 }
+const fn default_parallel_uploads() -> usize { 4 }
 fn default_root_path() -> String { "./root.dat".to_string() }
+const fn default_root_backups() -> usize { 3 }
+
+// The outer error is reading the file, which can pass, the inner one is a file that is not a root
+fn read_root(path: &str) -> std::io::Result<Result<Directory, String>> {
+    let data = std::fs::read(path)?;
+    Ok(Deserialize::deserialize(&mut Deserializer::new(&data[..])).map_err(|e| e.to_string()))
+}
+
+fn serialize_root(root: &Directory) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let mut serializer = Serializer::new(&mut data)
+        .with_struct_map(); // https://github.com/3Hren/msgpack-rust/issues/318
+    root.serialize(&mut serializer).map_err(|e| format!("Failed to serialize the root: {}", e))?;
+    Ok(data)
+}
 
 pub fn run_services(global: Arc<Global>) {
     for service in global.services.iter() {
         service.run(global.clone());
     }
+    if let Some(scrub) = &global.scrub {
+        scrub.run(global.clone());
+    }
 }
 
 impl Global {
     pub fn get_bucket(&self, name: &str) -> Option<&Bucket> {
-        self.buckets.get(name)
+        let bucket = self.buckets.get(name)?;
+        bucket.attach_cache(name, self.cache.as_ref());
+        bucket.attach_limit(&self.prefetch);
+        Some(bucket)
     }
     
     pub fn next_bucket(&self, max_size: usize, exclude: &[String]) -> Option<&String> {
@@ -45,6 +88,14 @@ impl Global {
             .map(|(bucket, _)| bucket)
     }
 
+    pub fn unfinished_blocks(&self) -> Result<Vec<BlockType>, String> {
+        let mut blocks = Vec::new();
+        for service in self.services.iter() {
+            blocks.extend(service.unfinished()?);
+        }
+        Ok(blocks)
+    }
+
     pub fn list_buckets(&self) -> Vec<&String> {
         self.buckets.keys().collect()
     }
@@ -56,28 +107,77 @@ impl Global {
             .map(|(bucket, _)| bucket)
     }
     
-    pub fn get_root(&self) -> Directory {
-        match std::fs::File::open(&self.root_path) {
-            Ok(file) => {
-                let mut de = Deserializer::new(&file);
-                match Deserialize::deserialize(&mut de) {
-                    Ok(root) => root,
-                    Err(_) => {
-                        std::fs::remove_file(&self.root_path).unwrap();
-                        Directory::new()
-                    }
-                }
-            },
-            Err(_) => {
-                Directory::new()
+    fn backup_path(&self, i: usize) -> String {
+        format!("{}.{}", self.root_path, i)
+    }
+
+    // A damaged root is never thrown away. The newest backup that can be read takes its place, and the damaged file is kept aside.
+    // Errors reading the file are only returned, they say nothing about what is in it.
+    pub fn get_root(&self) -> Result<Directory, String> {
+        let error = match read_root(&self.root_path) {
+            Ok(Ok(root)) => return Ok(root),
+            Ok(Err(e)) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Directory::new()),
+            Err(e) => return Err(format!("Failed to read the root {}: {}", self.root_path, e)),
+        };
+        let corrupt = format!("{}.corrupt", self.root_path);
+        println!("ERROR: the root in {} is damaged: {}", self.root_path, error);
+
+        let mut backup = None;
+        for i in 1..=self.root_backups {
+            let path = self.backup_path(i);
+            match read_root(&path) {
+                Ok(Ok(root)) => {
+                    backup = Some((path, root));
+                    break;
+                },
+                Ok(Err(e)) => println!("ERROR: the backup {} is damaged too: {}", path, e),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => return Err(format!("Failed to read the backup {}: {}", path, e)),
             }
         }
+
+        match backup {
+            Some((path, root)) => {
+                std::fs::copy(&self.root_path, &corrupt).map_err(|e| format!("Failed to move the damaged root to {}: {}", corrupt, e))?;
+                write_atomic(&self.root_path, &serialize_root(&root)?)?;
+                println!("ERROR: restored the root from {}, changes made after it are lost. The damaged root was moved to {}", path, corrupt);
+                Ok(root)
+            },
+            None => {
+                std::fs::rename(&self.root_path, &corrupt).map_err(|e| format!("Failed to move the damaged root to {}: {}", corrupt, e))?;
+                println!("ERROR: no backup of the root can be read, starting with an empty one. The damaged root was moved to {}", corrupt);
+                Ok(Directory::new())
+            },
+        }
     }
 
-    pub fn save_root(&self, root: &Directory) {
-        let mut file = std::fs::File::create(&self.root_path).unwrap();
-        let mut serializer = Serializer::new(&mut file)
-            .with_struct_map(); // https://github.com/3Hren/msgpack-rust/issues/318
-        root.serialize(&mut serializer).unwrap();
+    // Directories are read, changed and written back, so every change to the tree takes turns here, whichever service it comes from
+    pub async fn lock_tree(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.tree.lock().await
+    }
+
+    // a file kept next to the root, for other state that has to survive restarts
+    pub fn local_path(&self, name: &str) -> String {
+        std::path::Path::new(&self.root_path).with_file_name(name).to_string_lossy().to_string()
+    }
+
+    pub fn save_root(&self, root: &Directory) -> Result<(), String> {
+        let data = serialize_root(root)?;
+
+        if self.root_backups > 0 && std::path::Path::new(&self.root_path).exists() {
+            // the oldest backup drops out and the current root becomes root.dat.1, linked so it is never copied
+            for i in (1..self.root_backups).rev() {
+                let _ = std::fs::rename(self.backup_path(i), self.backup_path(i + 1));
+            }
+            let newest = self.backup_path(1);
+            let _ = std::fs::remove_file(&newest);
+            if std::fs::hard_link(&self.root_path, &newest).is_err() {
+                if let Err(e) = std::fs::copy(&self.root_path, &newest) {
+                    println!("Failed to back up the root: {}", e);
+                }
+            }
+        }
+        write_atomic(&self.root_path, &data)
     }
 }
\ No newline at end of file
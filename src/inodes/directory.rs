@@ -21,7 +21,7 @@ fn is_empty<T>(map: &HashMap<String, T>) -> bool {
 
 #[async_trait]
 impl Inode for Directory {
-    async fn metadata(&self) -> &Metadata {
+    async fn metadata<'a>(&'a self) -> &'a Metadata {
         &self.metadata
     }
 
@@ -1,11 +1,13 @@
-use std::{sync::Arc};
+use std::{ops::Range, sync::Arc};
 
 use async_trait::async_trait;
 use futures::{StreamExt, stream::BoxStream};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio_util::io::StreamReader;
 use serde::{Serialize, Deserialize};
 
-use crate::{blocks::{indirect_block::IndirectBlock, block::{Block, BlockType}}, global::Global};
-use super::{inode::{Inode, InodeType}, metadata::{Metadata, Size}};
+use crate::{blocks::{indirect_block::IndirectBlock, block::{Block, BlockType}, redundancy::Redundancy}, global::Global};
+use super::{inode::{Inode, InodeType}, metadata::{Metadata, Size, detect_mime}};
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,7 +18,7 @@ pub struct File {
 
 #[async_trait]
 impl Inode for File {
-    async fn metadata(&self) -> &Metadata {
+    async fn metadata<'a>(&'a self) -> &'a Metadata {
         &self.metadata
     }
 
@@ -32,25 +34,63 @@ impl File {
 
     pub async fn create(global: Arc<Global>, data: Vec<u8>) -> Result<Self, String> {
         let size = data.len();
-        let block = match IndirectBlock::create(global, data, 0).await? {
+        let block = BlockType::create(global, data, 0).await?;
+        Ok(Self::from_block(block, size))
+    }
+
+    // Creates the file from a reader, so it does not have to fit in memory
+    pub async fn create_from_reader<R: AsyncBufRead + Unpin + Send>(global: Arc<Global>, reader: &mut R, redundancy: Redundancy) -> Result<Self, String> {
+        let (block, size) = IndirectBlock::create_from_reader(global, reader, 0, redundancy).await?;
+        Ok(Self::from_block(block, size))
+    }
+
+    // Same as create_from_reader, but also detects the MIME type from the name and the first bytes of the data
+    pub async fn create_named<R: AsyncBufRead + Unpin + Send>(global: Arc<Global>, name: &str, reader: &mut R, redundancy: Redundancy) -> Result<Self, String> {
+        let head = reader.fill_buf().await.map_err(|e| format!("Could not read the data: {}", e))?;
+        let mime = detect_mime(name, head);
+        let mut file = Self::create_from_reader(global, reader, redundancy).await?;
+        file.metadata.mime = mime;
+        Ok(file)
+    }
+
+    // Wraps an indirect block that is already uploaded
+    pub fn from_block(block: BlockType, size: usize) -> Self {
+        let block = match block {
             BlockType::Indirect(block) => block,
             _ => panic!("This should never happen"),
         };
         let mut metadata = Metadata::new();
         metadata.size = Size::Bytes(size);
-        Ok(Self {
+        Self {
             data: block,
             metadata
-        })
+        }
     }
 
-    pub fn get(&self, global: Arc<Global>) -> BoxStream<Result<Vec<u8>, String>> {
+    pub fn get(&self, global: Arc<Global>) -> BoxStream<'_, Result<Vec<u8>, String>> {
+        self.get_range(global, 0..usize::MAX)
+    }
+
+    // Reads the given byte range, only the chunks overlapping it are downloaded. The range is clamped to the size of the file
+    pub fn get_range(&self, global: Arc<Global>, range: Range<usize>) -> BoxStream<'_, Result<Vec<u8>, String>> {
         Box::pin(async_stream::stream! {
-            let range = self.data.range(global.clone()).await?;
-            let mut stream = self.data.get(global.clone(), range.clone());
+            let file_range = self.data.range(global.clone()).await?;
+            let range = std::cmp::max(range.start, file_range.start)..std::cmp::min(range.end, file_range.end);
+            if range.start >= range.end {
+                return
+            }
+            let mut stream = self.data.get(global.clone(), range);
             while let Some(result) = stream.next().await {
                 yield result;
             }
         })
     }
-}
\ No newline at end of file
+
+    // Copies the data into new chunks, so the copy can be changed or deleted on its own
+    pub async fn copy(&self, global: Arc<Global>) -> Result<Self, String> {
+        let mut reader = StreamReader::new(self.get(global.clone()).map(|chunk| chunk.map(std::io::Cursor::new).map_err(std::io::Error::other)));
+        let mut copy = Self::create_from_reader(global.clone(), &mut reader, global.redundancy.clone()).await?;
+        copy.metadata.mime = self.metadata.mime.clone();
+        Ok(copy)
+    }
+}
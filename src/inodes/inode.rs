@@ -13,7 +13,7 @@ use super::{file::File, directory::Directory, metadata::Metadata};
 
 #[async_trait]
 pub trait Inode {
-    async fn metadata(&self) -> &Metadata;
+    async fn metadata<'a>(&'a self) -> &'a Metadata;
     async fn delete(&mut self, global: Arc<Global>) -> Result<(), String>;
 }
 
@@ -36,7 +36,7 @@ macro_rules! match_method {
 
 #[async_trait]
 impl Inode for InodeType {
-    async fn metadata(&self) -> &Metadata {
+    async fn metadata<'a>(&'a self) -> &'a Metadata {
         match_method!(self, metadata, ).await
     }
 
@@ -59,6 +59,10 @@ pub struct Metadata {
     #[serde(rename = "s")]
     #[serde(default, skip_serializing_if = "is_default")]
     pub size: Size,
+
+    #[serde(rename = "t")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>, // detected at upload, older files do not have it
 }
 
 const fn is_default(size: &Size) -> bool {
@@ -77,6 +81,7 @@ impl Metadata {
                 .unwrap_or_default()
                 .as_secs(),
             size: Size::Empty,
+            mime: None,
         }
     }
 
@@ -92,6 +97,14 @@ impl Metadata {
         self.size = size;
     }
 
+    // The stored MIME type, or a guess from the name for files uploaded before it was detected
+    pub fn content_type(&self, name: &str) -> String {
+        match &self.mime {
+            Some(mime) => mime.clone(),
+            None => mime_guess::from_path(name).first_or_octet_stream().essence_str().to_string(),
+        }
+    }
+
     pub fn human_created(&self) -> String {
         let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(self.created);
         let datetime: chrono::DateTime<chrono::Utc> = time.into();
@@ -104,3 +117,11 @@ impl Metadata {
         datetime.format("%Y-%m-%d %H:%M:%S").to_string()
     }
 }
+
+// Detects the MIME type from the first bytes of the file, falling back to the extension of the name
+pub fn detect_mime(name: &str, head: &[u8]) -> Option<String> {
+    match infer::get(head) {
+        Some(kind) => Some(kind.mime_type().to_string()),
+        None => mime_guess::from_path(name).first().map(|mime| mime.essence_str().to_string()),
+    }
+}
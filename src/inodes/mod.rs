@@ -1,4 +1,5 @@
 pub mod directory;
 pub mod file;
 pub mod inode;
-pub mod metadata;
\ No newline at end of file
+pub mod metadata;
+pub mod path;
\ No newline at end of file
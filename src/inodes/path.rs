@@ -0,0 +1,138 @@
+/*
+    Paths of names, like /photos/2023/cat.jpg, resolved starting at the root.
+    Every directory is stored under its own descriptor, so a changed directory is written back there and the ones above it stay the same.
+ */
+
+use std::sync::Arc;
+use futures::future::BoxFuture;
+
+use crate::{global::Global, stored::Stored};
+use super::{directory::Directory, inode::{Inode, InodeType}};
+
+// Splits a path into names, empty and "." parts are skipped
+pub fn split_path(path: &str) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => return Err("Paths can not go up".to_string()),
+            name => names.push(name.to_string()),
+        }
+    }
+    Ok(names)
+}
+
+// A directory together with the descriptor it is saved under, the root has none
+pub struct OpenDirectory {
+    pub stored: Option<Stored>,
+    pub directory: Directory,
+}
+
+impl OpenDirectory {
+    pub async fn save(self, global: Arc<Global>) -> Result<(), String> {
+        match self.stored {
+            Some(stored) => stored.put(global, self.directory.to_enum()).await,
+            None => global.save_root(&self.directory),
+        }
+    }
+}
+
+// Walks down to the directory, None if some part of the path does not exist or is not a directory
+pub async fn open_directory(global: Arc<Global>, names: &[String]) -> Result<Option<OpenDirectory>, String> {
+    let mut current = OpenDirectory { stored: None, directory: global.get_root()? };
+    for name in names {
+        let stored = match current.directory.get(name) {
+            Ok(stored) => stored.clone(),
+            Err(_) => return Ok(None),
+        };
+        current = match stored.get::<InodeType>(global.clone()).await? {
+            InodeType::Directory(directory) => OpenDirectory { stored: Some(stored), directory },
+            InodeType::File(_) => return Ok(None),
+        };
+    }
+    Ok(Some(current))
+}
+
+// Finds the inode the path points to, None if it does not exist. The root is not stored anywhere, so the path can not be empty
+pub async fn open(global: Arc<Global>, names: &[String]) -> Result<Option<(Stored, InodeType)>, String> {
+    let (name, parents) = names.split_last().ok_or("The root has no descriptor".to_string())?;
+    let parent = match open_directory(global.clone(), parents).await? {
+        Some(parent) => parent,
+        None => return Ok(None),
+    };
+    let stored = match parent.directory.get(name) {
+        Ok(stored) => stored.clone(),
+        Err(_) => return Ok(None),
+    };
+    let inode = stored.get::<InodeType>(global).await?;
+    Ok(Some((stored, inode)))
+}
+
+// Same as open_directory, but the missing directories on the way are created
+pub async fn create_directories(global: Arc<Global>, names: &[String]) -> Result<OpenDirectory, String> {
+    let mut current = OpenDirectory { stored: None, directory: global.get_root()? };
+    for name in names {
+        let stored = match current.directory.get(name) {
+            Ok(stored) => stored.clone(),
+            Err(_) => {
+                current.directory.add(global.clone(), name, Directory::new().to_enum()).await?;
+                let stored = current.directory.get(name)?.clone();
+                current.save(global.clone()).await?;
+                stored
+            },
+        };
+        current = match stored.get::<InodeType>(global.clone()).await? {
+            InodeType::Directory(directory) => OpenDirectory { stored: Some(stored), directory },
+            InodeType::File(_) => return Err(format!("{} is not a directory", name)),
+        };
+    }
+    Ok(current)
+}
+
+// Deletes an inode that is no longer linked from any directory, with everything it holds
+pub async fn delete_inode(global: Arc<Global>, stored: Stored) -> Result<(), String> {
+    stored.get::<InodeType>(global.clone()).await?.delete(global.clone()).await?;
+    stored.delete(global).await
+}
+
+// Same as copy_inode, but the copy is stored under its own descriptor, so it can be made before locking the tree and linked into a directory later
+pub async fn copy_stored(global: Arc<Global>, inode: &InodeType, deep: bool) -> Result<Stored, String> {
+    let mut copy = copy_inode(global.clone(), inode, deep).await?;
+    match Stored::create(global.clone(), &copy).await {
+        Ok(stored) => Ok(stored),
+        Err(e) => match copy.delete(global).await {
+            Ok(_) => Err(e),
+            Err(err) => Err(format!("{}, {}", e, err)),
+        },
+    }
+}
+
+// Makes a copy of the inode with its own chunks, directories are copied with everything in them if deep is set
+pub fn copy_inode(global: Arc<Global>, inode: &InodeType, deep: bool) -> BoxFuture<'_, Result<InodeType, String>> {
+    Box::pin(async move {
+        match inode {
+            InodeType::File(file) => Ok(file.copy(global.clone()).await?.to_enum()),
+            InodeType::Directory(directory) => {
+                let mut copy = Directory::new();
+                if !deep {
+                    return Ok(copy.to_enum());
+                }
+                let result = async {
+                    for (name, stored) in directory.list_tuples() {
+                        let child = stored.get::<InodeType>(global.clone()).await?;
+                        let child = copy_inode(global.clone(), &child, true).await?;
+                        copy.add(global.clone(), &name, child).await?;
+                    }
+                    Ok::<(), String>(())
+                }.await;
+                match result {
+                    Ok(_) => Ok(copy.to_enum()),
+                    Err(e) => match copy.delete(global).await {
+                        Ok(_) => Err(e),
+                        Err(err) => Err(format!("{}, {}", e, err)),
+                    },
+                }
+            },
+        }
+    })
+}
@@ -7,9 +7,17 @@ use std::sync::Arc;
 /* #region Modules */
 mod blocks;
 mod bucket;
+mod cache;
+mod compression;
 mod encryption;
+#[cfg(target_os = "linux")]
+mod fuse;
+mod gc;
 mod global;
 mod inodes;
+mod persist;
+mod prefetch;
+mod scrub;
 mod services;
 mod sources;
 mod shell;
@@ -23,6 +31,16 @@ mod tests; // this is only included when running tests
 const CONFIG_PATHS: [&str; 1] = ["./config.yml"];
 
 fn main() {
+    // these make values for the config, so they do not need one
+    let args: Vec<String> = args().collect();
+    if args.iter().any(|arg| arg == "--hash-password") {
+        return hash_password();
+    } else if args.iter().any(|arg| arg == "--new-token") {
+        let (token, hash) = services::http::auth::new_token();
+        println!("Token: {}\nPut this in the tokens of the user: {}", token, hash);
+        return;
+    }
+
     // Find config file path
     let mut config_path = None;
     if let Ok(path) = var("CD_CONFIG_PATH") {
@@ -44,10 +62,49 @@ fn main() {
     let global = Arc::new(global);
 
     // if run with --shell, start the shell
-    if args().any(|arg| arg == "--shell") {
+    if args.iter().any(|arg| arg == "--shell") {
         shell::shell(global);
+    } else if let Some(index) = args.iter().position(|arg| arg == "--mount") { // if run with --mount <path>, mount the tree there
+        match args.get(index + 1) {
+            Some(mountpoint) => mount(global, mountpoint),
+            None => {
+                println!("Usage: {} --mount <path>", args[0]);
+                std::process::exit(1);
+            }
+        }
     } else { // otherwise, start services
         global::run_services(global);
         std::thread::park();
     }
-}
\ No newline at end of file
+}
+
+// Reads a password from stdin and prints the hash that goes into the config
+fn hash_password() {
+    println!("Password:");
+    let mut password = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut password) {
+        println!("Could not read the password: {}", e);
+        std::process::exit(1);
+    }
+    match services::http::auth::hash_password(password.trim_end_matches(['\r', '\n'])) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => {
+            println!("Could not hash the password: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn mount(global: Arc<Global>, mountpoint: &str) {
+    if let Err(e) = fuse::mount(global, mountpoint) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount(_global: Arc<Global>, _mountpoint: &str) {
+    println!("Mounting is only supported on Linux");
+    std::process::exit(1);
+}
@@ -0,0 +1,51 @@
+/*
+    State that services keep in files next to the root, like unfinished uploads and revoked links.
+    Files are replaced whole, so a crash never leaves half of one behind, and a file that can not be read is an error instead of empty state.
+ */
+
+use serde::{de::DeserializeOwned, Serialize};
+use rmp_serde::{Deserializer, Serializer};
+
+// The file is written next to the target and renamed over it, so readers and crashes only ever see a whole file
+pub fn write_atomic(path: &str, data: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    let temp = format!("{}.{:08x}.tmp", path, rand::random::<u32>());
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, path)
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temp);
+        return Err(format!("Failed to write {}: {}", path, e));
+    }
+    // the rename itself is only durable once the directory is synced
+    #[cfg(unix)]
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        let parent = if parent.as_os_str().is_empty() { std::path::Path::new(".") } else { parent };
+        if let Ok(directory) = std::fs::File::open(parent) {
+            let _ = directory.sync_all();
+        }
+    }
+    Ok(())
+}
+
+// A missing file is the default state, anything else that goes wrong is returned
+pub fn load<T: DeserializeOwned + Default>(path: &str, what: &str) -> Result<T, String> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(format!("Failed to read the {} in {}: {}", what, path, e)),
+    };
+    serde::Deserialize::deserialize(&mut Deserializer::new(&data[..])).map_err(|e| format!("The {} in {} are damaged: {}", what, path, e))
+}
+
+pub fn save<T: Serialize>(path: &str, value: &T, what: &str) -> Result<(), String> {
+    let mut data = Vec::new();
+    let mut serializer = Serializer::new(&mut data)
+        .with_struct_map(); // https://github.com/3Hren/msgpack-rust/issues/318
+    value.serialize(&mut serializer).map_err(|e| format!("Could not save the {}: {}", what, e))?;
+    write_atomic(path, &data)
+}
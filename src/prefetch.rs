@@ -0,0 +1,49 @@
+/*
+    Prefetching fetches the next chunks of a file while the current one is being sent.
+    `blocks` decides how many chunks an indirect block fetches ahead, `depth` overrides it for the chunks of a source type.
+    `concurrent_reads` caps the reads of a source type across all files and buckets at once, so rate limited sources are not flooded.
+ */
+
+use std::{collections::HashMap, sync::{Arc, OnceLock}};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+#[derive(Deserialize, Debug)]
+pub struct Prefetch {
+    #[serde(default = "default_blocks")]
+    pub blocks: usize,
+    #[serde(default)]
+    depth: HashMap<String, usize>, // source type -> chunks fetched ahead
+    #[serde(default)]
+    concurrent_reads: HashMap<String, usize>, // source type -> reads in flight
+    #[serde(skip)]
+    limits: OnceLock<HashMap<String, Arc<Semaphore>>>,
+}
+
+const fn default_blocks() -> usize { 4 }
+
+impl Default for Prefetch {
+    fn default() -> Self {
+        Self { blocks: default_blocks(), depth: HashMap::new(), concurrent_reads: HashMap::new(), limits: OnceLock::new() }
+    }
+}
+
+impl Prefetch {
+    // How many chunks can be in flight when the next one comes from these source types, the smallest depth wins
+    pub fn depth<'a>(&self, source_types: impl IntoIterator<Item = &'a str>) -> usize {
+        let depth = source_types.into_iter()
+            .map(|source_type| self.depth.get(source_type).copied().unwrap_or(self.blocks))
+            .min()
+            .unwrap_or(self.blocks);
+        std::cmp::max(depth, 1)
+    }
+
+    // Returns the semaphore shared by all buckets of the source type, if it is limited
+    pub fn limit(&self, source_type: &str) -> Option<Arc<Semaphore>> {
+        self.limits.get_or_init(|| {
+            self.concurrent_reads.iter()
+                .map(|(source_type, limit)| (source_type.clone(), Arc::new(Semaphore::new(std::cmp::max(*limit, 1)))))
+                .collect()
+        }).get(source_type).cloned()
+    }
+}
@@ -0,0 +1,198 @@
+/*
+    Scrubbing walks the whole tree and reads every chunk to find out which files are damaged.
+    Blocks with redundancy rewrite their missing chunks to healthy buckets when repairing.
+ */
+
+use std::{sync::Arc, time::Duration};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+
+use crate::{blocks::block::Block, global::{Global, Descriptor}, inodes::{directory::Directory, inode::InodeType}};
+
+#[derive(Debug, Default)]
+pub struct ScrubReport {
+    pub checked: usize,       // chunks that were read
+    pub missing: usize,       // chunks that could not be read
+    pub lost: usize,          // chunks that could not be rebuilt from the other ones
+    pub repaired: usize,      // chunks that were rewritten
+    pub damaged: Vec<String>, // paths of the files that can not be read anymore
+    pub errors: Vec<String>,
+}
+
+impl ScrubReport {
+    // the chunk is missing, but it might be rebuilt from the other ones
+    pub fn missing(&mut self, error: String) {
+        self.missing += 1;
+        self.errors.push(error);
+    }
+
+    // the chunk is missing and there is nothing to rebuild it from
+    pub fn lost(&mut self, error: String) {
+        self.missing += 1;
+        self.lost += 1;
+        self.errors.push(error);
+    }
+
+    pub fn human_readable(&self) -> String {
+        format!("{} chunks checked, {} missing, {} lost, {} repaired, {} damaged files", self.checked, self.missing, self.lost, self.repaired, self.damaged.len())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ScrubConfig {
+    interval: u64, // seconds between two scrubs
+    #[serde(default = "default_repair")]
+    repair: bool,
+}
+
+const fn default_repair() -> bool { true }
+
+const MIN_INTERVAL: u64 = 60;
+
+impl ScrubConfig {
+    pub fn run(&self, global: Arc<Global>) {
+        // a zero interval would scrub without a break
+        let interval = Duration::from_secs(self.interval.max(MIN_INTERVAL));
+        let repair = self.repair;
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            loop {
+                std::thread::sleep(interval);
+                let report = rt.block_on(scrub(global.clone(), repair));
+                println!("Scrub finished: {}", report.human_readable());
+                for path in report.damaged.iter() {
+                    println!("Damaged: {}", path);
+                }
+            }
+        });
+    }
+}
+
+// reads the chunk and makes sure it has the expected size
+pub async fn check_chunk(global: &Global, bucket: &str, descriptor: &Descriptor, size: usize) -> Result<Vec<u8>, String> {
+    let bucket = global.get_bucket(bucket).ok_or(format!("Bucket {} not found", bucket))?;
+    let data = bucket.get(descriptor).await?;
+    if data.len() != size {
+        return Err(format!("Expected {} bytes, got {}", size, data.len()));
+    }
+    Ok(data)
+}
+
+// stores the chunk in a new place, avoiding the excluded buckets and preferably the one that failed
+pub async fn rewrite_chunk(global: &Global, data: Vec<u8>, exclude: &[String], failed: &str) -> Result<(String, Descriptor), String> {
+    let mut avoid = exclude.to_vec();
+    avoid.push(failed.to_string());
+    let bucket_name = global.next_bucket(data.len(), &avoid)
+        .or_else(|| global.next_bucket(data.len(), exclude))
+        .ok_or(format!("No bucket found for data of size {}", data.len()))?;
+    let bucket = global.get_bucket(bucket_name).ok_or("Bucket not found".to_string())?;
+    let descriptor = bucket.create().await?;
+    bucket.put(&descriptor, data).await?;
+    Ok((bucket_name.clone(), descriptor))
+}
+
+// the chunks a repair replaced and the ones it wrote instead, the old ones are only deleted once the new ones are saved
+#[derive(Debug, Default)]
+pub struct Repairs {
+    pub old: Vec<(String, Descriptor)>,
+    pub new: Vec<(String, Descriptor)>,
+}
+
+impl Repairs {
+    pub fn is_empty(&self) -> bool {
+        self.new.is_empty()
+    }
+
+    pub fn extend(&mut self, other: Repairs) {
+        self.old.extend(other.old);
+        self.new.extend(other.new);
+    }
+
+    // the block was saved, the old chunks might still be there in some broken form
+    pub async fn commit(self, global: &Global) {
+        for (bucket, descriptor) in self.old {
+            if let Some(bucket) = global.get_bucket(&bucket) {
+                let _ = bucket.delete(&descriptor).await;
+            }
+        }
+    }
+
+    // the block was not saved, so nothing refers to the new chunks
+    pub async fn discard(self, global: &Global, report: &mut ScrubReport) {
+        for (bucket_name, descriptor) in self.new {
+            let result = match global.get_bucket(&bucket_name) {
+                Some(bucket) => bucket.delete(&descriptor).await,
+                None => Err(format!("Bucket {} not found", bucket_name)),
+            };
+            if let Err(e) = result {
+                report.errors.push(format!("Could not delete the repaired chunk in {}: {}", bucket_name, e));
+            }
+        }
+    }
+}
+
+pub async fn scrub(global: Arc<Global>, repair: bool) -> ScrubReport {
+    let mut report = ScrubReport::default();
+    let root = match global.get_root() {
+        Ok(root) => root,
+        Err(e) => {
+            report.errors.push(e);
+            return report;
+        }
+    };
+    scrub_directory(global, &root, String::new(), &mut report, repair).await;
+    report
+}
+
+fn scrub_directory<'a>(global: Arc<Global>, dir: &'a Directory, path: String, report: &'a mut ScrubReport, repair: bool) -> BoxFuture<'a, ()> {
+    Box::pin(async move {
+        let mut children = dir.list_tuples();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, stored) in children {
+            let path = format!("{}/{}", path, name);
+            report.checked += 1;
+            let inode = match stored.get::<InodeType>(global.clone()).await {
+                Ok(inode) => inode,
+                Err(e) => {
+                    report.lost(format!("{}: {}", path, e));
+                    report.damaged.push(path);
+                    continue;
+                }
+            };
+            match inode {
+                InodeType::Directory(dir) => scrub_directory(global.clone(), &dir, path, report, repair).await,
+                InodeType::File(mut file) => {
+                    let before = rmp_serde::to_vec(&file.data).ok();
+                    let lost = report.lost;
+                    let repairs = file.data.scrub(global.clone(), report, repair).await;
+                    if report.lost > lost {
+                        report.damaged.push(path.clone());
+                    }
+                    // the file has to be saved, because the repaired chunks live in new places
+                    if !repairs.is_empty() {
+                        // the services might have changed the file meanwhile, so it is only saved if it is still the one we scrubbed
+                        let tree = global.lock_tree().await;
+                        let current = match stored.get::<InodeType>(global.clone()).await {
+                            Ok(InodeType::File(current)) => rmp_serde::to_vec(&current.data).ok(),
+                            _ => None,
+                        };
+                        let saved = if current.is_none() || current != before {
+                            report.errors.push(format!("{} changed while scrubbing, the repair was not saved", path));
+                            false
+                        } else if let Err(e) = stored.put(global.clone(), file.to_enum()).await {
+                            report.errors.push(format!("Could not save {}: {}", path, e));
+                            false
+                        } else {
+                            true
+                        };
+                        drop(tree);
+                        match saved {
+                            true => repairs.commit(&global).await,
+                            false => repairs.discard(&global, report).await,
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
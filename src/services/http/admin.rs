@@ -0,0 +1,95 @@
+/*
+    The bucket and maintenance pages, enabled with the admin setting. When users are configured only those with admin access to / can open them.
+    Scrubbing and garbage collection run while the request waits, like they do in the debug shell.
+    Garbage collection only lists the unused chunks here. Uploads of the other services are not visible from this process while they run, so deleting is left to the debug shell.
+ */
+
+use std::sync::Arc;
+use actix_web::{web, route, HttpRequest, HttpResponse, http::StatusCode};
+use serde::Deserialize;
+use yew::ServerRenderer;
+
+use crate::{gc, scrub};
+use super::{auth::Identity, html::routes::admin_page::{AdminPage, AdminPageProps}, service::{ServerData, render_error_status, who}};
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(admin_page)
+        .service(maintenance);
+}
+
+// Only admins get past this, everyone else gets the page to show instead
+async fn check(data: Arc<ServerData>, req: &HttpRequest) -> Result<Identity, HttpResponse> {
+    if !data.config.admin {
+        return Err(render_error_status(data, StatusCode::NOT_FOUND, "The admin pages are disabled.\nYou can enable them with the admin setting in the config file.".to_string()).await);
+    }
+    let identity = who(data.clone(), req).await?;
+    if !identity.admin() {
+        return Err(render_error_status(data, StatusCode::FORBIDDEN, "Only admins can see this page.".to_string()).await);
+    }
+    Ok(identity)
+}
+
+async fn render_admin(data: Arc<ServerData>, identity: Identity, report: Option<(String, Vec<String>)>) -> HttpResponse {
+    let renderer: ServerRenderer<_> = ServerRenderer::<AdminPage>::with_props(|| {
+        AdminPageProps {
+            data,
+            user: identity.user,
+            report,
+        }
+    });
+    let html = renderer.render().await;
+
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html)
+}
+
+/* #region Routes */
+
+#[route("/admin/", method = "GET")]
+async fn admin_page(data: web::Data<Arc<ServerData>>, req: HttpRequest) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    match check(arc.clone(), &req).await {
+        Ok(identity) => render_admin(arc, identity, None).await,
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct MaintenanceForm {
+    task: String,
+}
+
+#[route("/admin/", method = "POST")]
+async fn maintenance(data: web::Data<Arc<ServerData>>, form: web::Form<MaintenanceForm>, req: HttpRequest) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    let identity = match check(arc.clone(), &req).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let global = arc.global.clone();
+
+    let report = match form.task.as_str() {
+        "scrub" | "scrub-check" => {
+            let report = scrub::scrub(global, form.task == "scrub").await;
+            let details = report.errors.iter().cloned()
+                .chain(report.damaged.iter().map(|path| format!("Damaged: {}", path)))
+                .collect();
+            (format!("Scrub finished: {}", report.human_readable()), details)
+        },
+        "gc" => match gc::collect(global, false).await {
+            Ok(report) => {
+                let details = report.orphans.iter()
+                    .map(|(bucket, descriptor)| format!("{:<20} {}", bucket, String::from_utf8_lossy(descriptor)))
+                    .chain(report.errors.iter().map(|error| format!("Error: {}", error)))
+                    .collect();
+                (format!("Garbage collection finished: {}", report.human_readable()), details)
+            },
+            Err(e) => (format!("Garbage collection failed: {}", e), Vec::new()),
+        },
+        _ => return render_error_status(arc, StatusCode::BAD_REQUEST, "Unknown task".to_string()).await,
+    };
+    render_admin(arc, identity, Some(report)).await
+}
+
+/* #endregion */
@@ -0,0 +1,725 @@
+/*
+    A JSON API for scripts, next to the HTML interface. Paths are made of names, like /photos/cat.jpg, the same as in WebDAV.
+    Every error is answered with {"error": {"code": ..., "message": ...}} and a status code that fits it. The endpoints are described in /api/v1/openapi.json.
+ */
+
+use std::{cell::Cell, fmt::Display, sync::Arc};
+use actix_web::{web, route, HttpRequest, HttpResponse, ResponseError, http::{header, StatusCode}};
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{blocks::{block::Block, redundancy::Redundancy}, inodes::{inode::InodeType, metadata::Size, path::{copy_stored, create_directories, delete_inode, open, open_directory, split_path, OpenDirectory}, directory::Directory}, stored::Stored};
+use super::{auth::{Access, Identity, identify}, content::content_disposition, range, service::{ServerData, child}, share::{Share, ShareAccess, allowed, can_revoke}, upload::upload_stream};
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into() }
+    }
+
+    fn not_found(path: &[String]) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", format!("{} does not exist", display(path)))
+    }
+
+    fn conflict(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, code, message)
+    }
+
+    fn invalid(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+}
+
+// errors of the tree itself, like a bucket that can not be read
+impl From<String> for ApiError {
+    fn from(message: String) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        if self.status == StatusCode::UNAUTHORIZED {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(json!({ "error": { "code": self.code, "message": self.message } }))
+    }
+}
+
+type ApiResult = Result<HttpResponse, ApiError>;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Entry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,  // file or directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,  // files only, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<usize>,  // directories only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,  // files only
+    pub created: u64,
+    pub modified: u64,
+}
+
+impl Entry {
+    pub fn new(name: &str, inode: &InodeType) -> Self {
+        match inode {
+            InodeType::File(file) => Self {
+                name: name.to_string(),
+                kind: "file",
+                size: Some(match file.metadata.size {
+                    Size::Bytes(size) => size,
+                    _ => 0,
+                }),
+                entries: None,
+                mime: Some(file.metadata.content_type(name)),
+                created: file.metadata.created,
+                modified: file.metadata.modified,
+            },
+            InodeType::Directory(directory) => Self {
+                name: name.to_string(),
+                kind: "directory",
+                size: None,
+                entries: Some(directory.list().len()),
+                mime: None,
+                created: directory.metadata.created,
+                modified: directory.metadata.modified,
+            },
+        }
+    }
+}
+
+fn display(names: &[String]) -> String {
+    format!("/{}", names.join("/"))
+}
+
+fn names(path: &str) -> Result<Vec<String>, ApiError> {
+    split_path(path).map_err(|e| ApiError::invalid("invalid_path", e))
+}
+
+// Who is asking. Every endpoint resolves names from the root, so they are all hidden with it.
+fn check(data: &ServerData, req: &HttpRequest) -> Result<Identity, ApiError> {
+    if !data.config.see_root {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "forbidden", "The API needs see_root to be enabled"));
+    }
+    match identify(data, req) {
+        Ok(Some(identity)) => Ok(identity),
+        Ok(None) => Err(ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized", "Send an API token or log in")),
+        Err(e) => Err(ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized", e)),
+    }
+}
+
+// `access` is what the user has on the path, or on everything under it when the whole subtree is taken along
+fn allow(data: &ServerData, names: &[String], access: Access, need: Access) -> Result<(), ApiError> {
+    if need >= Access::Write && data.config.readonly {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "read_only", "The server is in read-only mode"));
+    }
+    if access < need {
+        let verb = if need >= Access::Write { "change" } else { "read" };
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "forbidden", format!("You can not {} {}", verb, display(names))));
+    }
+    Ok(())
+}
+
+fn visible(identity: &Identity, names: &[String]) -> Result<(), ApiError> {
+    match identity.visible(names) {
+        true => Ok(()),
+        false => Err(ApiError::new(StatusCode::FORBIDDEN, "forbidden", format!("You can not read {}", display(names)))),
+    }
+}
+
+// The inode at the path, the root included
+async fn find(data: &ServerData, names: &[String]) -> Result<InodeType, ApiError> {
+    if names.is_empty() {
+        return Ok(data.global.get_root()?.to_enum());
+    }
+    match open(data.global.clone(), names).await? {
+        Some((_, inode)) => Ok(inode),
+        None => Err(ApiError::not_found(names)),
+    }
+}
+
+async fn parent(data: &ServerData, names: &[String], create: bool) -> Result<OpenDirectory, ApiError> {
+    let parents = &names[..names.len() - 1];
+    if create {
+        return create_directories(data.global.clone(), parents).await.map_err(|e| ApiError::conflict("not_a_directory", e));
+    }
+    match open_directory(data.global.clone(), parents).await? {
+        Some(parent) => Ok(parent),
+        None => Err(ApiError::new(StatusCode::NOT_FOUND, "not_found", format!("The directory {} does not exist", display(parents)))),
+    }
+}
+
+fn query<T: DeserializeOwned>(req: &HttpRequest) -> Result<T, ApiError> {
+    web::Query::<T>::from_query(req.query_string()).map(|query| query.into_inner()).map_err(|e| ApiError::invalid("invalid_query", e.to_string()))
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(openapi)
+        .service(stat)
+        .service(list)
+        .service(download)
+        .service(upload)
+        .service(mkdir)
+        .service(delete)
+        .service(relocate)
+        .service(share_link)
+        .service(revoke_link);
+}
+
+/* #region Routes */
+
+#[route("/api/v1/openapi.json", method = "GET")]
+async fn openapi() -> HttpResponse {
+    HttpResponse::Ok().json(document())
+}
+
+#[route("/api/v1/stat/{path:.*}", method = "GET")]
+async fn stat(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let names = names(&path)?;
+    visible(&identity, &names)?;
+    let inode = find(&data, &names).await?;
+    Ok(HttpResponse::Ok().json(Entry::new(names.last().map_or("", |name| name.as_str()), &inode)))
+}
+
+#[route("/api/v1/list/{path:.*}", method = "GET")]
+async fn list(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let names = names(&path)?;
+    visible(&identity, &names)?;
+    let directory = match find(&data, &names).await? {
+        InodeType::Directory(directory) => directory,
+        InodeType::File(_) => return Err(ApiError::conflict("not_a_directory", format!("{} is a file", display(&names)))),
+    };
+    let mut children = directory.list_tuples().into_iter()
+        .filter(|(name, _)| identity.visible(&child(&names, name)))
+        .collect::<Vec<_>>();
+    children.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut entries = Vec::new();
+    for (name, stored) in children {
+        entries.push(Entry::new(&name, &stored.get::<InodeType>(data.global.clone()).await?));
+    }
+    Ok(HttpResponse::Ok().json(json!({ "path": display(&names), "entries": entries })))
+}
+
+// ?disposition=inline lets a browser show the file instead of saving it
+#[derive(Deserialize)]
+struct DownloadQuery {
+    disposition: Option<String>,
+}
+
+#[route("/api/v1/download/{path:.*}", method = "GET", method = "HEAD")]
+async fn download(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let query = query::<DownloadQuery>(&req)?;
+    let names = names(&path)?;
+    allow(&data, &names, identity.access(&names), Access::Read)?;
+    let file = match find(&data, &names).await? {
+        InodeType::File(file) => file,
+        InodeType::Directory(_) => return Err(ApiError::conflict("is_a_directory", format!("{} is a directory", display(&names)))),
+    };
+    let name = names.last().map_or("download", |name| name.as_str());
+    let inline = query.disposition.as_deref() == Some("inline");
+    let content_type = file.metadata.content_type(name);
+    let disposition = content_disposition(name, &content_type, inline);
+    Ok(range::respond(data.global.clone(), Arc::new(file), &req, content_type, disposition).await)
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    #[serde(default)]
+    overwrite: bool,
+    #[serde(default)]
+    parents: bool,
+    redundancy: Option<String>,
+}
+
+#[route("/api/v1/upload/{path:.*}", method = "PUT")]
+async fn upload(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest, payload: web::Payload) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let query = query::<UploadQuery>(&req)?;
+    let global = data.global.clone();
+    let names = names(&path)?;
+    allow(&data, &names, identity.lowest(&names), Access::Write)?;
+    let name = names.last().ok_or_else(|| ApiError::conflict("is_a_directory", "The root is a directory"))?;
+    let redundancy = match &query.redundancy {
+        Some(redundancy) => redundancy.parse::<Redundancy>().map_err(|e| ApiError::invalid("invalid_redundancy", e))?,
+        None => global.redundancy.clone(),
+    };
+    let max_size = data.config.max_upload_size;
+    let length = req.headers().get(header::CONTENT_LENGTH).and_then(|value| value.to_str().ok()).and_then(|value| value.parse::<usize>().ok());
+    if let (Some(length), Some(max_size)) = (length, max_size) {
+        if length > max_size {
+            return Err(too_large(max_size));
+        }
+    }
+
+    // checked before the upload so a refused one does not have to be sent first, and again afterwards
+    let exists = |parent: &OpenDirectory| match parent.directory.get(name) {
+        Ok(_) if !query.overwrite => Err(ApiError::conflict("already_exists", format!("{} already exists", display(&names)))),
+        _ => Ok(()),
+    };
+    let parent_directory = parent(&data, &names, query.parents).await?;
+    if let Some(InodeType::Directory(_)) = open(global.clone(), &names).await?.map(|(_, inode)| inode) {
+        return Err(ApiError::conflict("is_a_directory", format!("{} is a directory", display(&names))));
+    }
+    exists(&parent_directory)?;
+
+    let received = Cell::new(0usize);
+    let mut payload = payload.inspect(|chunk| if let Ok(chunk) = chunk { received.set(received.get() + chunk.len()) });
+    let file = match upload_stream(global.clone(), name, &mut payload, redundancy, max_size).await {
+        Ok(file) => file,
+        Err(_) if max_size.is_some_and(|max_size| received.get() > max_size) => return Err(too_large(max_size.unwrap_or(0))),
+        Err(e) => return Err(ApiError::from(e)),
+    };
+
+    let file_data = file.data.clone();
+    let result = async {
+        let _tree = data.global.lock_tree().await;
+        let mut parent = parent(&data, &names, false).await?;
+        let replaced = match parent.directory.get(name) {
+            Ok(stored) => match stored.get::<InodeType>(global.clone()).await? {
+                InodeType::Directory(_) => return Err(ApiError::conflict("is_a_directory", format!("{} is a directory", display(&names)))),
+                InodeType::File(_) => {
+                    exists(&parent)?;
+                    parent.directory.unlink(name).ok()
+                },
+            },
+            Err(_) => None,
+        };
+        let inode = file.to_enum();
+        let entry = Entry::new(name, &inode);
+        parent.directory.add(global.clone(), name, inode).await?;
+        parent.save(global.clone()).await?;
+        Ok::<_, ApiError>((replaced, entry))
+    }.await;
+    let (replaced, entry) = match result {
+        Ok(result) => result,
+        Err(e) => return match file_data.delete(global).await {
+            Ok(_) => Err(e),
+            Err(err) => Err(ApiError::from(format!("{}, {}", e.message, err))),
+        },
+    };
+
+    match replaced {
+        Some(stored) => {
+            delete_inode(global, stored).await?;
+            Ok(HttpResponse::Ok().json(entry))
+        },
+        None => Ok(HttpResponse::Created().json(entry)),
+    }
+}
+
+fn too_large(max_size: usize) -> ApiError {
+    ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "too_large", format!("The upload is larger than the limit of {} bytes", max_size))
+}
+
+#[derive(Deserialize)]
+struct MkdirQuery {
+    #[serde(default)]
+    parents: bool,
+}
+
+#[route("/api/v1/mkdir/{path:.*}", method = "POST")]
+async fn mkdir(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let query = query::<MkdirQuery>(&req)?;
+    let global = data.global.clone();
+    let names = names(&path)?;
+    allow(&data, &names, identity.lowest(&names), Access::Write)?;
+    let _tree = data.global.lock_tree().await;
+    // like mkdir -p, an existing directory is fine with parents
+    match (find(&data, &names).await, query.parents) {
+        (Ok(inode @ InodeType::Directory(_)), true) => return Ok(HttpResponse::Ok().json(Entry::new(names.last().map_or("", |name| name.as_str()), &inode))),
+        (Ok(_), _) => return Err(ApiError::conflict("already_exists", format!("{} already exists", display(&names)))),
+        (Err(e), _) if e.status != StatusCode::NOT_FOUND => return Err(e),
+        (Err(_), _) => (),
+    }
+    let name = &names[names.len() - 1];  // the root always exists
+    let mut parent = parent(&data, &names, query.parents).await?;
+    let directory = Directory::new().to_enum();
+    let entry = Entry::new(name, &directory);
+    parent.directory.add(global.clone(), name, directory).await?;
+    parent.save(global).await?;
+    Ok(HttpResponse::Created().json(entry))
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    recursive: bool,
+}
+
+#[route("/api/v1/delete/{path:.*}", method = "DELETE")]
+async fn delete(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let query = query::<DeleteQuery>(&req)?;
+    let global = data.global.clone();
+    let names = names(&path)?;
+    allow(&data, &names, identity.lowest(&names), Access::Write)?;
+    let name = names.last().ok_or_else(|| ApiError::new(StatusCode::FORBIDDEN, "forbidden", "The root can not be deleted"))?;
+    let _tree = data.global.lock_tree().await;
+    let mut parent = parent(&data, &names, false).await?;
+    let stored = parent.directory.get(name).map_err(|_| ApiError::not_found(&names))?.clone();
+    if let InodeType::Directory(directory) = stored.get::<InodeType>(global.clone()).await? {
+        if !query.recursive && !directory.list().is_empty() {
+            return Err(ApiError::conflict("not_empty", format!("{} is not empty, delete it with recursive=true", display(&names))));
+        }
+    }
+    parent.directory.unlink(name)?;
+    parent.save(global.clone()).await?;
+    delete_inode(global, stored).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+struct RelocateRequest {
+    from: String,
+    to: String,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+// Moves only relink the inode, copies upload the data again
+#[route("/api/v1/{operation:move|copy}", method = "POST")]
+async fn relocate(data: web::Data<Arc<ServerData>>, operation: web::Path<String>, body: web::Bytes, req: HttpRequest) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let global = data.global.clone();
+    let copy = operation.as_str() == "copy";
+    let request = serde_json::from_slice::<RelocateRequest>(&body).map_err(|e| ApiError::invalid("invalid_body", format!("The body is not a valid request: {}", e)))?;
+    let (names, target) = (names(&request.from)?, names(&request.to)?);
+    allow(&data, &names, identity.lowest(&names), if copy { Access::Read } else { Access::Write })?;
+    allow(&data, &target, identity.lowest(&target), Access::Write)?;
+    let (name, target_name) = match (names.last(), target.last()) {
+        (Some(name), Some(target_name)) => (name, target_name),
+        _ => return Err(ApiError::new(StatusCode::FORBIDDEN, "forbidden", "The root can not be moved, copied or replaced")),
+    };
+    if target == names {
+        return Err(ApiError::invalid("invalid_path", "The source and the destination are the same"));
+    }
+    if target.starts_with(&names) {
+        return Err(ApiError::invalid("invalid_path", "A directory can not be put inside itself"));
+    }
+
+    // copying downloads and uploads everything again, so it is done before locking the tree and only linked under the lock
+    let (parents, target_parents) = (&names[..names.len() - 1], &target[..target.len() - 1]);
+    let copied = match copy {
+        true => {
+            let (_, inode) = open(global.clone(), &names).await?.ok_or_else(|| ApiError::not_found(&names))?;
+            Some((copy_stored(global.clone(), &inode, true).await?, Entry::new(target_name, &inode)))
+        },
+        false => None,
+    };
+
+    let tree = data.global.lock_tree().await;
+    let linked = async {
+        let mut target_parent = parent(&data, &target, false).await?;
+        let replaced = match target_parent.directory.get(target_name) {
+            Ok(_) if !request.overwrite => return Err(ApiError::conflict("already_exists", format!("{} already exists", display(&target)))),
+            Ok(replaced) => match replaced.get::<InodeType>(global.clone()).await? {
+                InodeType::Directory(_) => return Err(ApiError::conflict("is_a_directory", format!("{} is a directory and can not be replaced", display(&target)))),
+                InodeType::File(_) => Some(replaced.clone()),
+            },
+            Err(_) => None,
+        };
+        if let Some((copied, entry)) = &copied {
+            target_parent.directory.unlink(target_name).ok();
+            target_parent.directory.put(target_name, copied.clone())?;
+            target_parent.save(global.clone()).await?;
+            return Ok((replaced, entry.clone()));
+        }
+
+        let (stored, inode) = open(global.clone(), &names).await?.ok_or_else(|| ApiError::not_found(&names))?;
+        let entry = Entry::new(target_name, &inode);
+        if parents == target_parents {
+            target_parent.directory.unlink(target_name).ok();
+            target_parent.directory.unlink(name)?;
+            target_parent.directory.put(target_name, stored)?;
+            target_parent.save(global.clone()).await?;
+            return Ok((replaced, entry));
+        }
+
+        // the source is unlinked first, so the inode is never in two directories, where deleting one copy would break the other
+        let mut source = parent(&data, &names, false).await?;
+        source.directory.unlink(name)?;
+        source.save(global.clone()).await?;
+        target_parent.directory.unlink(target_name).ok();
+        target_parent.directory.put(target_name, stored.clone())?;
+        if let Err(e) = target_parent.save(global.clone()).await {
+            // put back where it was, nothing else can have changed the directory while the tree is locked
+            let restored = async {
+                let mut source = parent(&data, &names, false).await?;
+                source.directory.put(name, stored)?;
+                source.save(global.clone()).await?;
+                Ok::<(), ApiError>(())
+            }.await;
+            return Err(match restored {
+                Ok(_) => e.into(),
+                Err(err) => format!("{}, {}", e, err.message).into(),
+            });
+        }
+        Ok::<(Option<Stored>, Entry), ApiError>((replaced, entry))
+    }.await;
+    drop(tree);
+
+    // a copy that did not end up in the destination belongs to nobody
+    let (replaced, entry) = match linked {
+        Ok(linked) => linked,
+        Err(e) => return Err(match copied {
+            Some((copied, _)) => match delete_inode(global, copied).await {
+                Ok(_) => e,
+                Err(err) => format!("{}, {}", e.message, err).into(),
+            },
+            None => e,
+        }),
+    };
+
+    match replaced {
+        Some(replaced) => {
+            delete_inode(global, replaced).await?;
+            Ok(HttpResponse::Ok().json(entry))
+        },
+        None => Ok(HttpResponse::Created().json(entry)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ShareQuery {
+    access: Option<String>,  // download by default
+    expires_in: Option<u64>,  // in seconds, a week by default
+}
+
+#[route("/api/v1/share/{path:.*}", method = "POST")]
+async fn share_link(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let query = query::<ShareQuery>(&req)?;
+    let names = names(&path)?;
+    let access = query.access.as_deref().unwrap_or("download").parse::<ShareAccess>().map_err(|e| ApiError::invalid("invalid_query", e))?;
+    let expires_in = query.expires_in.unwrap_or(7 * 24 * 60 * 60);
+    if expires_in == 0 {
+        return Err(ApiError::invalid("invalid_query", "expires_in has to be more than 0"));
+    }
+    let name = names.last().ok_or_else(|| ApiError::new(StatusCode::FORBIDDEN, "forbidden", "The root can not be shared"))?;
+    visible(&identity, &names)?;
+
+    let (stored, inode) = open(data.global.clone(), &names).await?.ok_or_else(|| ApiError::not_found(&names))?;
+    allowed(&data, &identity, &names, access, matches!(inode, InodeType::Directory(_)))
+        .map_err(|e| ApiError::new(StatusCode::FORBIDDEN, "forbidden", e))?;
+
+    let share = Share::new(&stored, name, access, expires_in, identity.user.clone());
+    Ok(HttpResponse::Created().json(json!({
+        "token": share.token(&data.secret),
+        "url": share.url(&data),
+        "access": access,
+        "expires": share.expires,
+    })))
+}
+
+#[derive(Deserialize)]
+struct RevokeRequest {
+    token: String,
+}
+
+#[route("/api/v1/revoke", method = "POST")]
+async fn revoke_link(data: web::Data<Arc<ServerData>>, body: web::Bytes, req: HttpRequest) -> ApiResult {
+    let identity = check(&data, &req)?;
+    let request = serde_json::from_slice::<RevokeRequest>(&body).map_err(|e| ApiError::invalid("invalid_body", format!("The body is not a valid request: {}", e)))?;
+    let share = Share::parse(&data.secret, &request.token).map_err(|e| ApiError::invalid("invalid_token", e))?;
+    if !can_revoke(&identity, &share) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "forbidden", "Only admins and the user who made a link can revoke it"));
+    }
+    data.shares.revoke(&share)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/* #endregion */
+
+fn path_parameter() -> serde_json::Value {
+    json!({ "name": "path", "in": "path", "required": true, "description": "Names separated by slashes, empty for the root", "schema": { "type": "string" } })
+}
+
+fn flag(name: &str, description: &str) -> serde_json::Value {
+    json!({ "name": name, "in": "query", "required": false, "description": description, "schema": { "type": "boolean", "default": false } })
+}
+
+fn responses(ok: &[(&str, &str, Option<&str>)], errors: &[&str]) -> serde_json::Value {
+    let mut responses = serde_json::Map::new();
+    for (status, description, schema) in ok {
+        let mut response = json!({ "description": description });
+        if let Some(schema) = schema {
+            response["content"] = json!({ "application/json": { "schema": { "$ref": format!("#/components/schemas/{}", schema) } } });
+        }
+        responses.insert(status.to_string(), response);
+    }
+    for status in errors {
+        responses.insert(status.to_string(), json!({ "$ref": "#/components/responses/Error" }));
+    }
+    serde_json::Value::Object(responses)
+}
+
+// The OpenAPI 3.0 description of the endpoints
+pub fn document() -> serde_json::Value {
+    let relocation = |summary: &str| json!({
+        "summary": summary,
+        "requestBody": { "required": true, "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Relocate" } } } },
+        "responses": responses(&[("200", "The destination was replaced", Some("Entry")), ("201", "The destination was created", Some("Entry"))], &["400", "401", "403", "404", "409", "500"]),
+    });
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "chunkdrive", "version": env!("CARGO_PKG_VERSION") },
+        "security": [{ "token": [] }, { "session": [] }],
+        "paths": {
+            "/api/v1/stat/{path}": { "get": {
+                "summary": "Metadata of a file or a directory",
+                "parameters": [path_parameter()],
+                "responses": responses(&[("200", "The entry", Some("Entry"))], &["400", "401", "403", "404", "500"]),
+            } },
+            "/api/v1/list/{path}": { "get": {
+                "summary": "The entries of a directory, sorted by name",
+                "parameters": [path_parameter()],
+                "responses": responses(&[("200", "The directory", Some("Listing"))], &["400", "401", "403", "404", "409", "500"]),
+            } },
+            "/api/v1/download/{path}": { "get": {
+                "summary": "The content of a file, Range requests are supported",
+                "parameters": [path_parameter(), { "name": "disposition", "in": "query", "required": false, "schema": { "type": "string", "enum": ["attachment", "inline"], "default": "attachment" } }],
+                "responses": {
+                    "200": { "description": "The file", "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } } },
+                    "206": { "description": "The requested ranges" },
+                    "400": { "$ref": "#/components/responses/Error" },
+                    "401": { "$ref": "#/components/responses/Error" },
+                    "403": { "$ref": "#/components/responses/Error" },
+                    "404": { "$ref": "#/components/responses/Error" },
+                    "409": { "$ref": "#/components/responses/Error" },
+                    "416": { "description": "The ranges are outside of the file" },
+                    "500": { "$ref": "#/components/responses/Error" },
+                },
+            } },
+            "/api/v1/upload/{path}": { "put": {
+                "summary": "Uploads the body as a file",
+                "parameters": [
+                    path_parameter(),
+                    flag("overwrite", "Replace the file if it exists"),
+                    flag("parents", "Create the missing directories on the way"),
+                    { "name": "redundancy", "in": "query", "required": false, "description": "none, replicate[:copies] or erasure[:data:parity], the configured one by default", "schema": { "type": "string" } },
+                ],
+                "requestBody": { "required": true, "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } } },
+                "responses": responses(&[("200", "The file was replaced", Some("Entry")), ("201", "The file was created", Some("Entry"))], &["400", "401", "403", "404", "409", "413", "500"]),
+            } },
+            "/api/v1/mkdir/{path}": { "post": {
+                "summary": "Creates a directory",
+                "parameters": [path_parameter(), flag("parents", "Create the missing directories on the way, an existing directory is not an error")],
+                "responses": responses(&[("200", "The directory already existed", Some("Entry")), ("201", "The directory was created", Some("Entry"))], &["400", "401", "403", "404", "409", "500"]),
+            } },
+            "/api/v1/delete/{path}": { "delete": {
+                "summary": "Deletes a file or a directory with its data",
+                "parameters": [path_parameter(), flag("recursive", "Delete directories that are not empty")],
+                "responses": responses(&[("204", "Deleted", None)], &["400", "401", "403", "404", "409", "500"]),
+            } },
+            "/api/v1/move": { "post": relocation("Moves a file or a directory, the data is not uploaded again") },
+            "/api/v1/copy": { "post": relocation("Copies a file or a directory with everything in it") },
+            "/api/v1/share/{path}": { "post": {
+                "summary": "Makes a signed link to a file or a directory that works without an account until it expires",
+                "parameters": [
+                    path_parameter(),
+                    { "name": "access", "in": "query", "required": false, "description": "What the link allows, upload only works for directories", "schema": { "type": "string", "enum": ["view", "download", "upload"], "default": "download" } },
+                    { "name": "expires_in", "in": "query", "required": false, "description": "Seconds until the link expires", "schema": { "type": "integer", "default": 604800 } },
+                ],
+                "responses": responses(&[("201", "The link was made", Some("Share"))], &["400", "401", "403", "404", "500"]),
+            } },
+            "/api/v1/revoke": { "post": {
+                "summary": "Revokes a share link, only admins and the user who made it can",
+                "requestBody": { "required": true, "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Revoke" } } } },
+                "responses": responses(&[("204", "Revoked", None)], &["400", "401", "403", "500"]),
+            } },
+        },
+        "components": {
+            "schemas": {
+                "Entry": {
+                    "type": "object",
+                    "required": ["name", "type", "created", "modified"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "type": { "type": "string", "enum": ["file", "directory"] },
+                        "size": { "type": "integer", "description": "Files only, in bytes" },
+                        "entries": { "type": "integer", "description": "Directories only" },
+                        "mime": { "type": "string", "description": "Files only" },
+                        "created": { "type": "integer", "description": "Unix time" },
+                        "modified": { "type": "integer", "description": "Unix time" },
+                    },
+                },
+                "Listing": {
+                    "type": "object",
+                    "required": ["path", "entries"],
+                    "properties": {
+                        "path": { "type": "string" },
+                        "entries": { "type": "array", "items": { "$ref": "#/components/schemas/Entry" } },
+                    },
+                },
+                "Relocate": {
+                    "type": "object",
+                    "required": ["from", "to"],
+                    "properties": {
+                        "from": { "type": "string" },
+                        "to": { "type": "string" },
+                        "overwrite": { "type": "boolean", "default": false, "description": "Replace a file at the destination, directories are never replaced" },
+                    },
+                },
+                "Share": {
+                    "type": "object",
+                    "required": ["token", "url", "access", "expires"],
+                    "properties": {
+                        "token": { "type": "string" },
+                        "url": { "type": "string", "description": "Relative to the server" },
+                        "access": { "type": "string", "enum": ["view", "download", "upload"] },
+                        "expires": { "type": "integer", "description": "Unix time" },
+                    },
+                },
+                "Revoke": {
+                    "type": "object",
+                    "required": ["token"],
+                    "properties": { "token": { "type": "string" } },
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["error"],
+                    "properties": { "error": {
+                        "type": "object",
+                        "required": ["code", "message"],
+                        "properties": {
+                            "code": { "type": "string", "enum": ["invalid_path", "invalid_query", "invalid_body", "invalid_redundancy", "invalid_token", "unauthorized", "forbidden", "read_only", "not_found", "already_exists", "not_a_directory", "is_a_directory", "not_empty", "too_large", "internal_error"] },
+                            "message": { "type": "string" },
+                        },
+                    } },
+                },
+            },
+            "securitySchemes": {
+                "token": { "type": "http", "scheme": "bearer", "description": "An API token of a user, only needed when users are configured" },
+                "session": { "type": "apiKey", "in": "cookie", "name": "session", "description": "The cookie set by logging in at /login" },
+            },
+            "responses": {
+                "Error": { "description": "The request failed", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } } },
+            },
+        },
+    })
+}
@@ -0,0 +1,362 @@
+/*
+    Users, what they may do and how a request proves who sent it.
+    Browsers log in with a password and get a session cookie signed with the secret, scripts send one of their API tokens as a bearer token.
+    Permissions are given to subtrees by path and the most specific one wins, so a user can write everywhere except in one directory.
+ */
+
+use std::{collections::HashMap, sync::Arc, time::{SystemTime, UNIX_EPOCH}};
+use actix_web::{web, route, cookie::{Cookie, SameSite, time::Duration}, http::header, HttpRequest, HttpResponse};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::Deserialize;
+use yew::ServerRenderer;
+
+use crate::{inodes::path::split_path, services::crypto::{constant_eq, hex, hmac, sha256_hex}};
+use super::{html::routes::login_page::{LoginPage, LoginPageProps}, service::ServerData};
+
+pub const SESSION_COOKIE: &str = "session";
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "read")]
+    Read,
+    #[serde(rename = "write")]
+    Write,
+    #[serde(rename = "admin")]
+    Admin,  // write, and on / also the bucket and maintenance pages
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct User {
+    #[serde(default)]
+    password: Option<String>,  // an argon2 hash from --hash-password, users without one can only use tokens
+    #[serde(default)]
+    tokens: Vec<String>,  // the sha256 of the tokens from --new-token
+    #[serde(default)]
+    permissions: HashMap<String, Access>,  // path -> access, nothing is allowed by default
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuthConfig {
+    #[serde(default = "default_session_expiration")]
+    session_expiration: u64,  // seconds
+    users: HashMap<String, User>,
+}
+
+const fn default_session_expiration() -> u64 { 7 * 24 * 60 * 60 }
+
+// What a request is allowed to do
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub user: Option<String>,  // None when authentication is disabled
+    rules: Vec<(Vec<String>, Access)>,
+}
+
+impl Identity {
+    // everyone is an admin when authentication is disabled, the readonly and see_root settings still apply
+    pub fn anonymous() -> Self {
+        Self { user: None, rules: vec![(Vec::new(), Access::Admin)] }
+    }
+
+    fn new(name: &str, user: &User) -> Result<Self, String> {
+        let rules = user.permissions.iter()
+            .map(|(path, access)| Ok((split_path(path).map_err(|e| format!("Invalid permission {} of {}: {}", path, name, e))?, *access)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { user: Some(name.to_string()), rules })
+    }
+
+    // the rule of the deepest path containing `names`
+    pub fn access(&self, names: &[String]) -> Access {
+        self.rules.iter()
+            .filter(|(path, _)| names.starts_with(path))
+            .max_by_key(|(path, _)| path.len())
+            .map_or(Access::None, |(_, access)| *access)
+    }
+
+    // the least access to anything under `names`, for operations that take the whole subtree with them
+    pub fn lowest(&self, names: &[String]) -> Access {
+        self.rules.iter()
+            .filter(|(path, _)| path.len() > names.len() && path.starts_with(names))
+            .map(|(_, access)| *access)
+            .fold(self.access(names), std::cmp::min)
+    }
+
+    // directories above the ones a user may read are shown, so they can be reached from the root
+    pub fn visible(&self, names: &[String]) -> bool {
+        self.access(names) >= Access::Read || self.rules.iter().any(|(path, access)| *access >= Access::Read && path.starts_with(names))
+    }
+
+    // the same access everywhere, so the names of a path do not matter
+    pub fn uniform(&self) -> bool {
+        self.rules.iter().all(|(path, _)| path.is_empty())
+    }
+
+    pub fn admin(&self) -> bool {
+        self.access(&[]) == Access::Admin
+    }
+}
+
+#[derive(Debug)]
+pub struct Auth {
+    users: HashMap<String, (User, Identity)>,
+    expiration: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+// a random one is made when none is configured, so nothing signed survives a restart
+pub fn secret(secret: Option<&String>) -> Vec<u8> {
+    match secret {
+        Some(secret) => secret.as_bytes().to_vec(),
+        None => rand::random::<[u8; 32]>().to_vec(),
+    }
+}
+
+// Signs a value, so it can be given to the client and trusted when it comes back
+pub fn sign(secret: &[u8], value: &str) -> String {
+    format!("{}.{}", value, hex(&hmac(secret, value.as_bytes())))
+}
+
+pub fn verify<'a>(secret: &[u8], signed: &'a str) -> Option<&'a str> {
+    let (value, signature) = signed.rsplit_once('.')?;
+    constant_eq(&hex(&hmac(secret, value.as_bytes())), signature).then_some(value)
+}
+
+impl Auth {
+    pub fn new(config: &AuthConfig) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        for (name, user) in config.users.iter() {
+            if let Some(password) = &user.password {
+                PasswordHash::new(password).map_err(|e| format!("Invalid password hash of {}: {}", name, e))?;
+            }
+            users.insert(name.clone(), (user.clone(), Identity::new(name, user)?));
+        }
+        Ok(Self { users, expiration: config.session_expiration })
+    }
+
+    pub fn expiration(&self) -> u64 {
+        self.expiration
+    }
+
+    // Checks the password and makes a session for the user
+    pub fn login(&self, secret: &[u8], name: &str, password: &str) -> Option<String> {
+        let (user, _) = self.users.get(name)?;
+        let hash = PasswordHash::new(user.password.as_ref()?).ok()?;
+        Argon2::default().verify_password(password.as_bytes(), &hash).ok()?;
+        Some(self.session(secret, name, user, now() + self.expiration))
+    }
+
+    // the password hash is signed too, so changing the password ends the sessions made with the old one
+    fn session(&self, secret: &[u8], name: &str, user: &User, expires: u64) -> String {
+        let value = format!("{}:{}", URL_SAFE_NO_PAD.encode(name), expires);
+        let password = user.password.as_deref().unwrap_or("");
+        format!("{}.{}", value, hex(&hmac(secret, format!("{}:{}", value, password).as_bytes())))
+    }
+
+    pub fn check_session(&self, secret: &[u8], cookie: &str) -> Option<Identity> {
+        let (value, _) = cookie.rsplit_once('.')?;
+        let (name, expires) = value.split_once(':')?;
+        let name = String::from_utf8(URL_SAFE_NO_PAD.decode(name).ok()?).ok()?;
+        let expires = expires.parse::<u64>().ok().filter(|expires| *expires > now())?;
+        let (user, identity) = self.users.get(&name)?;
+        constant_eq(&self.session(secret, &name, user, expires), cookie).then(|| identity.clone())
+    }
+
+    pub fn check_token(&self, token: &str) -> Option<Identity> {
+        let hash = sha256_hex(token.as_bytes());
+        self.users.values()
+            .find(|(user, _)| user.tokens.iter().any(|token| constant_eq(&token.to_lowercase(), &hash)))
+            .map(|(_, identity)| identity.clone())
+    }
+}
+
+// Who sent the request, None if nobody is logged in. A token that is not valid is an error instead of being ignored.
+pub fn identify(data: &ServerData, req: &HttpRequest) -> Result<Option<Identity>, String> {
+    let auth = match &data.auth {
+        Some(auth) => auth,
+        None => return Ok(Some(Identity::anonymous())),
+    };
+    if let Some(authorization) = req.headers().get(header::AUTHORIZATION) {
+        let token = authorization.to_str().ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or("Only bearer tokens are accepted")?;
+        return auth.check_token(token.trim()).map(Some).ok_or_else(|| "The token is not valid".to_string());
+    }
+    Ok(req.cookie(SESSION_COOKIE).and_then(|cookie| auth.check_session(&data.secret, cookie.value())))
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt).map(|hash| hash.to_string()).map_err(|e| e.to_string())
+}
+
+// A new API token and the hash that goes into the config
+pub fn new_token() -> (String, String) {
+    let token = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    let hash = sha256_hex(token.as_bytes());
+    (token, hash)
+}
+
+// Only paths on this server are followed after logging in
+pub fn redirect_target(data: &ServerData, next: Option<&str>) -> String {
+    match next {
+        Some(next) if next.starts_with('/') && !next.starts_with("//") && !next.contains('\\') => next.to_string(),
+        _ => format!("{}files/", data.config.path),
+    }
+}
+
+// Sends a browser to the login page, it comes back to the page it wanted afterwards
+pub fn login_redirect(data: &ServerData, req: &HttpRequest) -> HttpResponse {
+    let next = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    HttpResponse::Found()
+        .append_header((header::LOCATION, format!("{}login?next={}", data.config.path, urlencoding::encode(next))))
+        .finish()
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(login_page)
+        .service(login)
+        .service(logout);
+}
+
+async fn render_login(data: Arc<ServerData>, next: String, error: Option<String>) -> HttpResponse {
+    let status = match error {
+        Some(_) => actix_web::http::StatusCode::UNAUTHORIZED,
+        None => actix_web::http::StatusCode::OK,
+    };
+    let renderer: ServerRenderer<_> = ServerRenderer::<LoginPage>::with_props(|| {
+        LoginPageProps {
+            data,
+            next,
+            error,
+        }
+    });
+    let html = renderer.render().await;
+
+    HttpResponse::build(status)
+        .content_type("text/html")
+        .body(html)
+}
+
+/* #region Routes */
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
+#[route("/login", method = "GET")]
+async fn login_page(data: web::Data<Arc<ServerData>>, query: web::Query<LoginQuery>) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    let next = redirect_target(&arc, query.next.as_deref());
+    if arc.auth.is_none() {
+        return HttpResponse::Found().append_header((header::LOCATION, next)).finish();
+    }
+    render_login(arc, next, None).await
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+    next: Option<String>,
+}
+
+#[route("/login", method = "POST")]
+async fn login(data: web::Data<Arc<ServerData>>, form: web::Form<LoginForm>) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    let next = redirect_target(&arc, form.next.as_deref());
+    let form = form.into_inner();
+    let verifier = arc.clone();
+    // argon2 is slow on purpose, so it is kept off the threads answering requests
+    let session = web::block(move || match &verifier.auth {
+        Some(auth) => auth.login(&verifier.secret, &form.username, &form.password),
+        None => None,
+    }).await.ok().flatten();
+    let (session, expiration) = match (session, &arc.auth) {
+        (Some(session), Some(auth)) => (session, auth.expiration()),
+        _ => return render_login(arc, next, Some("Wrong username or password.".to_string())).await,
+    };
+
+    let cookie = Cookie::build(SESSION_COOKIE, session)
+        .path(arc.config.path.clone())
+        .http_only(true)
+        .secure(arc.config.tls_cert.is_some())
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(expiration as i64))
+        .finish();
+    HttpResponse::Found()
+        .cookie(cookie)
+        .append_header((header::LOCATION, next))
+        .finish()
+}
+
+#[route("/logout", method = "POST")]
+async fn logout(data: web::Data<Arc<ServerData>>) -> HttpResponse {
+    let mut cookie = Cookie::build(SESSION_COOKIE, "")
+        .path(data.config.path.clone())
+        .finish();
+    cookie.make_removal();
+    HttpResponse::Found()
+        .cookie(cookie)
+        .append_header((header::LOCATION, format!("{}login", data.config.path)))
+        .finish()
+}
+
+/* #endregion */
+
+#[cfg(test)]
+mod auth_tests {
+    use super::*;
+
+    fn names(path: &str) -> Vec<String> {
+        split_path(path).unwrap()
+    }
+
+    #[test]
+    fn deepest_rule_wins() {
+        let user = serde_yaml::from_str::<User>("permissions:\n  /: read\n  /shared: write\n  /shared/archive: read\n  /private: none\n").unwrap();
+        let identity = Identity::new("alice", &user).unwrap();
+        assert_eq!(identity.access(&names("/")), Access::Read);
+        assert_eq!(identity.access(&names("/shared/docs/a.txt")), Access::Write);
+        assert_eq!(identity.access(&names("/shared/archive/2023")), Access::Read);
+        assert_eq!(identity.access(&names("/private/x")), Access::None);
+        assert_eq!(identity.lowest(&names("/shared")), Access::Read);
+        assert_eq!(identity.lowest(&names("/shared/docs")), Access::Write);
+        assert!(!identity.visible(&names("/private")));
+        assert!(!identity.uniform() && !identity.admin());
+
+        let user = serde_yaml::from_str::<User>("permissions:\n  /photos/2023: read\n").unwrap();
+        let identity = Identity::new("bob", &user).unwrap();
+        assert!(identity.visible(&names("/")) && identity.visible(&names("/photos")));
+        assert!(!identity.visible(&names("/docs")));
+        assert_eq!(identity.access(&names("/photos")), Access::None);
+    }
+
+    #[test]
+    fn sessions_and_tokens() {
+        let (token, hash) = new_token();
+        let config = format!("users:\n  alice:\n    password: \"{}\"\n    tokens: [\"{}\"]\n    permissions:\n      /: admin\n", hash_password("hunter2").unwrap(), hash);
+        let auth = Auth::new(&serde_yaml::from_str::<AuthConfig>(&config).unwrap()).unwrap();
+        let secret = b"secret";
+
+        assert!(auth.login(secret, "alice", "wrong").is_none());
+        assert!(auth.login(secret, "mallory", "hunter2").is_none());
+        let session = auth.login(secret, "alice", "hunter2").unwrap();
+        assert!(auth.check_session(secret, &session).is_some_and(|identity| identity.admin()));
+        assert!(auth.check_session(b"other", &session).is_none());
+        let expired = auth.session(secret, "alice", &auth.users["alice"].0, now() - 1);
+        assert!(auth.check_session(secret, &expired).is_none());
+        let forged = session.replacen(&URL_SAFE_NO_PAD.encode("alice"), &URL_SAFE_NO_PAD.encode("bob"), 1);
+        assert!(auth.check_session(secret, &forged).is_none());
+
+        assert_eq!(auth.check_token(&token).and_then(|identity| identity.user), Some("alice".to_string()));
+        assert!(auth.check_token("not a token").is_none());
+        assert_eq!(verify(secret, &sign(secret, "a$b")), Some("a$b"));
+        assert_eq!(verify(secret, "a$b.00"), None);
+    }
+}
@@ -0,0 +1,58 @@
+/*
+    Headers that tell the browser what a downloaded file is and what it is called.
+ */
+
+use actix_web::http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue};
+
+// Types the browser shows without running anything from the file. Html, svg, xml and the like could run scripts on our origin, so they are always saved
+pub fn safe_inline(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    match essence.split_once('/') {
+        Some(("image", subtype)) => !subtype.contains("svg") && !subtype.contains("xml"),
+        Some(("audio" | "video", _)) => true,
+        _ => matches!(essence.as_str(), "text/plain" | "application/pdf"),
+    }
+}
+
+// Inline files are shown by the browser if it can and the type is safe to show, attachments are always saved
+pub fn content_disposition(name: &str, content_type: &str, inline: bool) -> ContentDisposition {
+    let inline = inline && safe_inline(content_type);
+    // old clients only understand the plain ascii filename, the others use the utf-8 one
+    let ascii = name.chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect::<String>();
+    ContentDisposition {
+        disposition: if inline { DispositionType::Inline } else { DispositionType::Attachment },
+        parameters: vec![
+            DispositionParam::Filename(ascii),
+            DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_string()),
+                language_tag: None,
+                value: name.as_bytes().to_vec(),
+            }),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod content_tests {
+    use super::*;
+
+    #[test]
+    fn disposition_header() {
+        assert_eq!(content_disposition("movie.mp4", "video/mp4", true).to_string(), "inline; filename=\"movie.mp4\"; filename*=UTF-8''movie.mp4");
+        let header = content_disposition("zażółć \"x\".txt", "text/plain", false).to_string();
+        assert!(header.starts_with("attachment; filename=\"za____ _x_.txt\"; filename*=UTF-8''za"));
+        assert!(header.contains("%C5%BC"));
+    }
+
+    #[test]
+    fn active_types_are_attachments() {
+        for content_type in ["text/html", "text/html; charset=utf-8", "image/svg+xml", "application/xhtml+xml", "text/xml", "application/javascript", "text/javascript", "application/octet-stream"] {
+            assert!(content_disposition("page", content_type, true).to_string().starts_with("attachment"), "{}", content_type);
+        }
+        for content_type in ["image/png", "video/webm", "audio/mpeg", "Text/Plain; charset=utf-8", "application/pdf"] {
+            assert!(content_disposition("file", content_type, true).to_string().starts_with("inline"), "{}", content_type);
+        }
+    }
+}
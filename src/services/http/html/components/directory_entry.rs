@@ -10,29 +10,32 @@ pub struct DirectoryEntryProps {
     pub path: Vec<String>,
     pub data: Arc<ServerData>,
     pub name: String,
-    pub inode: Stored
+    pub inode: Stored,
+    pub writable: bool
 }
 
 impl PartialEq for DirectoryEntryProps {
     fn eq(&self, other: &Self) -> bool {
         self.path == other.path &&
         self.name == other.name &&
-        self.inode == other.inode
+        self.inode == other.inode &&
+        self.writable == other.writable
     }
 }
 
 #[function_component]
 pub fn DirectoryEntry(props: &DirectoryEntryProps) -> Html {
     let url = format!("/files/{}/{}${}", props.path.join("/"), props.inode.as_url(), props.name.replace('$', "%24"));
+    let share = format!("{}shares/create/{}/{}${}", props.data.config.path, props.path.join("/"), props.inode.as_url(), props.name.replace('$', "%24"));
 
     html! {
         <li class="entry inode">
             <a href={ url.clone() }>{ &props.name }</a>
-            if !props.data.config.readonly {
-                <div class="edit">
-                    <button class="hamburger">{"☰"}</button>
-                    <nav class="menu">
-                        <ul>
+            <div class="edit">
+                <button class="hamburger">{"☰"}</button>
+                <nav class="menu">
+                    <ul>
+                        if props.writable {
                             <li class="delete-option destructive">
                                 <form action={ url.clone() } method="POST" class="delete" enctype="multipart/form-data">
                                     <input type="hidden" name="request" value="delete" />
@@ -45,10 +48,21 @@ pub fn DirectoryEntry(props: &DirectoryEntryProps) -> Html {
                                     <input type="submit" value="Cut" />
                                 </form>
                             </li>
-                        </ul>
-                    </nav>
-                </div>
-            }
+                        }
+                        <li class="share-option">
+                            <form action={ share } method="POST" class="share">
+                                <select name="access">
+                                    <option value="view">{"View"}</option>
+                                    <option value="download" selected=true>{"Download"}</option>
+                                    <option value="upload">{"Upload only"}</option>
+                                </select>
+                                <input type="number" name="days" value="7" min="1" title="Days until the link expires" />
+                                <input type="submit" value="Share" />
+                            </form>
+                        </li>
+                    </ul>
+                </nav>
+            </div>
         </li>
     }
 }
\ No newline at end of file
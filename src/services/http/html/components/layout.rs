@@ -10,11 +10,15 @@ pub struct LayoutProps {
     #[prop_or_default]
     pub children: Children,
     pub data: Arc<ServerData>,
+    #[prop_or_default]
+    pub user: Option<String>, // the logged in user, shown with a logout button
+    #[prop_or_default]
+    pub admin: bool, // links the admin page
 }
 
 impl PartialEq for LayoutProps {
     fn eq(&self, other: &Self) -> bool {
-        self.children == other.children
+        self.children == other.children && self.user == other.user && self.admin == other.admin
     }
 }
 
@@ -40,6 +44,15 @@ pub fn Layout(props: &LayoutProps) -> Html {
             <body>
                 <header>
                     <span>{ "chunkdrive" }</span>
+                    if props.admin {
+                        <a href={ format!("{}admin/", props.data.config.path) } class="admin">{ "Admin" }</a>
+                    }
+                    if let Some(user) = &props.user {
+                        <form action={ format!("{}logout", props.data.config.path) } method="POST" class="logout">
+                            <span>{ user.clone() }</span>
+                            <input type="submit" value="Log out" />
+                        </form>
+                    }
                     <input type="checkbox" id="theme-switcher" />
                 </header>
                 <section class="content">
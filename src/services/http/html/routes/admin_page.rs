@@ -0,0 +1,65 @@
+use yew::prelude::*;
+use yew::function_component;
+use std::sync::Arc;
+
+use crate::services::http::html::components::layout::Layout;
+use crate::services::http::service::ServerData;
+
+#[derive(Properties)]
+pub struct AdminPageProps {
+    pub data: Arc<ServerData>,
+    pub user: Option<String>,
+    pub report: Option<(String, Vec<String>)> // the summary and the details of the last maintenance task
+}
+
+impl PartialEq for AdminPageProps {
+    fn eq(&self, other: &Self) -> bool {
+        self.user == other.user && self.report == other.report
+    }
+}
+
+#[function_component(AdminPage)]
+pub fn admin_page(props: &AdminPageProps) -> Html {
+    let mut buckets = props.data.global.list_buckets();
+    buckets.sort();
+    let action = format!("{}admin/", props.data.config.path);
+
+    html! {
+        <Layout data={props.data.clone()} user={props.user.clone()} admin=true>
+            if let Some((summary, details)) = &props.report {
+                <article class="report banner">
+                    <h1>{summary.clone()}</h1>
+                    if !details.is_empty() {
+                        <pre>{details.join("\n")}</pre>
+                    }
+                </article>
+            }
+            <article class="buckets banner">
+                <h1>{"Buckets"}</h1>
+                <pre>
+                    { format!("{:<20} {:<20} {:<20} {:<20} {}\n", "Name", "Source", "Encryption", "Compression", "Chunk size") }
+                    { buckets.iter().map(|name| match props.data.global.get_bucket(name) {
+                        Some(bucket) => format!("{:<20} {}\n", name, bucket.human_readable()),
+                        None => format!("{:<20} not found\n", name),
+                    }).collect::<String>() }
+                </pre>
+            </article>
+            <article class="maintenance banner">
+                <h1>{"Maintenance"}</h1>
+                <p>{"Scrubbing reads every chunk and rewrites the missing ones. Garbage collection finds chunks that no file uses, delete them with gc delete in the debug shell while nothing is being uploaded."}</p>
+                <form action={action.clone()} method="POST">
+                    <input type="hidden" name="task" value="scrub-check" />
+                    <input type="submit" value="Check chunks" />
+                </form>
+                <form action={action.clone()} method="POST">
+                    <input type="hidden" name="task" value="scrub" />
+                    <input type="submit" value="Check and repair chunks" />
+                </form>
+                <form action={action} method="POST">
+                    <input type="hidden" name="task" value="gc" />
+                    <input type="submit" value="Find unused chunks" />
+                </form>
+            </article>
+        </Layout>
+    }
+}
@@ -12,7 +12,10 @@ pub struct DirectoryIndexProps {
     pub path: Vec<String>,
     pub data: Arc<ServerData>,
     pub dir: Directory,
-    pub cut_inode: Option<String>
+    pub cut_inode: Option<String>,
+    pub writable: bool,
+    pub user: Option<String>,
+    pub admin: bool
 }
 
 impl PartialEq for DirectoryIndexProps {
@@ -33,7 +36,7 @@ pub fn DirectoryIndex(props: &DirectoryIndexProps) -> Html {
     }).collect::<Vec<String>>();
     
     html! {
-        <Layout data={props.data.clone()}>
+        <Layout data={props.data.clone()} user={props.user.clone()} admin={props.admin}>
             <ul class="index">
                 if path.len() > 1 {
                     <li class="entry back">
@@ -46,15 +49,16 @@ pub fn DirectoryIndex(props: &DirectoryIndexProps) -> Html {
                 }
                 { props.dir.list_tuples().iter().map(|(name, inode)| {
                     html! {
-                        <DirectoryEntry name={name.clone()} inode={inode.clone()} data={props.data.clone()} path={path.clone()} />
+                        <DirectoryEntry name={name.clone()} inode={inode.clone()} data={props.data.clone()} path={path.clone()} writable={props.writable} />
                     }
                 }).collect::<Html>()}
-                if !props.data.config.readonly {
+                if props.writable {
                     <div class="create-entries">
                         <li class="entry create create-file">
                             <span>{"Upload file"}</span>
                             <button class="create-btn">{"↑"}</button>
                             <form action={ format!("/files/{}/", path.join("/")) } method="POST" enctype="multipart/form-data" class="create-form file-upload">
+                                <input type="text" name="redundancy" placeholder="Redundancy (optional)" />
                                 <input type="file" name="file" />
                                 <input type="submit" value="Upload file" />
                             </form>
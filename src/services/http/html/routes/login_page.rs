@@ -0,0 +1,39 @@
+use yew::prelude::*;
+use yew::function_component;
+use std::sync::Arc;
+
+use crate::services::http::html::components::layout::Layout;
+use crate::services::http::service::ServerData;
+
+#[derive(Properties)]
+pub struct LoginPageProps {
+    pub data: Arc<ServerData>,
+    pub next: String,
+    pub error: Option<String>
+}
+
+impl PartialEq for LoginPageProps {
+    fn eq(&self, other: &Self) -> bool {
+        self.next == other.next && self.error == other.error
+    }
+}
+
+#[function_component(LoginPage)]
+pub fn login_page(props: &LoginPageProps) -> Html {
+    html! {
+        <Layout data={props.data.clone()}>
+            <article class={ if props.error.is_some() { "login banner error" } else { "login banner" } }>
+                <h1>{"Log in"}</h1>
+                if let Some(error) = &props.error {
+                    <p>{error.clone()}</p>
+                }
+                <form action={ format!("{}login", props.data.config.path) } method="POST">
+                    <input type="hidden" name="next" value={props.next.clone()} />
+                    <p><input type="text" name="username" placeholder="Username" autocomplete="username" required=true /></p>
+                    <p><input type="password" name="password" placeholder="Password" autocomplete="current-password" required=true /></p>
+                    <p><input type="submit" value="Log in" /></p>
+                </form>
+            </article>
+        </Layout>
+    }
+}
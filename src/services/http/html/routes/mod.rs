@@ -1,2 +1,5 @@
+pub mod admin_page;
 pub mod directory_index;
-pub mod error_page;
\ No newline at end of file
+pub mod error_page;
+pub mod login_page;
+pub mod share_page;
\ No newline at end of file
@@ -0,0 +1,122 @@
+use yew::prelude::*;
+use yew::function_component;
+use std::sync::Arc;
+
+use crate::inodes::metadata::Size;
+use crate::services::http::html::components::layout::Layout;
+use crate::services::http::service::ServerData;
+
+#[derive(Clone, PartialEq)]
+pub struct ShareEntry {
+    pub name: String,
+    pub segment: Option<String>, // bucket$descriptor$name under the current directory, none for a shared file
+    pub directory: bool,
+    pub size: Size,
+}
+
+#[derive(Properties)]
+pub struct SharePageProps {
+    pub data: Arc<ServerData>,
+    pub base: String, // the link itself, /s/<token>/
+    pub path: Vec<String>,
+    pub title: String,
+    pub entries: Vec<ShareEntry>,
+    pub downloadable: bool,
+    pub uploadable: bool,
+    pub expires: String,
+}
+
+impl PartialEq for SharePageProps {
+    fn eq(&self, other: &Self) -> bool {
+        self.base == other.base &&
+        self.path == other.path &&
+        self.entries == other.entries &&
+        self.downloadable == other.downloadable &&
+        self.uploadable == other.uploadable
+    }
+}
+
+#[function_component(SharePage)]
+pub fn share_page(props: &SharePageProps) -> Html {
+    let current = format!("{}{}", props.base, props.path.iter().map(|part| format!("{}/", part)).collect::<String>());
+
+    html! {
+        <Layout data={props.data.clone()}>
+            <article class="share banner">
+                <h1>{props.title.clone()}</h1>
+                <p>{format!("This link expires on {}.", props.expires)}</p>
+                if !props.path.is_empty() {
+                    <a href={ props.base.clone() }>{"Back to the shared directory"}</a>
+                }
+            </article>
+            if !props.entries.is_empty() {
+                <ul class="listing">
+                    { props.entries.iter().map(|entry| {
+                        let url = match &entry.segment {
+                            Some(segment) if entry.directory => Some(format!("{}{}/", current, segment)),
+                            Some(segment) if props.downloadable => Some(format!("{}{}", current, segment)),
+                            None if props.downloadable => Some(format!("{}?disposition=attachment", current)),
+                            _ => None,
+                        };
+                        html! {
+                            <li class="entry inode">
+                                if let Some(url) = url {
+                                    <a href={url}>{ &entry.name }</a>
+                                } else {
+                                    <span>{ &entry.name }</span>
+                                }
+                                <span class="size">{ entry.size.human() }</span>
+                            </li>
+                        }
+                    }).collect::<Html>() }
+                </ul>
+            }
+            if props.uploadable {
+                <form action={ props.base.clone() } method="POST" class="upload" enctype="multipart/form-data">
+                    <input type="file" name="file" />
+                    <input type="submit" value="Upload" />
+                </form>
+            }
+        </Layout>
+    }
+}
+
+#[derive(Properties)]
+pub struct ShareLinkProps {
+    pub data: Arc<ServerData>,
+    pub url: String,
+    pub token: String,
+    pub name: String,
+    pub access: String,
+    pub expires: String,
+    pub user: Option<String>,
+}
+
+impl PartialEq for ShareLinkProps {
+    fn eq(&self, other: &Self) -> bool {
+        self.token == other.token && self.user == other.user
+    }
+}
+
+#[function_component(ShareLink)]
+pub fn share_link(props: &ShareLinkProps) -> Html {
+    let allows = match props.access.as_str() {
+        "view" => "see",
+        "upload" => "upload files to",
+        _ => "download",
+    };
+
+    html! {
+        <Layout data={props.data.clone()} user={props.user.clone()}>
+            <article class="share banner">
+                <h1>{format!("Share {}", props.name)}</h1>
+                <p>{format!("Anyone with this link can {} it until {}.", allows, props.expires)}</p>
+                <input type="text" readonly=true value={ props.url.clone() } />
+                <form action={ format!("{}shares/revoke", props.data.config.path) } method="POST" class="destructive">
+                    <input type="hidden" name="token" value={ props.token.clone() } />
+                    <input type="submit" value="Revoke" />
+                </form>
+            </article>
+        </Layout>
+    }
+}
@@ -1,2 +1,11 @@
+pub mod admin;
+pub mod api;
+pub mod auth;
+pub mod content;
 pub mod html;
-pub mod service;
\ No newline at end of file
+pub mod range;
+pub mod service;
+pub mod share;
+pub mod tls;
+pub mod tus;
+pub mod upload;
@@ -0,0 +1,232 @@
+/*
+    Byte range requests (RFC 9110), so players can seek and interrupted downloads can be resumed.
+    Only the chunks overlapping the requested ranges are downloaded, see File::get_range.
+ */
+
+use std::{ops::Range, sync::Arc, time::{Duration, UNIX_EPOCH}};
+use actix_web::{web, HttpRequest, HttpResponse, http::header::{self, ContentDisposition, HttpDate}};
+use futures::StreamExt;
+
+use crate::{blocks::block::Block, global::Global, inodes::file::File};
+
+// Files are shown on our origin, so whatever a browser renders from them runs without scripts or same-origin access
+const SANDBOX: &str = "sandbox";
+
+// More ranges than this are not worth a multipart response, the whole file is sent instead
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    Full,                        // no range or one we do not understand, so the whole file is sent
+    Partial(Vec<Range<usize>>),  // the satisfiable ranges, sorted and with the overlapping ones merged
+    Unsatisfiable,               // none of the ranges overlap the file
+}
+
+// Parses the value of the Range header for a file of the given size
+pub fn parse_ranges(header: &str, size: usize) -> Ranges {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return Ranges::Full,
+    };
+
+    let specs = specs.split(',').map(|spec| spec.trim()).filter(|spec| !spec.is_empty()).collect::<Vec<&str>>();
+    if specs.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for spec in specs {
+        let (start, end) = match spec.split_once('-') {
+            Some(parts) => parts,
+            None => return Ranges::Full,
+        };
+        let number = |part: &str| part.trim().parse::<usize>().ok();
+        let range = match (start.trim().is_empty(), end.trim().is_empty()) {
+            // bytes=-n, the last n bytes
+            (true, false) => match number(end) {
+                Some(0) => continue,
+                Some(suffix) => size.saturating_sub(suffix)..size,
+                None => return Ranges::Full,
+            },
+            // bytes=n-, everything from n
+            (false, true) => match number(start) {
+                Some(start) => start..size,
+                None => return Ranges::Full,
+            },
+            // bytes=a-b, both ends inclusive
+            (false, false) => match (number(start), number(end)) {
+                (Some(start), Some(end)) if start <= end => start..std::cmp::min(end.saturating_add(1), size),
+                _ => return Ranges::Full,
+            },
+            (true, true) => return Ranges::Full,
+        };
+        if range.start < size {
+            ranges.push(range);
+        }
+    }
+
+    // overlapping or adjacent ranges would send the same bytes again, so they are merged into one
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = std::cmp::max(last.end, range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    match merged.is_empty() {
+        true => Ranges::Unsatisfiable,
+        false => Ranges::Partial(merged),
+    }
+}
+
+// The ETag changes whenever the file is modified
+pub fn etag(file: &File, size: usize) -> String {
+    format!("\"{:x}-{:x}-{:x}\"", file.metadata.created, file.metadata.modified, size)
+}
+
+// The validators of the file
+fn validators(file: &File, size: usize) -> (String, HttpDate) {
+    let etag = etag(file, size);
+    let modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(file.metadata.modified));
+    (etag, modified)
+}
+
+// If-Range only allows a partial response if the file is still the one the client has
+fn if_range_matches(req: &HttpRequest, etag: &str, modified: &HttpDate) -> bool {
+    let value = match req.headers().get(header::IF_RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) => value.trim(),
+        None => return true,
+    };
+    if value.starts_with('"') {
+        return value == etag;
+    }
+    value.parse::<HttpDate>().is_ok_and(|date| date == *modified)
+}
+
+fn stream_range(global: Arc<Global>, file: Arc<File>, range: Range<usize>) -> impl futures::Stream<Item = Result<web::Bytes, std::io::Error>> {
+    async_stream::stream! {
+        let mut stream = file.get_range(global, range);
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => yield Ok(web::Bytes::from(chunk)),
+                Err(e) => yield Err(std::io::Error::other(e))
+            }
+        }
+    }
+}
+
+// Streams the file, or the parts of it asked for in the Range header
+pub async fn respond(global: Arc<Global>, file: Arc<File>, req: &HttpRequest, content_type: String, disposition: ContentDisposition) -> HttpResponse {
+    let size = match file.data.range(global.clone()).await {
+        Ok(range) => range.end,
+        Err(e) => return HttpResponse::InternalServerError().body(e),
+    };
+    let (etag, modified) = validators(&file, size);
+
+    let ranges = match req.headers().get(header::RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) if if_range_matches(req, &etag, &modified) => parse_ranges(value, size),
+        _ => Ranges::Full,
+    };
+
+    match ranges {
+        Ranges::Full => HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header(disposition)
+            .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+            .insert_header((header::CONTENT_SECURITY_POLICY, SANDBOX))
+            .insert_header((header::ACCEPT_RANGES, "bytes"))
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::LAST_MODIFIED, modified))
+            .no_chunking(size as u64)
+            .streaming(stream_range(global, file, 0..size)),
+        Ranges::Unsatisfiable => HttpResponse::RangeNotSatisfiable()
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+            .finish(),
+        Ranges::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0].clone();
+            HttpResponse::PartialContent()
+                .content_type(content_type)
+                .insert_header(disposition)
+                .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+                .insert_header((header::CONTENT_SECURITY_POLICY, SANDBOX))
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .insert_header((header::ETAG, etag))
+                .insert_header((header::LAST_MODIFIED, modified))
+                .insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end - 1, size)))
+                .no_chunking(range.len() as u64)
+                .streaming(stream_range(global, file, range))
+        },
+        Ranges::Partial(ranges) => {
+            // every range becomes a part of a multipart/byteranges body
+            let boundary = format!("{:016x}", rand::random::<u64>());
+            let parts = ranges.into_iter().map(|range| {
+                let head = format!("\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", boundary, content_type, range.start, range.end - 1, size);
+                (head, range)
+            }).collect::<Vec<(String, Range<usize>)>>();
+            let tail = format!("\r\n--{}--\r\n", boundary);
+            let length = parts.iter().map(|(head, range)| head.len() + range.len()).sum::<usize>() + tail.len();
+
+            HttpResponse::PartialContent()
+                .content_type(format!("multipart/byteranges; boundary={}", boundary))
+                .insert_header(disposition)
+                .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+                .insert_header((header::CONTENT_SECURITY_POLICY, SANDBOX))
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .insert_header((header::ETAG, etag))
+                .insert_header((header::LAST_MODIFIED, modified))
+                .no_chunking(length as u64)
+                .streaming(async_stream::stream! {
+                    for (head, range) in parts {
+                        yield Ok(web::Bytes::from(head));
+                        let mut stream = Box::pin(stream_range(global.clone(), file.clone(), range));
+                        while let Some(chunk) = stream.next().await {
+                            yield chunk;
+                        }
+                    }
+                    yield Ok::<web::Bytes, std::io::Error>(web::Bytes::from(tail));
+                })
+        },
+    }
+}
+
+#[cfg(test)]
+mod range_tests {
+    use super::*;
+
+    fn partial(ranges: &[(usize, usize)]) -> Ranges {
+        Ranges::Partial(ranges.iter().map(|(start, end)| *start..*end).collect())
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_ranges("bytes=0-99", 1000), partial(&[(0, 100)]));
+        assert_eq!(parse_ranges("bytes=900-", 1000), partial(&[(900, 1000)]));
+        assert_eq!(parse_ranges("bytes=-100", 1000), partial(&[(900, 1000)]));
+        assert_eq!(parse_ranges("bytes=-5000", 1000), partial(&[(0, 1000)]));
+        assert_eq!(parse_ranges("bytes=990-5000", 1000), partial(&[(990, 1000)]));
+        assert_eq!(parse_ranges("bytes=0-0, 10-19,-1", 1000), partial(&[(0, 1), (10, 20), (999, 1000)]));
+    }
+
+    #[test]
+    fn merges_and_limits_ranges() {
+        assert_eq!(parse_ranges("bytes=500-599,0-99,50-149,150-199", 1000), partial(&[(0, 200), (500, 600)]));
+        assert_eq!(parse_ranges("bytes=0-,0-,0-", 1000), partial(&[(0, 1000)]));
+        let many = (0..17).map(|i| format!("{}-{}", i * 10, i * 10 + 1)).collect::<Vec<String>>().join(",");
+        assert_eq!(parse_ranges(&format!("bytes={}", many), 1000), Ranges::Full);
+        let allowed = (0..16).map(|i| format!("{}-{}", i * 10, i * 10 + 1)).collect::<Vec<String>>().join(",");
+        assert!(matches!(parse_ranges(&format!("bytes={}", allowed), 1000), Ranges::Partial(ranges) if ranges.len() == 16));
+    }
+
+    #[test]
+    fn unsatisfiable_and_invalid() {
+        assert_eq!(parse_ranges("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=0-", 0), Ranges::Unsatisfiable);
+        // headers we do not understand are ignored and the whole file is sent
+        assert_eq!(parse_ranges("bytes=20-10", 1000), Ranges::Full);
+        assert_eq!(parse_ranges("items=0-10", 1000), Ranges::Full);
+        assert_eq!(parse_ranges("bytes=a-b", 1000), Ranges::Full);
+    }
+}
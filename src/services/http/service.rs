@@ -1,14 +1,13 @@
 use std::sync::Arc;
-use futures::StreamExt;
 use serde::Deserialize;
-use actix_web::{web, App, HttpServer, Responder, HttpResponse, route, cookie, HttpRequest};
-use actix_multipart::form::{MultipartForm, bytes::Bytes, text::Text};
+use actix_web::{web, App, HttpServer, Responder, HttpResponse, route, cookie, HttpRequest, http::StatusCode};
+use actix_multipart::Multipart;
 use tokio::io::AsyncReadExt;
 use yew::ServerRenderer;
 
-use crate::{global::Global, services::service::Service, inodes::{inode::{InodeType, Inode}, directory::Directory, file::File}, stored::Stored};
+use crate::{blocks::block::{Block, BlockType}, global::Global, services::service::Service, inodes::{inode::{InodeType, Inode}, directory::Directory, file::File, metadata::Size}, stored::Stored};
 
-use super::html::routes::{directory_index::{DirectoryIndexProps, DirectoryIndex}, error_page::{ErrorPage, ErrorPageProps}};
+use super::{admin, api, auth::{self, Access, Auth, AuthConfig, Identity, identify, login_redirect}, html::routes::{directory_index::{DirectoryIndexProps, DirectoryIndex}, error_page::{ErrorPage, ErrorPageProps}}, content::content_disposition, range, share::{self, ShareState}, tls::{self, CertResolver}, tus::{self, TusState}, upload::read_form};
 
 
 #[derive(Debug, Deserialize, Clone)]
@@ -17,6 +16,15 @@ pub struct HttpService {
     #[serde(default = "default_address")]
     pub(crate) address: String,
 
+    #[serde(default)]
+    pub(crate) tls_cert: Option<String>, // PEM files, TLS is used when both are set
+
+    #[serde(default)]
+    pub(crate) tls_key: Option<String>,
+
+    #[serde(default)]
+    pub(crate) redirect_port: Option<u16>, // a plain HTTP listener that sends everything to the TLS port
+
     #[serde(default = "default_path")]
     pub(crate) path: String,
 
@@ -26,20 +34,59 @@ pub struct HttpService {
     #[serde(default = "fn_true")]
     pub(crate) see_root: bool,
 
-    #[serde(default = "fn_true")]
-    pub(crate) admin: bool,
+    #[serde(default = "fn_false")]
+    pub(crate) admin: bool, // enables the bucket and maintenance pages, for admins only when auth is set
+
+    #[serde(default)]
+    pub(crate) auth: Option<AuthConfig>, // everyone can do everything without it
+
+    #[serde(default)]
+    pub(crate) secret: Option<String>, // signs session cookies and share links
 
     #[serde(default = "fn_style")]
     pub(crate) style_path: String,
 
     #[serde(default = "fn_script")]
     pub(crate) script_path: String,
+
+    #[serde(default)]
+    pub(crate) max_upload_size: Option<usize>, // in bytes, unlimited by default
+
+    #[serde(default = "fn_tus_path")]
+    pub(crate) tus_path: String,
+
+    #[serde(default = "fn_tus_expiration")]
+    pub(crate) tus_expiration: u64, // seconds since the last PATCH before an unfinished upload is deleted
+
+    #[serde(default)]
+    pub(crate) shares_path: Option<String>, // revoked share links, next to the root by default
 }
 
 #[derive(Debug)]
 pub struct ServerData {
     pub global: Arc<Global>,
     pub config: HttpService,
+    pub tus: TusState,
+    pub auth: Option<Auth>,
+    pub secret: Vec<u8>,  // signs what is given to the client and trusted when it comes back
+    pub shares: ShareState,
+}
+
+impl ServerData {
+    pub fn new(global: Arc<Global>, config: HttpService) -> Result<Self, String> {
+        let auth = match &config.auth {
+            Some(auth) => Some(Auth::new(auth)?),
+            None => None,
+        };
+        Ok(Self {
+            shares: ShareState::load(&config.shares_path.clone().unwrap_or_else(|| global.local_path("shares.dat")))?,
+            global,
+            secret: auth::secret(config.secret.as_ref()),
+            tus: TusState::load(&config.tus_path)?,
+            config,
+            auth,
+        })
+    }
 }
 
 fn default_address() -> String { "127.0.0.1".to_string() }
@@ -48,10 +95,18 @@ const fn fn_false() -> bool { false }
 const fn fn_true() -> bool { true }
 fn fn_style() -> String { "./style.css".to_string() }
 fn fn_script() -> String { "./script.js".to_string() }
+fn fn_tus_path() -> String { "./tus.dat".to_string() }
+const fn fn_tus_expiration() -> u64 { 24 * 60 * 60 }
 
 impl Service for HttpService {
     fn run(&self, global: Arc<Global>) {
-        let data = Arc::new(ServerData { global, config: self.clone() });
+        let data = match ServerData::new(global, self.clone()) {
+            Ok(data) => Arc::new(data),
+            Err(e) => {
+                println!("Failed to run HTTP service: {}", e);
+                return;
+            }
+        };
         std::thread::spawn(move || {
             match run_blocking(data) {
                 Ok(_) => {},
@@ -59,32 +114,88 @@ impl Service for HttpService {
             }
         });
     }
+
+    fn unfinished(&self) -> Result<Vec<BlockType>, String> {
+        Ok(tus::load_uploads(&self.tus_path)?.into_values().flat_map(|upload| upload.blocks).collect())
+    }
 }
 
 fn run_blocking(data: Arc<ServerData>) -> Result<(), String> {
-    println!("Starting HTTP service on http://{}:{}", data.config.address, data.config.port);
+    let tls = match (&data.config.tls_cert, &data.config.tls_key) {
+        (Some(cert), Some(key)) => Some(Arc::new(CertResolver::new(cert, key)?)),
+        (None, None) => None,
+        _ => return Err("tls_cert and tls_key have to be set together".to_string()),
+    };
+    if tls.is_none() && data.config.redirect_port.is_some() {
+        return Err("redirect_port needs tls_cert and tls_key".to_string());
+    }
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    println!("Starting HTTP service on {}://{}:{}", scheme, data.config.address, data.config.port);
     let rt = tokio::runtime::Runtime::new().unwrap();
     let data_clone = data.clone();
+    let expire_data = data.clone();
+    rt.spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            for e in expire_data.tus.expire(expire_data.global.clone()).await {
+                println!("Failed to delete an expired upload: {}", e);
+            }
+        }
+    });
     rt.block_on(async {
-        HttpServer::new(move || {
+        let server = HttpServer::new(move || {
             App::new()
                 .app_data(web::Data::new(data_clone.clone()))
-                .service(style)
-                .service(script)
-                .service(redirect)
-                .service(get)
-                .service(post)
-        })
-        .bind(format!("{}:{}", data.config.address, data.config.port))
+                .configure(configure)
+        });
+        let address = format!("{}:{}", data.config.address, data.config.port);
+        let server = match &tls {
+            Some(resolver) => {
+                tls::reload_on_hangup(resolver.clone());
+                server.bind_rustls_021(address, tls::server_config(resolver.clone()))
+            },
+            None => server.bind(address),
+        }
         .map_err(|e| format!("Failed to bind to port: {}", e))?
-        .run()
-        .await
+        .run();
+
+        match data.config.redirect_port {
+            Some(port) => {
+                println!("Redirecting http://{}:{} to HTTPS", data.config.address, port);
+                let redirect_data = data.clone();
+                let plain = HttpServer::new(move || {
+                    App::new()
+                        .app_data(web::Data::new(redirect_data.clone()))
+                        .default_service(web::to(tls::to_https))
+                })
+                .bind(format!("{}:{}", data.config.address, port))
+                .map_err(|e| format!("Failed to bind to port: {}", e))?
+                .run();
+                tokio::try_join!(server, plain).map(|_| ())
+            },
+            None => server.await,
+        }
         .map_err(|e| format!("Failed to run server: {}", e))
     })?;
 
     Ok(())
 }
 
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(style)
+        .service(script)
+        .service(redirect)
+        .service(get)
+        .service(post)
+        .configure(tus::configure)
+        .configure(api::configure)
+        .configure(auth::configure)
+        .configure(admin::configure)
+        .configure(share::configure);
+}
+
 fn get_stored(path: &Vec<String>) -> Result<Stored, String> {
     let entry = path.last().ok_or("Invalid path")?;
     let parts = entry.split('$').collect::<Vec<&str>>();
@@ -96,7 +207,7 @@ fn get_stored(path: &Vec<String>) -> Result<Stored, String> {
     Stored::from_url(&bucket, &descriptor)
 }
 
-async fn get_inode(data: Arc<ServerData>, path: &Vec<String>) -> Result<InodeType, String> {
+pub(super) async fn get_inode(data: Arc<ServerData>, path: &Vec<String>) -> Result<InodeType, String> {
     let stored = get_stored(path)?;
 
     let inode = stored.get::<InodeType>(data.global.clone()).await?;
@@ -104,12 +215,79 @@ async fn get_inode(data: Arc<ServerData>, path: &Vec<String>) -> Result<InodeTyp
     Ok(inode)
 }
 
-async fn render_directory(data: Arc<ServerData>, path: Vec<String>, directory: Directory, cookie: Option<cookie::Cookie<'static>>) -> HttpResponse {
-    let renderer: ServerRenderer<_> = ServerRenderer::<DirectoryIndex>::with_props(|| {
+// The user sending the request, browsers that are not logged in are sent to the login page
+pub(super) async fn who(data: Arc<ServerData>, req: &HttpRequest) -> Result<Identity, HttpResponse> {
+    match identify(&data, req) {
+        Ok(Some(identity)) => Ok(identity),
+        Ok(None) => Err(login_redirect(&data, req)),
+        Err(e) => Err(render_error_status(data, StatusCode::UNAUTHORIZED, e).await),
+    }
+}
+
+const NO_ACCESS: &str = "You do not have access to this path.";
+
+// The names of a /files/ path, to check permissions against.
+// Users with the same access everywhere do not need them, and neither do paths that start with a descriptor instead of the root.
+pub(super) async fn resolve(data: Arc<ServerData>, identity: &Identity, path: &[String]) -> Result<Vec<String>, String> {
+    if identity.uniform() {
+        return Ok(Vec::new());
+    }
+    walk(data.global.clone(), data.global.get_root()?, path).await
+}
+
+// Follows a /files/ path from a directory. Descriptors can be made up, so every entry has to be found in the directory before it.
+pub(super) async fn walk(global: Arc<Global>, mut directory: Directory, path: &[String]) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    for (i, part) in path.iter().enumerate() {
+        let parts = part.splitn(3, '$').collect::<Vec<&str>>();
+        if parts.len() != 3 {
+            return Err(NO_ACCESS.to_string());
+        }
+        let name = parts[2].to_string();
+        let stored = Stored::from_url(parts[0], parts[1])?;
+        if directory.get(&name).ok() != Some(&stored) {
+            return Err(NO_ACCESS.to_string());
+        }
+        names.push(name);
+        if i + 1 < path.len() {
+            directory = match stored.get::<InodeType>(global.clone()).await? {
+                InodeType::Directory(directory) => directory,
+                InodeType::File(_) => return Err("Path is not a directory".to_string()),
+            };
+        }
+    }
+    Ok(names)
+}
+
+pub(super) fn child(names: &[String], name: &str) -> Vec<String> {
+    [names, &[name.to_string()]].concat()
+}
+
+async fn render_directory(data: Arc<ServerData>, path: Vec<String>, directory: Directory, cookie: Option<cookie::Cookie<'static>>, identity: &Identity, names: &[String]) -> HttpResponse {
+    // entries the user can not see are left out
+    let directory = match identity.uniform() {
+        true => directory,
+        false => {
+            let mut visible = Directory::new();
+            for (name, stored) in directory.list_tuples() {
+                if identity.visible(&child(names, &name)) {
+                    visible.put(&name, stored).ok();
+                }
+            }
+            visible
+        }
+    };
+    let writable = !data.config.readonly && identity.access(names) >= Access::Write;
+    let admin = data.config.admin && identity.admin();
+    let user = identity.user.clone();
+    let renderer: ServerRenderer<_> = ServerRenderer::<DirectoryIndex>::with_props(move || {
         DirectoryIndexProps {
             data,
             path,
             dir: directory,
+            writable,
+            user,
+            admin,
             cut_inode: if let Some(cookie) = cookie {
                 match cookie.value() {
                     "" => None,
@@ -128,6 +306,10 @@ async fn render_directory(data: Arc<ServerData>, path: Vec<String>, directory: D
 }
 
 async fn render_error(data: Arc<ServerData>, error: String) -> HttpResponse {
+    render_error_status(data, StatusCode::SERVICE_UNAVAILABLE, error).await
+}
+
+pub(super) async fn render_error_status(data: Arc<ServerData>, status: StatusCode, error: String) -> HttpResponse {
     let renderer: ServerRenderer<_> = ServerRenderer::<ErrorPage>::with_props(|| {
         ErrorPageProps {
             data,
@@ -136,7 +318,7 @@ async fn render_error(data: Arc<ServerData>, error: String) -> HttpResponse {
     });
     let html = renderer.render().await;
 
-    HttpResponse::ServiceUnavailable()
+    HttpResponse::build(status)
         .content_type("text/html")
         .body(html)
 }
@@ -210,6 +392,12 @@ async fn redirect(data: web::Data<Arc<ServerData>>) -> impl Responder {
         .finish()
 }
 
+// ?disposition=attachment makes the browser save the file instead of showing it
+#[derive(Deserialize)]
+struct DownloadQuery {
+    disposition: Option<String>,
+}
+
 #[route("/files/{path:.*}", method = "GET")]
 async fn get(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest) -> impl Responder {
     let arc = data.as_ref().clone();
@@ -219,9 +407,20 @@ async fn get(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: Htt
     }
 
     let path = path.into_inner().split('/').map(|part| part.to_string()).filter(|part| !part.is_empty()).collect::<Vec<String>>();
+    let identity = match who(arc.clone(), &req).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let names = match resolve(arc.clone(), &identity, &path).await {
+        Ok(names) => names,
+        Err(e) => return render_error_status(arc, StatusCode::FORBIDDEN, e).await,
+    };
     
     let inode = match path.is_empty() {
-        true => arc.global.get_root().to_enum(),
+        true => match arc.global.get_root() {
+            Ok(root) => root.to_enum(),
+            Err(err) => return render_error(arc, err).await,
+        },
         false => {
             let inode = get_inode(arc.clone(), &path).await;
             match inode {
@@ -234,36 +433,33 @@ async fn get(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: Htt
     let directory = match inode {
         InodeType::Directory(dir) => dir,
         InodeType::File(file) => {
-            // if the path is a file, stream it
-            return HttpResponse::Ok()
-                .content_type("application/octet-stream")
-                .streaming(async_stream::stream! {
-                    let mut stream = file.get(arc.global.clone());
-
-                    while let Some(chunk) = stream.next().await {
-                        match chunk {
-                            Ok(chunk) => yield Ok(web::Bytes::from(chunk)),
-                            Err(e) => yield Err(std::io::Error::new(std::io::ErrorKind::Other, e))
-                        }
-                    }
-                });
+            if identity.access(&names) < Access::Read {
+                return render_error_status(arc, StatusCode::FORBIDDEN, NO_ACCESS.to_string()).await;
+            }
+            // if the path is a file, stream it (or the ranges the client asked for)
+            let name = match path.last().map(|entry| entry.splitn(3, '$').collect::<Vec<&str>>()) {
+                Some(parts) if parts.len() == 3 => parts[2].to_string(),
+                _ => "download".to_string(),
+            };
+            let inline = match web::Query::<DownloadQuery>::from_query(req.query_string()) {
+                Ok(query) => query.disposition.as_deref() != Some("attachment"),
+                Err(_) => true,
+            };
+            let content_type = file.metadata.content_type(&name);
+            let disposition = content_disposition(&name, &content_type, inline);
+            return range::respond(arc.global.clone(), Arc::new(file), &req, content_type, disposition).await;
         }
     };
 
     // otherwise, render an html index of the directory
-    render_directory(arc, path, directory, req.cookie("cut-inode")).await
-}
-
-#[derive(MultipartForm)]
-pub struct Upload {
-    file: Option<Bytes>,
-    directory_name: Option<Text<String>>,
-    request: Option<Text<String>>,
-    paste_name: Option<Text<String>>,
+    if !identity.visible(&names) {
+        return render_error_status(arc, StatusCode::FORBIDDEN, NO_ACCESS.to_string()).await;
+    }
+    render_directory(arc, path, directory, req.cookie("cut-inode"), &identity, &names).await
 }
 
 #[route("/files/{path:.*}", method = "POST")]
-async fn post(data: web::Data<Arc<ServerData>>, path: web::Path<String>, form: MultipartForm<Upload>, req: HttpRequest) -> impl Responder {
+async fn post(data: web::Data<Arc<ServerData>>, path: web::Path<String>, payload: Multipart, req: HttpRequest) -> impl Responder {
     let arc = data.as_ref().clone();
     
     if data.config.readonly {
@@ -275,17 +471,44 @@ async fn post(data: web::Data<Arc<ServerData>>, path: web::Path<String>, form: M
     }
 
     let path = path.into_inner().split('/').map(|part| part.to_string()).filter(|part| !part.is_empty()).collect::<Vec<String>>();
+    let identity = match who(arc.clone(), &req).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let names = match resolve(arc.clone(), &identity, &path).await {
+        Ok(names) => names,
+        Err(e) => return render_error_status(arc, StatusCode::FORBIDDEN, e).await,
+    };
+    // every request changes the path or adds to it, what it adds and subtrees it takes along are checked again below
+    let forbidden = || render_error_status(arc.clone(), StatusCode::FORBIDDEN, "You can not change this path.".to_string());
+    if identity.access(&names) < Access::Write {
+        return forbidden().await;
+    }
 
-    match &form.file {
-        Some(file) => return match post_got_file(arc.clone(), path, file).await {
-            Ok(response) => response,
-            Err(e) => render_error(arc, e).await,
+    let form = match read_form(arc.global.clone(), payload, data.config.max_upload_size).await {
+        Ok(form) => form,
+        Err(e) => return render_error(arc, e).await,
+    };
+
+    match form.file {
+        Some((filename, file)) => {
+            if identity.lowest(&child(&names, &filename)) < Access::Write {
+                return match file.data.delete(arc.global.clone()).await {
+                    Ok(_) => forbidden().await,
+                    Err(e) => render_error(arc, e).await,
+                };
+            }
+            return match post_got_file(arc.clone(), path, filename, file).await {
+                Ok(response) => response,
+                Err(e) => render_error(arc, e).await,
+            };
         },
         None => {},
     }
 
     match &form.directory_name {
-        Some(directory_name) => return match post_got_directory(arc.clone(), path, &directory_name.0).await {
+        Some(directory_name) if identity.lowest(&child(&names, directory_name)) < Access::Write => return forbidden().await,
+        Some(directory_name) => return match post_got_directory(arc.clone(), path, directory_name).await {
             Ok(response) => response,
             Err(e) => render_error(arc, e).await,
         },
@@ -293,8 +516,9 @@ async fn post(data: web::Data<Arc<ServerData>>, path: web::Path<String>, form: M
     }
 
     match &form.request {
+        Some(_) if identity.lowest(&names) < Access::Write => return forbidden().await,
         Some(request) => {
-            match request.0.as_str() {
+            match request.as_str() {
                 "delete" => return match  post_got_delete(arc.clone(), path).await {
                     Ok(response) => response,
                     Err(e) => render_error(arc, e).await,
@@ -309,12 +533,15 @@ async fn post(data: web::Data<Arc<ServerData>>, path: web::Path<String>, form: M
         None => {},
     }
 
-    if form.paste_name.is_some() {
+    if let Some(paste_name) = &form.paste_name {
         let cookie = req.cookie("cut-inode");
         if cookie.is_none() {
             return render_error(arc, "Invalid cookie".to_string()).await;
         }
-        return match post_got_paste(arc.clone(), path, form.paste_name.as_ref().unwrap().0.as_str().to_owned(), cookie.unwrap()).await {
+        if identity.lowest(&child(&names, paste_name)) < Access::Write {
+            return forbidden().await;
+        }
+        return match post_got_paste(arc.clone(), path, paste_name.to_owned(), cookie.unwrap(), &identity, &names).await {
             Ok(response) => response,
             Err(e) => render_error(arc, e).await,
         }
@@ -323,69 +550,54 @@ async fn post(data: web::Data<Arc<ServerData>>, path: web::Path<String>, form: M
     render_error(arc, "Invalid request".to_string()).await
 }
 
-async fn post_got_file(arc: Arc<ServerData>, path: Vec<String>, file: &Bytes) -> Result<HttpResponse, String> {
-    let filename = match file.file_name.clone() {
-        Some(name) => name,
-        None => { return Ok(HttpResponse::Found()
-            .append_header(("Location", format!("{}files/{}", arc.config.path, path.join("/"))))
-            .finish()) }
-    };
-
-    if file.data.is_empty() {
+async fn post_got_file(arc: Arc<ServerData>, path: Vec<String>, filename: String, file: File) -> Result<HttpResponse, String> {
+    if matches!(file.metadata.size, Size::Bytes(0)) {
         return Ok(HttpResponse::Found()
             .append_header(("Location", format!("{}files/{}", arc.config.path, path.join("/"))))
             .finish());
     }
 
+    // the file is already uploaded, so its chunks have to be removed if it can not be added to the directory
+    let data = file.data.clone();
+    if let Err(e) = add_inode(arc.clone(), &path, &filename, file.to_enum()).await {
+        return match data.delete(arc.global.clone()).await {
+            Ok(_) => Err(e),
+            Err(err) => Err(format!("{}, {}", e, err)),
+        };
+    }
+
+    Ok(HttpResponse::Found()
+        .append_header(("Location", format!("{}files/{}", arc.config.path, path.join("/"))))
+        .finish())
+}
+
+pub(super) async fn add_inode(arc: Arc<ServerData>, path: &Vec<String>, filename: &String, inode: InodeType) -> Result<(), String> {
+    let _tree = arc.global.lock_tree().await;
     let mut directory;
     let stored: Option<Stored>;
 
     if !path.is_empty() {
-        stored = match get_stored(&path) {
-            Ok(stored) => Some(stored),
-            Err(e) => return Err(e),
-        };
-
+        stored = Some(get_stored(path)?);
         directory = match stored.as_ref().unwrap().get::<InodeType>(arc.global.clone()).await {
             Ok(InodeType::Directory(dir)) => dir,
             Ok(_) => Err("Path is not a directory".to_string())?,
             Err(e) => Err(e)?,
         };
     } else {
-        directory = arc.global.get_root();
+        directory = arc.global.get_root()?;
         stored = None;
     }
-    
-    let bytes = file.data.to_vec();
-
-    let file = match File::create(arc.global.clone(), bytes).await {
-        Ok(file) => file,
-        Err(e) => Err(e)?,
-    };
 
-    match directory.add(arc.global.clone(), &filename, file.to_enum()).await {
-        Ok(_) => {},
-        Err(e) => Err(e)?,
-    };
+    directory.add(arc.global.clone(), filename, inode).await?;
 
     match stored {
-        Some(stored) => {
-            match stored.put(arc.global.clone(), directory.to_enum()).await {
-                Ok(_) => {},
-                Err(e) => Err(e)?,
-            };
-        }
-        None => {
-            arc.global.save_root(&directory)
-        }
+        Some(stored) => stored.put(arc.global.clone(), directory.to_enum()).await,
+        None => arc.global.save_root(&directory),
     }
-
-    Ok(HttpResponse::Found()
-        .append_header(("Location", format!("{}files/{}", arc.config.path, path.join("/"))))
-        .finish())
 }
 
 async fn post_got_directory(arc: Arc<ServerData>, path: Vec<String>, directory_name: &String) -> Result<HttpResponse, String> {
+    let _tree = arc.global.lock_tree().await;
     let mut directory;
     let stored: Option<Stored>;
 
@@ -401,7 +613,7 @@ async fn post_got_directory(arc: Arc<ServerData>, path: Vec<String>, directory_n
             Err(e) => Err(e)?,
         };
     } else {
-        directory = arc.global.get_root();
+        directory = arc.global.get_root()?;
         stored = None;
     }
 
@@ -418,7 +630,7 @@ async fn post_got_directory(arc: Arc<ServerData>, path: Vec<String>, directory_n
             };
         }
         None => {
-            arc.global.save_root(&directory)
+            arc.global.save_root(&directory)?;
         }
     }
 
@@ -428,6 +640,7 @@ async fn post_got_directory(arc: Arc<ServerData>, path: Vec<String>, directory_n
 }
 
 async fn post_got_delete(arc: Arc<ServerData>, path: Vec<String>) -> Result<HttpResponse, String> {
+    let _tree = arc.global.lock_tree().await;
     if path.is_empty() {
         return Err("Invalid path".to_string());
     }
@@ -464,7 +677,7 @@ async fn post_got_delete(arc: Arc<ServerData>, path: Vec<String>) -> Result<Http
             Err(e) => Err(e)?,
         };
     } else {
-        directory = arc.global.get_root();
+        directory = arc.global.get_root()?;
         stored = None;
     }
 
@@ -484,7 +697,7 @@ async fn post_got_delete(arc: Arc<ServerData>, path: Vec<String>) -> Result<Http
             Err(e) => Err(e)?,
         };
     } else {
-        arc.global.save_root(&directory)
+        arc.global.save_root(&directory)?;
     }
 
     let mut inode = match removed.get::<InodeType>(arc.global.clone()).await {
@@ -509,6 +722,7 @@ async fn post_got_delete(arc: Arc<ServerData>, path: Vec<String>) -> Result<Http
 }
 
 async fn post_got_cut(arc: Arc<ServerData>, path: Vec<String>) -> Result<HttpResponse, String> {
+    let _tree = arc.global.lock_tree().await;
     if path.is_empty() {
         return Err("Invalid path".to_string());
     }
@@ -545,7 +759,7 @@ async fn post_got_cut(arc: Arc<ServerData>, path: Vec<String>) -> Result<HttpRes
             Err(e) => Err(e)?,
         };
     } else {
-        directory = arc.global.get_root();
+        directory = arc.global.get_root()?;
         stored = None;
     }
 
@@ -565,10 +779,15 @@ async fn post_got_cut(arc: Arc<ServerData>, path: Vec<String>) -> Result<HttpRes
             Err(e) => Err(e)?,
         };
     } else {
-        arc.global.save_root(&directory)
+        arc.global.save_root(&directory)?;
     }
 
-    let cookie = cookie::Cookie::build("cut-inode", unlinked.as_url())
+    // with users, the cookie is signed so nobody can paste what they were not allowed to cut
+    let value = match arc.auth {
+        Some(_) => auth::sign(&arc.secret, &unlinked.as_url()),
+        None => unlinked.as_url(),
+    };
+    let cookie = cookie::Cookie::build("cut-inode", value)
         .path(arc.config.path.clone())
         .max_age(cookie::time::Duration::MAX)
         .finish();
@@ -579,11 +798,16 @@ async fn post_got_cut(arc: Arc<ServerData>, path: Vec<String>) -> Result<HttpRes
         .finish())
 }
 
-async fn post_got_paste(arc: Arc<ServerData>, path: Vec<String>, paste_name: String, cookie: cookie::Cookie<'static>) -> Result<HttpResponse, String> {
+async fn post_got_paste(arc: Arc<ServerData>, path: Vec<String>, paste_name: String, cookie: cookie::Cookie<'static>, identity: &Identity, names: &[String]) -> Result<HttpResponse, String> {
+    let tree = arc.global.lock_tree().await;
     let mut directory;
     let stored: Option<Stored>;
 
-    let split = cookie.value().split('$').collect::<Vec<&str>>();
+    let value = match arc.auth {
+        Some(_) => auth::verify(&arc.secret, cookie.value()).ok_or("Invalid cookie")?,
+        None => cookie.value(),
+    };
+    let split = value.split('$').collect::<Vec<&str>>();
     if split.len() != 2 {
         return Err("Invalid cookie".to_string());
     }
@@ -605,7 +829,7 @@ async fn post_got_paste(arc: Arc<ServerData>, path: Vec<String>, paste_name: Str
             Err(e) => Err(e)?,
         };
     } else {
-        directory = arc.global.get_root();
+        directory = arc.global.get_root()?;
         stored = None;
     }
 
@@ -622,7 +846,7 @@ async fn post_got_paste(arc: Arc<ServerData>, path: Vec<String>, paste_name: Str
             };
         }
         None => {
-            arc.global.save_root(&directory)
+            arc.global.save_root(&directory)?;
         }
     }
 
@@ -634,15 +858,16 @@ async fn post_got_paste(arc: Arc<ServerData>, path: Vec<String>, paste_name: Str
                 Err(e) => Err(e)?,
             }
         }
-        None => arc.global.get_root()
+        None => arc.global.get_root()?
     };
+    drop(tree);
     
     let c = cookie::Cookie::build("cut-inode", "")
         .path(arc.config.path.clone())
         .max_age(cookie::time::Duration::seconds(1))
         .finish();
 
-    let mut page = render_directory(arc, path, directory, None).await;
+    let mut page = render_directory(arc, path, directory, None, identity, names).await;
     match page.add_removal_cookie(&c) {
         Ok(_) => {},
         Err(e) => Err(format!("Failed to add cookie: {}", e))?,
@@ -0,0 +1,399 @@
+/*
+    Share links give access to one file or directory without an account. The token in the link holds the target, what the link allows and when it expires, signed with the secret.
+    Revoked links are kept in a local file next to the root until they would have expired anyway.
+ */
+
+use std::{collections::HashMap, str::FromStr, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
+use actix_multipart::Multipart;
+use actix_web::{web, route, HttpRequest, HttpResponse, http::{header, StatusCode}};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use yew::ServerRenderer;
+
+use crate::{blocks::block::Block, persist, inodes::{inode::InodeType, metadata::Size}, stored::Stored};
+use super::{auth::{self, Access, Identity}, content::content_disposition, html::routes::share_page::{ShareEntry, ShareLink, ShareLinkProps, SharePage, SharePageProps}, range, service::{ServerData, add_inode, render_error_status, resolve, walk, who}, upload::read_form};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ShareAccess {
+    #[serde(rename = "view")]
+    View,  // the names and sizes of everything under the target
+    #[serde(rename = "download")]
+    Download,  // the files too
+    #[serde(rename = "upload")]
+    Upload,  // new files in the target directory, nothing can be seen
+}
+
+impl FromStr for ShareAccess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "view" => Ok(Self::View),
+            "download" => Ok(Self::Download),
+            "upload" => Ok(Self::Upload),
+            _ => Err(format!("Unknown share access {}, expected view, download or upload", s)),
+        }
+    }
+}
+
+impl ShareAccess {
+    pub fn human_readable(&self) -> &'static str {
+        match self {
+            Self::View => "view",
+            Self::Download => "download",
+            Self::Upload => "upload",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Share {
+    #[serde(rename = "i")]
+    pub id: String,  // what is revoked
+    #[serde(rename = "t")]
+    pub target: String,  // bucket$descriptor
+    #[serde(rename = "n")]
+    pub name: String,
+    #[serde(rename = "a")]
+    pub access: ShareAccess,
+    #[serde(rename = "e")]
+    pub expires: u64,  // unix time
+    #[serde(rename = "u", default)]
+    pub user: Option<String>,  // who made the link, they can revoke it
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+impl Share {
+    pub fn new(target: &Stored, name: &str, access: ShareAccess, expires_in: u64, user: Option<String>) -> Self {
+        Self {
+            id: format!("{:032x}", rand::random::<u128>()),
+            target: target.as_url(),
+            name: name.to_string(),
+            access,
+            expires: now().saturating_add(expires_in),
+            user,
+        }
+    }
+
+    pub fn token(&self, secret: &[u8]) -> String {
+        let data = rmp_serde::to_vec(self).expect("shares can always be serialized");
+        auth::sign(secret, &URL_SAFE_NO_PAD.encode(data))
+    }
+
+    // Only checks the signature, expired and revoked links are still read
+    pub fn parse(secret: &[u8], token: &str) -> Result<Self, String> {
+        let data = auth::verify(secret, token).ok_or("This link is not valid")?;
+        let data = URL_SAFE_NO_PAD.decode(data).map_err(|_| "This link is not valid")?;
+        rmp_serde::from_slice(&data).map_err(|_| "This link is not valid".to_string())
+    }
+
+    pub fn url(&self, data: &ServerData) -> String {
+        format!("{}s/{}/", data.config.path, self.token(&data.secret))
+    }
+}
+
+// What a user needs to share an entry, view and download links show everything under it while uploads only go in the target
+pub fn allowed(data: &ServerData, identity: &Identity, names: &[String], access: ShareAccess, directory: bool) -> Result<(), String> {
+    match access {
+        ShareAccess::Upload if !directory => Err("Only directories can take uploads".to_string()),
+        ShareAccess::Upload if data.config.readonly => Err("Server is in read-only mode.".to_string()),
+        ShareAccess::Upload if identity.access(names) < Access::Write => Err("You can not upload here.".to_string()),
+        ShareAccess::View | ShareAccess::Download if identity.lowest(names) < Access::Read => Err("You can not share this path.".to_string()),
+        _ => Ok(()),
+    }
+}
+
+// Admins can revoke every link, users only the ones they made
+pub fn can_revoke(identity: &Identity, share: &Share) -> bool {
+    identity.admin() || (identity.user.is_some() && identity.user == share.user)
+}
+
+#[derive(Debug, Default)]
+pub struct ShareState {
+    path: String,
+    revoked: Mutex<HashMap<String, u64>>,  // id -> when the link expires, it is forgotten afterwards
+}
+
+impl ShareState {
+    // A file that can not be read stops the service, starting without it would let revoked links work again
+    pub fn load(path: &str) -> Result<Self, String> {
+        Ok(Self {
+            path: path.to_string(),
+            revoked: Mutex::new(persist::load(path, "revoked links")?),
+        })
+    }
+
+    fn save(&self, revoked: &HashMap<String, u64>) -> Result<(), String> {
+        persist::save(&self.path, revoked, "revoked links")
+    }
+
+    pub fn revoke(&self, share: &Share) -> Result<(), String> {
+        let mut revoked = self.revoked.lock().unwrap();
+        let time = now();
+        revoked.retain(|_, expires| *expires > time);
+        if share.expires > time {
+            revoked.insert(share.id.clone(), share.expires);
+        }
+        self.save(&revoked)
+    }
+
+    // The share of a token that can be used right now
+    pub fn check(&self, secret: &[u8], token: &str) -> Result<Share, String> {
+        let share = Share::parse(secret, token)?;
+        if share.expires <= now() {
+            return Err("This link has expired".to_string());
+        }
+        if self.revoked.lock().unwrap().contains_key(&share.id) {
+            return Err("This link was revoked".to_string());
+        }
+        Ok(share)
+    }
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(create_link)
+        .service(revoke_link)
+        .service(open_link)
+        .service(upload_link);
+}
+
+fn expiry(expires: u64) -> String {
+    let time = UNIX_EPOCH + std::time::Duration::from_secs(expires);
+    let datetime: chrono::DateTime<chrono::Utc> = time.into();
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+async fn render_link(data: Arc<ServerData>, share: Share, req: &HttpRequest, user: Option<String>) -> HttpResponse {
+    let info = req.connection_info().clone();
+    let props = ShareLinkProps {
+        url: format!("{}://{}{}", info.scheme(), info.host(), share.url(&data)),
+        token: share.token(&data.secret),
+        name: share.name.clone(),
+        access: share.access.human_readable().to_string(),
+        expires: expiry(share.expires),
+        user,
+        data,
+    };
+    let renderer: ServerRenderer<_> = ServerRenderer::<ShareLink>::with_props(|| props);
+    let html = renderer.render().await;
+
+    HttpResponse::Created()
+        .content_type("text/html")
+        .body(html)
+}
+
+async fn render_share(props: SharePageProps) -> HttpResponse {
+    let renderer: ServerRenderer<_> = ServerRenderer::<SharePage>::with_props(|| props);
+    let html = renderer.render().await;
+
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html)
+}
+
+/* #region Routes */
+
+#[derive(Deserialize)]
+struct CreateForm {
+    access: String,
+    days: Option<u64>,  // 7 by default
+}
+
+#[route("/shares/create/{path:.*}", method = "POST")]
+async fn create_link(data: web::Data<Arc<ServerData>>, path: web::Path<String>, form: web::Form<CreateForm>, req: HttpRequest) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    let identity = match who(arc.clone(), &req).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let path = path.into_inner().split('/').map(|part| part.to_string()).filter(|part| !part.is_empty()).collect::<Vec<String>>();
+    let names = match resolve(arc.clone(), &identity, &path).await {
+        Ok(names) => names,
+        Err(e) => return render_error_status(arc, StatusCode::FORBIDDEN, e).await,
+    };
+    let access = match form.access.parse::<ShareAccess>() {
+        Ok(access) => access,
+        Err(e) => return render_error_status(arc, StatusCode::BAD_REQUEST, e).await,
+    };
+    let days = form.days.unwrap_or(7);
+    if days == 0 {
+        return render_error_status(arc, StatusCode::BAD_REQUEST, "Links have to last at least a day".to_string()).await;
+    }
+
+    let parts = path.last().map(|part| part.splitn(3, '$').collect::<Vec<&str>>()).unwrap_or_default();
+    if parts.len() != 3 {
+        return render_error_status(arc, StatusCode::BAD_REQUEST, "The root can not be shared".to_string()).await;
+    }
+    let stored = match Stored::from_url(parts[0], parts[1]) {
+        Ok(stored) => stored,
+        Err(e) => return render_error_status(arc, StatusCode::BAD_REQUEST, e).await,
+    };
+    let directory = match stored.get::<InodeType>(arc.global.clone()).await {
+        Ok(inode) => matches!(inode, InodeType::Directory(_)),
+        Err(e) => return render_error_status(arc, StatusCode::NOT_FOUND, e).await,
+    };
+    if let Err(e) = allowed(&arc, &identity, &names, access, directory) {
+        return render_error_status(arc, StatusCode::FORBIDDEN, e).await;
+    }
+
+    let share = Share::new(&stored, parts[2], access, days.saturating_mul(24 * 60 * 60), identity.user.clone());
+    render_link(arc, share, &req, identity.user).await
+}
+
+#[derive(Deserialize)]
+struct RevokeForm {
+    token: String,
+}
+
+#[route("/shares/revoke", method = "POST")]
+async fn revoke_link(data: web::Data<Arc<ServerData>>, form: web::Form<RevokeForm>, req: HttpRequest) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    let identity = match who(arc.clone(), &req).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let share = match Share::parse(&arc.secret, &form.token) {
+        Ok(share) => share,
+        Err(e) => return render_error_status(arc, StatusCode::BAD_REQUEST, e).await,
+    };
+    if !can_revoke(&identity, &share) {
+        return render_error_status(arc, StatusCode::FORBIDDEN, "Only admins and the user who made a link can revoke it.".to_string()).await;
+    }
+    match arc.shares.revoke(&share) {
+        Ok(_) => HttpResponse::Found()
+            .append_header((header::LOCATION, format!("{}files/", arc.config.path)))
+            .finish(),
+        Err(e) => render_error_status(arc, StatusCode::INTERNAL_SERVER_ERROR, e).await,
+    }
+}
+
+// ?disposition=attachment makes the browser save the file instead of showing it
+#[derive(Deserialize)]
+struct DownloadQuery {
+    disposition: Option<String>,
+}
+
+#[route("/s/{token}/{path:.*}", method = "GET", method = "HEAD")]
+async fn open_link(data: web::Data<Arc<ServerData>>, params: web::Path<(String, String)>, req: HttpRequest) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    let global = arc.global.clone();
+    let (token, path) = params.into_inner();
+    let share = match arc.shares.check(&arc.secret, &token) {
+        Ok(share) => share,
+        Err(e) => return render_error_status(arc, StatusCode::FORBIDDEN, e).await,
+    };
+    let path = path.split('/').map(|part| part.to_string()).filter(|part| !part.is_empty()).collect::<Vec<String>>();
+    let target = match share.target.split_once('$').map(|(bucket, descriptor)| Stored::from_url(bucket, descriptor)) {
+        Some(Ok(target)) => target,
+        _ => return render_error_status(arc, StatusCode::BAD_REQUEST, "This link is not valid".to_string()).await,
+    };
+    let not_found = "The shared file or directory does not exist anymore".to_string();
+    let inode = match target.get::<InodeType>(global.clone()).await {
+        Ok(inode) => inode,
+        Err(_) => return render_error_status(arc, StatusCode::NOT_FOUND, not_found).await,
+    };
+
+    // the path below the target is checked like in /files/, so the link only reaches what is inside the target
+    let (name, inode) = match (inode, path.last()) {
+        (inode, None) => (share.name.clone(), inode),
+        (InodeType::Directory(directory), Some(last)) if share.access != ShareAccess::Upload => {
+            let names = match walk(global.clone(), directory, &path).await {
+                Ok(names) => names,
+                Err(e) => return render_error_status(arc, StatusCode::FORBIDDEN, e).await,
+            };
+            let parts = last.splitn(3, '$').collect::<Vec<&str>>();
+            match Stored::from_url(parts[0], parts[1]) {
+                Ok(stored) => match stored.get::<InodeType>(global.clone()).await {
+                    Ok(inode) => (names[names.len() - 1].clone(), inode),
+                    Err(e) => return render_error_status(arc, StatusCode::NOT_FOUND, e).await,
+                },
+                Err(e) => return render_error_status(arc, StatusCode::BAD_REQUEST, e).await,
+            }
+        },
+        _ => return render_error_status(arc, StatusCode::NOT_FOUND, not_found).await,
+    };
+
+    let base = format!("{}s/{}/", arc.config.path, token);
+    let mut props = SharePageProps {
+        data: arc.clone(),
+        base,
+        path: path.clone(),
+        title: name.clone(),
+        entries: Vec::new(),
+        downloadable: share.access == ShareAccess::Download,
+        uploadable: false,
+        expires: expiry(share.expires),
+    };
+    match inode {
+        InodeType::File(file) if share.access == ShareAccess::Download => {
+            let inline = match web::Query::<DownloadQuery>::from_query(req.query_string()) {
+                Ok(query) => query.disposition.as_deref() != Some("attachment"),
+                Err(_) => true,
+            };
+            let content_type = file.metadata.content_type(&name);
+            let disposition = content_disposition(&name, &content_type, inline);
+            range::respond(global, Arc::new(file), &req, content_type, disposition).await
+        },
+        InodeType::File(file) => {
+            props.entries.push(ShareEntry { name, segment: None, directory: false, size: file.metadata.size });
+            render_share(props).await
+        },
+        InodeType::Directory(_) if share.access == ShareAccess::Upload => {
+            props.uploadable = !arc.config.readonly;
+            render_share(props).await
+        },
+        InodeType::Directory(directory) => {
+            let mut children = directory.list_tuples();
+            children.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (name, stored) in children {
+                let (directory, size) = match stored.get::<InodeType>(global.clone()).await {
+                    Ok(InodeType::Directory(directory)) => (true, Size::Entries(directory.list().len())),
+                    Ok(InodeType::File(file)) => (false, file.metadata.size),
+                    Err(e) => return render_error_status(arc, StatusCode::SERVICE_UNAVAILABLE, e).await,
+                };
+                let segment = Some(format!("{}${}", stored.as_url(), name.replace('$', "%24")));
+                props.entries.push(ShareEntry { name, segment, directory, size });
+            }
+            render_share(props).await
+        },
+    }
+}
+
+#[route("/s/{token}/", method = "POST")]
+async fn upload_link(data: web::Data<Arc<ServerData>>, token: web::Path<String>, payload: Multipart) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    let share = match arc.shares.check(&arc.secret, &token) {
+        Ok(share) => share,
+        Err(e) => return render_error_status(arc, StatusCode::FORBIDDEN, e).await,
+    };
+    if share.access != ShareAccess::Upload {
+        return render_error_status(arc, StatusCode::FORBIDDEN, "This link does not take uploads".to_string()).await;
+    }
+    if arc.config.readonly {
+        return render_error_status(arc, StatusCode::FORBIDDEN, "Server is in read-only mode.".to_string()).await;
+    }
+
+    let form = match read_form(arc.global.clone(), payload, arc.config.max_upload_size).await {
+        Ok(form) => form,
+        Err(e) => return render_error_status(arc, StatusCode::BAD_REQUEST, e).await,
+    };
+    if let Some((filename, file)) = form.file {
+        // the file is already uploaded, so its chunks have to be removed if it can not be added, like when the name is taken
+        let blocks = file.data.clone();
+        if let Err(e) = add_inode(arc.clone(), &vec![share.target.clone()], &filename, file.to_enum()).await {
+            let e = match blocks.delete(arc.global.clone()).await {
+                Ok(_) => e,
+                Err(err) => format!("{}, {}", e, err),
+            };
+            return render_error_status(arc, StatusCode::CONFLICT, e).await;
+        }
+    }
+    HttpResponse::Found()
+        .append_header((header::LOCATION, format!("{}s/{}/?uploaded", arc.config.path, token)))
+        .finish()
+}
+
+/* #endregion */
@@ -0,0 +1,115 @@
+/*
+    TLS for the HTTP service, with the certificate and key read from PEM files. The certificate is picked for every handshake, so it can be replaced while the server runs.
+    SIGHUP reads the files again, which is what certificate renewal tools send after writing new ones. Connections that are already open keep the old certificate.
+ */
+
+use std::{fs::File, io::BufReader, sync::{Arc, RwLock}};
+use actix_web::{web, HttpRequest, HttpResponse, http::header};
+use rustls::{Certificate, PrivateKey, ServerConfig, server::{ClientHello, ResolvesServerCert}, sign::{self, CertifiedKey}};
+
+use super::service::ServerData;
+
+pub struct CertResolver {
+    cert_path: String,
+    key_path: String,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+fn load(cert_path: &str, key_path: &str) -> Result<CertifiedKey, String> {
+    let file = File::open(cert_path).map_err(|e| format!("Failed to open {}: {}", cert_path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file)).map_err(|e| format!("Failed to read {}: {}", cert_path, e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", cert_path));
+    }
+
+    let file = File::open(key_path).map_err(|e| format!("Failed to open {}: {}", key_path, e))?;
+    let key = rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(|e| format!("Failed to read {}: {}", key_path, e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::RSAKey(key) | rustls_pemfile::Item::ECKey(key) => Some(key),
+            _ => None,
+        })
+        .ok_or(format!("No private key found in {}", key_path))?;
+    let key = sign::any_supported_type(&PrivateKey(key)).map_err(|e| format!("Unsupported private key in {}: {}", key_path, e))?;
+
+    Ok(CertifiedKey::new(certs.into_iter().map(Certificate).collect(), key))
+}
+
+impl CertResolver {
+    pub fn new(cert_path: &str, key_path: &str) -> Result<Self, String> {
+        Ok(Self {
+            key: RwLock::new(Arc::new(load(cert_path, key_path)?)),
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+        })
+    }
+
+    // The old certificate stays in use when the new files can not be read
+    pub fn reload(&self) -> Result<(), String> {
+        let key = load(&self.cert_path, &self.key_path)?;
+        *self.key.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.key.read().unwrap().clone()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+pub fn server_config(resolver: Arc<CertResolver>) -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver)
+}
+
+#[cfg(unix)]
+pub fn reload_on_hangup(resolver: Arc<CertResolver>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                println!("Failed to listen for SIGHUP, certificates will not be reloaded: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match resolver.reload() {
+                Ok(_) => println!("Reloaded the TLS certificate"),
+                Err(e) => println!("Failed to reload the TLS certificate: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_hangup(_resolver: Arc<CertResolver>) {}
+
+// The same host without the port of the plain listener, and the same path on the TLS port
+pub fn https_location(host: &str, port: u16, path: &str) -> String {
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !host.ends_with(']') && port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    match port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    }
+}
+
+// Everything that reaches the redirect listener is sent here, 308 keeps the method and the body for API clients
+pub async fn to_https(data: web::Data<Arc<ServerData>>, req: HttpRequest) -> HttpResponse {
+    let path = req.uri().path_and_query().map(|path| path.as_str()).unwrap_or("/");
+    let location = https_location(req.connection_info().host(), data.config.port, path);
+    HttpResponse::PermanentRedirect()
+        .append_header((header::LOCATION, location))
+        .finish()
+}
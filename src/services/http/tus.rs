@@ -0,0 +1,482 @@
+/*
+    Resumable uploads using the tus 1.0 protocol (https://tus.io/protocols/resumable-upload), with the creation, expiration and termination extensions.
+    Every PATCH is cut into chunks that are uploaded as direct blocks right away, so an interrupted upload only loses the chunk in flight.
+    Unfinished uploads are kept in a local file like the root, when one completes it becomes a file in its target directory.
+ */
+
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use actix_web::{web, route, HttpRequest, HttpResponse, HttpResponseBuilder, http::{header::{self, HttpDate}, StatusCode}};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{blocks::{block::{Block, BlockType}, indirect_block::IndirectBlock}, global::Global, persist, inodes::{file::File, inode::InodeType, metadata::detect_mime}};
+use super::{auth::{Access, Identity, identify}, service::{ServerData, get_inode, add_inode, child, resolve}};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TusUpload {
+    #[serde(rename = "p")]
+    pub path: Vec<String>,  // the directory the file goes to, like in the /files/ urls
+    #[serde(rename = "n")]
+    pub name: String,
+    #[serde(rename = "l")]
+    pub length: usize,
+    #[serde(rename = "o")]
+    pub offset: usize,
+    #[serde(rename = "b")]
+    pub blocks: Vec<BlockType>,  // the chunks uploaded so far, in order
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,  // detected from the first chunk
+    #[serde(rename = "e")]
+    pub expires: u64,  // unix time, refreshed by every PATCH
+    #[serde(rename = "u", default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,  // only the user who created the upload can see and continue it
+}
+
+#[derive(Debug, Default)]
+struct Uploads {
+    uploads: HashMap<String, TusUpload>,
+    busy: HashSet<String>,  // uploads with a PATCH in progress
+}
+
+#[derive(Debug, Default)]
+pub struct TusState {
+    path: String,
+    uploads: Mutex<Uploads>,
+}
+
+// Marks an upload as busy until it is dropped, so two PATCH requests can not write the same upload
+struct Busy<'a>(&'a TusState, String);
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.0.uploads.lock().unwrap().busy.remove(&self.1);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+// Also read by garbage collection, which may run in another process than the service
+pub fn load_uploads(path: &str) -> Result<HashMap<String, TusUpload>, String> {
+    persist::load(path, "unfinished uploads")
+}
+
+impl TusState {
+    pub fn load(path: &str) -> Result<Self, String> {
+        Ok(Self {
+            path: path.to_string(),
+            uploads: Mutex::new(Uploads { uploads: load_uploads(path)?, busy: HashSet::new() }),
+        })
+    }
+
+    fn save(&self, uploads: &HashMap<String, TusUpload>) -> Result<(), String> {
+        persist::save(&self.path, uploads, "unfinished uploads")
+    }
+
+    pub fn get(&self, id: &str) -> Option<TusUpload> {
+        let uploads = self.uploads.lock().unwrap();
+        uploads.uploads.get(id).filter(|upload| upload.expires > now()).cloned()
+    }
+
+    fn insert(&self, id: String, upload: TusUpload) -> Result<(), String> {
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.uploads.insert(id, upload);
+        self.save(&uploads.uploads)
+    }
+
+    // Only forgotten once that is saved, otherwise it stays as it was
+    fn remove(&self, id: &str) -> Result<(), String> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let removed = match uploads.uploads.remove(id) {
+            Some(removed) => removed,
+            None => return Ok(()),
+        };
+        if let Err(e) = self.save(&uploads.uploads) {
+            uploads.uploads.insert(id.to_string(), removed);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn lock(&self, id: &str) -> Option<Busy<'_>> {
+        let mut uploads = self.uploads.lock().unwrap();
+        match uploads.busy.insert(id.to_string()) {
+            true => Some(Busy(self, id.to_string())),
+            false => None,
+        }
+    }
+
+    // Forgets the expired uploads and deletes their chunks, uploads that are being written to are left alone
+    pub async fn expire(&self, global: Arc<Global>) -> Vec<String> {
+        let expired = {
+            let mut uploads = self.uploads.lock().unwrap();
+            let time = now();
+            let ids = uploads.uploads.iter()
+                .filter(|(id, upload)| upload.expires <= time && !uploads.busy.contains(*id))
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<String>>();
+            let expired = ids.iter().filter_map(|id| uploads.uploads.remove(id)).collect::<Vec<TusUpload>>();
+            if expired.is_empty() {
+                return Vec::new();
+            }
+            if let Err(e) = self.save(&uploads.uploads) {
+                // the uploads stay in the file, so their chunks must not be deleted yet
+                for (id, upload) in ids.into_iter().zip(expired) {
+                    uploads.uploads.insert(id, upload);
+                }
+                return vec![e];
+            }
+            expired
+        };
+
+        let mut errors = Vec::new();
+        for upload in expired {
+            errors.extend(delete_blocks(global.clone(), &upload.blocks).await);
+        }
+        errors
+    }
+}
+
+async fn delete_blocks(global: Arc<Global>, blocks: &[BlockType]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for block in blocks.iter() {
+        if let Err(e) = block.delete(global.clone()).await {
+            errors.push(e);
+        }
+    }
+    errors
+}
+
+// Empty pieces do not count, an empty PATCH that retries a finished upload can have one
+async fn has_more(payload: &mut web::Payload) -> bool {
+    while let Some(bytes) = payload.next().await {
+        match bytes {
+            Ok(bytes) if bytes.is_empty() => continue,
+            _ => return true,
+        }
+    }
+    false
+}
+
+fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut response = HttpResponse::build(status);
+    response.insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header((header::CACHE_CONTROL, "no-store"));
+    response
+}
+
+fn expires_header(upload: &TusUpload) -> (&'static str, HttpDate) {
+    ("Upload-Expires", HttpDate::from(UNIX_EPOCH + Duration::from_secs(upload.expires)))
+}
+
+// Every request except OPTIONS has to say which version of the protocol it speaks
+fn check_version(req: &HttpRequest) -> Result<(), HttpResponse> {
+    match req.headers().get("Tus-Resumable").and_then(|value| value.to_str().ok()) {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(tus_response(StatusCode::PRECONDITION_FAILED).insert_header(("Tus-Version", TUS_VERSION)).finish()),
+    }
+}
+
+fn header_number(req: &HttpRequest, name: &str) -> Option<usize> {
+    req.headers().get(name)?.to_str().ok()?.trim().parse::<usize>().ok()
+}
+
+// Scripts do not follow redirects to a login page, so they get a status instead
+fn check_user(data: &ServerData, req: &HttpRequest) -> Result<Identity, HttpResponse> {
+    match identify(data, req) {
+        Ok(Some(identity)) => Ok(identity),
+        Ok(None) => Err(tus_response(StatusCode::UNAUTHORIZED).body("Log in or send an API token.")),
+        Err(e) => Err(tus_response(StatusCode::UNAUTHORIZED).body(e)),
+    }
+}
+
+// Uploads of other users are treated as if they did not exist
+fn find_upload(data: &ServerData, id: &str, identity: &Identity) -> Option<TusUpload> {
+    data.tus.get(id).filter(|upload| upload.user == identity.user)
+}
+
+// Upload-Metadata is a list of "key base64(value)" pairs, the value can be left out
+pub fn parse_metadata(header: &str) -> HashMap<String, String> {
+    header.split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next().filter(|key| !key.is_empty())?;
+            let value = match parts.next() {
+                Some(value) => String::from_utf8(STANDARD.decode(value.trim()).ok()?).ok()?,
+                None => String::new(),
+            };
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+// Turns the uploaded chunks into a file in the target directory
+async fn complete(data: Arc<ServerData>, upload: &TusUpload) -> Result<(), String> {
+    let block = IndirectBlock::from_blocks(data.global.clone(), upload.blocks.clone()).await?;
+    let mut file = File::from_block(block.clone(), upload.length);
+    file.metadata.mime = upload.mime.clone();
+    let global = data.global.clone();
+    match add_inode(data, &upload.path, &upload.name, file.to_enum()).await {
+        Ok(_) => Ok(()),
+        // the chunks still belong to the upload
+        Err(err) => match IndirectBlock::release(global, block).await {
+            Ok(_) => Err(err),
+            Err(e) => Err(format!("{}, {}", err, e)),
+        },
+    }
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(options)
+        .service(create)
+        .service(head)
+        .service(patch)
+        .service(terminate);
+}
+
+/* #region Routes */
+
+#[route("/tus/{tail:.*}", method = "OPTIONS")]
+async fn options(data: web::Data<Arc<ServerData>>) -> HttpResponse {
+    let mut response = tus_response(StatusCode::NO_CONTENT);
+    response.insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS));
+    if let Some(max_size) = data.config.max_upload_size {
+        response.insert_header(("Tus-Max-Size", max_size.to_string()));
+    }
+    response.finish()
+}
+
+#[route("/tus/files/{path:.*}", method = "POST")]
+async fn create(data: web::Data<Arc<ServerData>>, path: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    if let Err(response) = check_version(&req) {
+        return response;
+    }
+    if data.config.readonly {
+        return tus_response(StatusCode::FORBIDDEN).body("Server is in read-only mode.");
+    }
+    if data.config.see_root && path.is_empty() {
+        return tus_response(StatusCode::FORBIDDEN).body("Unauthorized.");
+    }
+    let identity = match check_user(&arc, &req) {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+
+    let length = match header_number(&req, "Upload-Length") {
+        Some(length) => length,
+        None => return tus_response(StatusCode::BAD_REQUEST).body("Upload-Length is required."),
+    };
+    if data.config.max_upload_size.is_some_and(|max_size| length > max_size) {
+        return tus_response(StatusCode::PAYLOAD_TOO_LARGE).body("The upload is larger than the limit.");
+    }
+    let metadata = match req.headers().get("Upload-Metadata").and_then(|value| value.to_str().ok()) {
+        Some(value) => parse_metadata(value),
+        None => HashMap::new(),
+    };
+    let name = match metadata.get("filename").or(metadata.get("name")) {
+        Some(name) if !name.is_empty() => name.clone(),
+        _ => return tus_response(StatusCode::BAD_REQUEST).body("The filename is missing from Upload-Metadata."),
+    };
+
+    // fail early if the target is not a directory, instead of after the whole upload
+    let path = path.into_inner().split('/').map(|part| part.to_string()).filter(|part| !part.is_empty()).collect::<Vec<String>>();
+    match resolve(arc.clone(), &identity, &path).await {
+        Ok(names) if identity.lowest(&child(&names, &name)) >= Access::Write => {},
+        Ok(_) => return tus_response(StatusCode::FORBIDDEN).body("You can not upload here."),
+        Err(e) => return tus_response(StatusCode::FORBIDDEN).body(e),
+    }
+    if !path.is_empty() {
+        match get_inode(arc.clone(), &path).await {
+            Ok(InodeType::Directory(_)) => {},
+            Ok(_) => return tus_response(StatusCode::BAD_REQUEST).body("Path is not a directory"),
+            Err(e) => return tus_response(StatusCode::NOT_FOUND).body(e),
+        }
+    }
+
+    let id = format!("{:032x}", rand::random::<u128>());
+    let upload = TusUpload {
+        path,
+        name,
+        length,
+        offset: 0,
+        blocks: Vec::new(),
+        mime: None,
+        expires: now() + data.config.tus_expiration,
+        user: identity.user,
+    };
+
+    // an empty upload is complete right away
+    let result = match length {
+        0 => complete(arc.clone(), &upload).await,
+        _ => data.tus.insert(id.clone(), upload.clone()),
+    };
+    match result {
+        Ok(_) => tus_response(StatusCode::CREATED)
+            .insert_header((header::LOCATION, format!("{}tus/uploads/{}", data.config.path, id)))
+            .insert_header(expires_header(&upload))
+            .finish(),
+        Err(e) => tus_response(StatusCode::INTERNAL_SERVER_ERROR).body(e),
+    }
+}
+
+#[route("/tus/uploads/{id}", method = "HEAD")]
+async fn head(data: web::Data<Arc<ServerData>>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    if let Err(response) = check_version(&req) {
+        return response;
+    }
+    let identity = match check_user(&data, &req) {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    match find_upload(&data, &id, &identity) {
+        Some(upload) => tus_response(StatusCode::OK)
+            .insert_header(("Upload-Offset", upload.offset.to_string()))
+            .insert_header(("Upload-Length", upload.length.to_string()))
+            .insert_header(expires_header(&upload))
+            .finish(),
+        None => tus_response(StatusCode::NOT_FOUND).finish(),
+    }
+}
+
+#[route("/tus/uploads/{id}", method = "PATCH")]
+async fn patch(data: web::Data<Arc<ServerData>>, id: web::Path<String>, mut payload: web::Payload, req: HttpRequest) -> HttpResponse {
+    let arc = data.as_ref().clone();
+    let global = arc.global.clone();
+    if let Err(response) = check_version(&req) {
+        return response;
+    }
+    if req.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()) != Some("application/offset+octet-stream") {
+        return tus_response(StatusCode::UNSUPPORTED_MEDIA_TYPE).finish();
+    }
+    let identity = match check_user(&data, &req) {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    if find_upload(&data, &id, &identity).is_none() {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+    let _busy = match data.tus.lock(&id) {
+        Some(busy) => busy,
+        None => return tus_response(StatusCode::LOCKED).body("The upload is already being written to."),
+    };
+    let mut upload = match data.tus.get(&id) {
+        Some(upload) => upload,
+        None => return tus_response(StatusCode::NOT_FOUND).finish(),
+    };
+    match header_number(&req, "Upload-Offset") {
+        Some(offset) if offset == upload.offset => {},
+        Some(_) => return tus_response(StatusCode::CONFLICT).body("Upload-Offset does not match the upload."),
+        None => return tus_response(StatusCode::BAD_REQUEST).body("Upload-Offset is required."),
+    }
+
+    // the body is cut into chunks as big as the buckets allow, every chunk is saved as soon as it is complete
+    let mut chunk = Vec::new();
+    let mut error = None;
+    let mut ended = false;
+    while upload.offset < upload.length && error.is_none() {
+        let plan = match global.redundancy.plan(&global, upload.length - upload.offset) {
+            Ok(plan) => plan,
+            Err(e) => {
+                error = Some((StatusCode::INTERNAL_SERVER_ERROR, e));
+                break;
+            }
+        };
+        let len = plan.len;
+        while chunk.len() < len && !ended {
+            match payload.next().await {
+                Some(Ok(bytes)) => chunk.extend_from_slice(&bytes),
+                Some(Err(e)) => {
+                    // the client is gone, but what arrived so far is still worth keeping
+                    error = Some((StatusCode::BAD_REQUEST, format!("Could not read the upload: {}", e)));
+                    ended = true;
+                },
+                None => ended = true,
+            }
+        }
+        if chunk.is_empty() {
+            break;
+        }
+        let rest = chunk.split_off(std::cmp::min(len, chunk.len()));
+
+        if upload.offset == 0 {
+            upload.mime = detect_mime(&upload.name, &chunk);
+        }
+        let size = chunk.len();
+        match global.redundancy.upload(global.clone(), plan, chunk, upload.offset).await {
+            Ok(block) => {
+                upload.blocks.push(block);
+                upload.offset += size;
+                upload.expires = now() + data.config.tus_expiration;
+                if let Err(e) = data.tus.insert(id.to_string(), upload.clone()) {
+                    error = Some((StatusCode::INTERNAL_SERVER_ERROR, e));
+                }
+            },
+            Err(e) => error = Some((StatusCode::INTERNAL_SERVER_ERROR, e)),
+        }
+        chunk = rest;
+    }
+    if error.is_none() && (!chunk.is_empty() || (!ended && has_more(&mut payload).await)) {
+        error = Some((StatusCode::BAD_REQUEST, "The body is longer than Upload-Length.".to_string()));
+    }
+    if let Some((status, e)) = error {
+        return tus_response(status).insert_header(("Upload-Offset", upload.offset.to_string())).body(e);
+    }
+
+    if upload.offset == upload.length {
+        // the upload is forgotten before the file is linked, so expiring it can never delete the chunks of a finished file.
+        // if linking fails it is put back, so it can be retried or it expires with its chunks
+        if let Err(e) = data.tus.remove(&id) {
+            return tus_response(StatusCode::INTERNAL_SERVER_ERROR).body(e);
+        }
+        if let Err(e) = complete(arc.clone(), &upload).await {
+            let e = match data.tus.insert(id.to_string(), upload.clone()) {
+                Ok(_) => e,
+                Err(err) => format!("{}, {}", e, err),
+            };
+            return tus_response(StatusCode::INTERNAL_SERVER_ERROR).body(e);
+        }
+    }
+
+    tus_response(StatusCode::NO_CONTENT)
+        .insert_header(("Upload-Offset", upload.offset.to_string()))
+        .insert_header(expires_header(&upload))
+        .finish()
+}
+
+#[route("/tus/uploads/{id}", method = "DELETE")]
+async fn terminate(data: web::Data<Arc<ServerData>>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    if let Err(response) = check_version(&req) {
+        return response;
+    }
+    let identity = match check_user(&data, &req) {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    if find_upload(&data, &id, &identity).is_none() {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+    let _busy = match data.tus.lock(&id) {
+        Some(busy) => busy,
+        None => return tus_response(StatusCode::LOCKED).body("The upload is being written to."),
+    };
+    let upload = match data.tus.get(&id) {
+        Some(upload) => upload,
+        None => return tus_response(StatusCode::NOT_FOUND).finish(),
+    };
+    if let Err(e) = data.tus.remove(&id) {
+        return tus_response(StatusCode::INTERNAL_SERVER_ERROR).body(e);
+    }
+    let errors = delete_blocks(data.global.clone(), &upload.blocks).await;
+    match errors.is_empty() {
+        true => tus_response(StatusCode::NO_CONTENT).finish(),
+        false => tus_response(StatusCode::INTERNAL_SERVER_ERROR).body(errors.join(", ")),
+    }
+}
+/* #endregion */
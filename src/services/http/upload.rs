@@ -0,0 +1,119 @@
+/*
+    Reads the multipart forms posted to /files/ without buffering them.
+    The uploaded file is cut into chunks while it is still arriving, so uploads do not have to fit in memory or on disk.
+ */
+
+use std::{fmt::Display, sync::Arc};
+use actix_multipart::{Field, Multipart};
+use actix_web::web;
+use futures::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio_util::io::StreamReader;
+
+use crate::{blocks::{block::Block, redundancy::Redundancy}, global::Global, inodes::file::File};
+
+const MAX_TEXT_FIELD: usize = 64 * 1024; // the other fields are short, so they are kept in memory
+
+#[derive(Default)]
+pub struct PostForm {
+    pub file: Option<(String, File)>, // the name and the already uploaded file
+    pub redundancy: Option<String>,
+    pub directory_name: Option<String>,
+    pub request: Option<String>,
+    pub paste_name: Option<String>,
+}
+
+async fn read_text(field: &mut Field) -> Result<String, String> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.next().await {
+        data.extend(chunk.map_err(|e| format!("Could not read the form: {}", e))?);
+        if data.len() > MAX_TEXT_FIELD {
+            return Err(format!("The field {} is too long", field.name()));
+        }
+    }
+    String::from_utf8(data).map_err(|_| format!("The field {} is not valid text", field.name()))
+}
+
+// Creates a file from a stream that can not leave this thread, like a request body, failing once more than max_size bytes arrived
+pub async fn upload_stream<S, E>(global: Arc<Global>, name: &str, stream: &mut S, redundancy: Redundancy, max_size: Option<usize>) -> Result<File, String>
+where S: Stream<Item = Result<web::Bytes, E>> + Unpin, E: Display {
+    // the chunks are created on other threads, so the data is passed on through a channel
+    let (sender, mut receiver) = mpsc::channel::<std::io::Result<web::Bytes>>(4);
+    let feed = async move {
+        let mut received = 0usize;
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => {
+                    received += chunk.len();
+                    match max_size {
+                        Some(max_size) if received > max_size => Err(std::io::Error::other(format!("The upload is larger than the limit of {} bytes", max_size))),
+                        _ => Ok(chunk),
+                    }
+                },
+                Err(e) => Err(std::io::Error::other(format!("Could not read the upload: {}", e))),
+            };
+            let failed = chunk.is_err();
+            if sender.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    };
+    let stream = async_stream::stream! {
+        while let Some(chunk) = receiver.recv().await {
+            yield chunk;
+        }
+    };
+    // the reader owns the receiver, so it goes away with the upload and the feed can not wait for it forever
+    let create = async move {
+        let mut reader = StreamReader::new(Box::pin(stream));
+        File::create_named(global, name, &mut reader, redundancy).await
+    };
+    tokio::pin!(feed, create);
+    tokio::select! {
+        file = &mut create => file,  // the rest of the body is not needed, finished or not
+        _ = &mut feed => create.await,
+    }
+}
+
+// Reads the whole form, the file is uploaded as soon as its field arrives
+pub async fn read_form(global: Arc<Global>, mut payload: Multipart, max_upload_size: Option<usize>) -> Result<PostForm, String> {
+    let mut form = PostForm::default();
+    let result = async {
+        while let Some(field) = payload.next().await {
+            let mut field = field.map_err(|e| format!("Could not read the form: {}", e))?;
+            match field.name() {
+                // the redundancy field comes before the file in the upload form
+                "file" if form.file.is_none() => {
+                    let name = match field.content_disposition().get_filename() {
+                        Some(name) if !name.is_empty() => name.to_string(),
+                        _ => continue, // no file was selected
+                    };
+                    let redundancy = match &form.redundancy {
+                        Some(redundancy) if !redundancy.is_empty() => redundancy.parse::<Redundancy>()?,
+                        _ => global.redundancy.clone(),
+                    };
+                    let file = upload_stream(global.clone(), &name, &mut field, redundancy, max_upload_size).await?;
+                    form.file = Some((name, file));
+                },
+                "file" => return Err("Only one file can be uploaded at once".to_string()),
+                "redundancy" => form.redundancy = Some(read_text(&mut field).await?),
+                "directory_name" => form.directory_name = Some(read_text(&mut field).await?),
+                "request" => form.request = Some(read_text(&mut field).await?),
+                "paste_name" => form.paste_name = Some(read_text(&mut field).await?),
+                _ => { read_text(&mut field).await?; },
+            }
+        }
+        Ok::<(), String>(())
+    }.await;
+
+    // the file is not referenced anywhere yet, so it has to go if the rest of the form is broken
+    if let Err(err) = result {
+        if let Some((_, file)) = form.file {
+            if let Err(e) = file.data.delete(global).await {
+                return Err(format!("{}, {}", err, e));
+            }
+        }
+        return Err(err);
+    }
+    Ok(form)
+}
@@ -1,2 +1,5 @@
+pub mod crypto;
 pub mod http;
-pub mod service;
\ No newline at end of file
+pub mod s3;
+pub mod service;
+pub mod webdav;
\ No newline at end of file
@@ -0,0 +1,233 @@
+/*
+    AWS Signature Version 4, in the Authorization header or in a presigned url.
+    https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-authenticating-requests.html
+ */
+
+use actix_web::{HttpRequest, http::StatusCode};
+use chrono::{NaiveDateTime, Utc};
+use serde::Deserialize;
+
+use crate::services::crypto::{constant_eq, hex, hmac, sha256_hex};
+use super::xml::S3Error;
+
+pub const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+pub const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+const MAX_SKEW: i64 = 15 * 60; // seconds
+const MAX_EXPIRES: i64 = 7 * 24 * 60 * 60; // seconds, the longest a presigned url may live
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Credential {
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+// What a verified request was signed with, the chunks of a streaming upload are signed with the same key
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub key: Vec<u8>,
+    pub date: String,  // yyyymmddThhmmssZ
+    pub scope: String,  // date/region/service/aws4_request
+    pub signature: String,
+    pub payload: String,  // x-amz-content-sha256, a hash or how the body is sent
+}
+
+pub fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+// Everything except the unreserved characters is percent encoded, the slashes of a path are kept
+pub fn uri_encode(text: &str, encode_slash: bool) -> String {
+    encode_bytes(text.as_bytes(), encode_slash)
+}
+
+fn encode_bytes(bytes: &[u8], encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for &byte in bytes {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// The path as it is signed, decoded and encoded again so every client ends up with the same one
+pub fn canonical_uri(path: &str) -> String {
+    encode_bytes(&urlencoding::decode_binary(path.as_bytes()), false)
+}
+
+fn decode(text: &str) -> String {
+    String::from_utf8_lossy(&urlencoding::decode_binary(text.as_bytes())).into_owned()
+}
+
+// The query pairs decoded, in the order they were sent
+pub fn query_pairs(query: &str) -> Vec<(String, String)> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (key, value),
+            None => (pair, ""),
+        })
+        .map(|(key, value)| (decode(key), decode(value)))
+        .collect()
+}
+
+pub fn canonical_query(pairs: &[(String, String)]) -> String {
+    let mut pairs = pairs.iter()
+        .filter(|(key, _)| key != "X-Amz-Signature")
+        .map(|(key, value)| (uri_encode(key, true), uri_encode(value, true)))
+        .collect::<Vec<(String, String)>>();
+    pairs.sort();
+    pairs.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<String>>().join("&")
+}
+
+pub fn canonical_request(method: &str, path: &str, query: &str, headers: &[(String, String)], signed_headers: &str, payload: &str) -> String {
+    let headers = headers.iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.split_whitespace().collect::<Vec<&str>>().join(" ")))
+        .collect::<String>();
+    format!("{}\n{}\n{}\n{}\n{}\n{}", method, path, query, headers, signed_headers, payload)
+}
+
+pub fn string_to_sign(date: &str, scope: &str, canonical_request: &str) -> String {
+    format!("{}\n{}\n{}\n{}", ALGORITHM, date, scope, sha256_hex(canonical_request.as_bytes()))
+}
+
+// Every chunk of a streaming upload is signed on top of the signature of the previous one
+pub fn chunk_signature(signature: &Signature, previous: &str, chunk: &[u8]) -> String {
+    let string_to_sign = format!("{}-PAYLOAD\n{}\n{}\n{}\n{}\n{}", ALGORITHM, signature.date, signature.scope, previous, EMPTY_SHA256, sha256_hex(chunk));
+    hex(&hmac(&signature.key, string_to_sign.as_bytes()))
+}
+
+fn denied(code: &'static str, message: &str) -> S3Error {
+    S3Error::new(StatusCode::FORBIDDEN, code, message)
+}
+
+// Parses "AWS4-HMAC-SHA256 Credential=..., SignedHeaders=..., Signature=..."
+fn authorization_fields(header: &str) -> Option<(String, String, String)> {
+    let fields = header.strip_prefix(ALGORITHM)?;
+    let (mut credential, mut signed_headers, mut signature) = (None, None, None);
+    for field in fields.split(',') {
+        match field.trim().split_once('=') {
+            Some(("Credential", value)) => credential = Some(value.to_string()),
+            Some(("SignedHeaders", value)) => signed_headers = Some(value.to_string()),
+            Some(("Signature", value)) => signature = Some(value.to_string()),
+            _ => {},
+        }
+    }
+    Some((credential?, signed_headers?, signature?))
+}
+
+// Checks the signature of the request against the configured credentials
+pub fn verify(req: &HttpRequest, credentials: &[Credential], region: &str) -> Result<Signature, S3Error> {
+    let pairs = query_pairs(req.query_string());
+    let query = |name: &str| pairs.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone());
+    let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+
+    let (credential, signed_headers, signature, date, payload) = match header("authorization") {
+        Some(authorization) => {
+            let (credential, signed_headers, signature) = authorization_fields(&authorization)
+                .ok_or(S3Error::new(StatusCode::BAD_REQUEST, "AuthorizationHeaderMalformed", "The authorization header is malformed"))?;
+            let date = header("x-amz-date").ok_or(denied("AccessDenied", "x-amz-date is required"))?;
+            let payload = header("x-amz-content-sha256").ok_or(S3Error::new(StatusCode::BAD_REQUEST, "InvalidRequest", "x-amz-content-sha256 is required"))?;
+            (credential, signed_headers, signature, date, payload)
+        },
+        None if query("X-Amz-Algorithm").as_deref() == Some(ALGORITHM) => {
+            let field = |name: &str| query(name).ok_or(S3Error::new(StatusCode::BAD_REQUEST, "AuthorizationQueryParametersError", &format!("{} is required", name)));
+            let date = field("X-Amz-Date")?;
+            let expires = field("X-Amz-Expires")?.parse::<i64>().map_err(|_| S3Error::new(StatusCode::BAD_REQUEST, "AuthorizationQueryParametersError", "X-Amz-Expires is not a number"))?;
+            if !(1..=MAX_EXPIRES).contains(&expires) {
+                return Err(S3Error::new(StatusCode::BAD_REQUEST, "AuthorizationQueryParametersError", "X-Amz-Expires must be between 1 and 604800 seconds"));
+            }
+            let signed = NaiveDateTime::parse_from_str(&date, "%Y%m%dT%H%M%SZ").map_err(|_| denied("AccessDenied", "X-Amz-Date is invalid"))?;
+            let age = Utc::now().naive_utc().signed_duration_since(signed).num_seconds();
+            // signed in the future, so it would stay valid for longer than it claims
+            if age < -MAX_SKEW {
+                return Err(denied("AccessDenied", "Request is not valid yet"));
+            }
+            if age > expires {
+                return Err(denied("AccessDenied", "Request has expired"));
+            }
+            (field("X-Amz-Credential")?, field("X-Amz-SignedHeaders")?, field("X-Amz-Signature")?, date, UNSIGNED_PAYLOAD.to_string())
+        },
+        None => return Err(denied("AccessDenied", "Anonymous access is not allowed")),
+    };
+
+    // Credential is access_key/date/region/service/aws4_request
+    let (access_key, scope) = credential.split_once('/').ok_or(denied("AccessDenied", "The credential is malformed"))?;
+    let scope_parts = scope.split('/').collect::<Vec<&str>>();
+    if scope_parts.len() != 4 || scope_parts[3] != "aws4_request" || !date.starts_with(scope_parts[0]) {
+        return Err(denied("AccessDenied", "The credential scope is malformed"));
+    }
+    if scope_parts[1] != region || scope_parts[2] != "s3" {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "AuthorizationHeaderMalformed", &format!("The region is wrong, expecting {}", region)));
+    }
+    let secret = credentials.iter().find(|credential| credential.access_key == access_key)
+        .ok_or(denied("InvalidAccessKeyId", "The access key does not exist"))?;
+
+    if header("authorization").is_some() {
+        let signed = NaiveDateTime::parse_from_str(&date, "%Y%m%dT%H%M%SZ").map_err(|_| denied("AccessDenied", "x-amz-date is invalid"))?;
+        if Utc::now().naive_utc().signed_duration_since(signed).num_seconds().abs() > MAX_SKEW {
+            return Err(denied("RequestTimeTooSkewed", "The difference between the request time and the server's time is too large"));
+        }
+    }
+
+    let headers = signed_headers.split(';')
+        .map(|name| (name.to_string(), req.headers().get_all(name).filter_map(|value| value.to_str().ok()).collect::<Vec<&str>>().join(",").trim().to_string()))
+        .collect::<Vec<(String, String)>>();
+    let canonical = canonical_request(req.method().as_str(), &canonical_uri(req.path()), &canonical_query(&pairs), &headers, &signed_headers, &payload);
+    let key = signing_key(&secret.secret_key, scope_parts[0], scope_parts[1], scope_parts[2]);
+    let expected = hex(&hmac(&key, string_to_sign(&date, scope, &canonical).as_bytes()));
+    if !constant_eq(&expected, &signature) {
+        return Err(denied("SignatureDoesNotMatch", "The request signature we calculated does not match the signature you provided"));
+    }
+
+    Ok(Signature { key, date, scope: scope.to_string(), signature, payload })
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use super::*;
+
+    // the examples from the AWS documentation
+    const SECRET: &str = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+
+    #[test]
+    fn signs_like_aws() {
+        let headers = [
+            ("host".to_string(), "examplebucket.s3.amazonaws.com".to_string()),
+            ("range".to_string(), "bytes=0-9".to_string()),
+            ("x-amz-content-sha256".to_string(), EMPTY_SHA256.to_string()),
+            ("x-amz-date".to_string(), "20130524T000000Z".to_string()),
+        ];
+        let canonical = canonical_request("GET", "/test.txt", "", &headers, "host;range;x-amz-content-sha256;x-amz-date", EMPTY_SHA256);
+        let key = signing_key(SECRET, "20130524", "us-east-1", "s3");
+        let signature = hex(&hmac(&key, string_to_sign("20130524T000000Z", "20130524/us-east-1/s3/aws4_request", &canonical).as_bytes()));
+        assert_eq!(signature, "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41");
+    }
+
+    #[test]
+    fn signs_chunks_like_aws() {
+        let signature = Signature {
+            key: signing_key(SECRET, "20130524", "us-east-1", "s3"),
+            date: "20130524T000000Z".to_string(),
+            scope: "20130524/us-east-1/s3/aws4_request".to_string(),
+            signature: "4f232c4386841ef735655705268965c44a0e4690baa4adea153f7db9fa80a0a9".to_string(),
+            payload: "STREAMING-AWS4-HMAC-SHA256-PAYLOAD".to_string(),
+        };
+        let first = chunk_signature(&signature, &signature.signature, &[b'a'; 65536]);
+        assert_eq!(first, "ad80c730a21e5b8d04586a2213dd63b9a0e99e0e2307b0ade35a65485a288648");
+    }
+
+    #[test]
+    fn canonical_queries() {
+        let pairs = query_pairs("list-type=2&prefix=a%20b%2Fc&delimiter=%2F&uploads");
+        assert_eq!(canonical_query(&pairs), "delimiter=%2F&list-type=2&prefix=a%20b%2Fc&uploads=");
+        assert_eq!(uri_encode("/bucket/a b+c~.txt", false), "/bucket/a%20b%2Bc~.txt");
+        assert_eq!(canonical_uri("/bucket/%C3%A9t%c3%a9%20x"), "/bucket/%C3%A9t%C3%A9%20x");
+    }
+}
@@ -0,0 +1,253 @@
+/*
+    Request bodies, checked against what the signature says about them.
+    A body is either sent as is, with or without its sha256 signed, or in aws-chunked encoding where every chunk carries its own signature.
+    https://docs.aws.amazon.com/AmazonS3/latest/API/sigv4-streaming.html
+ */
+
+use std::{cell::RefCell, rc::Rc};
+use actix_web::{web, http::StatusCode};
+use futures::{Stream, StreamExt, stream::LocalBoxStream};
+use sha2::{Digest, Sha256};
+
+use crate::services::crypto::hex;
+use super::{auth::{Signature, UNSIGNED_PAYLOAD, chunk_signature}, xml::S3Error};
+
+const MAX_CHUNK_HEADER: usize = 4096;
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;  // clients send 64 KiB or 8 MiB chunks
+const MAX_XML_BODY: usize = 4 * 1024 * 1024;  // a list of 10000 parts fits
+
+#[derive(Debug)]
+enum Encoding {
+    Unsigned,
+    Sha256(String),
+    Chunked(Option<Signature>),  // None for the unsigned chunks used with trailing checksums
+}
+
+fn encoding(signature: &Signature) -> Result<Encoding, S3Error> {
+    match signature.payload.as_str() {
+        UNSIGNED_PAYLOAD => Ok(Encoding::Unsigned),
+        "STREAMING-UNSIGNED-PAYLOAD-TRAILER" => Ok(Encoding::Chunked(None)),
+        "STREAMING-AWS4-HMAC-SHA256-PAYLOAD" | "STREAMING-AWS4-HMAC-SHA256-PAYLOAD-TRAILER" => Ok(Encoding::Chunked(Some(signature.clone()))),
+        hash if hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()) => Ok(Encoding::Sha256(hash.to_ascii_lowercase())),
+        _ => Err(S3Error::new(StatusCode::BAD_REQUEST, "InvalidArgument", "x-amz-content-sha256 is not supported")),
+    }
+}
+
+// The decoded body. A stream can only fail with a string, so the error the client should get is kept next to it
+pub struct Body {
+    pub stream: LocalBoxStream<'static, Result<web::Bytes, String>>,
+    error: Rc<RefCell<Option<S3Error>>>,
+}
+
+impl Body {
+    // What to answer when reading the body failed, the client's fault if the body was wrong
+    pub fn error(&self, error: String) -> S3Error {
+        self.error.borrow().clone().unwrap_or(S3Error::from(error))
+    }
+}
+
+// Appends the next piece of the payload to the buffer, false once it ended
+async fn fill<S, E>(payload: &mut S, buffer: &mut Vec<u8>) -> Result<bool, String>
+where S: Stream<Item = Result<web::Bytes, E>> + Unpin, E: std::fmt::Display {
+    match payload.next().await {
+        Some(Ok(bytes)) => {
+            buffer.extend_from_slice(&bytes);
+            Ok(true)
+        },
+        Some(Err(e)) => Err(format!("Could not read the body: {}", e)),
+        None => Ok(false),
+    }
+}
+
+fn find_line(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(2).position(|window| window == b"\r\n")
+}
+
+// Parses "size;chunk-signature=signature", the size is in hex
+fn chunk_header(line: &[u8]) -> Option<(usize, Option<String>)> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (size, extensions) = line.split_once(';').unwrap_or((line, ""));
+    let size = usize::from_str_radix(size.trim(), 16).ok()?;
+    let signature = extensions.split(';')
+        .filter_map(|extension| extension.trim().strip_prefix("chunk-signature="))
+        .map(|signature| signature.to_string())
+        .next();
+    Some((size, signature))
+}
+
+pub fn decode<S, E>(mut payload: S, signature: &Signature) -> Result<Body, S3Error>
+where S: Stream<Item = Result<web::Bytes, E>> + Unpin + 'static, E: std::fmt::Display + 'static {
+    let encoding = encoding(signature)?;
+    let error = Rc::new(RefCell::new(None));
+    let failed = error.clone();
+    let fail = move |error: S3Error| {
+        let message = error.to_string();
+        *failed.borrow_mut() = Some(error);
+        Err(message)
+    };
+
+    let stream: LocalBoxStream<'static, Result<web::Bytes, String>> = match encoding {
+        Encoding::Unsigned => Box::pin(payload.map(|chunk| chunk.map_err(|e| format!("Could not read the body: {}", e)))),
+        Encoding::Sha256(expected) => Box::pin(async_stream::stream! {
+            let mut hasher = Sha256::new();
+            while let Some(chunk) = payload.next().await {
+                match chunk {
+                    Ok(chunk) => {
+                        hasher.update(&chunk);
+                        yield Ok(chunk);
+                    },
+                    Err(e) => {
+                        yield Err(format!("Could not read the body: {}", e));
+                        return;
+                    },
+                }
+            }
+            if hex(&hasher.finalize()) != expected {
+                yield fail(S3Error::new(StatusCode::BAD_REQUEST, "XAmzContentSHA256Mismatch", "The provided x-amz-content-sha256 header does not match what was computed"));
+            }
+        }),
+        Encoding::Chunked(signature) => Box::pin(async_stream::stream! {
+            let malformed = || S3Error::new(StatusCode::BAD_REQUEST, "IncompleteBody", "The aws-chunked body is malformed");
+            let mut buffer = Vec::new();
+            let mut previous = signature.as_ref().map(|signature| signature.signature.clone()).unwrap_or_default();
+            loop {
+                // the header line of the chunk
+                let line = loop {
+                    if let Some(end) = find_line(&buffer) {
+                        break end;
+                    }
+                    if buffer.len() > MAX_CHUNK_HEADER {
+                        yield fail(malformed());
+                        return;
+                    }
+                    match fill(&mut payload, &mut buffer).await {
+                        Ok(true) => {},
+                        Ok(false) => {
+                            yield fail(malformed());
+                            return;
+                        },
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        },
+                    }
+                };
+                let (size, chunk_signature_value) = match chunk_header(&buffer[..line]) {
+                    Some(header) if header.0 <= MAX_CHUNK_SIZE => header,
+                    _ => {
+                        yield fail(malformed());
+                        return;
+                    },
+                };
+                buffer.drain(..line + 2);
+
+                // the data, followed by a line break unless it is the last chunk with trailers after it
+                while buffer.len() < size + 2 && (size > 0 || buffer.len() < 2) {
+                    match fill(&mut payload, &mut buffer).await {
+                        Ok(true) => {},
+                        Ok(false) if size == 0 => break,
+                        Ok(false) => {
+                            yield fail(malformed());
+                            return;
+                        },
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        },
+                    }
+                }
+                let data = buffer[..size].to_vec();
+                if let Some(signature) = &signature {
+                    let expected = chunk_signature(signature, &previous, &data);
+                    if chunk_signature_value.as_deref() != Some(expected.as_str()) {
+                        yield fail(S3Error::new(StatusCode::FORBIDDEN, "SignatureDoesNotMatch", "The chunk signature does not match"));
+                        return;
+                    }
+                    previous = expected;
+                }
+                if size == 0 {
+                    // trailing checksums are not checked
+                    return;
+                }
+                if &buffer[size..size + 2] != b"\r\n" {
+                    yield fail(malformed());
+                    return;
+                }
+                buffer.drain(..size + 2);
+                yield Ok(web::Bytes::from(data));
+            }
+        }),
+    };
+
+    Ok(Body { stream, error })
+}
+
+// Reads a small body, like the XML of CompleteMultipartUpload, into memory
+pub async fn read_body(mut body: Body) -> Result<String, S3Error> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.stream.next().await {
+        match chunk {
+            Ok(chunk) => data.extend_from_slice(&chunk),
+            Err(e) => return Err(body.error(e)),
+        }
+        if data.len() > MAX_XML_BODY {
+            return Err(S3Error::new(StatusCode::BAD_REQUEST, "MaxMessageLengthExceeded", "The request body is too large"));
+        }
+    }
+    String::from_utf8(data).map_err(|_| S3Error::new(StatusCode::BAD_REQUEST, "MalformedXML", "The body is not valid text"))
+}
+
+#[cfg(test)]
+mod body_tests {
+    use super::*;
+    use crate::services::crypto::sha256_hex;
+    use super::super::auth::signing_key;
+
+    fn signature(payload: &str) -> Signature {
+        Signature {
+            key: signing_key("secret", "20240101", "us-east-1", "s3"),
+            date: "20240101T000000Z".to_string(),
+            scope: "20240101/us-east-1/s3/aws4_request".to_string(),
+            signature: "seed".to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    async fn collect(payload: Vec<&'static [u8]>, signature: &Signature) -> Result<Vec<u8>, S3Error> {
+        let payload = futures::stream::iter(payload.into_iter().map(|piece| Ok::<_, String>(web::Bytes::from_static(piece))));
+        let mut body = decode(payload, signature)?;
+        let mut data = Vec::new();
+        while let Some(chunk) = body.stream.next().await {
+            data.extend(chunk.map_err(|e| body.error(e))?);
+        }
+        Ok(data)
+    }
+
+    #[actix_web::test]
+    async fn checks_hashes() {
+        assert_eq!(collect(vec![b"hello ", b"world"], &signature(&sha256_hex(b"hello world"))).await.unwrap(), b"hello world");
+        assert_eq!(collect(vec![b"hello ", b"there"], &signature(&sha256_hex(b"hello world"))).await.unwrap_err().code, "XAmzContentSHA256Mismatch");
+        assert_eq!(collect(vec![b"anything"], &signature(UNSIGNED_PAYLOAD)).await.unwrap(), b"anything");
+    }
+
+    #[actix_web::test]
+    async fn decodes_chunks() {
+        let signed = signature("STREAMING-AWS4-HMAC-SHA256-PAYLOAD");
+        let first = chunk_signature(&signed, "seed", b"hello ");
+        let second = chunk_signature(&signed, &first, b"world");
+        let last = chunk_signature(&signed, &second, b"");
+        let body = format!("6;chunk-signature={}\r\nhello \r\n5;chunk-signature={}\r\nworld\r\n0;chunk-signature={}\r\n\r\n", first, second, last);
+        let body: &'static [u8] = Box::leak(body.into_bytes().into_boxed_slice());
+        // split in awkward places
+        let pieces = vec![&body[..3], &body[3..90], &body[90..95], &body[95..]];
+        assert_eq!(collect(pieces, &signed).await.unwrap(), b"hello world");
+
+        let forged = format!("6;chunk-signature={}\r\nhellO \r\n0;chunk-signature={}\r\n\r\n", first, last);
+        let forged: &'static [u8] = Box::leak(forged.into_bytes().into_boxed_slice());
+        assert_eq!(collect(vec![forged], &signed).await.unwrap_err().code, "SignatureDoesNotMatch");
+
+        let unsigned = signature("STREAMING-UNSIGNED-PAYLOAD-TRAILER");
+        assert_eq!(collect(vec![b"b\r\nhello world\r\n0\r\nx-amz-checksum-crc32:DUoRhQ==\r\n\r\n"], &unsigned).await.unwrap(), b"hello world");
+        assert_eq!(collect(vec![b"b\r\nhello"], &unsigned).await.unwrap_err().code, "IncompleteBody");
+    }
+}
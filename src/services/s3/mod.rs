@@ -0,0 +1,5 @@
+pub mod auth;
+pub mod body;
+pub mod multipart;
+pub mod service;
+pub mod xml;
@@ -0,0 +1,154 @@
+/*
+    Multipart uploads. Every part is uploaded into its own chunks right away and the chunks are remembered in a local file, like the tus uploads.
+    Completing an upload moves the chunks of the parts after each other and puts them in one file, nothing is uploaded again.
+ */
+
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
+use serde::{Deserialize, Serialize};
+
+use crate::{blocks::block::{Block, BlockType}, global::Global, persist};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Part {
+    #[serde(rename = "e")]
+    pub etag: String,
+    #[serde(rename = "s")]
+    pub size: usize,
+    #[serde(rename = "b")]
+    pub blocks: Vec<BlockType>,  // starting at 0, they are moved into place on completion
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MultipartUpload {
+    #[serde(rename = "k")]
+    pub key: String,  // with the bucket, like in the path of the request
+    #[serde(rename = "p")]
+    pub parts: BTreeMap<u32, Part>,
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,  // the Content-Type given when the upload was created
+    #[serde(rename = "c")]
+    pub created: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+// Also read by garbage collection, which may run in another process than the service
+pub fn load_uploads(path: &str) -> Result<HashMap<String, MultipartUpload>, String> {
+    persist::load(path, "multipart uploads")
+}
+
+impl MultipartUpload {
+    pub fn new(key: String, mime: Option<String>) -> Self {
+        Self { key, parts: BTreeMap::new(), mime, created: now() }
+    }
+
+    pub fn blocks(&self) -> Vec<BlockType> {
+        self.parts.values().flat_map(|part| part.blocks.iter().cloned()).collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MultipartState {
+    path: String,
+    uploads: Mutex<HashMap<String, MultipartUpload>>,
+}
+
+impl MultipartState {
+    pub fn load(path: &str) -> Result<Self, String> {
+        Ok(Self {
+            path: path.to_string(),
+            uploads: Mutex::new(load_uploads(path)?),
+        })
+    }
+
+    fn save(&self, uploads: &HashMap<String, MultipartUpload>) -> Result<(), String> {
+        persist::save(&self.path, uploads, "multipart uploads")
+    }
+
+    pub fn get(&self, id: &str) -> Option<MultipartUpload> {
+        self.uploads.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn insert(&self, id: String, upload: MultipartUpload) -> Result<(), String> {
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.insert(id, upload);
+        self.save(&uploads)
+    }
+
+    // Takes the upload out, so it can not be completed or aborted twice
+    pub fn take(&self, id: &str) -> Result<Option<MultipartUpload>, String> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads.remove(id);
+        if upload.is_some() {
+            self.save(&uploads)?;
+        }
+        Ok(upload)
+    }
+
+    // Adds the part, returns the one it replaced. Err(None) if the upload is gone, the part still belongs to the caller then
+    pub fn put_part(&self, id: &str, number: u32, part: Part) -> Result<Option<Part>, Option<String>> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads.get_mut(id).ok_or(None)?;
+        let replaced = upload.parts.insert(number, part);
+        self.save(&uploads).map_err(Some)?;
+        Ok(replaced)
+    }
+
+    // Forgets the uploads created more than max_age seconds ago and deletes the chunks of their parts
+    pub async fn expire(&self, global: Arc<Global>, max_age: u64) -> Vec<String> {
+        let expired = {
+            let mut uploads = self.uploads.lock().unwrap();
+            let time = now();
+            let ids = uploads.iter()
+                .filter(|(_, upload)| upload.created.saturating_add(max_age) <= time)
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<String>>();
+            let expired = ids.iter().filter_map(|id| uploads.remove(id)).collect::<Vec<MultipartUpload>>();
+            if expired.is_empty() {
+                return Vec::new();
+            }
+            if let Err(e) = self.save(&uploads) {
+                // the uploads stay in the file, so their chunks must not be deleted yet
+                for (id, upload) in ids.into_iter().zip(expired) {
+                    uploads.insert(id, upload);
+                }
+                return vec![e];
+            }
+            expired
+        };
+        let mut errors = Vec::new();
+        for upload in expired {
+            if let Err(e) = delete_blocks(global.clone(), &upload.blocks()).await {
+                errors.push(e);
+            }
+        }
+        errors
+    }
+
+    // The uploads of the bucket, sorted by key and id
+    pub fn list(&self, bucket: &str) -> Vec<(String, MultipartUpload)> {
+        let prefix = format!("{}/", bucket);
+        let uploads = self.uploads.lock().unwrap();
+        let mut list = uploads.iter()
+            .filter(|(_, upload)| upload.key.starts_with(&prefix))
+            .map(|(id, upload)| (id.clone(), upload.clone()))
+            .collect::<Vec<(String, MultipartUpload)>>();
+        list.sort_by(|(a_id, a), (b_id, b)| (&a.key, a_id).cmp(&(&b.key, b_id)));
+        list
+    }
+}
+
+pub async fn delete_blocks(global: Arc<Global>, blocks: &[BlockType]) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for block in blocks.iter() {
+        if let Err(e) = block.delete(global.clone()).await {
+            errors.push(e);
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join(", ")),
+    }
+}
@@ -0,0 +1,784 @@
+/*
+    An S3 compatible API, so S3 clients and tools like the aws cli or rclone can use chunkdrive.
+    The directories in the root are the buckets and keys are paths inside them, the key "2023/cat.jpg" in the bucket "photos" is the file /photos/2023/cat.jpg.
+    Only path style urls (http://host/bucket/key) are understood. S3 has no directories, so the ones emptied by a delete are removed with it.
+ */
+
+use std::{sync::Arc, time::{Duration, UNIX_EPOCH}};
+use actix_web::{web, App, HttpServer, HttpRequest, HttpResponse, http::{header, StatusCode}};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+
+use crate::{blocks::{block::{Block, BlockType}, indirect_block::IndirectBlock}, global::Global, inodes::{directory::Directory, file::File, inode::InodeType, metadata::{Size, detect_mime}, path::{create_directories, delete_inode, open, open_directory}}, services::{crypto::hex, http::{content::content_disposition, range, upload::upload_stream}, service::Service}};
+use super::{auth::{Credential, query_pairs, uri_encode, verify}, body::{Body, decode, read_body}, multipart::{MultipartState, MultipartUpload, Part, delete_blocks, load_uploads}, xml::{HEADER, NAMESPACE, S3Error, element, elements, escape}};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct S3Service {
+    pub(crate) port: u16,
+    #[serde(default = "default_address")]
+    pub(crate) address: String,
+
+    #[serde(default = "default_region")]
+    pub(crate) region: String, // clients have to sign their requests for this region
+
+    #[serde(default)]
+    pub(crate) readonly: bool,
+
+    pub(crate) credentials: Vec<Credential>,
+
+    #[serde(default = "default_multipart_path")]
+    pub(crate) multipart_path: String, // where the unfinished multipart uploads are kept
+
+    #[serde(default = "default_multipart_expiration")]
+    pub(crate) multipart_expiration: u64, // seconds after its creation before an unfinished multipart upload is deleted
+}
+
+#[derive(Debug)]
+pub struct S3Data {
+    pub global: Arc<Global>,
+    pub config: S3Service,
+    pub multipart: MultipartState,
+}
+
+impl S3Data {
+    pub fn new(global: Arc<Global>, config: S3Service) -> Result<Self, String> {
+        let multipart = MultipartState::load(&config.multipart_path)?;
+        Ok(Self { global, config, multipart })
+    }
+}
+
+fn default_address() -> String { "127.0.0.1".to_string() }
+fn default_region() -> String { "us-east-1".to_string() }
+fn default_multipart_path() -> String { "./s3_multipart.dat".to_string() }
+const fn default_multipart_expiration() -> u64 { 7 * 24 * 60 * 60 }
+
+const MAX_KEYS: usize = 1000;
+const MAX_PART_NUMBER: u32 = 10000;
+const EMPTY_ETAG: &str = "\"d41d8cd98f00b204e9800998ecf8427e\""; // the md5 of nothing, what S3 gives empty objects
+// the query parameters that make a request about something else than the bucket or the object itself
+const SUBRESOURCES: &[&str] = &["acl", "attributes", "cors", "delete", "encryption", "legal-hold", "lifecycle", "location", "logging", "notification", "object-lock", "policy", "replication", "restore", "retention", "tagging", "torrent", "uploadId", "uploads", "versioning", "versions", "website"];
+
+impl Service for S3Service {
+    fn run(&self, global: Arc<Global>) {
+        let data = match S3Data::new(global, self.clone()) {
+            Ok(data) => Arc::new(data),
+            Err(e) => {
+                println!("Failed to run S3 service: {}", e);
+                return;
+            }
+        };
+        std::thread::spawn(move || {
+            match run_blocking(data) {
+                Ok(_) => {},
+                Err(e) => println!("Failed to run S3 service: {}", e),
+            }
+        });
+    }
+
+    fn unfinished(&self) -> Result<Vec<BlockType>, String> {
+        Ok(load_uploads(&self.multipart_path)?.values().flat_map(|upload| upload.blocks()).collect())
+    }
+}
+
+fn run_blocking(data: Arc<S3Data>) -> Result<(), String> {
+    println!("Starting S3 service on http://{}:{}", data.config.address, data.config.port);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let data_clone = data.clone();
+    let expire_data = data.clone();
+    rt.spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            for e in expire_data.multipart.expire(expire_data.global.clone(), expire_data.config.multipart_expiration).await {
+                println!("Failed to delete an expired multipart upload: {}", e);
+            }
+        }
+    });
+    rt.block_on(async {
+        HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(data_clone.clone()))
+                .configure(configure)
+        })
+        .bind(format!("{}:{}", data.config.address, data.config.port))
+        .map_err(|e| format!("Failed to bind to port: {}", e))?
+        .run()
+        .await
+        .map_err(|e| format!("Failed to run server: {}", e))
+    })?;
+
+    Ok(())
+}
+
+// The operation depends on the method, the path and the query, so every request goes through one handler
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.default_service(web::to(dispatch));
+}
+
+fn xml(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/xml")
+        .body(format!("{}{}", HEADER, body))
+}
+
+fn timestamp(seconds: u64) -> String {
+    let time: DateTime<Utc> = (UNIX_EPOCH + Duration::from_secs(seconds)).into();
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn not_implemented() -> S3Error {
+    S3Error::new(StatusCode::NOT_IMPLEMENTED, "NotImplemented", "A header or query you provided implies functionality that is not implemented")
+}
+
+fn conflict(message: &str) -> S3Error {
+    S3Error::new(StatusCode::CONFLICT, "InvalidRequest", message)
+}
+
+// Splits the path into the bucket and the key
+fn target(path: &str) -> Result<(Option<String>, Option<String>), S3Error> {
+    let path = urlencoding::decode(path).map_err(|_| S3Error::new(StatusCode::BAD_REQUEST, "InvalidURI", "The path is not valid utf-8"))?;
+    let path = path.trim_start_matches('/');
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    if matches!(bucket, "." | "..") {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "InvalidBucketName", "The specified bucket is not valid"));
+    }
+    let bucket = Some(bucket.to_string()).filter(|bucket| !bucket.is_empty());
+    let key = Some(key.to_string()).filter(|key| !key.is_empty());
+    Ok((bucket, key))
+}
+
+// The path of the key, starting with the bucket, and whether it names a directory like "2023/"
+fn key_names(bucket: &str, key: &str) -> Result<(Vec<String>, bool), S3Error> {
+    let directory = key.ends_with('/');
+    let mut names = vec![bucket.to_string()];
+    for name in key.strip_suffix('/').unwrap_or(key).split('/') {
+        match name {
+            "" | "." | ".." => return Err(S3Error::invalid_argument("Keys can not have empty, . or .. parts")),
+            name => names.push(name.to_string()),
+        }
+    }
+    Ok((names, directory))
+}
+
+fn param<'a>(query: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+// The Content-Type the client gave, the ones sent by default are left to detection
+fn content_type(req: &HttpRequest) -> Option<String> {
+    header_value(req, "content-type")
+        .filter(|value| !matches!(*value, "application/octet-stream" | "binary/octet-stream" | "application/x-www-form-urlencoded"))
+        .map(|value| value.to_string())
+}
+
+fn file_size(file: &File) -> usize {
+    match file.metadata.size {
+        Size::Bytes(size) => size,
+        _ => 0,
+    }
+}
+
+async fn dispatch(data: web::Data<Arc<S3Data>>, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let data = data.as_ref().clone();
+    handle(data, &req, payload).await.unwrap_or_else(|e| e.response(req.path()))
+}
+
+async fn handle(data: Arc<S3Data>, req: &HttpRequest, payload: web::Payload) -> Result<HttpResponse, S3Error> {
+    let signature = verify(req, &data.config.credentials, &data.config.region)?;
+    let (bucket, key) = target(req.path())?;
+    let query = query_pairs(req.query_string());
+    let subresource = query.iter().map(|(key, _)| key.as_str()).find(|key| SUBRESOURCES.contains(key));
+    let method = req.method().as_str();
+    if data.config.readonly && !matches!(method, "GET" | "HEAD") {
+        return Err(S3Error::new(StatusCode::FORBIDDEN, "AccessDenied", "Server is in read-only mode"));
+    }
+    let copy = req.headers().contains_key("x-amz-copy-source");
+
+    match (method, bucket, key, subresource) {
+        ("GET", None, None, None) => list_buckets(data).await,
+        (_, None, _, _) => Err(S3Error::new(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed", "The specified method is not allowed against this resource")),
+
+        ("GET", Some(bucket), None, None) => list_objects(data, &bucket, &query).await,
+        ("GET", Some(bucket), None, Some("location")) => {
+            open_bucket(data.global.clone(), &bucket).await?;
+            // us-east-1 is the default, which S3 leaves empty
+            let region = Some(data.config.region.as_str()).filter(|region| *region != "us-east-1").unwrap_or_default();
+            Ok(xml(format!("<LocationConstraint xmlns=\"{}\">{}</LocationConstraint>", NAMESPACE, escape(region))))
+        },
+        ("GET", Some(bucket), None, Some("uploads")) => list_uploads(data, &bucket).await,
+        ("HEAD", Some(bucket), None, None) => {
+            open_bucket(data.global.clone(), &bucket).await?;
+            Ok(HttpResponse::Ok().insert_header(("x-amz-bucket-region", data.config.region.clone())).finish())
+        },
+        ("PUT", Some(bucket), None, None) => create_bucket(data, &bucket).await,
+        ("DELETE", Some(bucket), None, None) => delete_bucket(data, &bucket).await,
+        ("POST", Some(bucket), None, Some("delete")) => delete_objects(data, &bucket, decode(payload, &signature)?).await,
+
+        ("GET" | "HEAD", Some(bucket), Some(key), None) => get_object(data, &bucket, &key, req).await,
+        ("PUT", Some(bucket), Some(key), None) if copy => copy_object(data, &bucket, &key, req).await,
+        ("PUT", Some(bucket), Some(key), None) => put_object(data, &bucket, &key, req, decode(payload, &signature)?).await,
+        ("DELETE", Some(bucket), Some(key), None) => {
+            let (names, directory) = key_names(&bucket, &key)?;
+            open_bucket(data.global.clone(), &bucket).await?;
+            remove(&data, &names, directory).await?;
+            Ok(HttpResponse::NoContent().finish())
+        },
+
+        ("POST", Some(bucket), Some(key), Some("uploads")) => create_multipart(data, &bucket, &key, req).await,
+        ("PUT", Some(bucket), Some(key), Some("uploadId")) if !copy => upload_part(data, &bucket, &key, &query, decode(payload, &signature)?).await,
+        ("POST", Some(bucket), Some(key), Some("uploadId")) => complete_multipart(data, &bucket, &key, &query, decode(payload, &signature)?).await,
+        ("DELETE", Some(bucket), Some(key), Some("uploadId")) => abort_multipart(data, &bucket, &key, &query).await,
+        ("GET", Some(bucket), Some(key), Some("uploadId")) => list_parts(data, &bucket, &key, &query).await,
+
+        _ => Err(not_implemented()),
+    }
+}
+
+// The directory of the bucket, buckets are the directories in the root
+async fn open_bucket(global: Arc<Global>, bucket: &str) -> Result<Directory, S3Error> {
+    match open_directory(global, &[bucket.to_string()]).await? {
+        Some(bucket) => Ok(bucket.directory),
+        None => Err(S3Error::no_such_bucket()),
+    }
+}
+
+// True if a file is where one of the directories should be. S3 allows both "a" and "a/b", chunkdrive does not
+async fn blocked(global: Arc<Global>, names: &[String]) -> Result<bool, String> {
+    let mut directory = global.get_root()?;
+    for name in names {
+        let stored = match directory.get(name) {
+            Ok(stored) => stored.clone(),
+            Err(_) => return Ok(false),
+        };
+        directory = match stored.get::<InodeType>(global.clone()).await? {
+            InodeType::Directory(directory) => directory,
+            InodeType::File(_) => return Ok(true),
+        };
+    }
+    Ok(false)
+}
+
+// Links the file at the path, replacing the file that was there, and returns its ETag. The file is deleted if it can not be linked
+async fn place(data: &S3Data, names: &[String], file: File) -> Result<String, S3Error> {
+    let global = data.global.clone();
+    let _tree = data.global.lock_tree().await;
+    let etag = range::etag(&file, file_size(&file));
+    let file_data = file.data.clone();
+    let (name, parents) = names.split_last().ok_or(S3Error::invalid_argument("The key is empty"))?;
+    let result = async {
+        let mut parent = create_directories(global.clone(), parents).await?;
+        if let Ok(stored) = parent.directory.get(name) {
+            if let InodeType::Directory(_) = stored.get::<InodeType>(global.clone()).await? {
+                return Err(conflict("A directory with this name already exists"));
+            }
+        }
+        let replaced = parent.directory.unlink(name).ok();
+        parent.directory.add(global.clone(), name, file.to_enum()).await?;
+        parent.save(global.clone()).await?;
+        Ok::<_, S3Error>(replaced)
+    }.await;
+    match result {
+        Ok(Some(replaced)) => delete_inode(global, replaced).await?,
+        Ok(None) => {},
+        Err(e) => {
+            file_data.delete(global).await.map_err(|err| format!("{}, {}", e.message, err))?;
+            return Err(e);
+        },
+    }
+    Ok(etag)
+}
+
+// S3 has no directories, so the ones left empty go away. The bucket stays
+async fn prune(global: Arc<Global>, names: &[String]) -> Result<(), String> {
+    for depth in (2..=names.len()).rev() {
+        let (name, parents) = names[..depth].split_last().unwrap();
+        let mut parent = match open_directory(global.clone(), parents).await? {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+        let stored = match parent.directory.get(name) {
+            Ok(stored) => stored.clone(),
+            Err(_) => return Ok(()),
+        };
+        match stored.get::<InodeType>(global.clone()).await? {
+            InodeType::Directory(directory) if directory.list().is_empty() => {},
+            _ => return Ok(()),
+        }
+        parent.directory.unlink(name)?;
+        parent.save(global.clone()).await?;
+        delete_inode(global.clone(), stored).await?;
+    }
+    Ok(())
+}
+
+// Deletes the key, keys that do not exist are not an error. A directory only goes away if it is empty
+async fn remove(data: &S3Data, names: &[String], directory: bool) -> Result<(), String> {
+    let global = data.global.clone();
+    let _tree = data.global.lock_tree().await;
+    let (name, parents) = match names.split_last() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    let mut parent = match open_directory(global.clone(), parents).await? {
+        Some(parent) => parent,
+        None => return Ok(()),
+    };
+    let stored = match parent.directory.get(name) {
+        Ok(stored) => stored.clone(),
+        Err(_) => return Ok(()),
+    };
+    match (stored.get::<InodeType>(global.clone()).await?, directory) {
+        (InodeType::File(_), false) => {},
+        (InodeType::Directory(inner), true) if inner.list().is_empty() => {},
+        _ => return Ok(()),
+    }
+    parent.directory.unlink(name)?;
+    parent.save(global.clone()).await?;
+    delete_inode(global.clone(), stored).await?;
+    prune(global, parents).await
+}
+
+async fn list_buckets(data: Arc<S3Data>) -> Result<HttpResponse, S3Error> {
+    let mut children = data.global.get_root()?.list_tuples();
+    children.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut buckets = String::new();
+    for (name, stored) in children {
+        if let InodeType::Directory(directory) = stored.get::<InodeType>(data.global.clone()).await? {
+            buckets.push_str(&format!("<Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket>", escape(&name), timestamp(directory.metadata.created)));
+        }
+    }
+    Ok(xml(format!(
+        "<ListAllMyBucketsResult xmlns=\"{}\"><Owner><ID>chunkdrive</ID><DisplayName>chunkdrive</DisplayName></Owner><Buckets>{}</Buckets></ListAllMyBucketsResult>",
+        NAMESPACE, buckets
+    )))
+}
+
+async fn create_bucket(data: Arc<S3Data>, bucket: &str) -> Result<HttpResponse, S3Error> {
+    let global = data.global.clone();
+    let _tree = data.global.lock_tree().await;
+    let mut root = open_directory(global.clone(), &[]).await?.ok_or("The root can not be opened".to_string())?;
+    let name = bucket.to_string();
+    if root.directory.get(&name).is_ok() {
+        return Err(S3Error::new(StatusCode::CONFLICT, "BucketAlreadyOwnedByYou", "A file or bucket with this name already exists"));
+    }
+    root.directory.add(global.clone(), &name, Directory::new().to_enum()).await?;
+    root.save(global).await?;
+    Ok(HttpResponse::Ok().insert_header((header::LOCATION, format!("/{}", uri_encode(bucket, true)))).finish())
+}
+
+async fn delete_bucket(data: Arc<S3Data>, bucket: &str) -> Result<HttpResponse, S3Error> {
+    let global = data.global.clone();
+    let _tree = data.global.lock_tree().await;
+    if !open_bucket(global.clone(), bucket).await?.list().is_empty() {
+        return Err(S3Error::new(StatusCode::CONFLICT, "BucketNotEmpty", "The bucket you tried to delete is not empty"));
+    }
+    let mut root = open_directory(global.clone(), &[]).await?.ok_or("The root can not be opened".to_string())?;
+    let stored = root.directory.unlink(&bucket.to_string())?;
+    root.save(global.clone()).await?;
+    delete_inode(global, stored).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+enum Entry {
+    Object { key: String, size: usize, modified: u64, etag: String },
+    Prefix(String),
+}
+
+impl Entry {
+    fn key(&self) -> &str {
+        match self {
+            Entry::Object { key, .. } => key,
+            Entry::Prefix(prefix) => prefix,
+        }
+    }
+}
+
+// Collects the keys under the directory that start with the prefix. Directories that can not hold such keys are not opened,
+// and with "/" as the delimiter the ones that would be rolled up into a common prefix are not opened either
+fn walk<'a>(global: Arc<Global>, directory: Directory, base: String, prefix: &'a str, delimiter: Option<&'a str>, found: &'a mut Vec<Entry>) -> BoxFuture<'a, Result<(), String>> {
+    Box::pin(async move {
+        for (name, stored) in directory.list_tuples() {
+            let key = format!("{}{}", base, name);
+            let directory_key = format!("{}/", key);
+            if !key.starts_with(prefix) && !prefix.starts_with(&directory_key) {
+                continue;
+            }
+            match stored.get::<InodeType>(global.clone()).await? {
+                InodeType::File(file) => if key.starts_with(prefix) {
+                    let size = file_size(&file);
+                    found.push(Entry::Object { etag: range::etag(&file, size), key, size, modified: file.metadata.modified });
+                },
+                InodeType::Directory(_) if delimiter == Some("/") && directory_key.starts_with(prefix) && directory_key.len() > prefix.len() => {
+                    found.push(Entry::Prefix(directory_key));
+                },
+                // empty directories show up like the "2023/" keys S3 clients make folders with
+                InodeType::Directory(inner) if inner.list().is_empty() => if directory_key.starts_with(prefix) {
+                    found.push(Entry::Object { key: directory_key, size: 0, modified: inner.metadata.modified, etag: EMPTY_ETAG.to_string() });
+                },
+                InodeType::Directory(inner) => walk(global.clone(), inner, directory_key, prefix, delimiter, found).await?,
+            }
+        }
+        Ok(())
+    })
+}
+
+// ListObjectsV2, or the original ListObjects without list-type=2
+async fn list_objects(data: Arc<S3Data>, bucket: &str, query: &[(String, String)]) -> Result<HttpResponse, S3Error> {
+    let directory = open_bucket(data.global.clone(), bucket).await?;
+    let v2 = param(query, "list-type") == Some("2");
+    let prefix = param(query, "prefix").unwrap_or_default();
+    let delimiter = param(query, "delimiter").filter(|delimiter| !delimiter.is_empty());
+    let max_keys = match param(query, "max-keys") {
+        Some(value) => value.parse::<usize>().map_err(|_| S3Error::invalid_argument("max-keys is not a number"))?.min(MAX_KEYS),
+        None => MAX_KEYS,
+    };
+    let url = match param(query, "encoding-type") {
+        Some("url") => true,
+        Some(_) => return Err(S3Error::invalid_argument("Invalid encoding-type")),
+        None => false,
+    };
+    let continuation = match param(query, "continuation-token").filter(|_| v2) {
+        Some(token) => Some(URL_SAFE_NO_PAD.decode(token).ok().and_then(|token| String::from_utf8(token).ok())
+            .ok_or(S3Error::invalid_argument("The continuation token provided is incorrect"))?),
+        None => None,
+    };
+    let after = match v2 {
+        true => continuation.clone().or(param(query, "start-after").map(|after| after.to_string())),
+        false => param(query, "marker").map(|marker| marker.to_string()),
+    }.unwrap_or_default();
+
+    let mut found = Vec::new();
+    walk(data.global.clone(), directory, String::new(), prefix, delimiter, &mut found).await?;
+
+    // keys with the delimiter after the prefix are rolled up into common prefixes
+    let mut entries = found.into_iter().map(|entry| match (entry, delimiter) {
+        (Entry::Object { key, .. }, Some(delimiter)) if key[prefix.len()..].contains(delimiter) => {
+            let end = prefix.len() + key[prefix.len()..].find(delimiter).unwrap() + delimiter.len();
+            Entry::Prefix(key[..end].to_string())
+        },
+        (entry, _) => entry,
+    }).filter(|entry| entry.key() > after.as_str()).collect::<Vec<Entry>>();
+    entries.sort_by(|a, b| a.key().cmp(b.key()));
+    entries.dedup_by(|a, b| a.key() == b.key());
+
+    let truncated = entries.len() > max_keys;
+    entries.truncate(max_keys);
+    let next = entries.last().filter(|_| truncated).map(|entry| entry.key().to_string());
+
+    let encode = |text: &str| escape(&match url {
+        true => uri_encode(text, false),
+        false => text.to_string(),
+    });
+    let mut contents = String::new();
+    let mut prefixes = String::new();
+    for entry in entries.iter() {
+        match entry {
+            Entry::Object { key, size, modified, etag } => contents.push_str(&format!(
+                "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+                encode(key), timestamp(*modified), escape(etag), size
+            )),
+            Entry::Prefix(prefix) => prefixes.push_str(&format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", encode(prefix))),
+        }
+    }
+
+    let mut body = format!("<ListBucketResult xmlns=\"{}\"><Name>{}</Name><Prefix>{}</Prefix>", NAMESPACE, escape(bucket), encode(prefix));
+    if let Some(delimiter) = delimiter {
+        body.push_str(&format!("<Delimiter>{}</Delimiter>", encode(delimiter)));
+    }
+    if url {
+        body.push_str("<EncodingType>url</EncodingType>");
+    }
+    match v2 {
+        true => {
+            if let Some(start_after) = param(query, "start-after") {
+                body.push_str(&format!("<StartAfter>{}</StartAfter>", encode(start_after)));
+            }
+            if let Some(token) = param(query, "continuation-token") {
+                body.push_str(&format!("<ContinuationToken>{}</ContinuationToken>", escape(token)));
+            }
+            if let Some(next) = &next {
+                body.push_str(&format!("<NextContinuationToken>{}</NextContinuationToken>", URL_SAFE_NO_PAD.encode(next)));
+            }
+            body.push_str(&format!("<KeyCount>{}</KeyCount>", entries.len()));
+        },
+        false => {
+            body.push_str(&format!("<Marker>{}</Marker>", encode(&after)));
+            if let Some(next) = &next {
+                body.push_str(&format!("<NextMarker>{}</NextMarker>", encode(next)));
+            }
+        },
+    }
+    body.push_str(&format!("<MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>", max_keys, truncated, contents, prefixes));
+    Ok(xml(body))
+}
+
+async fn get_object(data: Arc<S3Data>, bucket: &str, key: &str, req: &HttpRequest) -> Result<HttpResponse, S3Error> {
+    let (names, directory) = key_names(bucket, key)?;
+    open_bucket(data.global.clone(), bucket).await?;
+    match open(data.global.clone(), &names).await? {
+        Some((_, InodeType::File(file))) if !directory => {
+            let name = names.last().unwrap();
+            let content_type = file.metadata.content_type(name);
+            let disposition = content_disposition(name, &content_type, true);
+            Ok(range::respond(data.global.clone(), Arc::new(file), req, content_type, disposition).await)
+        },
+        Some((_, InodeType::Directory(inner))) if directory => Ok(HttpResponse::Ok()
+            .content_type("application/x-directory")
+            .insert_header((header::ETAG, EMPTY_ETAG))
+            .insert_header((header::LAST_MODIFIED, header::HttpDate::from(UNIX_EPOCH + Duration::from_secs(inner.metadata.modified))))
+            .finish()),
+        _ => Err(S3Error::no_such_key()),
+    }
+}
+
+async fn put_object(data: Arc<S3Data>, bucket: &str, key: &str, req: &HttpRequest, mut body: Body) -> Result<HttpResponse, S3Error> {
+    let global = data.global.clone();
+    let (names, directory) = key_names(bucket, key)?;
+    open_bucket(global.clone(), bucket).await?;
+
+    // "2023/" is how S3 clients make folders, it can not have content
+    if directory {
+        if !read_body(body).await?.is_empty() {
+            return Err(S3Error::invalid_argument("Keys ending with a slash are directories and can not have content"));
+        }
+        let _tree = data.global.lock_tree().await;
+        if blocked(global.clone(), &names).await? {
+            return Err(conflict("A file is in the way of the directory"));
+        }
+        create_directories(global, &names).await?;
+        return Ok(HttpResponse::Ok().insert_header((header::ETAG, EMPTY_ETAG)).finish());
+    }
+
+    let (name, parents) = names.split_last().unwrap();
+    if blocked(global.clone(), parents).await? {
+        return Err(conflict("A file is in the way of the directory"));
+    }
+    if let Some((_, InodeType::Directory(_))) = open(global.clone(), &names).await? {
+        return Err(conflict("A directory with this name already exists"));
+    }
+
+    let mut file = upload_stream(global.clone(), name, &mut body.stream, global.redundancy.clone(), None).await.map_err(|e| body.error(e))?;
+    if let Some(mime) = content_type(req) {
+        file.metadata.mime = Some(mime);
+    }
+    let etag = place(&data, &names, file).await?;
+    Ok(HttpResponse::Ok().insert_header((header::ETAG, etag)).finish())
+}
+
+async fn copy_object(data: Arc<S3Data>, bucket: &str, key: &str, req: &HttpRequest) -> Result<HttpResponse, S3Error> {
+    let global = data.global.clone();
+    // x-amz-copy-source is "bucket/key" or "/bucket/key", percent encoded and maybe with a version
+    let source = header_value(req, "x-amz-copy-source").unwrap_or_default();
+    let source = source.split_once('?').map_or(source, |(source, _)| source);
+    let source = urlencoding::decode(source).map_err(|_| S3Error::invalid_argument("x-amz-copy-source is not valid utf-8"))?;
+    let (source_bucket, source_key) = source.trim_start_matches('/').split_once('/')
+        .ok_or(S3Error::invalid_argument("x-amz-copy-source must be a bucket and a key"))?;
+    let (source_names, source_directory) = key_names(source_bucket, source_key)?;
+    let (names, directory) = key_names(bucket, key)?;
+    if source_directory || directory {
+        return Err(S3Error::invalid_argument("Directories can not be copied"));
+    }
+    let replace = header_value(req, "x-amz-metadata-directive") == Some("REPLACE");
+    if source_names == names && !replace {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "InvalidRequest", "This copy request is illegal because it is trying to copy an object to itself without changing the object's metadata"));
+    }
+
+    open_bucket(global.clone(), source_bucket).await?;
+    open_bucket(global.clone(), bucket).await?;
+    let file = match open(global.clone(), &source_names).await? {
+        Some((_, InodeType::File(file))) => file,
+        _ => return Err(S3Error::no_such_key()),
+    };
+    if blocked(global.clone(), &names[..names.len() - 1]).await? {
+        return Err(conflict("A file is in the way of the directory"));
+    }
+
+    let mut copy = file.copy(global.clone()).await?;
+    if let (true, Some(mime)) = (replace, content_type(req)) {
+        copy.metadata.mime = Some(mime);
+    }
+    let modified = copy.metadata.modified;
+    let etag = place(&data, &names, copy).await?;
+    Ok(xml(format!("<CopyObjectResult><LastModified>{}</LastModified><ETag>{}</ETag></CopyObjectResult>", timestamp(modified), escape(&etag))))
+}
+
+async fn delete_objects(data: Arc<S3Data>, bucket: &str, body: Body) -> Result<HttpResponse, S3Error> {
+    let global = data.global.clone();
+    let body = read_body(body).await?;
+    open_bucket(global.clone(), bucket).await?;
+    let quiet = element(&body, "Quiet").as_deref() == Some("true");
+    let keys = elements(&body, "Object").into_iter().filter_map(|object| element(object, "Key")).collect::<Vec<String>>();
+    if keys.is_empty() || keys.len() > MAX_KEYS {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "MalformedXML", "The request must list between 1 and 1000 keys"));
+    }
+
+    let mut result = String::new();
+    for key in keys {
+        let outcome = match key_names(bucket, &key) {
+            Ok((names, directory)) => remove(&data, &names, directory).await.map_err(S3Error::from),
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(_) if quiet => {},
+            Ok(_) => result.push_str(&format!("<Deleted><Key>{}</Key></Deleted>", escape(&key))),
+            Err(e) => result.push_str(&format!("<Error><Key>{}</Key><Code>{}</Code><Message>{}</Message></Error>", escape(&key), e.code, escape(&e.message))),
+        }
+    }
+    Ok(xml(format!("<DeleteResult xmlns=\"{}\">{}</DeleteResult>", NAMESPACE, result)))
+}
+
+// The upload the request is about, it has to be for the same key
+fn multipart_upload(data: &S3Data, bucket: &str, key: &str, query: &[(String, String)]) -> Result<(String, MultipartUpload), S3Error> {
+    let id = param(query, "uploadId").unwrap_or_default();
+    match data.multipart.get(id) {
+        Some(upload) if upload.key == format!("{}/{}", bucket, key) => Ok((id.to_string(), upload)),
+        _ => Err(S3Error::no_such_upload()),
+    }
+}
+
+async fn create_multipart(data: Arc<S3Data>, bucket: &str, key: &str, req: &HttpRequest) -> Result<HttpResponse, S3Error> {
+    let (_, directory) = key_names(bucket, key)?;
+    if directory {
+        return Err(S3Error::invalid_argument("Keys ending with a slash are directories and can not have content"));
+    }
+    open_bucket(data.global.clone(), bucket).await?;
+    let id = hex(&rand::random::<[u8; 16]>());
+    data.multipart.insert(id.clone(), MultipartUpload::new(format!("{}/{}", bucket, key), content_type(req)))?;
+    Ok(xml(format!(
+        "<InitiateMultipartUploadResult xmlns=\"{}\"><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+        NAMESPACE, escape(bucket), escape(key), id
+    )))
+}
+
+async fn upload_part(data: Arc<S3Data>, bucket: &str, key: &str, query: &[(String, String)], mut body: Body) -> Result<HttpResponse, S3Error> {
+    let global = data.global.clone();
+    let number = param(query, "partNumber").and_then(|number| number.parse::<u32>().ok())
+        .filter(|number| (1..=MAX_PART_NUMBER).contains(number))
+        .ok_or(S3Error::invalid_argument("Part number must be an integer between 1 and 10000, inclusive"))?;
+    let (id, _) = multipart_upload(&data, bucket, key, query)?;
+
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let file = upload_stream(global.clone(), name, &mut body.stream, global.redundancy.clone(), None).await.map_err(|e| body.error(e))?;
+    let etag = format!("\"{}\"", hex(&rand::random::<[u8; 16]>()));
+    let part = Part { etag: etag.clone(), size: file_size(&file), blocks: file.data.into_blocks() };
+    let blocks = part.blocks.clone();
+    match data.multipart.put_part(&id, number, part) {
+        Ok(Some(replaced)) => delete_blocks(global, &replaced.blocks).await?,
+        Ok(None) => {},
+        // completed or aborted while the part was uploading
+        Err(None) => {
+            delete_blocks(global, &blocks).await?;
+            return Err(S3Error::no_such_upload());
+        },
+        Err(Some(e)) => return Err(S3Error::from(e)),
+    }
+    Ok(HttpResponse::Ok().insert_header((header::ETAG, etag)).finish())
+}
+
+async fn complete_multipart(data: Arc<S3Data>, bucket: &str, key: &str, query: &[(String, String)], body: Body) -> Result<HttpResponse, S3Error> {
+    let global = data.global.clone();
+    let body = read_body(body).await?;
+    let (names, _) = key_names(bucket, key)?;
+    let (id, upload) = multipart_upload(&data, bucket, key, query)?;
+
+    let malformed = || S3Error::new(StatusCode::BAD_REQUEST, "MalformedXML", "The XML you provided was not well-formed");
+    let mut listed = Vec::new();
+    for part in elements(&body, "Part") {
+        let number = element(part, "PartNumber").and_then(|number| number.parse::<u32>().ok()).ok_or(malformed())?;
+        let etag = element(part, "ETag").ok_or(malformed())?;
+        listed.push((number, etag));
+    }
+    if listed.is_empty() {
+        return Err(malformed());
+    }
+    if listed.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "InvalidPartOrder", "The list of parts was not in ascending order"));
+    }
+    for (number, etag) in listed.iter() {
+        match upload.parts.get(number) {
+            Some(part) if part.etag.trim_matches('"') == etag.trim_matches('"') => {},
+            _ => return Err(S3Error::new(StatusCode::BAD_REQUEST, "InvalidPart", "One or more of the specified parts could not be found")),
+        }
+    }
+
+    // taken out first, so it can not be completed twice
+    let mut upload = data.multipart.take(&id)?.ok_or(S3Error::no_such_upload())?;
+    let parts = listed.iter().filter_map(|(number, _)| upload.parts.remove(number)).collect::<Vec<Part>>();
+    let unused = upload.blocks();
+
+    // the parts were uploaded on their own, so their chunks are moved after each other
+    let mut blocks = Vec::new();
+    let mut size = 0;
+    let result = async {
+        for part in parts.iter() {
+            for block in part.blocks.iter() {
+                let mut block = block.clone();
+                block.shift(global.clone(), size).await?;
+                blocks.push(block);
+            }
+            size += part.size;
+        }
+        IndirectBlock::from_blocks(global.clone(), blocks.clone()).await
+    }.await;
+    let block = match result {
+        Ok(block) => block,
+        Err(e) => {
+            let all = parts.into_iter().flat_map(|part| part.blocks).chain(unused).collect::<Vec<_>>();
+            return Err(match delete_blocks(global, &all).await {
+                Ok(_) => S3Error::from(e),
+                Err(err) => S3Error::from(format!("{}, {}", e, err)),
+            });
+        },
+    };
+
+    let mut file = File::from_block(block, size);
+    file.metadata.mime = upload.mime.clone().or(detect_mime(names.last().unwrap(), &[]));
+    let etag = place(&data, &names, file).await;
+    // the parts left out of the list are not needed anymore
+    if let Err(e) = delete_blocks(global, &unused).await {
+        println!("Failed to delete the unused parts of {}: {}", id, e);
+    }
+    let etag = etag?;
+
+    Ok(xml(format!(
+        "<CompleteMultipartUploadResult xmlns=\"{}\"><Location>/{}/{}</Location><Bucket>{}</Bucket><Key>{}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+        NAMESPACE, escape(&uri_encode(bucket, true)), escape(&uri_encode(key, false)), escape(bucket), escape(key), escape(&etag)
+    )))
+}
+
+async fn abort_multipart(data: Arc<S3Data>, bucket: &str, key: &str, query: &[(String, String)]) -> Result<HttpResponse, S3Error> {
+    let (id, _) = multipart_upload(&data, bucket, key, query)?;
+    let upload = data.multipart.take(&id)?.ok_or(S3Error::no_such_upload())?;
+    delete_blocks(data.global.clone(), &upload.blocks()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn list_parts(data: Arc<S3Data>, bucket: &str, key: &str, query: &[(String, String)]) -> Result<HttpResponse, S3Error> {
+    let (id, upload) = multipart_upload(&data, bucket, key, query)?;
+    let parts = upload.parts.iter().map(|(number, part)| format!(
+        "<Part><PartNumber>{}</PartNumber><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size></Part>",
+        number, timestamp(upload.created), escape(&part.etag), part.size
+    )).collect::<String>();
+    Ok(xml(format!(
+        "<ListPartsResult xmlns=\"{}\"><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId><StorageClass>STANDARD</StorageClass><MaxParts>{}</MaxParts><IsTruncated>false</IsTruncated>{}</ListPartsResult>",
+        NAMESPACE, escape(bucket), escape(key), id, MAX_PART_NUMBER, parts
+    )))
+}
+
+async fn list_uploads(data: Arc<S3Data>, bucket: &str) -> Result<HttpResponse, S3Error> {
+    open_bucket(data.global.clone(), bucket).await?;
+    let uploads = data.multipart.list(bucket).into_iter().map(|(id, upload)| format!(
+        "<Upload><Key>{}</Key><UploadId>{}</UploadId><StorageClass>STANDARD</StorageClass><Initiated>{}</Initiated></Upload>",
+        escape(&upload.key[bucket.len() + 1..]), id, timestamp(upload.created)
+    )).collect::<String>();
+    Ok(xml(format!(
+        "<ListMultipartUploadsResult xmlns=\"{}\"><Bucket>{}</Bucket><KeyMarker></KeyMarker><UploadIdMarker></UploadIdMarker><MaxUploads>{}</MaxUploads><IsTruncated>false</IsTruncated>{}</ListMultipartUploadsResult>",
+        NAMESPACE, escape(bucket), MAX_KEYS, uploads
+    )))
+}
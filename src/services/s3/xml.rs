@@ -0,0 +1,162 @@
+/*
+    The XML S3 speaks, errors included. The documents are small and flat, so they are written with format! and read with a tag search.
+ */
+
+use std::fmt::Display;
+use actix_web::{HttpResponse, http::StatusCode};
+
+pub const HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+pub const NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+#[derive(Debug, Clone)]
+pub struct S3Error {
+    pub status: StatusCode,
+    pub code: &'static str,  // https://docs.aws.amazon.com/AmazonS3/latest/API/ErrorResponses.html
+    pub message: String,
+}
+
+impl S3Error {
+    pub fn new(status: StatusCode, code: &'static str, message: &str) -> Self {
+        Self { status, code, message: message.to_string() }
+    }
+
+    pub fn no_such_bucket() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "NoSuchBucket", "The specified bucket does not exist")
+    }
+
+    pub fn no_such_key() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "NoSuchKey", "The specified key does not exist")
+    }
+
+    pub fn no_such_upload() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "NoSuchUpload", "The specified multipart upload does not exist")
+    }
+
+    pub fn invalid_argument(message: &str) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidArgument", message)
+    }
+
+    pub fn response(&self, resource: &str) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .content_type("application/xml")
+            .body(format!(
+                "{}<Error><Code>{}</Code><Message>{}</Message><Resource>{}</Resource></Error>",
+                HEADER, self.code, escape(&self.message), escape(resource)
+            ))
+    }
+}
+
+// Errors from the rest of chunkdrive are ours, not the client's
+impl From<String> for S3Error {
+    fn from(message: String) -> Self {
+        Self { status: StatusCode::INTERNAL_SERVER_ERROR, code: "InternalError", message }
+    }
+}
+
+impl Display for S3Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => entity.strip_prefix('#').and_then(|number| number.parse::<u32>().ok()).and_then(char::from_u32),
+            },
+        };
+        match character {
+            Some(character) => {
+                result.push(character);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// The contents of every <tag> element, in order. Nested elements of the same name are not supported
+pub fn elements<'a>(body: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}", tag), format!("</{}>", tag));
+    let mut found = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        // <tag> or <tag attributes>, not <tagged>
+        let content = match after.chars().next() {
+            Some('>') => &after[1..],
+            Some(' ') | Some('\t') | Some('\r') | Some('\n') => match after.find('>') {
+                Some(end) => &after[end + 1..],
+                None => break,
+            },
+            Some('/') => {
+                found.push("");
+                rest = after;
+                continue;
+            },
+            _ => {
+                rest = after;
+                continue;
+            },
+        };
+        match content.find(&close) {
+            Some(end) => {
+                found.push(&content[..end]);
+                rest = &content[end + close.len()..];
+            },
+            None => break,
+        }
+    }
+    found
+}
+
+// The text of the first <tag> element
+pub fn element(body: &str, tag: &str) -> Option<String> {
+    elements(body, tag).first().map(|text| unescape(text.trim()))
+}
+
+#[cfg(test)]
+mod xml_tests {
+    use super::*;
+
+    #[test]
+    fn reads_elements() {
+        let body = "<CompleteMultipartUpload xmlns=\"x\"><Part><PartNumber>1</PartNumber><ETag>&quot;a&quot;</ETag></Part><Part>\n<PartNumber>2</PartNumber><ETag>\"b\"</ETag></Part></CompleteMultipartUpload>";
+        let parts = elements(body, "Part");
+        assert_eq!(parts.len(), 2);
+        assert_eq!(element(parts[0], "ETag").unwrap(), "\"a\"");
+        assert_eq!(element(parts[1], "PartNumber").unwrap(), "2");
+        assert_eq!(elements("<Delete><Quiet>true</Quiet><Object><Key>a &amp; b&#x2F;c</Key></Object></Delete>", "Key"), vec!["a &amp; b&#x2F;c"]);
+        assert_eq!(unescape("a &amp; b&#x2F;c &#47; &bogus; &"), "a & b/c / &bogus; &");
+        assert!(elements(body, "Parts").is_empty());
+    }
+}
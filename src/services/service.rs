@@ -1,25 +1,44 @@
 use std::sync::Arc;
 use serde::Deserialize;
 
-use crate::global::Global;
+use crate::{blocks::block::BlockType, global::Global};
 
-use super::http::service::HttpService;
+use super::{http::service::HttpService, s3::service::S3Service, webdav::service::WebdavService};
 
 pub trait Service {
     fn run(&self, global: Arc<Global>);
+
+    // Chunks of uploads that are not in the tree yet but will be, read from what the service keeps on disk
+    fn unfinished(&self) -> Result<Vec<BlockType>, String> {
+        Ok(Vec::new())
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum ServiceType {
     #[serde(rename = "http")]
-    Http(HttpService),
+    Http(Box<HttpService>),
+    #[serde(rename = "webdav")]
+    Webdav(WebdavService),
+    #[serde(rename = "s3")]
+    S3(S3Service),
 }
 
 impl Service for ServiceType {
     fn run(&self, global: Arc<Global>) {
         match self {
             ServiceType::Http(service) => service.run(global),
+            ServiceType::Webdav(service) => service.run(global),
+            ServiceType::S3(service) => service.run(global),
+        }
+    }
+
+    fn unfinished(&self) -> Result<Vec<BlockType>, String> {
+        match self {
+            ServiceType::Http(service) => service.unfinished(),
+            ServiceType::Webdav(service) => service.unfinished(),
+            ServiceType::S3(service) => service.unfinished(),
         }
     }
 }
@@ -0,0 +1,2 @@
+pub mod propfind;
+pub mod service;
\ No newline at end of file
@@ -0,0 +1,90 @@
+/*
+    The multistatus bodies PROPFIND answers with (RFC 4918).
+    Every request gets all the properties we have, the requested ones are not looked at.
+ */
+
+use std::time::{Duration, UNIX_EPOCH};
+use actix_web::http::header::HttpDate;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+#[derive(Debug)]
+pub struct PropEntry {
+    pub href: String,  // already percent encoded
+    pub name: String,
+    pub collection: bool,
+    pub size: usize,
+    pub created: u64,
+    pub modified: u64,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Percent encodes every name of the path, collections end with a slash
+pub fn href(prefix: &str, names: &[String], collection: bool) -> String {
+    let mut href = prefix.trim_end_matches('/').to_string();
+    for name in names {
+        href.push('/');
+        href.push_str(&urlencoding::encode(name));
+    }
+    if collection || names.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+fn entry(entry: &PropEntry) -> String {
+    let created: DateTime<Utc> = (UNIX_EPOCH + Duration::from_secs(entry.created)).into();
+    let modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(entry.modified));
+    let mut props = format!(
+        "<D:displayname>{}</D:displayname><D:creationdate>{}</D:creationdate><D:getlastmodified>{}</D:getlastmodified>",
+        escape(&entry.name), created.to_rfc3339_opts(SecondsFormat::Secs, true), modified
+    );
+    match entry.collection {
+        true => props.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+        false => props.push_str(&format!("<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>", entry.size)),
+    }
+    if let Some(content_type) = &entry.content_type {
+        props.push_str(&format!("<D:getcontenttype>{}</D:getcontenttype>", escape(content_type)));
+    }
+    if let Some(etag) = &entry.etag {
+        props.push_str(&format!("<D:getetag>{}</D:getetag>", escape(etag)));
+    }
+    format!("<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>", escape(&entry.href), props)
+}
+
+pub fn multistatus(entries: &[PropEntry]) -> String {
+    let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">");
+    for item in entries {
+        body.push_str(&entry(item));
+    }
+    body.push_str("</D:multistatus>");
+    body
+}
+
+#[cfg(test)]
+mod propfind_tests {
+    use super::*;
+
+    #[test]
+    fn renders_entries() {
+        assert_eq!(href("/dav/", &["a b".to_string(), "c&d.txt".to_string()], false), "/dav/a%20b/c%26d.txt");
+        assert_eq!(href("/", &[], true), "/");
+        assert_eq!(href("/", &["dir".to_string()], true), "/dir/");
+
+        let body = multistatus(&[
+            PropEntry { href: "/".to_string(), name: String::new(), collection: true, size: 0, created: 0, modified: 0, content_type: None, etag: None },
+            PropEntry { href: "/a%26b".to_string(), name: "a&b".to_string(), collection: false, size: 12, created: 0, modified: 0, content_type: Some("text/plain".to_string()), etag: Some("\"1\"".to_string()) },
+        ]);
+        assert!(body.contains("<D:href>/</D:href><D:propstat><D:prop><D:displayname></D:displayname><D:creationdate>1970-01-01T00:00:00Z</D:creationdate><D:getlastmodified>Thu, 01 Jan 1970 00:00:00 GMT</D:getlastmodified><D:resourcetype><D:collection/></D:resourcetype>"));
+        assert!(body.contains("<D:displayname>a&amp;b</D:displayname>"));
+        assert!(body.contains("<D:getcontentlength>12</D:getcontentlength><D:getcontenttype>text/plain</D:getcontenttype><D:getetag>&quot;1&quot;</D:getetag>"));
+    }
+}
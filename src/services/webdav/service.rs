@@ -0,0 +1,418 @@
+/*
+    WebDAV (RFC 4918, class 1) on top of the directories and files, so chunkdrive can be mounted by file managers, rclone or davfs2.
+    Paths are made of names starting at the root, unlike the descriptor urls of the HTTP service. There is no locking.
+ */
+
+use std::sync::Arc;
+use actix_web::{web, App, HttpServer, HttpRequest, HttpResponse, http::{header, StatusCode}};
+use serde::Deserialize;
+
+use crate::{blocks::block::Block, global::Global, inodes::{directory::Directory, inode::InodeType, metadata::Size, path::{copy_stored, delete_inode, open, open_directory, split_path}}, stored::Stored, services::{http::{content::content_disposition, range, upload::upload_stream}, service::Service}};
+use super::propfind::{PropEntry, href, multistatus};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebdavService {
+    pub(crate) port: u16,
+    #[serde(default = "default_address")]
+    pub(crate) address: String,
+
+    #[serde(default = "default_path")]
+    pub(crate) path: String, // the prefix the root is served under
+
+    #[serde(default)]
+    pub(crate) readonly: bool,
+
+    #[serde(default)]
+    pub(crate) max_upload_size: Option<usize>, // in bytes, unlimited by default
+}
+
+#[derive(Debug)]
+pub struct WebdavData {
+    pub global: Arc<Global>,
+    pub config: WebdavService,
+}
+
+fn default_address() -> String { "127.0.0.1".to_string() }
+fn default_path() -> String { "/".to_string() }
+
+const ALLOW: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, MKCOL, DELETE, MOVE, COPY";
+
+impl Service for WebdavService {
+    fn run(&self, global: Arc<Global>) {
+        let data = Arc::new(WebdavData { global, config: self.clone() });
+        std::thread::spawn(move || {
+            match run_blocking(data) {
+                Ok(_) => {},
+                Err(e) => println!("Failed to run WebDAV service: {}", e),
+            }
+        });
+    }
+}
+
+fn run_blocking(data: Arc<WebdavData>) -> Result<(), String> {
+    println!("Starting WebDAV service on http://{}:{}{}", data.config.address, data.config.port, data.config.path);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let data_clone = data.clone();
+    rt.block_on(async {
+        HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(data_clone.clone()))
+                .configure(configure)
+        })
+        .bind(format!("{}:{}", data.config.address, data.config.port))
+        .map_err(|e| format!("Failed to bind to port: {}", e))?
+        .run()
+        .await
+        .map_err(|e| format!("Failed to run server: {}", e))
+    })?;
+
+    Ok(())
+}
+
+// WebDAV uses its own methods, so every request goes through one handler
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.default_service(web::to(dispatch));
+}
+
+fn error(status: StatusCode, message: String) -> HttpResponse {
+    HttpResponse::build(status).content_type("text/plain").body(message)
+}
+
+// Turns the path of a request or a Destination header into names, None if it is outside of the prefix
+fn request_names(prefix: &str, path: &str) -> Option<Result<Vec<String>, String>> {
+    let path = urlencoding::decode(path).ok()?;
+    let prefix = prefix.trim_end_matches('/');
+    let rest = path.strip_prefix(prefix)?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    Some(split_path(rest))
+}
+
+// The Destination header is usually a full url, only its path matters
+fn destination(data: &WebdavData, req: &HttpRequest) -> Result<Vec<String>, HttpResponse> {
+    let value = req.headers().get("Destination").and_then(|value| value.to_str().ok())
+        .ok_or(error(StatusCode::BAD_REQUEST, "Destination is required".to_string()))?;
+    let path = match value.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+        None => value,
+    };
+    match request_names(&data.config.path, path) {
+        Some(Ok(names)) => Ok(names),
+        Some(Err(e)) => Err(error(StatusCode::BAD_REQUEST, e)),
+        None => Err(error(StatusCode::BAD_GATEWAY, "Destination is on another server".to_string())),
+    }
+}
+
+async fn dispatch(data: web::Data<Arc<WebdavData>>, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let data = data.as_ref().clone();
+    let names = match request_names(&data.config.path, req.path()) {
+        Some(Ok(names)) => names,
+        Some(Err(e)) => return error(StatusCode::BAD_REQUEST, e),
+        None => return error(StatusCode::NOT_FOUND, "Not found".to_string()),
+    };
+
+    let method = req.method().as_str().to_string();
+    if data.config.readonly && matches!(method.as_str(), "PUT" | "MKCOL" | "DELETE" | "MOVE" | "COPY") {
+        return error(StatusCode::FORBIDDEN, "Server is in read-only mode".to_string());
+    }
+    let result = match method.as_str() {
+        "OPTIONS" => Ok(HttpResponse::Ok()
+            .insert_header(("DAV", "1"))
+            .insert_header((header::ALLOW, ALLOW))
+            .insert_header(("MS-Author-Via", "DAV"))
+            .finish()),
+        "PROPFIND" => propfind(data, names, &req).await,
+        "GET" | "HEAD" => get(data, names, &req).await,
+        "PUT" => put(data, names, &req, payload).await,
+        "MKCOL" => mkcol(data, names, &req).await,
+        "DELETE" => delete(data, names).await,
+        "MOVE" => relocate(data, names, &req, false).await,
+        "COPY" => relocate(data, names, &req, true).await,
+        _ => Ok(HttpResponse::MethodNotAllowed().insert_header((header::ALLOW, ALLOW)).finish()),
+    };
+    result.unwrap_or_else(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+fn prop_entry(prefix: &str, names: &[String], inode: &InodeType) -> PropEntry {
+    let name = names.last().cloned().unwrap_or_default();
+    match inode {
+        InodeType::Directory(directory) => PropEntry {
+            href: href(prefix, names, true),
+            name,
+            collection: true,
+            size: 0,
+            created: directory.metadata.created,
+            modified: directory.metadata.modified,
+            content_type: None,
+            etag: None,
+        },
+        InodeType::File(file) => {
+            let size = match file.metadata.size {
+                Size::Bytes(size) => size,
+                _ => 0,
+            };
+            PropEntry {
+                href: href(prefix, names, false),
+                content_type: Some(file.metadata.content_type(&name)),
+                etag: Some(range::etag(file, size)),
+                name,
+                collection: false,
+                size,
+                created: file.metadata.created,
+                modified: file.metadata.modified,
+            }
+        },
+    }
+}
+
+async fn propfind(data: Arc<WebdavData>, names: Vec<String>, req: &HttpRequest) -> Result<HttpResponse, String> {
+    // clients list directories one level at a time, walking the whole tree would be far too slow
+    let depth = match req.headers().get("Depth").and_then(|value| value.to_str().ok()) {
+        Some("0") => 0,
+        Some("1") | None => 1,
+        Some(_) => return Ok(error(StatusCode::FORBIDDEN, "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>".to_string())),
+    };
+
+    let inode = match names.is_empty() {
+        true => data.global.get_root()?.to_enum(),
+        false => match open(data.global.clone(), &names).await? {
+            Some((_, inode)) => inode,
+            None => return Ok(error(StatusCode::NOT_FOUND, "Not found".to_string())),
+        },
+    };
+
+    let mut entries = vec![prop_entry(&data.config.path, &names, &inode)];
+    if let (InodeType::Directory(directory), 1) = (&inode, depth) {
+        let mut children = directory.list_tuples();
+        children.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (name, stored) in children {
+            let child = stored.get::<InodeType>(data.global.clone()).await?;
+            let child_names = [names.clone(), vec![name]].concat();
+            entries.push(prop_entry(&data.config.path, &child_names, &child));
+        }
+    }
+
+    Ok(HttpResponse::build(StatusCode::MULTI_STATUS)
+        .content_type("application/xml; charset=utf-8")
+        .body(multistatus(&entries)))
+}
+
+async fn get(data: Arc<WebdavData>, names: Vec<String>, req: &HttpRequest) -> Result<HttpResponse, String> {
+    if names.is_empty() {
+        return Ok(HttpResponse::MethodNotAllowed().insert_header((header::ALLOW, ALLOW)).finish());
+    }
+    match open(data.global.clone(), &names).await? {
+        Some((_, InodeType::File(file))) => {
+            let name = names.last().unwrap();
+            let content_type = file.metadata.content_type(name);
+            let disposition = content_disposition(name, &content_type, true);
+            Ok(range::respond(data.global.clone(), Arc::new(file), req, content_type, disposition).await)
+        },
+        Some((_, InodeType::Directory(_))) => Ok(HttpResponse::MethodNotAllowed().insert_header((header::ALLOW, ALLOW)).finish()),
+        None => Ok(error(StatusCode::NOT_FOUND, "Not found".to_string())),
+    }
+}
+
+async fn put(data: Arc<WebdavData>, names: Vec<String>, req: &HttpRequest, mut payload: web::Payload) -> Result<HttpResponse, String> {
+    let global = data.global.clone();
+    let (name, parents) = match names.split_last() {
+        Some(split) => split,
+        None => return Ok(HttpResponse::MethodNotAllowed().insert_header((header::ALLOW, ALLOW)).finish()),
+    };
+    let length = req.headers().get(header::CONTENT_LENGTH).and_then(|value| value.to_str().ok()).and_then(|value| value.parse::<usize>().ok());
+    if let (Some(length), Some(max_size)) = (length, data.config.max_upload_size) {
+        if length > max_size {
+            return Ok(error(StatusCode::PAYLOAD_TOO_LARGE, "The upload is larger than the limit".to_string()));
+        }
+    }
+    if open_directory(global.clone(), parents).await?.is_none() {
+        return Ok(error(StatusCode::CONFLICT, "The parent directory does not exist".to_string()));
+    }
+    if let Some((_, InodeType::Directory(_))) = open(global.clone(), &names).await? {
+        return Ok(error(StatusCode::METHOD_NOT_ALLOWED, "A directory can not be overwritten by a file".to_string()));
+    }
+
+    let file = upload_stream(global.clone(), name, &mut payload, global.redundancy.clone(), data.config.max_upload_size).await?;
+
+    // the directory is opened again, as it could have changed during the upload
+    let file_data = file.data.clone();
+    let result = async {
+        let _tree = global.lock_tree().await;
+        let mut parent = open_directory(global.clone(), parents).await?.ok_or("The parent directory was removed".to_string())?;
+        let replaced = parent.directory.unlink(name).ok();
+        parent.directory.add(global.clone(), name, file.to_enum()).await?;
+        parent.save(global.clone()).await?;
+        Ok::<_, String>(replaced)
+    }.await;
+    let replaced = match result {
+        Ok(replaced) => replaced,
+        Err(e) => return match file_data.delete(global).await {
+            Ok(_) => Err(e),
+            Err(err) => Err(format!("{}, {}", e, err)),
+        },
+    };
+
+    match replaced {
+        Some(stored) => {
+            delete_inode(global, stored).await?;
+            Ok(HttpResponse::NoContent().finish())
+        },
+        None => Ok(HttpResponse::Created().finish()),
+    }
+}
+
+async fn mkcol(data: Arc<WebdavData>, names: Vec<String>, req: &HttpRequest) -> Result<HttpResponse, String> {
+    let global = data.global.clone();
+    let (name, parents) = match names.split_last() {
+        Some(split) => split,
+        None => return Ok(error(StatusCode::METHOD_NOT_ALLOWED, "The root already exists".to_string())),
+    };
+    if req.headers().get(header::CONTENT_LENGTH).is_some_and(|value| value != "0") {
+        return Ok(error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "MKCOL does not take a body".to_string()));
+    }
+    let _tree = global.lock_tree().await;
+    let mut parent = match open_directory(global.clone(), parents).await? {
+        Some(parent) => parent,
+        None => return Ok(error(StatusCode::CONFLICT, "The parent directory does not exist".to_string())),
+    };
+    if parent.directory.get(name).is_ok() {
+        return Ok(error(StatusCode::METHOD_NOT_ALLOWED, format!("{} already exists", name)));
+    }
+    parent.directory.add(global.clone(), name, Directory::new().to_enum()).await?;
+    parent.save(global.clone()).await?;
+    Ok(HttpResponse::Created().finish())
+}
+
+async fn delete(data: Arc<WebdavData>, names: Vec<String>) -> Result<HttpResponse, String> {
+    let global = data.global.clone();
+    let (name, parents) = match names.split_last() {
+        Some(split) => split,
+        None => return Ok(error(StatusCode::FORBIDDEN, "The root can not be deleted".to_string())),
+    };
+    let tree = global.lock_tree().await;
+    let mut parent = match open_directory(global.clone(), parents).await? {
+        Some(parent) => parent,
+        None => return Ok(error(StatusCode::NOT_FOUND, "Not found".to_string())),
+    };
+    let stored = match parent.directory.unlink(name) {
+        Ok(stored) => stored,
+        Err(_) => return Ok(error(StatusCode::NOT_FOUND, "Not found".to_string())),
+    };
+    parent.save(global.clone()).await?;
+    drop(tree);
+
+    delete_inode(global, stored).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// MOVE and COPY, they only differ in what ends up at the destination
+async fn relocate(data: Arc<WebdavData>, names: Vec<String>, req: &HttpRequest, copy: bool) -> Result<HttpResponse, String> {
+    let global = data.global.clone();
+    let target = match destination(&data, req) {
+        Ok(target) => target,
+        Err(response) => return Ok(response),
+    };
+    let (name, parents) = match names.split_last() {
+        Some(split) => split,
+        None => return Ok(error(StatusCode::FORBIDDEN, "The root can not be moved or copied".to_string())),
+    };
+    let (target_name, target_parents) = match target.split_last() {
+        Some(split) => split,
+        None => return Ok(error(StatusCode::FORBIDDEN, "The root can not be overwritten".to_string())),
+    };
+    if target == names {
+        return Ok(error(StatusCode::FORBIDDEN, "The source and the destination are the same".to_string()));
+    }
+    if target.starts_with(&names) {
+        return Ok(error(StatusCode::CONFLICT, "A directory can not be put inside itself".to_string()));
+    }
+    let overwrite = !matches!(req.headers().get("Overwrite").and_then(|value| value.to_str().ok()), Some("F") | Some("f"));
+    let deep = !matches!(req.headers().get("Depth").and_then(|value| value.to_str().ok()), Some("0"));
+
+    // copying downloads and uploads everything again, so it is done before locking the tree and only linked under the lock
+    let copied = match copy {
+        true => match open(global.clone(), &names).await? {
+            Some((_, inode)) => Some(copy_stored(global.clone(), &inode, deep).await?),
+            None => return Ok(error(StatusCode::NOT_FOUND, "Not found".to_string())),
+        },
+        false => None,
+    };
+
+    let tree = global.lock_tree().await;
+    let linked = async {
+        let mut target_parent = match open_directory(global.clone(), target_parents).await? {
+            Some(parent) => parent,
+            None => return Ok(Err(error(StatusCode::CONFLICT, "The destination directory does not exist".to_string()))),
+        };
+        if target_parent.directory.get(target_name).is_ok() && !overwrite {
+            return Ok(Err(error(StatusCode::PRECONDITION_FAILED, "The destination already exists".to_string())));
+        }
+        if let Some(copied) = &copied {
+            let replaced = target_parent.directory.unlink(target_name).ok();
+            target_parent.directory.put(target_name, copied.clone())?;
+            target_parent.save(global.clone()).await?;
+            return Ok(Ok(replaced));
+        }
+
+        let stored = match open(global.clone(), &names).await? {
+            Some((stored, _)) => stored,
+            None => return Ok(Err(error(StatusCode::NOT_FOUND, "Not found".to_string()))),
+        };
+        // a move only relinks the inode, the chunks stay where they are
+        if parents == target_parents {
+            let replaced = target_parent.directory.unlink(target_name).ok();
+            target_parent.directory.unlink(name)?;
+            target_parent.directory.put(target_name, stored)?;
+            target_parent.save(global.clone()).await?;
+            return Ok(Ok(replaced));
+        }
+
+        // the source is unlinked first, so the inode is never in two directories, where deleting one copy would break the other
+        let mut parent = open_directory(global.clone(), parents).await?.ok_or("The source directory was removed".to_string())?;
+        parent.directory.unlink(name)?;
+        parent.save(global.clone()).await?;
+        let replaced = target_parent.directory.unlink(target_name).ok();
+        target_parent.directory.put(target_name, stored.clone())?;
+        if let Err(e) = target_parent.save(global.clone()).await {
+            // put back where it was, nothing else can have changed the directory while the tree is locked
+            let restored = async {
+                let mut parent = open_directory(global.clone(), parents).await?.ok_or("The source directory was removed".to_string())?;
+                parent.directory.put(name, stored)?;
+                parent.save(global.clone()).await
+            }.await;
+            return Err(match restored {
+                Ok(_) => e,
+                Err(err) => format!("{}, {}", e, err),
+            });
+        }
+        Ok::<Result<Option<Stored>, HttpResponse>, String>(Ok(replaced))
+    }.await;
+    drop(tree);
+
+    // a copy that did not end up in the destination belongs to nobody
+    let replaced = match linked {
+        Ok(Ok(replaced)) => replaced,
+        Ok(Err(response)) => {
+            if let Some(copied) = copied {
+                delete_inode(global, copied).await?;
+            }
+            return Ok(response);
+        },
+        Err(e) => return Err(match copied {
+            Some(copied) => match delete_inode(global, copied).await {
+                Ok(_) => e,
+                Err(err) => format!("{}, {}", e, err),
+            },
+            None => e,
+        }),
+    };
+
+    match replaced {
+        Some(stored) => {
+            delete_inode(global, stored).await?;
+            Ok(HttpResponse::NoContent().finish())
+        },
+        None => Ok(HttpResponse::Created().finish()),
+    }
+}
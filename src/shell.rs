@@ -1,9 +1,9 @@
-use std::{sync::Arc, io::{Write, BufReader, Read}};
+use std::{sync::Arc, io::Write};
 use liner::{Context, Completer};
 use futures::StreamExt;
 use tokio::runtime::Runtime;
 
-use crate::{global::Global, inodes::{directory::Directory, inode::{InodeType, Inode}, metadata::Metadata, file::File}, stored::Stored};
+use crate::{blocks::redundancy::Redundancy, gc, global::Global, inodes::{directory::Directory, inode::{InodeType, Inode}, metadata::Metadata, file::File}, scrub, stored::Stored};
 
 fn tokenize_line(line: &str) -> Vec<String> {
     let mut tokens = Vec::new();
@@ -117,6 +117,9 @@ const COMMANDS: &[Command] = &[
     ("stat",   stat, "Prints metadata about a file or directory."),
     ("lsbk",   bucket_list, "Lists all buckets."),
     ("bktest", bucket_test, "Tests a bucket."),
+    ("cache",  cache_stats, "Prints the cache hits and misses of every bucket."),
+    ("scrub",  scrub, "Reads every chunk and repairs the missing ones."),
+    ("gc",     gc, "Lists descriptors that are not used by any file, \"gc delete\" deletes them."),
     ("dbg",    dbg, "Prints debug information about an object."),
     ("root",   |_, _, path, cwd, _| { path.clear(); cwd.clear(); Ok(()) }, "Returns to the root directory"),
     ("cwd",    |_, _, path, _, _| Ok(println!("/{}", path.join("/"))), "Prints the current working directory."),
@@ -140,7 +143,7 @@ fn dbg(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd: &m
     } else if args[0] == "." {
         let rt = Runtime::new().unwrap();
         if cwd.is_empty() {
-            dbg!(global.get_root());
+            dbg!(global.get_root()?);
         } else {
             let inode: InodeType = rt.block_on(cwd.last().unwrap().get(global.clone()))?;
             dbg!(inode);
@@ -156,7 +159,7 @@ fn dbg(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd: &m
                     _ => Err("Not in a directory.".to_string())?
                 }
             },
-            None => global.get_root()
+            None => global.get_root()?
         };
         let stored = dir.get(&args[0])?;
         let inode: InodeType = rt.block_on(stored.get(global.clone()))?;
@@ -178,7 +181,7 @@ fn ls(global: &Arc<Global>, _args: Vec<String>, _path: &mut Vec<String>, cwd: &m
                 _ => Err("Not in a directory.".to_string())?
             }
         },
-        None => global.get_root()
+        None => global.get_root()?
     };
     
     for name in dir.list() {
@@ -194,11 +197,11 @@ fn mkdir(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd:
     if cwd.is_empty() {
         // root directory
         let rt = Runtime::new().unwrap();
-        let mut root = global.get_root();
+        let mut root = global.get_root()?;
         rt.block_on(async {
             root.add(global.clone(), &args[0], Directory::new().to_enum()).await
         })?;
-        global.save_root(&root);
+        global.save_root(&root)?;
 
     } else {
         let rt = Runtime::new().unwrap();
@@ -242,7 +245,7 @@ fn cd(global: &Arc<Global>, args: Vec<String>, path: &mut Vec<String>, cwd: &mut
                 _ => Err("Not in a directory.".to_string())?
             }
         },
-        None => global.get_root()
+        None => global.get_root()?
     };
     let mut found = false;
     for name in dir.list() {
@@ -265,11 +268,11 @@ fn rm(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd: &mu
     }
     if cwd.is_empty() {
         let rt = Runtime::new().unwrap();
-        let mut root = global.get_root();
+        let mut root = global.get_root()?;
         let err = rt.block_on(async {
             root.remove(global.clone(), &args[0]).await
         });
-        global.save_root(&root);
+        global.save_root(&root)?;
         err?;
     } else {
         let rt = Runtime::new().unwrap();
@@ -306,11 +309,11 @@ fn cut(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd: &m
                 _ => Err("Not in a directory.".to_string())?
             }
         },
-        None => global.get_root()
+        None => global.get_root()?
     };
     let stored = dir.unlink(&args[0])?;
     if cwd.is_empty() {
-        global.save_root(&dir);
+        global.save_root(&dir)?;
     } else {
         let cwd = cwd.last_mut().unwrap();
         rt.block_on(async {
@@ -337,14 +340,14 @@ fn paste(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd:
                 _ => Err("Not in a directory.".to_string())?
             }
         },
-        None => global.get_root()
+        None => global.get_root()?
     };
 
     let stored = clipboard.take().unwrap();
     dir.put(&args[0], stored)?;
 
     if cwd.is_empty() {
-        global.save_root(&dir);
+        global.save_root(&dir)?;
     } else {
         let cwd = cwd.last_mut().unwrap();
         rt.block_on(async {
@@ -379,8 +382,8 @@ fn stat(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd: &
 
     if args[0] == "." {
         if cwd.is_empty() {
+            let root = global.get_root()?;
             let metadata: Metadata = rt.block_on(async {
-                let root = global.get_root();
                 root.metadata().await.clone()
             });
             println!("Type: Directory");
@@ -400,7 +403,7 @@ fn stat(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd: &
                     _ => Err("Not in a directory.".to_string())?
                 }
             },
-            None => global.get_root()
+            None => global.get_root()?
         };
         let stored = dir.get(&args[0])?;
         let inode: InodeType = rt.block_on(stored.get(global.clone()))?;
@@ -416,20 +419,21 @@ fn stat(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd: &
 }
 
 fn upload(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd: &mut Vec<Stored>, _clipboard: &mut Option<Stored>) -> Result<(), String> {
-    if args.len() != 1 {
-        return Err("Usage: up <file>".to_string());
+    if args.is_empty() || args.len() > 2 {
+        return Err("Usage: up <file> [none|replicate[:copies]|erasure[:data:parity]]".to_string());
     }
+    let redundancy = match args.get(1) {
+        Some(redundancy) => Some(redundancy.parse::<Redundancy>()?),
+        None => None
+    };
 
     let file_name = args[0].clone().split('/').last().ok_or("Invalid file name.")?.to_string();
-    let file = std::fs::File::open(&args[0]).map_err(|_| "Failed to open file.")?;
-    let mut reader = BufReader::new(file);
-    let mut data = Vec::new();
-
-    reader.read_to_end(&mut data).map_err(|_| "Failed to read file.")?;
+    let rt = Runtime::new().unwrap();
+    let file = rt.block_on(tokio::fs::File::open(&args[0])).map_err(|_| "Failed to open file.")?;
+    let mut reader = tokio::io::BufReader::new(file);
 
-    println!("Read {} bytes. Uploading...", data.len());
+    println!("Uploading...");
 
-    let rt = Runtime::new().unwrap();
     let mut dir = match cwd.last() {
         Some(cwd) => {
             let inode: InodeType = rt.block_on(cwd.get(global.clone()))?;
@@ -438,12 +442,13 @@ fn upload(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cwd:
                 _ => Err("Not in a directory.".to_string())?
             }
         },
-        None => global.get_root()
+        None => global.get_root()?
     };
-    let file = rt.block_on(File::create(global.clone(), data))?;
+    let redundancy = redundancy.unwrap_or_else(|| global.redundancy.clone());
+    let file = rt.block_on(File::create_named(global.clone(), &file_name, &mut reader, redundancy))?;
     rt.block_on(dir.add(global.clone(), &file_name, file.to_enum()))?;
     if cwd.is_empty() {
-        global.save_root(&dir);
+        global.save_root(&dir)?;
     } else {
         let cwd = cwd.last_mut().unwrap();
         rt.block_on(async {
@@ -470,7 +475,7 @@ fn download(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cw
                 _ => Err("Not in a directory.".to_string())?
             }
         },
-        None => global.get_root()
+        None => global.get_root()?
     };
 
     let stored = dir.get(&args[0])?;
@@ -493,7 +498,7 @@ fn download(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, cw
 }
 
 fn bucket_list(global: &Arc<Global>, _args: Vec<String>, _path: &mut Vec<String>, _cwd: &mut Vec<Stored>, _clipboard: &mut Option<Stored>) -> Result<(), String> {
-    println!("  {:<20} {:<20} {:<20} {}" , "Name", "Source", "Encryption", "Max block size");
+    println!("  {:<20} {:<20} {:<20} {:<20} {}" , "Name", "Source", "Encryption", "Compression", "Max block size");
     for bucket in global.list_buckets() {
         let b_type = match global.get_bucket(bucket) {
             Some(bucket) => bucket.human_readable(),
@@ -501,6 +506,7 @@ fn bucket_list(global: &Arc<Global>, _args: Vec<String>, _path: &mut Vec<String>
         };
         println!("  {:<20} {}", bucket, b_type);
     }
+    println!("Redundancy: {}", global.redundancy.human_readable());
     Ok(())
 }
 
@@ -543,4 +549,60 @@ fn bucket_test(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>,
     println!("OK.");
 
     Ok(())
-}
\ No newline at end of file
+}
+fn scrub(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, _cwd: &mut Vec<Stored>, _clipboard: &mut Option<Stored>) -> Result<(), String> {
+    let repair = match args.iter().map(|arg| arg.as_str()).collect::<Vec<&str>>().as_slice() {
+        [] => true,
+        ["check"] => false,
+        _ => return Err("Usage: scrub [check]".to_string())
+    };
+
+    println!("Scrubbing...");
+    let rt = Runtime::new().unwrap();
+    let report = rt.block_on(scrub::scrub(global.clone(), repair));
+    for error in report.errors.iter() {
+        println!("  {}", error);
+    }
+    for path in report.damaged.iter() {
+        println!("Damaged: {}", path);
+    }
+    println!("{}.", report.human_readable());
+
+    Ok(())
+}
+
+fn gc(global: &Arc<Global>, args: Vec<String>, _path: &mut Vec<String>, _cwd: &mut Vec<Stored>, _clipboard: &mut Option<Stored>) -> Result<(), String> {
+    let delete = match args.iter().map(|arg| arg.as_str()).collect::<Vec<&str>>().as_slice() {
+        [] => false,
+        ["delete"] => true,
+        _ => return Err("Usage: gc [delete]".to_string())
+    };
+
+    let rt = Runtime::new().unwrap();
+    let report = rt.block_on(gc::collect(global.clone(), delete))?;
+    for (bucket, descriptor) in report.orphans.iter() {
+        println!("  {:<20} {}", bucket, String::from_utf8_lossy(descriptor));
+    }
+    for error in report.errors.iter() {
+        println!("Error: {}", error);
+    }
+    println!("{}.", report.human_readable());
+    if !delete && !report.orphans.is_empty() {
+        println!("Run \"gc delete\" to delete them.");
+    }
+
+    Ok(())
+}
+
+fn cache_stats(global: &Arc<Global>, _args: Vec<String>, _path: &mut Vec<String>, _cwd: &mut Vec<Stored>, _clipboard: &mut Option<Stored>) -> Result<(), String> {
+    println!("  {:<20} {:<10} {:<10} Cache size", "Name", "Hits", "Misses");
+    let mut buckets = global.list_buckets();
+    buckets.sort();
+    for name in buckets {
+        match global.get_bucket(name).and_then(|bucket| bucket.cache()) {
+            Some(cache) => println!("  {:<20} {:<10} {:<10} {} / {}", name, cache.hits(), cache.misses(), cache.cache().size(), cache.cache().max_size()),
+            None => println!("  {:<20} not cached", name)
+        }
+    }
+    Ok(())
+}
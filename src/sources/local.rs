@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use serde::Deserialize;
-use tokio::{io::{BufReader, AsyncReadExt, AsyncWriteExt}, fs::{File, remove_file, OpenOptions}};
+use tokio::{io::{BufReader, AsyncReadExt, AsyncWriteExt}, fs::{File, remove_file, OpenOptions, read_dir}};
 use rand::{thread_rng, Rng, distributions::Alphanumeric};
 
 use crate::global::Descriptor;
@@ -52,7 +52,8 @@ impl Source for LocalSource {
             Err(e) => return Err(format!("Error opening file: {}", e))
         };
         // Write the data to the file
-        file.write(&data.to_vec()).await.map_err(|e| format!("Error writing file: {}", e))?;
+        file.write_all(&data).await.map_err(|e| format!("Error writing file: {}", e))?;
+        file.flush().await.map_err(|e| format!("Error writing file: {}", e))?;
         Ok(())
     }
 
@@ -86,4 +87,23 @@ impl Source for LocalSource {
         file.write_all(b"").await.map_err(|e| format!("Error writing file: {}", e))?;
         Ok(descriptor.into_bytes())
     }
+
+    async fn list(&self) -> Result<Vec<Descriptor>, String> {
+        let mut entries = read_dir(&self.folder).await.map_err(|e| format!("Error reading folder: {}", e))?;
+        let mut descriptors = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(|e| format!("Error reading folder: {}", e))? {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue
+            };
+            // the folder might contain other files, so we only return the ones that look like our descriptors
+            if name.len() != self.descriptor_length || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                continue;
+            }
+            if entry.file_type().await.map(|t| t.is_file()).unwrap_or(false) {
+                descriptors.push(name.into_bytes());
+            }
+        }
+        Ok(descriptors)
+    }
 }
\ No newline at end of file
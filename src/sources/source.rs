@@ -12,6 +12,9 @@ pub trait Source {
     async fn put(&self, descriptor: &Descriptor, data: Vec<u8>) -> Result<(), String>;
     async fn delete(&self, descriptor: &Descriptor) -> Result<(), String>;
     async fn create(&self) -> Result<Descriptor, String>;
+    async fn list(&self) -> Result<Vec<Descriptor>, String> {
+        Err("Listing is not supported by this source".to_string())
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -44,6 +47,15 @@ impl SourceType {
             SourceType::GithubRelease(_) => "github releases",
         }
     }
+
+    // the name used in the config
+    pub fn type_name(&self) -> &str {
+        match self {
+            SourceType::LocalSource(_) => "local",
+            SourceType::DiscordWebhook(_) => "discord_webhook",
+            SourceType::GithubRelease(_) => "github_releases",
+        }
+    }
 }
 
 #[async_trait]
@@ -67,4 +79,8 @@ impl Source for SourceType {
     async fn create(&self) -> Result<Descriptor, String> {
         match_method!(self, create, ).await
     }
+
+    async fn list(&self) -> Result<Vec<Descriptor>, String> {
+        match_method!(self, list, ).await
+    }
 }
\ No newline at end of file
@@ -4,7 +4,7 @@
     It uses messagepack for serialization for backwards compatibility.
  */
 
-use std::sync::Arc;
+use std::{hash::{Hash, Hasher}, sync::Arc};
 use serde::{Serialize, Deserialize};
 use rmp_serde::{Serializer, Deserializer};
 use crate::global::{Global, Descriptor};
@@ -24,6 +24,15 @@ impl PartialEq for Stored {
     }
 }
 
+impl Eq for Stored {}
+
+impl Hash for Stored {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bucket.hash(state);
+        self.descriptor.hash(state);
+    }
+}
+
 impl Stored {
     pub async fn get<T: Deserialize<'static>>(&self, global: Arc<Global>) -> Result<T, String> {
         // Get bucket
@@ -86,6 +95,10 @@ impl Stored {
             .await
     }
 
+    pub fn descriptor(&self) -> &Descriptor {
+        &self.descriptor
+    }
+
     pub fn as_url(&self) -> String {
         format!("{}${}", urlencoding::encode(&self.bucket).replace('$', "%24"), urlencoding::encode_binary(&self.descriptor).replace('$', "%24"))
     }
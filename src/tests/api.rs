@@ -0,0 +1,161 @@
+use std::{env, sync::Arc};
+use actix_web::{App, web, body::to_bytes, dev::ServiceResponse, test::{TestRequest, init_service, call_service}, http::{header, StatusCode}};
+use serde_json::{json, Value};
+use serde_yaml::from_str;
+
+use crate::{global::Global, services::http::{api, service::{HttpService, ServerData}, }};
+use super::utils::make_temp_config_multi;
+
+fn server_data(name: &str, extra: &str) -> Arc<ServerData> {
+    let folder = env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(2, 400, &format!("direct_block_count: 3\nroot_path: {}\n", folder.join("root.dat").display()))).unwrap();
+    let config = from_str::<HttpService>(&format!("port: 0\ntus_path: {}\n{}", folder.join("tus.dat").display(), extra)).unwrap();
+    Arc::new(ServerData::new(Arc::new(global), config).unwrap())
+}
+
+async fn json_body(response: ServiceResponse) -> Value {
+    serde_json::from_slice(&to_bytes(response.into_body()).await.unwrap()).unwrap()
+}
+
+macro_rules! call {
+    ($app:expr, $request:expr) => {{
+        let response = call_service($app, $request.to_request()).await;
+        let status = response.status();
+        (status, json_body(response).await)
+    }};
+}
+
+#[actix_web::test]
+async fn api_operations() {
+    let data = server_data("chunkdrive_api", "");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(api::configure)).await;
+    let content = (0..1_500u32).map(|x| (x % 233) as u8).collect::<Vec<u8>>();
+
+    let (status, root) = call!(&app, TestRequest::get().uri("/api/v1/stat/"));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(root["type"], "directory");
+    assert_eq!(root["entries"], 0);
+
+    let (status, entry) = call!(&app, TestRequest::post().uri("/api/v1/mkdir/docs"));
+    assert_eq!((status, entry["name"].as_str()), (StatusCode::CREATED, Some("docs")));
+    let (status, error) = call!(&app, TestRequest::post().uri("/api/v1/mkdir/docs"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::CONFLICT, &json!("already_exists")));
+    assert_eq!(call!(&app, TestRequest::post().uri("/api/v1/mkdir/docs?parents=true")).0, StatusCode::OK);
+    assert_eq!(call!(&app, TestRequest::post().uri("/api/v1/mkdir/a/b/c?parents=true")).0, StatusCode::CREATED);
+    let (status, error) = call!(&app, TestRequest::post().uri("/api/v1/mkdir/missing/c"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::NOT_FOUND, &json!("not_found")));
+    let (status, error) = call!(&app, TestRequest::get().uri("/api/v1/stat/docs/../x"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::BAD_REQUEST, &json!("invalid_path")));
+
+    let (status, entry) = call!(&app, TestRequest::put().uri("/api/v1/upload/docs/a%20file.txt").set_payload(content.clone()));
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!((entry["type"].as_str(), entry["size"].as_u64(), entry["mime"].as_str()), (Some("file"), Some(1_500), Some("text/plain")));
+    let (status, error) = call!(&app, TestRequest::put().uri("/api/v1/upload/docs/a%20file.txt").set_payload("new"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::CONFLICT, &json!("already_exists")));
+    let (status, error) = call!(&app, TestRequest::put().uri("/api/v1/upload/docs").set_payload("new"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::CONFLICT, &json!("is_a_directory")));
+    let (status, error) = call!(&app, TestRequest::put().uri("/api/v1/upload/x?overwrite=maybe").set_payload("new"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::BAD_REQUEST, &json!("invalid_query")));
+    let (status, entry) = call!(&app, TestRequest::put().uri("/api/v1/upload/new/dir/b.bin?parents=true").set_payload("b"));
+    assert_eq!((status, entry["size"].as_u64()), (StatusCode::CREATED, Some(1)));
+
+    let (status, listing) = call!(&app, TestRequest::get().uri("/api/v1/list/"));
+    assert_eq!(status, StatusCode::OK);
+    let names = listing["entries"].as_array().unwrap().iter().map(|entry| entry["name"].as_str().unwrap()).collect::<Vec<&str>>();
+    assert_eq!(names, vec!["a", "docs", "new"]);
+    let (status, error) = call!(&app, TestRequest::get().uri("/api/v1/list/docs/a%20file.txt"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::CONFLICT, &json!("not_a_directory")));
+
+    let response = call_service(&app, TestRequest::get().uri("/api/v1/download/docs/a%20file.txt").insert_header((header::RANGE, "bytes=100-199")).to_request()).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert!(response.headers().get(header::CONTENT_DISPOSITION).unwrap().to_str().unwrap().starts_with("attachment"));
+    assert_eq!(to_bytes(response.into_body()).await.unwrap().to_vec(), content[100..200].to_vec());
+    let (status, error) = call!(&app, TestRequest::get().uri("/api/v1/download/docs"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::CONFLICT, &json!("is_a_directory")));
+
+    // copies get their own chunks, moves keep them
+    let (status, entry) = call!(&app, TestRequest::post().uri("/api/v1/copy").set_json(json!({ "from": "/docs", "to": "/copy" })));
+    assert_eq!((status, entry["name"].as_str()), (StatusCode::CREATED, Some("copy")));
+    let (status, _) = call!(&app, TestRequest::post().uri("/api/v1/move").set_json(json!({ "from": "/copy/a file.txt", "to": "/new/moved.txt" })));
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, error) = call!(&app, TestRequest::post().uri("/api/v1/move").set_json(json!({ "from": "/docs/a file.txt", "to": "/new/moved.txt" })));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::CONFLICT, &json!("already_exists")));
+    let (status, _) = call!(&app, TestRequest::post().uri("/api/v1/move").set_json(json!({ "from": "/docs/a file.txt", "to": "/new/moved.txt", "overwrite": true })));
+    assert_eq!(status, StatusCode::OK);
+    let (status, error) = call!(&app, TestRequest::post().uri("/api/v1/move").set_json(json!({ "from": "/new/moved.txt", "to": "/new/dir", "overwrite": true })));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::CONFLICT, &json!("is_a_directory")));
+    let (status, error) = call!(&app, TestRequest::post().uri("/api/v1/move").set_json(json!({ "from": "/a", "to": "/a/b/a" })));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::BAD_REQUEST, &json!("invalid_path")));
+    let (status, error) = call!(&app, TestRequest::post().uri("/api/v1/copy").set_payload("{\"from\": 1}"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::BAD_REQUEST, &json!("invalid_body")));
+    let response = call_service(&app, TestRequest::get().uri("/api/v1/download/new/moved.txt").to_request()).await;
+    assert_eq!(to_bytes(response.into_body()).await.unwrap().to_vec(), content);
+    assert_eq!(call!(&app, TestRequest::get().uri("/api/v1/stat/docs/a%20file.txt")).0, StatusCode::NOT_FOUND);
+
+    let (status, error) = call!(&app, TestRequest::delete().uri("/api/v1/delete/a"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::CONFLICT, &json!("not_empty")));
+    assert_eq!(call_service(&app, TestRequest::delete().uri("/api/v1/delete/a?recursive=true").to_request()).await.status(), StatusCode::NO_CONTENT);
+    assert_eq!(call!(&app, TestRequest::delete().uri("/api/v1/delete/a")).0, StatusCode::NOT_FOUND);
+    assert_eq!(call!(&app, TestRequest::delete().uri("/api/v1/delete/")).0, StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn api_limits() {
+    let data = server_data("chunkdrive_api_readonly", "readonly: true\n");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(api::configure)).await;
+    let (status, error) = call!(&app, TestRequest::post().uri("/api/v1/mkdir/docs"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::FORBIDDEN, &json!("read_only")));
+    assert_eq!(call!(&app, TestRequest::get().uri("/api/v1/list/")).0, StatusCode::OK);
+
+    let data = server_data("chunkdrive_api_hidden", "see_root: false\n");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(api::configure)).await;
+    let (status, error) = call!(&app, TestRequest::get().uri("/api/v1/list/"));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::FORBIDDEN, &json!("forbidden")));
+
+    // nothing is left behind when an upload is refused
+    let data = server_data("chunkdrive_api_limit", "max_upload_size: 1000\n");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(api::configure)).await;
+    let (status, error) = call!(&app, TestRequest::put().uri("/api/v1/upload/big.bin").set_payload(vec![1u8; 1_500]));
+    assert_eq!((status, &error["error"]["code"]), (StatusCode::PAYLOAD_TOO_LARGE, &json!("too_large")));
+    assert_eq!(call!(&app, TestRequest::get().uri("/api/v1/stat/big.bin")).0, StatusCode::NOT_FOUND);
+
+    let (status, document) = call!(&app, TestRequest::get().uri("/api/v1/openapi.json"));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(document["openapi"], "3.0.3");
+    assert_eq!(document["paths"].as_object().unwrap().len(), 10);
+}
+
+#[actix_web::test]
+async fn copies_that_are_not_linked_are_deleted() {
+    // the buckets get their own folder, so the chunks of other tests are not counted
+    let folder = env::temp_dir().join("chunkdrive_api_copy");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(folder.join("chunks")).unwrap();
+    let global = from_str::<Global>(&format!(r#"
+buckets:
+    copy1:
+        source:
+            type: local
+            folder: {}
+            max_size: 400
+            descriptor_length: 3
+root_path: {}
+"#, folder.join("chunks").display(), folder.join("root.dat").display())).unwrap();
+    let config = from_str::<HttpService>(&format!("port: 0\ntus_path: {}\n", folder.join("tus.dat").display())).unwrap();
+    let data = Arc::new(ServerData::new(Arc::new(global), config).unwrap());
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(api::configure)).await;
+    let chunks = || std::fs::read_dir(folder.join("chunks")).unwrap().count();
+
+    assert_eq!(call!(&app, TestRequest::put().uri("/api/v1/upload/a.bin").set_payload(vec![1u8; 1_000])).0, StatusCode::CREATED);
+    assert_eq!(call!(&app, TestRequest::put().uri("/api/v1/upload/b.bin").set_payload(vec![2u8; 1_000])).0, StatusCode::CREATED);
+    let before = chunks();
+    let (status, _) = call!(&app, TestRequest::post().uri("/api/v1/copy").set_json(json!({ "from": "/a.bin", "to": "/b.bin" })));
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = call!(&app, TestRequest::post().uri("/api/v1/copy").set_json(json!({ "from": "/a.bin", "to": "/missing/c.bin" })));
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(chunks(), before);
+
+    let _ = std::fs::remove_dir_all(folder);
+}
@@ -0,0 +1,135 @@
+use std::{env, sync::Arc};
+use actix_web::{App, web, body::to_bytes, cookie::Cookie, test::{TestRequest, init_service, call_service}, http::{header, StatusCode}};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_yaml::from_str;
+
+use crate::{global::Global, inodes::path::open, services::http::{auth::{hash_password, new_token}, service::{self, HttpService, ServerData}}};
+use super::utils::make_temp_config_multi;
+
+fn server_data(name: &str, extra: &str) -> Arc<ServerData> {
+    let folder = env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(2, 400, &format!("direct_block_count: 3\nroot_path: {}\n", folder.join("root.dat").display()))).unwrap();
+    let config = from_str::<HttpService>(&format!("port: 0\ntus_path: {}\n{}", folder.join("tus.dat").display(), extra)).unwrap();
+    Arc::new(ServerData::new(Arc::new(global), config).unwrap())
+}
+
+macro_rules! status {
+    ($app:expr, $request:expr) => {
+        call_service($app, $request.to_request()).await.status()
+    };
+}
+
+macro_rules! body {
+    ($app:expr, $request:expr) => {
+        String::from_utf8(to_bytes(call_service($app, $request.to_request()).await.into_body()).await.unwrap().to_vec()).unwrap()
+    };
+}
+
+#[actix_web::test]
+async fn users_and_permissions() {
+    let (token, hash) = new_token();
+    let config = format!(r#"admin: true
+secret: test
+auth:
+    users:
+        root:
+            password: "{}"
+            permissions:
+                /: admin
+        alice:
+            tokens: ["{}"]
+            permissions:
+                /shared: write
+                /shared/archive: read
+"#, hash_password("hunter2").unwrap(), hash);
+    let data = server_data("chunkdrive_auth", &config);
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(service::configure)).await;
+    let alice = ("Authorization", format!("Bearer {}", token));
+
+    // nobody is let in without logging in
+    let response = call_service(&app, TestRequest::get().uri("/files/").to_request()).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/login?next=%2Ffiles%2F");
+    let response = call_service(&app, TestRequest::get().uri("/api/v1/list/").to_request()).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers().get(header::WWW_AUTHENTICATE).unwrap(), "Bearer");
+    assert_eq!(status!(&app, TestRequest::get().uri("/api/v1/list/").insert_header(("Authorization", "Bearer nope"))), StatusCode::UNAUTHORIZED);
+    assert_eq!(status!(&app, TestRequest::get().uri("/api/v1/list/").cookie(Cookie::new("session", "cm9vdA:99999999999.00"))), StatusCode::UNAUTHORIZED);
+
+    let login = |password: &str, next: &str| TestRequest::post().uri("/login").set_form([("username", "root"), ("password", password), ("next", next)]);
+    assert_eq!(status!(&app, login("wrong", "/files/")), StatusCode::UNAUTHORIZED);
+    let response = call_service(&app, login("hunter2", "//example.com/").to_request()).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/files/");
+    let session = response.response().cookies().find(|cookie| cookie.name() == "session").unwrap().into_owned();
+    assert!(session.http_only().unwrap_or(false));
+
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/mkdir/shared/archive?parents=true").cookie(session.clone())), StatusCode::CREATED);
+    assert_eq!(status!(&app, TestRequest::put().uri("/api/v1/upload/private/secret.txt?parents=true").cookie(session.clone()).set_payload("secret")), StatusCode::CREATED);
+    assert_eq!(status!(&app, TestRequest::put().uri("/api/v1/upload/shared/archive/old.txt").cookie(session.clone()).set_payload("old")), StatusCode::CREATED);
+
+    // alice only sees the way to her directory and can not change the archive in it
+    let listing = body!(&app, TestRequest::get().uri("/api/v1/list/").insert_header(alice.clone()));
+    assert!(listing.contains("\"shared\"") && !listing.contains("private"));
+    assert_eq!(status!(&app, TestRequest::get().uri("/api/v1/list/private").insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::get().uri("/api/v1/download/private/secret.txt").insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::put().uri("/api/v1/upload/shared/a.txt").insert_header(alice.clone()).set_payload("a")), StatusCode::CREATED);
+    assert_eq!(status!(&app, TestRequest::get().uri("/api/v1/download/shared/archive/old.txt").insert_header(alice.clone())), StatusCode::OK);
+    assert_eq!(status!(&app, TestRequest::put().uri("/api/v1/upload/shared/archive/b.txt").insert_header(alice.clone()).set_payload("b")), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::delete().uri("/api/v1/delete/shared?recursive=true").insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/mkdir/mine").insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    let relocate = |operation: &str, from: &str, to: &str| TestRequest::post().uri(&format!("/api/v1/{}", operation)).insert_header(alice.clone()).set_json(serde_json::json!({ "from": from, "to": to }));
+    assert_eq!(status!(&app, relocate("move", "/shared/a.txt", "/private/a.txt")), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, relocate("copy", "/private/secret.txt", "/shared/secret.txt")), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, relocate("copy", "/shared/archive", "/shared/copy")), StatusCode::CREATED);
+    assert_eq!(status!(&app, relocate("move", "/shared/archive", "/shared/moved")), StatusCode::FORBIDDEN);
+
+    // the html index hides the same entries, and descriptors of other directories are not accepted
+    let index = body!(&app, TestRequest::get().uri("/files/").insert_header(alice.clone()));
+    assert!(index.contains(">shared<") && !index.contains("private"));
+    let private = open(data.global.clone(), &["private".to_string()]).await.unwrap().unwrap().0;
+    let forged = format!("/files/{}$shared/", private.as_url());
+    assert_eq!(status!(&app, TestRequest::get().uri(&forged).insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    assert!(body!(&app, TestRequest::get().uri(&forged).cookie(session.clone())).contains("secret.txt"));
+
+    // uploads need write access to the target and belong to whoever started them
+    let tus = |path: &str| TestRequest::post().uri(path)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", "10"))
+        .insert_header(("Upload-Metadata", format!("filename {}", STANDARD.encode("t.bin"))));
+    let shared = open(data.global.clone(), &["shared".to_string()]).await.unwrap().unwrap().0;
+    let archive = open(data.global.clone(), &["shared".to_string(), "archive".to_string()]).await.unwrap().unwrap().0;
+    let (shared, archive) = (format!("/tus/files/{}$shared/", shared.as_url()), format!("/tus/files/{}$shared/{}$archive/", shared.as_url(), archive.as_url()));
+    assert_eq!(status!(&app, tus(&archive).insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    let response = call_service(&app, tus(&shared).cookie(session.clone()).to_request()).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    let head = || TestRequest::default().method("HEAD".parse().unwrap()).uri(&location).insert_header(("Tus-Resumable", "1.0.0"));
+    assert_eq!(status!(&app, head().insert_header(alice.clone())), StatusCode::NOT_FOUND);
+    assert_eq!(status!(&app, head().cookie(session.clone())), StatusCode::OK);
+
+    assert_eq!(status!(&app, TestRequest::get().uri("/admin/").insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    assert!(body!(&app, TestRequest::get().uri("/admin/").cookie(session.clone())).contains("multi0"));
+    let report = body!(&app, TestRequest::post().uri("/admin/").cookie(session.clone()).set_form([("task", "gc")]));
+    assert!(report.contains("Garbage collection finished"));
+    assert_eq!(status!(&app, TestRequest::post().uri("/admin/").cookie(session.clone()).set_form([("task", "gc-delete")])), StatusCode::BAD_REQUEST);
+
+    let response = call_service(&app, TestRequest::post().uri("/logout").cookie(session.clone()).to_request()).await;
+    assert_eq!(response.response().cookies().find(|cookie| cookie.name() == "session").unwrap().value(), "");
+}
+
+#[actix_web::test]
+async fn admin_pages_are_disabled_by_default() {
+    let data = server_data("chunkdrive_auth_disabled", "");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(service::configure)).await;
+    assert_eq!(status!(&app, TestRequest::get().uri("/admin/")), StatusCode::NOT_FOUND);
+    // without users everyone can do everything, like before
+    assert_eq!(status!(&app, TestRequest::get().uri("/files/")), StatusCode::OK);
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/mkdir/docs")), StatusCode::CREATED);
+
+    let data = server_data("chunkdrive_auth_enabled", "admin: true\n");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(service::configure)).await;
+    assert_eq!(status!(&app, TestRequest::get().uri("/admin/")), StatusCode::OK);
+}
@@ -34,4 +34,68 @@ fn simple_unencrypted() {
 #[test]
 fn simple_encrypted() {
     shared_default(true);
-}
\ No newline at end of file
+}
+#[test]
+fn encrypted_with_kdf() {
+    let cfg = format!(r#"
+buckets:
+    kdf1:
+        source:
+            type: local
+            folder: {}
+            max_size: 25
+            descriptor_length: 3
+        encryption:
+            type: aes
+            key: "correct horse battery staple"
+            kdf:
+                type: argon2id
+                salt: "chunkdrive-salt"
+                memory: 64
+                iterations: 1
+        "#, std::env::temp_dir().display());
+    let global = from_str::<Global>(&cfg).unwrap();
+    let bucket = global.get_bucket("kdf1").unwrap();
+
+    let data = [1u8, 2, 3, 4, 5].repeat(3);
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let descriptor = bucket.create().await.unwrap();
+        bucket.put(&descriptor, data.clone()).await.unwrap();
+        assert_eq!(bucket.get(&descriptor).await.unwrap(), data);
+        bucket.delete(&descriptor).await.unwrap();
+    });
+}
+
+#[test]
+fn compressed_before_encryption() {
+    let folder = std::env::temp_dir();
+    let cfg = format!(r#"
+buckets:
+    zstd1:
+        source:
+            type: local
+            folder: {}
+            max_size: 1000
+            descriptor_length: 3
+        encryption:
+            type: chacha20poly1305
+            key: "12345678901234567890123456789012"
+        compression:
+            type: zstd
+        "#, folder.display());
+    let global = from_str::<Global>(&cfg).unwrap();
+    let bucket = global.get_bucket("zstd1").unwrap();
+    assert_eq!(bucket.max_size(), 1000 - 28 - 1);
+
+    let text = "all work and no play makes jack a dull boy ".repeat(20).into_bytes();
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let descriptor = bucket.create().await.unwrap();
+        bucket.put(&descriptor, text.clone()).await.unwrap();
+        let stored = std::fs::metadata(folder.join(std::str::from_utf8(&descriptor).unwrap())).unwrap().len() as usize;
+        assert!(stored < text.len() / 4);
+        assert_eq!(bucket.get(&descriptor).await.unwrap(), text);
+        bucket.delete(&descriptor).await.unwrap();
+    });
+}
@@ -0,0 +1,118 @@
+use std::{env, path::PathBuf};
+use serde_yaml::from_str;
+
+use crate::global::Global;
+
+// every test gets its own cache folder, because caches with the same folder are shared
+fn make_cache_config(name: &str, cache_size: usize, global_cache: bool) -> (PathBuf, String) {
+    let folder = env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    let cache = format!("cache:\n    folder: {}\n    max_size: {}\n", folder.display(), cache_size);
+    let config = format!(r#"
+buckets:
+    cached1:
+        source:
+            type: local
+            folder: {}
+            max_size: 30
+            descriptor_length: 3
+{}
+"#, env::temp_dir().display(), if global_cache { cache } else { cache.lines().map(|line| format!("        {}\n", line)).collect() });
+    (folder, config)
+}
+
+fn remove_behind_back(descriptor: &[u8]) {
+    std::fs::remove_file(env::temp_dir().join(std::str::from_utf8(descriptor).unwrap())).unwrap();
+}
+
+#[tokio::test]
+async fn hits_and_invalidation() {
+    let (folder, config) = make_cache_config("chunkdrive_cache_hits", 1000, false);
+    let global = from_str::<Global>(&config).unwrap();
+    let bucket = global.get_bucket("cached1").unwrap();
+    let cache = bucket.cache().unwrap();
+
+    let descriptor = bucket.create().await.unwrap();
+    bucket.put(&descriptor, vec![1, 2, 3]).await.unwrap();
+    assert_eq!(bucket.get(&descriptor).await.unwrap(), vec![1, 2, 3]);
+    assert_eq!((cache.hits(), cache.misses()), (0, 1));
+    assert_eq!(bucket.get(&descriptor).await.unwrap(), vec![1, 2, 3]);
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+    // put drops the cached copy
+    bucket.put(&descriptor, vec![4, 5, 6]).await.unwrap();
+    assert_eq!(bucket.get(&descriptor).await.unwrap(), vec![4, 5, 6]);
+    assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+    // the cached copy is used even if the source lost it
+    let other = bucket.create().await.unwrap();
+    bucket.put(&other, vec![7, 8, 9]).await.unwrap();
+    bucket.get(&other).await.unwrap();
+    remove_behind_back(&other);
+    assert_eq!(bucket.get(&other).await.unwrap(), vec![7, 8, 9]);
+
+    // delete drops it as well
+    bucket.delete(&descriptor).await.unwrap();
+    assert!(bucket.get(&descriptor).await.is_err());
+
+    let _ = std::fs::remove_dir_all(folder);
+}
+
+#[tokio::test]
+async fn evicts_least_recently_used() {
+    let (folder, config) = make_cache_config("chunkdrive_cache_lru", 25, false);
+    let global = from_str::<Global>(&config).unwrap();
+    let bucket = global.get_bucket("cached1").unwrap();
+    let cache = bucket.cache().unwrap();
+
+    let mut descriptors = Vec::new();
+    for i in 0..3u8 {
+        let descriptor = bucket.create().await.unwrap();
+        bucket.put(&descriptor, vec![i; 10]).await.unwrap();
+        descriptors.push(descriptor);
+    }
+    bucket.get(&descriptors[0]).await.unwrap();
+    bucket.get(&descriptors[1]).await.unwrap();
+    bucket.get(&descriptors[0]).await.unwrap(); // the second one is now the least recently used
+    bucket.get(&descriptors[2]).await.unwrap();
+    assert!(cache.cache().size() <= 25);
+
+    let misses = cache.misses();
+    bucket.get(&descriptors[0]).await.unwrap();
+    assert_eq!(cache.misses(), misses);
+    bucket.get(&descriptors[1]).await.unwrap();
+    assert_eq!(cache.misses(), misses + 1);
+
+    for descriptor in descriptors {
+        bucket.delete(&descriptor).await.unwrap();
+    }
+    let _ = std::fs::remove_dir_all(folder);
+}
+
+#[test]
+fn global_cache_skips_local_buckets() {
+    let (_, config) = make_cache_config("chunkdrive_cache_global", 1000, true);
+    let global = from_str::<Global>(&config).unwrap();
+    assert!(global.get_bucket("cached1").unwrap().cache().is_none());
+}
+
+#[tokio::test]
+async fn changes_during_a_read_are_not_cached() {
+    let (folder, config) = make_cache_config("chunkdrive_cache_race", 1000, false);
+    let global = from_str::<Global>(&config).unwrap();
+    let bucket = global.get_bucket("cached1").unwrap();
+    let cache = bucket.cache().unwrap();
+
+    let descriptor = bucket.create().await.unwrap();
+    bucket.put(&descriptor, vec![1, 2, 3]).await.unwrap();
+
+    // a read that started before the put would cache the old data
+    let reservation = cache.reserve(&descriptor);
+    bucket.put(&descriptor, vec![4, 5, 6]).await.unwrap();
+    cache.insert(reservation, &[1, 2, 3]).await;
+    assert_eq!(cache.cache().size(), 0);
+    assert_eq!(bucket.get(&descriptor).await.unwrap(), vec![4, 5, 6]);
+
+    bucket.delete(&descriptor).await.unwrap();
+    let _ = std::fs::remove_dir_all(folder);
+}
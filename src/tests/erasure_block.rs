@@ -0,0 +1,96 @@
+use std::sync::Arc;
+use futures::StreamExt;
+use serde_yaml::from_str;
+
+use crate::{blocks::{block::{Block, BlockType}, erasure_block::ErasureBlock, redundancy::Redundancy}, global::Global, inodes::file::File};
+use super::utils::make_temp_config_multi;
+
+async fn read_all(global: Arc<Global>, block: &BlockType, range: std::ops::Range<usize>) -> Result<Vec<u8>, String> {
+    let mut got = Vec::new();
+    let mut stream = block.get(global, range);
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk?);
+    }
+    Ok(got)
+}
+
+// removes the shards with the given indices behind the block's back
+async fn drop_shards(global: Arc<Global>, block: &BlockType, indices: &[usize]) {
+    let shards = match block {
+        BlockType::Erasure(block) => block.shards(),
+        _ => panic!("Expected an erasure block"),
+    };
+    for i in indices {
+        let bucket = global.get_bucket(&shards[*i].bucket).unwrap();
+        bucket.delete(&shards[*i].descriptor).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn rebuilds_from_parity() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(5, 30, "")).unwrap());
+    let data = (0..89u8).collect::<Vec<u8>>(); // not a multiple of the data shard count
+    let block = ErasureBlock::create_erasure(global.clone(), data.clone(), 0, 3, 2).await.unwrap();
+    assert_eq!(read_all(global.clone(), &block, 0..data.len()).await.unwrap(), data);
+
+    // any two shards can go missing
+    drop_shards(global.clone(), &block, &[0, 2]).await;
+    assert_eq!(read_all(global.clone(), &block, 0..data.len()).await.unwrap(), data);
+    assert_eq!(read_all(global.clone(), &block, 10..50).await.unwrap(), data[10..50].to_vec());
+
+    // but not three
+    drop_shards(global.clone(), &block, &[3]).await;
+    assert!(read_all(global.clone(), &block, 0..data.len()).await.is_err());
+}
+
+#[tokio::test]
+async fn put_rewrites_shards() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(3, 30, "")).unwrap());
+    let data = (0..40u8).collect::<Vec<u8>>();
+    let mut block = ErasureBlock::create_erasure(global.clone(), data.clone(), 0, 2, 1).await.unwrap();
+    let data1 = data.iter().map(|x| x + 1).collect::<Vec<u8>>();
+    block.put(global.clone(), data1.clone(), 0..data1.len()).await.unwrap();
+    drop_shards(global.clone(), &block, &[1]).await;
+    assert_eq!(read_all(global.clone(), &block, 0..data1.len()).await.unwrap(), data1);
+}
+
+#[tokio::test]
+async fn file_with_erasure() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(4, 700, "")).unwrap());
+    let data = [1u8, 2, 3, 4, 5].repeat(2_000);
+    let redundancy = "erasure:3:1".parse::<Redundancy>().unwrap();
+    let file = File::create_from_reader(global.clone(), &mut data.as_slice(), redundancy).await.unwrap();
+    assert!(format!("{:?}", file.data).contains("Erasure"));
+    let mut got = Vec::new();
+    let mut stream = file.get(global.clone());
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    assert_eq!(got, data);
+    file.data.delete(global).await.unwrap();
+}
+
+#[test]
+fn parse_redundancy() {
+    assert!(matches!("none".parse::<Redundancy>(), Ok(Redundancy::None)));
+    assert!(matches!("replicate:3".parse::<Redundancy>(), Ok(Redundancy::Replicate { copies: 3 })));
+    assert!(matches!("erasure:4:2".parse::<Redundancy>(), Ok(Redundancy::Erasure { data: 4, parity: 2 })));
+    assert!("erasure:4".parse::<Redundancy>().is_err());
+    assert!("mirror".parse::<Redundancy>().is_err());
+}
+
+#[tokio::test]
+async fn put_replaces_failed_shards() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(3, 30, "")).unwrap());
+    let data = (0..40u8).collect::<Vec<u8>>();
+    let mut block = ErasureBlock::create_erasure(global.clone(), data.clone(), 0, 2, 1).await.unwrap();
+
+    // the local source can not write to a chunk that is gone, so the first shard fails
+    drop_shards(global.clone(), &block, &[0]).await;
+    let data1 = data.iter().map(|x| x + 1).collect::<Vec<u8>>();
+    block.put(global.clone(), data1.clone(), 0..data1.len()).await.unwrap();
+
+    // the new data can be read from the replaced shard and any other one
+    drop_shards(global.clone(), &block, &[1]).await;
+    assert_eq!(read_all(global.clone(), &block, 0..data1.len()).await.unwrap(), data1);
+}
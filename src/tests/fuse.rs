@@ -0,0 +1,75 @@
+use std::{env, os::unix::fs::MetadataExt, sync::Arc};
+use serde_yaml::from_str;
+
+use crate::{fuse::{Session, unmount}, global::Global};
+use super::utils::make_temp_config_multi;
+
+#[test]
+fn fuse_mount() {
+    let folder = env::temp_dir().join("chunkdrive_fuse");
+    let _ = std::fs::remove_dir_all(&folder);
+    let mountpoint = folder.join("mnt");
+    std::fs::create_dir_all(&mountpoint).unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(2, 400, &format!("direct_block_count: 3\nroot_path: {}\n", folder.join("root.dat").display()))).unwrap();
+    let mountpoint = mountpoint.to_str().unwrap().to_string();
+
+    // mounting needs root and /dev/fuse, which are not there everywhere
+    let session = match Session::mount(&mountpoint) {
+        Ok(session) => session,
+        Err(e) => {
+            println!("Skipping the FUSE test: {}", e);
+            return;
+        },
+    };
+    let server = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        session.run(Arc::new(global), &runtime)
+    });
+
+    let result = std::panic::catch_unwind(|| {
+        let root = std::path::Path::new(&mountpoint);
+        let content = (0..1_500u32).map(|x| (x % 233) as u8).collect::<Vec<u8>>();
+
+        std::fs::create_dir(root.join("docs")).unwrap();
+        assert!(std::fs::create_dir(root.join("docs")).is_err());
+        std::fs::write(root.join("docs/a.bin"), &content).unwrap();
+        assert_eq!(std::fs::read(root.join("docs/a.bin")).unwrap(), content);
+        assert_eq!(std::fs::metadata(root.join("docs/a.bin")).unwrap().len(), 1_500);
+        let ino = std::fs::metadata(root.join("docs/a.bin")).unwrap().ino();
+
+        // changed files are uploaded again when they are closed, under the same inode
+        let file = std::fs::OpenOptions::new().write(true).open(root.join("docs/a.bin")).unwrap();
+        std::os::unix::fs::FileExt::write_all_at(&file, b"chunk", 1_000).unwrap();
+        drop(file);
+        let mut changed = content.clone();
+        changed[1_000..1_005].copy_from_slice(b"chunk");
+        assert_eq!(std::fs::read(root.join("docs/a.bin")).unwrap(), changed);
+
+        std::fs::rename(root.join("docs/a.bin"), root.join("b.bin")).unwrap();
+        assert_eq!(std::fs::metadata(root.join("b.bin")).unwrap().ino(), ino);
+        assert!(std::fs::metadata(root.join("docs/a.bin")).is_err());
+        std::fs::write(root.join("docs/c.txt"), "small").unwrap();
+        std::fs::rename(root.join("docs/c.txt"), root.join("b.bin")).unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("b.bin")).unwrap(), "small");
+
+        std::fs::OpenOptions::new().write(true).open(root.join("b.bin")).unwrap().set_len(2).unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("b.bin")).unwrap(), "sm");
+
+        let mut names = std::fs::read_dir(root).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect::<Vec<String>>();
+        names.sort();
+        assert_eq!(names, vec!["b.bin", "docs"]);
+        assert!(std::fs::remove_dir(root.join("b.bin")).is_err());
+        std::fs::remove_file(root.join("b.bin")).unwrap();
+        std::fs::write(root.join("docs/d.txt"), "d").unwrap();
+        assert!(std::fs::remove_dir(root.join("docs")).is_err());
+        std::fs::remove_file(root.join("docs/d.txt")).unwrap();
+        std::fs::remove_dir(root.join("docs")).unwrap();
+        assert_eq!(std::fs::read_dir(root).unwrap().count(), 0);
+    });
+
+    unmount(&mountpoint).unwrap();
+    server.join().unwrap().unwrap();
+    if let Err(e) = result {
+        std::panic::resume_unwind(e);
+    }
+}
@@ -0,0 +1,83 @@
+use std::{env, path::PathBuf, sync::Arc};
+use futures::StreamExt;
+use serde_yaml::from_str;
+
+use crate::{gc::collect, global::Global, inodes::{directory::Directory, file::File, inode::InodeType}};
+
+// gc deletes everything it does not know about, so it gets its own folder instead of the shared temp dir
+fn make_gc_config(name: &str) -> (PathBuf, String) {
+    let folder = env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let config = format!(r#"
+buckets:
+    gc1:
+        source:
+            type: local
+            folder: {0}
+            max_size: 700
+            descriptor_length: 3
+root_path: {0}/root.dat
+"#, folder.display());
+    (folder, config)
+}
+
+#[tokio::test]
+async fn deletes_only_orphans() {
+    let (folder, config) = make_gc_config("chunkdrive_gc_orphans");
+    let global = Arc::new(from_str::<Global>(&config).unwrap());
+    let data = [1u8, 2, 3, 4, 5].repeat(400);
+
+    let mut root = Directory::new();
+    let file = File::create(global.clone(), data.clone()).await.unwrap();
+    root.add(global.clone(), &"kept".to_string(), file.to_enum()).await.unwrap();
+    global.save_root(&root).unwrap();
+
+    // a file that never made it into a directory and an empty placeholder
+    File::create(global.clone(), data.clone()).await.unwrap();
+    global.get_bucket("gc1").unwrap().create().await.unwrap();
+    std::fs::write(folder.join("notes.txt"), b"not ours").unwrap();
+
+    let report = collect(global.clone(), false).await.unwrap();
+    assert!(report.orphans.len() > 1);
+    assert_eq!(report.deleted, 0);
+
+    let report = collect(global.clone(), true).await.unwrap();
+    assert_eq!(report.deleted, report.orphans.len());
+    assert!(report.errors.is_empty());
+    assert!(collect(global.clone(), false).await.unwrap().orphans.is_empty());
+    assert!(folder.join("notes.txt").exists());
+
+    // the reachable file is untouched
+    let file = match global.get_root().unwrap().get(&"kept".to_string()).unwrap().get::<InodeType>(global.clone()).await.unwrap() {
+        InodeType::File(file) => file,
+        _ => panic!("Expected a file"),
+    };
+    let mut got = Vec::new();
+    let mut stream = file.get(global.clone());
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    assert_eq!(got, data);
+
+    let _ = std::fs::remove_dir_all(folder);
+}
+
+#[tokio::test]
+async fn aborts_on_unreadable_tree() {
+    let (folder, config) = make_gc_config("chunkdrive_gc_unreadable");
+    let global = Arc::new(from_str::<Global>(&config).unwrap());
+
+    let mut root = Directory::new();
+    let file = File::create(global.clone(), [1u8; 100].to_vec()).await.unwrap();
+    root.add(global.clone(), &"lost".to_string(), file.to_enum()).await.unwrap();
+    global.save_root(&root).unwrap();
+
+    // without the inode we can not know which chunks are used
+    let stored = root.get(&"lost".to_string()).unwrap().clone();
+    stored.delete(global.clone()).await.unwrap();
+    assert!(collect(global.clone(), true).await.is_err());
+    assert_eq!(global.get_bucket("gc1").unwrap().list().await.unwrap().len(), 1);
+
+    let _ = std::fs::remove_dir_all(folder);
+}
@@ -1,5 +1,23 @@
+pub mod api;
+pub mod auth;
 pub mod block;
 pub mod bucket;
+pub mod cache;
 pub mod direct_block;
+pub mod erasure_block;
+#[cfg(target_os = "linux")]
+pub mod fuse;
+pub mod gc;
+pub mod prefetch;
+pub mod range;
+pub mod replicated_block;
+pub mod root;
+pub mod s3;
+pub mod scrub;
+pub mod share;
 pub mod stored;
-pub mod utils;
\ No newline at end of file
+pub mod tls;
+pub mod tus;
+pub mod upload;
+pub mod utils;
+pub mod webdav;
\ No newline at end of file
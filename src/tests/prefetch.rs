@@ -0,0 +1,114 @@
+use std::sync::Arc;
+use futures::StreamExt;
+use serde_yaml::from_str;
+
+use crate::{blocks::block::{Block, BlockType}, global::Global};
+use super::utils::make_temp_config_multi;
+
+async fn read_range(global: Arc<Global>, block: &BlockType, range: std::ops::Range<usize>) -> Vec<u8> {
+    let mut got = Vec::new();
+    let mut stream = block.get(global, range);
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    got
+}
+
+#[tokio::test]
+async fn prefetched_reads_stay_in_order() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(2, 700, r#"
+prefetch:
+    blocks: 3
+    concurrent_reads:
+        local: 2
+"#)).unwrap());
+    assert_eq!(global.prefetch.limit("local").unwrap().available_permits(), 2);
+    assert!(global.prefetch.limit("discord_webhook").is_none());
+
+    let data = (0..12_000u32).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+    let block = BlockType::create(global.clone(), data.clone(), 0).await.unwrap();
+    assert_eq!(read_range(global.clone(), &block, 0..data.len()).await, data);
+    assert_eq!(read_range(global.clone(), &block, 45..9_000).await, data[45..9_000].to_vec());
+    block.delete(global).await.unwrap();
+}
+
+#[test]
+fn sources_are_not_limited_by_default() {
+    let global = from_str::<Global>(&make_temp_config_multi(1, 30, "")).unwrap();
+    assert_eq!(global.prefetch.blocks, 4);
+    assert!(global.prefetch.limit("local").is_none());
+}
+
+
+// Reads a block whose chunks are fifos and returns the most chunks that were being read at once
+#[cfg(target_os = "linux")]
+async fn reads_in_flight(name: &str, prefetch: &str) -> usize {
+    use std::{ffi::CString, io::Write, os::unix::ffi::OsStrExt, time::Duration};
+
+    // the bucket gets its own folder, so the fifos are not in the way of other tests
+    let folder = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let global = Arc::new(from_str::<Global>(&format!(r#"
+buckets:
+    fifo:
+        source:
+            type: local
+            folder: {}
+            max_size: 100
+            descriptor_length: 3
+direct_block_count: 10
+{}
+"#, folder.display(), prefetch)).unwrap());
+    let data = (0..600u32).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+    let block = BlockType::create(global.clone(), data.clone(), 0).await.unwrap();
+
+    // every chunk becomes a fifo that we keep open, so reading it waits until we hand over the data
+    let children = match &block {
+        BlockType::Indirect(block) => block.clone().into_blocks(),
+        _ => panic!("Expected an indirect block"),
+    };
+    let mut chunks = Vec::new();
+    for child in children {
+        let path = match &child {
+            BlockType::Direct(child) => folder.join(std::str::from_utf8(child.chunk().1).unwrap()),
+            _ => panic!("Expected direct blocks"),
+        };
+        let content = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let fifo = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        chunks.push((path, Some(fifo), content));
+    }
+    // our own descriptor is one of them, so a chunk is being read if there is another one
+    let being_read = |path: &std::path::Path| std::fs::read_dir("/proc/self/fd").unwrap()
+        .filter_map(|fd| std::fs::read_link(fd.ok()?.path()).ok())
+        .filter(|target| target == path)
+        .count() > 1;
+
+    let reader = tokio::spawn({
+        let (global, block) = (global.clone(), block.clone());
+        async move { read_range(global, &block, 0..600).await }
+    });
+    let mut most = 0;
+    while chunks.iter().any(|(_, fifo, _)| fifo.is_some()) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let reading = chunks.iter().filter(|(path, fifo, _)| fifo.is_some() && being_read(path)).count();
+        most = std::cmp::max(most, reading);
+        // the first chunk that is being read gets its data, closing our end lets the read finish
+        if let Some((_, fifo, content)) = chunks.iter_mut().find(|(path, fifo, _)| fifo.is_some() && being_read(path)) {
+            fifo.take().unwrap().write_all(content).unwrap();
+        }
+    }
+    assert_eq!(reader.await.unwrap(), data);
+    let _ = std::fs::remove_dir_all(folder);
+    most
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn depth_per_source_type() {
+    assert_eq!(reads_in_flight("chunkdrive_prefetch_global", "prefetch:\n    blocks: 4\n").await, 4);
+    assert_eq!(reads_in_flight("chunkdrive_prefetch_depth", "prefetch:\n    blocks: 4\n    depth:\n        local: 2\n        discord_webhook: 1\n").await, 2);
+}
@@ -0,0 +1,104 @@
+use std::{env, sync::Arc};
+use futures::StreamExt;
+use serde_yaml::from_str;
+
+use actix_web::{HttpRequest, HttpResponse, body::to_bytes, http::{header, StatusCode}, test::TestRequest};
+
+use crate::{blocks::block::Block, global::Global, inodes::file::File, services::http::{content::content_disposition, range::respond}};
+use super::utils::make_temp_config_multi;
+
+async fn read_range(global: Arc<Global>, file: &File, range: std::ops::Range<usize>) -> Vec<u8> {
+    let mut got = Vec::new();
+    let mut stream = file.get_range(global, range);
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    got
+}
+
+#[tokio::test]
+async fn reads_only_overlapping_chunks() {
+    // the cache counts every download as a miss, so we can see which chunks were fetched
+    let folder = env::temp_dir().join("chunkdrive_range_cache");
+    let _ = std::fs::remove_dir_all(&folder);
+    let config = format!(r#"
+buckets:
+    ranged1:
+        source:
+            type: local
+            folder: {}
+            max_size: 700
+            descriptor_length: 3
+        cache:
+            folder: {}
+            max_size: 1000000
+direct_block_count: 3
+"#, env::temp_dir().display(), folder.display());
+    let global = Arc::new(from_str::<Global>(&config).unwrap());
+    let cache = global.get_bucket("ranged1").unwrap().cache().unwrap();
+
+    let data = (0..10_000u32).map(|x| (x % 249) as u8).collect::<Vec<u8>>();
+    let file = File::create(global.clone(), data.clone()).await.unwrap();
+
+    // the start of the file is a single chunk, the stored blocks after it are not even opened
+    assert_eq!(read_range(global.clone(), &file, 10..100).await, data[10..100].to_vec());
+    assert_eq!(cache.misses(), 1);
+
+    for range in [0..10_000, 650..760, 2_000..7_777, 9_999..10_000] {
+        assert_eq!(read_range(global.clone(), &file, range.clone()).await, data[range].to_vec());
+    }
+    assert_eq!(read_range(global.clone(), &file, 9_000..20_000).await, data[9_000..].to_vec());
+    assert!(read_range(global.clone(), &file, 10_000..10_500).await.is_empty());
+
+    file.data.delete(global).await.unwrap();
+    let _ = std::fs::remove_dir_all(folder);
+}
+
+async fn download(global: Arc<Global>, file: Arc<File>, request: HttpRequest) -> HttpResponse {
+    respond(global, file, &request, "video/mp4".to_string(), content_disposition("clip.mp4", "video/mp4", true)).await
+}
+
+#[tokio::test]
+async fn http_range_responses() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(1, 700, "")).unwrap());
+    let data = (0..3_000u32).map(|x| (x % 241) as u8).collect::<Vec<u8>>();
+    let file = Arc::new(File::create(global.clone(), data.clone()).await.unwrap());
+
+    // the whole file
+    let response = download(global.clone(), file.clone(), TestRequest::default().to_http_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+    assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "video/mp4");
+    assert!(response.headers().get(header::CONTENT_DISPOSITION).unwrap().to_str().unwrap().starts_with("inline; filename=\"clip.mp4\""));
+    let etag = response.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+    assert_eq!(to_bytes(response.into_body()).await.unwrap().to_vec(), data);
+
+    // a single range
+    let request = TestRequest::default().insert_header((header::RANGE, "bytes=1000-1099")).to_http_request();
+    let response = download(global.clone(), file.clone(), request).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 1000-1099/3000");
+    assert_eq!(to_bytes(response.into_body()).await.unwrap().to_vec(), data[1000..1100].to_vec());
+
+    // multiple ranges
+    let request = TestRequest::default().insert_header((header::RANGE, "bytes=0-9,-10")).to_http_request();
+    let response = download(global.clone(), file.clone(), request).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert!(response.headers().get(header::CONTENT_TYPE).unwrap().to_str().unwrap().starts_with("multipart/byteranges"));
+    let body = to_bytes(response.into_body()).await.unwrap().to_vec();
+    assert!(body.windows(data[2990..].len()).any(|window| window == &data[2990..]));
+    assert!(String::from_utf8_lossy(&body).contains("Content-Range: bytes 2990-2999/3000"));
+
+    // If-Range with a stale validator gets the whole file, a matching one gets the range
+    let request = TestRequest::default().insert_header((header::RANGE, "bytes=0-9")).insert_header((header::IF_RANGE, "\"stale\"")).to_http_request();
+    assert_eq!(download(global.clone(), file.clone(), request).await.status(), StatusCode::OK);
+    let request = TestRequest::default().insert_header((header::RANGE, "bytes=0-9")).insert_header((header::IF_RANGE, etag)).to_http_request();
+    assert_eq!(download(global.clone(), file.clone(), request).await.status(), StatusCode::PARTIAL_CONTENT);
+
+    let request = TestRequest::default().insert_header((header::RANGE, "bytes=5000-")).to_http_request();
+    let response = download(global.clone(), file.clone(), request).await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers().get(header::CONTENT_RANGE).unwrap(), "bytes */3000");
+
+    file.data.delete(global).await.unwrap();
+}
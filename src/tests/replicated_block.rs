@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use futures::StreamExt;
+use serde_yaml::from_str;
+
+use crate::{blocks::{block::{Block, BlockType}, replicated_block::ReplicatedBlock}, global::Global};
+use super::utils::make_temp_config_multi;
+
+async fn read_all(global: Arc<Global>, block: &BlockType, len: usize) -> Result<Vec<u8>, String> {
+    let mut got = Vec::new();
+    let mut stream = block.get(global, 0..len);
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk?);
+    }
+    Ok(got)
+}
+
+#[tokio::test]
+async fn survives_missing_copy() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(3, 30, "")).unwrap());
+    let data = [1u8, 2, 3, 4, 5].repeat(4);
+    let block = ReplicatedBlock::create_replicated(global.clone(), data.clone(), 0, 3).await.unwrap();
+    let copies = match &block {
+        BlockType::Replicated(block) => block.copies(),
+        _ => panic!("Expected a replicated block"),
+    };
+    assert_eq!(copies.len(), 3);
+
+    // remove the first two copies behind the block's back
+    for copy in copies.iter().take(2) {
+        let bucket = global.get_bucket(&copy.bucket).unwrap();
+        bucket.delete(&copy.descriptor).await.unwrap();
+    }
+    assert_eq!(read_all(global.clone(), &block, data.len()).await.unwrap(), data);
+
+    assert!(block.delete(global.clone()).await.is_err()); // two copies are already gone
+    assert!(read_all(global.clone(), &block, data.len()).await.is_err());
+}
+
+#[tokio::test]
+async fn needs_enough_buckets() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(2, 30, "")).unwrap());
+    let data = [1u8, 2, 3, 4, 5].repeat(4);
+    assert!(ReplicatedBlock::create_replicated(global.clone(), data, 0, 3).await.is_err());
+}
+
+#[tokio::test]
+async fn used_by_indirect_blocks() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(2, 700, r#"
+redundancy:
+    type: replicate
+    copies: 2
+"#)).unwrap());
+    let data = [1u8, 2, 3, 4, 5].repeat(2_000);
+    let block = BlockType::create(global.clone(), data.clone(), 0).await.unwrap();
+    assert!(format!("{:?}", block).contains("Replicated"));
+    assert_eq!(read_all(global.clone(), &block, data.len()).await.unwrap(), data);
+    block.delete(global).await.unwrap();
+}
+
+#[tokio::test]
+async fn put_replaces_failed_copies() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(3, 30, "")).unwrap());
+    let data = [1u8, 2, 3, 4, 5].repeat(4);
+    let mut block = match ReplicatedBlock::create_replicated(global.clone(), data.clone(), 0, 3).await.unwrap() {
+        BlockType::Replicated(block) => block,
+        _ => panic!("Expected a replicated block"),
+    };
+
+    // the local source can not write to a chunk that is gone, so the first copy fails
+    let lost = block.copies()[0].clone();
+    global.get_bucket(&lost.bucket).unwrap().delete(&lost.descriptor).await.unwrap();
+
+    let changed = [9u8, 8, 7, 6, 5].repeat(4);
+    block.put(global.clone(), changed.clone(), 0..changed.len()).await.unwrap();
+    assert_ne!(block.copies()[0].descriptor, lost.descriptor);
+    for replica in block.copies() {
+        let bucket = global.get_bucket(&replica.bucket).unwrap();
+        assert_eq!(bucket.get(&replica.descriptor).await.unwrap(), changed);
+    }
+    block.delete(global).await.unwrap();
+}
@@ -0,0 +1,77 @@
+use std::{env, path::PathBuf, sync::Arc};
+use serde_yaml::from_str;
+
+use crate::{global::Global, inodes::directory::Directory};
+use super::utils::make_temp_config_multi;
+
+fn make_global(name: &str) -> (PathBuf, Arc<Global>) {
+    let folder = env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(1, 400, &format!("root_path: {}\nroot_backups: 2\n", folder.join("root.dat").display()))).unwrap();
+    (folder, Arc::new(global))
+}
+
+fn names(root: &Directory) -> Vec<String> {
+    let mut names = root.list();
+    names.sort();
+    names
+}
+
+async fn save_versions(global: Arc<Global>) {
+    let mut root = Directory::new();
+    for name in ["a", "b", "c"] {
+        root.add(global.clone(), &name.to_string(), Directory::new().to_enum()).await.unwrap();
+        global.save_root(&root).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn saves_keep_backups() {
+    let (folder, global) = make_global("chunkdrive_root_backups");
+    save_versions(global.clone()).await;
+
+    assert_eq!(names(&global.get_root().unwrap()), vec!["a", "b", "c"]);
+    let backup = |i: usize| Global::get_root(&from_str::<Global>(&make_temp_config_multi(1, 400, &format!("root_path: {}\n", folder.join(format!("root.dat.{}", i)).display()))).unwrap()).unwrap();
+    assert_eq!(names(&backup(1)), vec!["a", "b"]);
+    assert_eq!(names(&backup(2)), vec!["a"]);
+    assert!(!folder.join("root.dat.3").exists());
+    // nothing is left behind by the writes
+    let files = std::fs::read_dir(&folder).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect::<Vec<String>>();
+    assert!(files.iter().all(|file| !file.ends_with(".tmp")));
+}
+
+#[tokio::test]
+async fn corrupt_root_falls_back_to_backups() {
+    let (folder, global) = make_global("chunkdrive_root_corrupt");
+    save_versions(global.clone()).await;
+
+    // the newest readable backup is restored and the broken root is kept
+    std::fs::write(folder.join("root.dat"), b"\xc1 not msgpack").unwrap();
+    std::fs::write(folder.join("root.dat.1"), b"").unwrap();
+    assert_eq!(names(&global.get_root().unwrap()), vec!["a"]);
+    assert_eq!(std::fs::read(folder.join("root.dat.corrupt")).unwrap(), b"\xc1 not msgpack");
+    assert_eq!(names(&global.get_root().unwrap()), vec!["a"]);
+
+    // without backups the tree starts empty, but the broken root is still not deleted
+    std::fs::write(folder.join("root.dat"), b"\xc1").unwrap();
+    std::fs::remove_file(folder.join("root.dat.1")).unwrap();
+    std::fs::remove_file(folder.join("root.dat.2")).unwrap();
+    assert!(global.get_root().unwrap().list().is_empty());
+    assert_eq!(std::fs::read(folder.join("root.dat.corrupt")).unwrap(), b"\xc1");
+    assert!(!folder.join("root.dat").exists());
+}
+
+#[tokio::test]
+async fn unreadable_root_is_left_alone() {
+    let (folder, global) = make_global("chunkdrive_root_unreadable");
+    save_versions(global.clone()).await;
+
+    // failing to read the file says nothing about what is in it, so neither it nor the backups are touched
+    std::fs::remove_file(folder.join("root.dat")).unwrap();
+    std::fs::create_dir(folder.join("root.dat")).unwrap();
+    assert!(global.get_root().is_err());
+    assert!(folder.join("root.dat").is_dir());
+    assert!(!folder.join("root.dat.corrupt").exists());
+    assert!(global.save_root(&Directory::new()).is_err());
+}
@@ -0,0 +1,291 @@
+use std::{env, sync::Arc};
+use actix_web::{App, web, body::to_bytes, dev::ServiceResponse, test::{TestRequest, init_service, call_service}, http::{header, Method, StatusCode}};
+use chrono::{Duration, Utc};
+use serde_yaml::from_str;
+
+use crate::{gc::collect, global::Global, services::{crypto::{hex, hmac, sha256_hex}, s3::{auth::{ALGORITHM, Signature, canonical_query, canonical_request, canonical_uri, chunk_signature, query_pairs, signing_key, string_to_sign}, service::{S3Data, S3Service, configure}}}};
+use super::utils::make_temp_config_multi;
+
+const ACCESS_KEY: &str = "chunkdrive";
+const SECRET_KEY: &str = "very secret";
+
+fn s3_data(name: &str, extra: &str) -> Arc<S3Data> {
+    let folder = env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(2, 400, &format!("direct_block_count: 3\nroot_path: {}\n", folder.join("root.dat").display()))).unwrap();
+    let config = from_str::<S3Service>(&format!(
+        "port: 0\nmultipart_path: {}\ncredentials:\n  - access_key: {}\n    secret_key: {}\n{}",
+        folder.join("multipart.dat").display(), ACCESS_KEY, SECRET_KEY, extra
+    )).unwrap();
+    Arc::new(S3Data::new(Arc::new(global), config).unwrap())
+}
+
+// Signs the request like an S3 client would, returns the signature for the chunks of a streaming upload
+fn sign(method: &str, uri: &str, payload: &str, secret: &str) -> (TestRequest, Signature) {
+    let date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let scope = format!("{}/us-east-1/s3/aws4_request", &date[..8]);
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let headers = [
+        ("host".to_string(), "127.0.0.1".to_string()),
+        ("x-amz-content-sha256".to_string(), payload.to_string()),
+        ("x-amz-date".to_string(), date.clone()),
+    ];
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical = canonical_request(method, &canonical_uri(path), &canonical_query(&query_pairs(query)), &headers, signed_headers, payload);
+    let key = signing_key(secret, &date[..8], "us-east-1", "s3");
+    let signature = hex(&hmac(&key, string_to_sign(&date, &scope, &canonical).as_bytes()));
+
+    let mut request = TestRequest::default().method(Method::from_bytes(method.as_bytes()).unwrap()).uri(uri);
+    for (name, value) in headers {
+        request = request.insert_header((name, value));
+    }
+    let request = request.insert_header((header::AUTHORIZATION, format!("{} Credential={}/{}, SignedHeaders={}, Signature={}", ALGORITHM, ACCESS_KEY, scope, signed_headers, signature)));
+    (request, Signature { key, date, scope, signature, payload: payload.to_string() })
+}
+
+fn signed(method: &str, uri: &str, body: &[u8]) -> TestRequest {
+    sign(method, uri, &sha256_hex(body), SECRET_KEY).0.set_payload(body.to_vec())
+}
+
+async fn body(response: ServiceResponse) -> String {
+    String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+}
+
+async fn bytes(response: ServiceResponse) -> Vec<u8> {
+    to_bytes(response.into_body()).await.unwrap().to_vec()
+}
+
+fn between<'a>(text: &'a str, start: &str, end: &str) -> &'a str {
+    let from = text.find(start).unwrap() + start.len();
+    &text[from..from + text[from..].find(end).unwrap()]
+}
+
+macro_rules! status {
+    ($app:expr, $request:expr) => {
+        call_service($app, $request.to_request()).await.status()
+    };
+}
+
+#[actix_web::test]
+async fn s3_authentication() {
+    let data = s3_data("chunkdrive_s3_auth", "readonly: true\n");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(configure)).await;
+
+    assert_eq!(status!(&app, TestRequest::get().uri("/")), StatusCode::FORBIDDEN);
+    let response = call_service(&app, sign("GET", "/", &sha256_hex(b""), "wrong").0.to_request()).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(body(response).await.contains("<Code>SignatureDoesNotMatch</Code>"));
+    // signed for another path
+    let (request, _) = sign("GET", "/other", &sha256_hex(b""), SECRET_KEY);
+    assert_eq!(status!(&app, request.uri("/")), StatusCode::FORBIDDEN);
+
+    assert_eq!(status!(&app, signed("GET", "/", b"")), StatusCode::OK);
+    let response = call_service(&app, signed("PUT", "/photos", b"").to_request()).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(body(response).await.contains("<Code>AccessDenied</Code>"));
+
+    // presigned urls carry everything in the query
+    let presign = |date: &str, expires: &str| {
+        let scope = format!("{}/us-east-1/s3/aws4_request", &date[..8]);
+        let query = format!("X-Amz-Algorithm={}&X-Amz-Credential={}%2F{}&X-Amz-Date={}&X-Amz-Expires={}&X-Amz-SignedHeaders=host", ALGORITHM, ACCESS_KEY, scope.replace('/', "%2F"), date, expires);
+        let canonical = canonical_request("GET", "/", &canonical_query(&query_pairs(&query)), &[("host".to_string(), "127.0.0.1".to_string())], "host", "UNSIGNED-PAYLOAD");
+        let signature = hex(&hmac(&signing_key(SECRET_KEY, &date[..8], "us-east-1", "s3"), string_to_sign(date, &scope, &canonical).as_bytes()));
+        (query, signature)
+    };
+    let presigned = |(query, signature): (String, String)| TestRequest::get().uri(&format!("/?{}&X-Amz-Signature={}", query, signature)).insert_header((header::HOST, "127.0.0.1"));
+    let date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    assert_eq!(status!(&app, presigned(presign(&date, "60"))), StatusCode::OK);
+    assert_eq!(status!(&app, presigned((presign(&date, "60").0, "0".repeat(64)))), StatusCode::FORBIDDEN);
+    // expiry must be between one second and a week
+    assert_eq!(status!(&app, presigned(presign(&date, "0"))), StatusCode::BAD_REQUEST);
+    assert_eq!(status!(&app, presigned(presign(&date, "604801"))), StatusCode::BAD_REQUEST);
+    assert_eq!(status!(&app, presigned(presign(&date, "604800"))), StatusCode::OK);
+    // signed in the future beyond the allowed skew
+    let later = (Utc::now() + Duration::hours(1)).format("%Y%m%dT%H%M%SZ").to_string();
+    assert_eq!(status!(&app, presigned(presign(&later, "60"))), StatusCode::FORBIDDEN);
+    let soon = (Utc::now() + Duration::minutes(5)).format("%Y%m%dT%H%M%SZ").to_string();
+    assert_eq!(status!(&app, presigned(presign(&soon, "60"))), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn s3_objects() {
+    let data = s3_data("chunkdrive_s3_objects", "");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(configure)).await;
+    let content = (0..1_500u32).map(|x| (x % 241) as u8).collect::<Vec<u8>>();
+
+    assert_eq!(status!(&app, signed("PUT", "/photos", b"")), StatusCode::OK);
+    assert_eq!(status!(&app, signed("PUT", "/photos", b"")), StatusCode::CONFLICT);
+    assert!(body(call_service(&app, signed("GET", "/", b"").to_request()).await).await.contains("<Name>photos</Name>"));
+    assert_eq!(status!(&app, signed("PUT", "/missing/file", b"x")), StatusCode::NOT_FOUND);
+
+    let response = call_service(&app, signed("PUT", "/photos/2023/a%20cat.jpg", &content).insert_header((header::CONTENT_TYPE, "image/jpeg")).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(header::ETAG));
+    assert_eq!(status!(&app, signed("PUT", "/photos/2023/dog.txt", b"woof")), StatusCode::OK);
+    assert_eq!(status!(&app, signed("PUT", "/photos/top.txt", b"top")), StatusCode::OK);
+    assert_eq!(status!(&app, signed("PUT", "/photos/empty/", b"")), StatusCode::OK);
+    assert_eq!(status!(&app, signed("PUT", "/photos/top.txt/inner", b"x")), StatusCode::CONFLICT);
+
+    // a body that does not match its signed hash is refused and not stored
+    let (request, _) = sign("PUT", "/photos/bad.txt", &sha256_hex(b"good"), SECRET_KEY);
+    let response = call_service(&app, request.set_payload("evil").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(body(response).await.contains("<Code>XAmzContentSHA256Mismatch</Code>"));
+    assert_eq!(status!(&app, signed("HEAD", "/photos/bad.txt", b"")), StatusCode::NOT_FOUND);
+
+    // aws-chunked bodies with signed chunks
+    let (request, signature) = sign("PUT", "/photos/chunked.txt", "STREAMING-AWS4-HMAC-SHA256-PAYLOAD", SECRET_KEY);
+    let first = chunk_signature(&signature, &signature.signature, b"hello ");
+    let second = chunk_signature(&signature, &first, b"world");
+    let last = chunk_signature(&signature, &second, b"");
+    let chunked = format!("6;chunk-signature={}\r\nhello \r\n5;chunk-signature={}\r\nworld\r\n0;chunk-signature={}\r\n\r\n", first, second, last);
+    assert_eq!(status!(&app, request.insert_header(("content-encoding", "aws-chunked")).set_payload(chunked)), StatusCode::OK);
+    assert_eq!(bytes(call_service(&app, signed("GET", "/photos/chunked.txt", b"").to_request()).await).await, b"hello world");
+
+    // listing
+    let listing = body(call_service(&app, signed("GET", "/photos?list-type=2&delimiter=%2F", b"").to_request()).await).await;
+    assert!(listing.contains("<CommonPrefixes><Prefix>2023/</Prefix></CommonPrefixes><CommonPrefixes><Prefix>empty/</Prefix></CommonPrefixes>"));
+    assert!(listing.contains("<Key>top.txt</Key>"));
+    assert!(!listing.contains("dog.txt"));
+    let listing = body(call_service(&app, signed("GET", "/photos?list-type=2", b"").to_request()).await).await;
+    let keys = listing.split("<Key>").skip(1).map(|part| part.split("</Key>").next().unwrap()).collect::<Vec<&str>>();
+    assert_eq!(keys, vec!["2023/a cat.jpg", "2023/dog.txt", "chunked.txt", "empty/", "top.txt"]);
+    assert!(listing.contains("<Size>1500</Size>"));
+    let listing = body(call_service(&app, signed("GET", "/photos?list-type=2&prefix=2023%2Fd&encoding-type=url", b"").to_request()).await).await;
+    assert!(listing.contains("<Key>2023/dog.txt</Key>") && !listing.contains("a%20cat"));
+    let listing = body(call_service(&app, signed("GET", "/photos?list-type=2&max-keys=2", b"").to_request()).await).await;
+    assert!(listing.contains("<IsTruncated>true</IsTruncated>") && listing.contains("<KeyCount>2</KeyCount>"));
+    let token = between(&listing, "<NextContinuationToken>", "</NextContinuationToken>").to_string();
+    let listing = body(call_service(&app, signed("GET", &format!("/photos?list-type=2&max-keys=2&continuation-token={}", token), b"").to_request()).await).await;
+    assert!(listing.contains("<Key>chunked.txt</Key><") && listing.contains("<Key>empty/</Key>") && !listing.contains("dog.txt"));
+    let listing = body(call_service(&app, signed("GET", "/photos?marker=empty%2F", b"").to_request()).await).await;
+    assert!(listing.contains("<Key>top.txt</Key>") && !listing.contains("chunked.txt"));
+
+    // reading
+    let response = call_service(&app, signed("GET", "/photos/2023/a%20cat.jpg", b"").to_request()).await;
+    assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "image/jpeg");
+    assert_eq!(bytes(response).await, content);
+    let response = call_service(&app, signed("GET", "/photos/2023/a%20cat.jpg", b"").insert_header((header::RANGE, "bytes=100-199")).to_request()).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(bytes(response).await, content[100..200].to_vec());
+    let response = call_service(&app, signed("GET", "/photos/nothing", b"").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(body(response).await.contains("<Code>NoSuchKey</Code>"));
+
+    // copies have their own chunks
+    let response = call_service(&app, signed("PUT", "/photos/copy.jpg", b"").insert_header(("x-amz-copy-source", "/photos/2023/a%20cat.jpg")).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body(response).await.contains("<CopyObjectResult>"));
+    assert_eq!(status!(&app, signed("PUT", "/photos/copy.jpg", b"").insert_header(("x-amz-copy-source", "photos/copy.jpg"))), StatusCode::BAD_REQUEST);
+    assert_eq!(status!(&app, signed("DELETE", "/photos/2023/a%20cat.jpg", b"")), StatusCode::NO_CONTENT);
+    assert_eq!(bytes(call_service(&app, signed("GET", "/photos/copy.jpg", b"").to_request()).await).await, content);
+
+    // deleting
+    assert_eq!(status!(&app, signed("DELETE", "/photos", b"")), StatusCode::CONFLICT);
+    let delete = b"<Delete><Object><Key>2023/dog.txt</Key></Object><Object><Key>top.txt</Key></Object><Object><Key>empty/</Key></Object><Object><Key>nothing</Key></Object></Delete>";
+    let result = body(call_service(&app, signed("POST", "/photos?delete", delete).to_request()).await).await;
+    assert!(result.contains("<Deleted><Key>2023/dog.txt</Key></Deleted>") && result.contains("<Deleted><Key>nothing</Key></Deleted>"));
+    for path in ["/photos/copy.jpg", "/photos/chunked.txt"] {
+        assert_eq!(status!(&app, signed("DELETE", path, b"")), StatusCode::NO_CONTENT);
+    }
+    // the emptied 2023 directory went away with its last file
+    assert!(data.global.get_root().unwrap().list() == vec!["photos".to_string()]);
+    assert_eq!(status!(&app, signed("DELETE", "/photos", b"")), StatusCode::NO_CONTENT);
+    assert!(data.global.get_root().unwrap().list().is_empty());
+}
+
+#[actix_web::test]
+async fn s3_multipart() {
+    let data = s3_data("chunkdrive_s3_multipart", "");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(configure)).await;
+    let content = (0..3_000u32).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+    assert_eq!(status!(&app, signed("PUT", "/backups", b"")), StatusCode::OK);
+
+    let created = body(call_service(&app, signed("POST", "/backups/disk/image.bin?uploads", b"").to_request()).await).await;
+    let id = between(&created, "<UploadId>", "</UploadId>").to_string();
+    let part = |number: u32, data: &[u8]| signed("PUT", &format!("/backups/disk/image.bin?partNumber={}&uploadId={}", number, id), data);
+
+    // out of order, replaced, and one that is left out
+    let mut etags = Vec::new();
+    for (number, range) in [(2, 1300..3000), (1, 0..10), (1, 0..1300), (3, 0..5)] {
+        let response = call_service(&app, part(number, &content[range]).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        etags.push(response.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string());
+    }
+    assert_eq!(status!(&app, part(0, b"x")), StatusCode::BAD_REQUEST);
+    let parts = body(call_service(&app, signed("GET", &format!("/backups/disk/image.bin?uploadId={}", id), b"").to_request()).await).await;
+    assert!(parts.contains("<PartNumber>1</PartNumber>") && parts.contains("<Size>1300</Size>"));
+    assert!(body(call_service(&app, signed("GET", "/backups?uploads", b"").to_request()).await).await.contains(&id));
+
+    let complete = |first: &str, second: &str| format!(
+        "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>{}</ETag></Part><Part><PartNumber>2</PartNumber><ETag>{}</ETag></Part></CompleteMultipartUpload>",
+        first, second
+    );
+    let uri = format!("/backups/disk/image.bin?uploadId={}", id);
+    // the replaced part is gone
+    assert_eq!(status!(&app, signed("POST", &uri, complete(&etags[1], &etags[0]).as_bytes())), StatusCode::BAD_REQUEST);
+    let response = call_service(&app, signed("POST", &uri, complete(&etags[2], &etags[0]).as_bytes()).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body(response).await.contains("<Key>disk/image.bin</Key>"));
+    assert_eq!(status!(&app, signed("POST", &uri, complete(&etags[2], &etags[0]).as_bytes())), StatusCode::NOT_FOUND);
+
+    let response = call_service(&app, signed("GET", "/backups/disk/image.bin", b"").to_request()).await;
+    assert_eq!(bytes(response).await, content);
+    let response = call_service(&app, signed("GET", "/backups/disk/image.bin", b"").insert_header((header::RANGE, "bytes=1250-1349")).to_request()).await;
+    assert_eq!(bytes(response).await, content[1250..1350].to_vec());
+
+    // aborting deletes the parts
+    let created = body(call_service(&app, signed("POST", "/backups/other?uploads", b"").to_request()).await).await;
+    let other = between(&created, "<UploadId>", "</UploadId>").to_string();
+    assert_eq!(status!(&app, signed("PUT", &format!("/backups/other?partNumber=1&uploadId={}", other), &content)), StatusCode::OK);
+    assert_eq!(status!(&app, signed("DELETE", &format!("/backups/other?uploadId={}", other), b"")), StatusCode::NO_CONTENT);
+    assert_eq!(status!(&app, signed("DELETE", &format!("/backups/other?uploadId={}", other), b"")), StatusCode::NOT_FOUND);
+    assert!(data.multipart.list("backups").is_empty());
+
+    assert_eq!(status!(&app, signed("DELETE", "/backups/disk/image.bin", b"")), StatusCode::NO_CONTENT);
+    assert_eq!(status!(&app, signed("DELETE", "/backups", b"")), StatusCode::NO_CONTENT);
+}
+
+#[actix_web::test]
+async fn s3_multipart_gc_and_expiry() {
+    let folder = env::temp_dir().join("chunkdrive_s3_multipart_gc");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let multipart = folder.join("multipart.dat");
+    let service = format!("port: 0\nmultipart_path: {}\ncredentials:\n  - access_key: {}\n    secret_key: {}\n", multipart.display(), ACCESS_KEY, SECRET_KEY);
+    let services = format!("services:\n  - type: s3\n{}", service.lines().map(|line| format!("    {}\n", line)).collect::<String>());
+    // gc deletes everything it does not know about, so the bucket is not in the shared temp dir
+    let buckets = format!("buckets:\n  gc:\n    source:\n      type: local\n      folder: {}\n      max_size: 400\n      descriptor_length: 3\n", folder.display());
+    let global = Arc::new(from_str::<Global>(&format!("{}direct_block_count: 3\nroot_path: {}\n{}", buckets, folder.join("root.dat").display(), services)).unwrap());
+    let data = Arc::new(S3Data::new(global.clone(), from_str::<S3Service>(&service).unwrap()).unwrap());
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(configure)).await;
+    let content = (0..3_000u32).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+    assert_eq!(status!(&app, signed("PUT", "/backups", b"")), StatusCode::OK);
+
+    // parts of unfinished uploads are not orphans
+    let created = body(call_service(&app, signed("POST", "/backups/image.bin?uploads", b"").to_request()).await).await;
+    let id = between(&created, "<UploadId>", "</UploadId>").to_string();
+    let response = call_service(&app, signed("PUT", &format!("/backups/image.bin?partNumber=1&uploadId={}", id), &content).to_request()).await;
+    let etag = response.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+    let report = collect(global.clone(), true).await.unwrap();
+    assert!(report.orphans.is_empty());
+    let complete = format!("<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>{}</ETag></Part></CompleteMultipartUpload>", etag);
+    assert_eq!(status!(&app, signed("POST", &format!("/backups/image.bin?uploadId={}", id), complete.as_bytes())), StatusCode::OK);
+    assert_eq!(bytes(call_service(&app, signed("GET", "/backups/image.bin", b"").to_request()).await).await, content);
+
+    // stale uploads are forgotten together with their parts
+    let created = body(call_service(&app, signed("POST", "/backups/stale.bin?uploads", b"").to_request()).await).await;
+    let id = between(&created, "<UploadId>", "</UploadId>").to_string();
+    assert_eq!(status!(&app, signed("PUT", &format!("/backups/stale.bin?partNumber=1&uploadId={}", id), &content)), StatusCode::OK);
+    assert!(data.multipart.expire(global.clone(), 60).await.is_empty());
+    assert_eq!(data.multipart.list("backups").len(), 1);
+    assert!(data.multipart.expire(global.clone(), 0).await.is_empty());
+    assert!(data.multipart.list("backups").is_empty());
+    assert!(collect(global.clone(), false).await.unwrap().orphans.is_empty());
+
+    // a damaged file stops both the service and garbage collection
+    std::fs::write(&multipart, b"\xc1").unwrap();
+    assert!(S3Data::new(global.clone(), from_str::<S3Service>(&service).unwrap()).is_err());
+    assert!(collect(global.clone(), true).await.is_err());
+}
@@ -0,0 +1,139 @@
+use std::{env, sync::Arc};
+use futures::StreamExt;
+use serde_yaml::from_str;
+
+use crate::{blocks::{block::{Block, BlockType}, erasure_block::ErasureBlock, replicated_block::ReplicatedBlock}, global::Global, inodes::{directory::Directory, file::File}, scrub::{scrub, ScrubReport}};
+use super::utils::make_temp_config_multi;
+
+async fn read_all(global: Arc<Global>, block: &BlockType, len: usize) -> Result<Vec<u8>, String> {
+    let mut got = Vec::new();
+    let mut stream = block.get(global, 0..len);
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk?);
+    }
+    Ok(got)
+}
+
+// removes the chunks (copies or shards) with the given indices behind the block's back
+async fn drop_chunks(global: Arc<Global>, block: &BlockType, indices: &[usize]) {
+    let chunks = match block {
+        BlockType::Replicated(block) => block.copies().iter().map(|copy| (copy.bucket.clone(), copy.descriptor.clone())).collect::<Vec<_>>(),
+        BlockType::Erasure(block) => block.shards().iter().map(|shard| (shard.bucket.clone(), shard.descriptor.clone())).collect::<Vec<_>>(),
+        _ => panic!("Expected a block with redundancy"),
+    };
+    for i in indices {
+        let (bucket, descriptor) = &chunks[*i];
+        global.get_bucket(bucket).unwrap().delete(descriptor).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn repairs_replicated_block() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(4, 30, "")).unwrap());
+    let data = [1u8, 2, 3, 4, 5].repeat(4);
+    let mut block = ReplicatedBlock::create_replicated(global.clone(), data.clone(), 0, 3).await.unwrap();
+    drop_chunks(global.clone(), &block, &[0]).await;
+
+    let mut report = ScrubReport::default();
+    assert!(block.scrub(global.clone(), &mut report, false).await.is_empty());
+    assert_eq!((report.checked, report.missing, report.lost, report.repaired), (3, 1, 0, 0));
+
+    let mut report = ScrubReport::default();
+    let repairs = block.scrub(global.clone(), &mut report, true).await;
+    assert_eq!((repairs.old.len(), repairs.new.len()), (1, 1));
+    assert_eq!((report.missing, report.lost, report.repaired), (1, 0, 1));
+
+    // the repaired copy is enough to read the data
+    drop_chunks(global.clone(), &block, &[1, 2]).await;
+    assert_eq!(read_all(global.clone(), &block, data.len()).await.unwrap(), data);
+}
+
+#[tokio::test]
+async fn repairs_erasure_block() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(5, 30, "")).unwrap());
+    let data = (0..80u8).collect::<Vec<u8>>();
+    let mut block = ErasureBlock::create_erasure(global.clone(), data.clone(), 0, 3, 2).await.unwrap();
+    drop_chunks(global.clone(), &block, &[0, 4]).await;
+
+    let mut report = ScrubReport::default();
+    assert_eq!(block.scrub(global.clone(), &mut report, true).await.new.len(), 2);
+    assert_eq!((report.checked, report.missing, report.lost, report.repaired), (5, 2, 0, 2));
+
+    // the rebuilt data and parity shards are used to read the data
+    drop_chunks(global.clone(), &block, &[1, 2]).await;
+    assert_eq!(read_all(global.clone(), &block, data.len()).await.unwrap(), data);
+
+    let mut report = ScrubReport::default();
+    drop_chunks(global.clone(), &block, &[3]).await;
+    assert!(block.scrub(global.clone(), &mut report, true).await.is_empty());
+    assert_eq!((report.missing, report.lost, report.repaired), (3, 1, 0));
+}
+
+#[tokio::test]
+async fn repairs_are_applied_by_the_caller() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(4, 30, "")).unwrap());
+    let data = [1u8, 2, 3, 4, 5].repeat(4);
+    let mut block = ReplicatedBlock::create_replicated(global.clone(), data.clone(), 0, 3).await.unwrap();
+    let (bucket, descriptor) = match &block {
+        BlockType::Replicated(block) => (block.copies()[0].bucket.clone(), block.copies()[0].descriptor.clone()),
+        _ => panic!("Expected a replicated block"),
+    };
+    // a broken copy that can still be read
+    global.get_bucket(&bucket).unwrap().put(&descriptor, vec![9; 3]).await.unwrap();
+    let exists = |(bucket, descriptor): &(String, Vec<u8>)| {
+        let global = global.clone();
+        let (bucket, descriptor) = (bucket.clone(), descriptor.clone());
+        async move { global.get_bucket(&bucket).unwrap().get(&descriptor).await.is_ok() }
+    };
+
+    // nothing is deleted until the caller knows whether the block was saved
+    let mut report = ScrubReport::default();
+    let repairs = block.clone().scrub(global.clone(), &mut report, true).await;
+    assert_eq!(repairs.old, vec![(bucket.clone(), descriptor.clone())]);
+    let new = repairs.new[0].clone();
+    assert!(exists(&repairs.old[0]).await && exists(&new).await);
+
+    // an unsaved repair removes the new copy and keeps the old one
+    repairs.discard(&global, &mut report).await;
+    assert!(!exists(&new).await);
+    assert!(exists(&(bucket.clone(), descriptor.clone())).await);
+
+    // a saved repair removes the old copy
+    let repairs = block.scrub(global.clone(), &mut report, true).await;
+    let new = repairs.new[0].clone();
+    repairs.commit(&global).await;
+    assert!(!exists(&(bucket, descriptor)).await);
+    assert!(exists(&new).await);
+    assert_eq!(read_all(global.clone(), &block, data.len()).await.unwrap(), data);
+    block.delete(global.clone()).await.unwrap();
+}
+
+#[tokio::test]
+async fn reports_damaged_files() {
+    let root_path = env::temp_dir().join("chunkdrive_scrub_root.dat");
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(1, 700, &format!("root_path: {}\n", root_path.display()))).unwrap());
+    let mut root = Directory::new();
+    let healthy = File::create(global.clone(), [1u8; 100].to_vec()).await.unwrap();
+    let damaged = File::create(global.clone(), [2u8; 100].to_vec()).await.unwrap();
+
+    // the file has a single direct block, which we remove behind its back
+    match &damaged.data.clone().into_blocks()[0] {
+        BlockType::Direct(block) => {
+            let (bucket, descriptor) = block.chunk();
+            global.get_bucket(bucket).unwrap().delete(descriptor).await.unwrap();
+        },
+        _ => panic!("Expected a direct block"),
+    }
+
+    root.add(global.clone(), &"healthy".to_string(), healthy.to_enum()).await.unwrap();
+    root.add(global.clone(), &"damaged".to_string(), damaged.to_enum()).await.unwrap();
+    global.save_root(&root).unwrap();
+
+    let report = scrub(global.clone(), true).await;
+    assert_eq!(report.damaged, vec!["/damaged".to_string()]);
+    assert_eq!((report.lost, report.repaired), (1, 0));
+
+    let _ = root.remove(global.clone(), &"healthy".to_string()).await;
+    let _ = root.remove(global.clone(), &"damaged".to_string()).await;
+    let _ = std::fs::remove_file(root_path);
+}
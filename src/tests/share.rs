@@ -0,0 +1,163 @@
+use std::{env, sync::Arc};
+use actix_web::{App, web, body::to_bytes, test::{TestRequest, init_service, call_service}, http::{header, StatusCode}};
+use serde_json::Value;
+use serde_yaml::from_str;
+
+use crate::{global::Global, inodes::path::open, services::http::{auth::{hash_password, new_token}, service::{self, HttpService, ServerData}, share::{Share, ShareAccess}}};
+use super::utils::make_temp_config_multi;
+
+fn server_data(name: &str, extra: &str, clean: bool) -> Arc<ServerData> {
+    let folder = env::temp_dir().join(name);
+    if clean {
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+    std::fs::create_dir_all(&folder).unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(2, 400, &format!("direct_block_count: 3\nroot_path: {}\n", folder.join("root.dat").display()))).unwrap();
+    let config = from_str::<HttpService>(&format!("port: 0\nsecret: test\ntus_path: {}\n{}", folder.join("tus.dat").display(), extra)).unwrap();
+    Arc::new(ServerData::new(Arc::new(global), config).unwrap())
+}
+
+macro_rules! status {
+    ($app:expr, $request:expr) => {
+        call_service($app, $request.to_request()).await.status()
+    };
+}
+
+macro_rules! body {
+    ($app:expr, $request:expr) => {
+        to_bytes(call_service($app, $request.to_request()).await.into_body()).await.unwrap().to_vec()
+    };
+}
+
+macro_rules! json {
+    ($app:expr, $request:expr) => {
+        serde_json::from_slice::<Value>(&body!($app, $request)).unwrap()
+    };
+}
+
+fn upload(url: &str, filename: &str, data: &[u8]) -> TestRequest {
+    let mut body = format!("--XYZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n", filename).into_bytes();
+    body.extend(data);
+    body.extend(b"\r\n--XYZ--\r\n");
+    TestRequest::post().uri(url).insert_header((header::CONTENT_TYPE, "multipart/form-data; boundary=XYZ")).set_payload(body)
+}
+
+#[actix_web::test]
+async fn share_links() {
+    let data = server_data("chunkdrive_share", "", true);
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(service::configure)).await;
+    assert_eq!(status!(&app, TestRequest::put().uri("/api/v1/upload/docs/a.txt?parents=true").set_payload("hello")), StatusCode::CREATED);
+    assert_eq!(status!(&app, TestRequest::put().uri("/api/v1/upload/docs/sub/b.txt?parents=true").set_payload("world")), StatusCode::CREATED);
+    assert_eq!(status!(&app, TestRequest::put().uri("/api/v1/upload/private.txt").set_payload("secret")), StatusCode::CREATED);
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/mkdir/drop")), StatusCode::CREATED);
+
+    // download links list the directory and serve what is in it
+    let share = json!(&app, TestRequest::post().uri("/api/v1/share/docs?access=download"));
+    assert_eq!(share["access"], "download");
+    let url = share["url"].as_str().unwrap().to_string();
+    let listing = String::from_utf8(body!(&app, TestRequest::get().uri(&url))).unwrap();
+    assert!(listing.contains(">a.txt<") && listing.contains(">sub<") && !listing.contains("private"));
+    let a = open(data.global.clone(), &["docs".to_string(), "a.txt".to_string()]).await.unwrap().unwrap().0;
+    let a = format!("{}{}$a.txt", url, a.as_url());
+    assert_eq!(body!(&app, TestRequest::get().uri(&a)), b"hello");
+
+    // paths outside of the target are not reachable, even with real descriptors
+    let private = open(data.global.clone(), &["private.txt".to_string()]).await.unwrap().unwrap().0;
+    assert_eq!(status!(&app, TestRequest::get().uri(&format!("{}{}$private.txt", url, private.as_url()))), StatusCode::FORBIDDEN);
+    let tampered = url.replacen("/s/", "/s/x", 1);
+    assert_eq!(status!(&app, TestRequest::get().uri(&tampered)), StatusCode::FORBIDDEN);
+
+    // view links only list
+    let view = json!(&app, TestRequest::post().uri("/api/v1/share/docs?access=view"))["url"].as_str().unwrap().to_string();
+    let a = a.replace(&url, &view);
+    assert_ne!(body!(&app, TestRequest::get().uri(&a)), b"hello");
+    let listing = String::from_utf8(body!(&app, TestRequest::get().uri(&view))).unwrap();
+    assert!(listing.contains(">a.txt<") && !listing.contains("a.txt\""));
+
+    // upload links take files and show nothing
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/share/docs/a.txt?access=upload")), StatusCode::FORBIDDEN);
+    let drop = json!(&app, TestRequest::post().uri("/api/v1/share/drop?access=upload"))["url"].as_str().unwrap().to_string();
+    assert_eq!(status!(&app, upload(&drop, "c.txt", b"dropped")), StatusCode::FOUND);
+    assert_eq!(status!(&app, upload(&url, "d.txt", b"nope")), StatusCode::FORBIDDEN);
+    assert_eq!(body!(&app, TestRequest::get().uri("/api/v1/download/drop/c.txt")), b"dropped");
+    assert!(!String::from_utf8(body!(&app, TestRequest::get().uri(&drop))).unwrap().contains("c.txt"));
+
+    // the html interface makes links too
+    let segment = format!("{}$docs", open(data.global.clone(), &["docs".to_string()]).await.unwrap().unwrap().0.as_url());
+    let response = call_service(&app, TestRequest::post().uri(&format!("/shares/create/{}", segment)).set_form([("access", "view"), ("days", "1")]).to_request()).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert!(String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap().contains("/s/"));
+    assert_eq!(status!(&app, TestRequest::post().uri("/shares/create/").set_form([("access", "view")])), StatusCode::BAD_REQUEST);
+
+    // expired and revoked links stop working, revocations survive restarts
+    let stored = open(data.global.clone(), &["docs".to_string()]).await.unwrap().unwrap().0;
+    let expired = Share::new(&stored, "docs", ShareAccess::View, 0, None).url(&data);
+    assert_eq!(status!(&app, TestRequest::get().uri(&expired)), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/revoke").set_json(serde_json::json!({ "token": share["token"] }))), StatusCode::NO_CONTENT);
+    assert_eq!(status!(&app, TestRequest::get().uri(&url)), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::get().uri(&view)), StatusCode::OK);
+
+    let data = server_data("chunkdrive_share", "", false);
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(service::configure)).await;
+    assert_eq!(status!(&app, TestRequest::get().uri(&url)), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::get().uri(&view)), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn share_permissions() {
+    let (token, hash) = new_token();
+    let (other, other_hash) = new_token();
+    let config = format!(r#"auth:
+    users:
+        root:
+            password: "{}"
+            permissions:
+                /: admin
+        alice:
+            tokens: ["{}"]
+            permissions:
+                /shared: write
+                /shared/archive: read
+        bob:
+            tokens: ["{}"]
+            permissions:
+                /shared: read
+"#, hash_password("hunter2").unwrap(), hash, other_hash);
+    let data = server_data("chunkdrive_share_auth", &config, true);
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(service::configure)).await;
+    let alice = ("Authorization", format!("Bearer {}", token));
+    let bob = ("Authorization", format!("Bearer {}", other));
+
+    let response = call_service(&app, TestRequest::post().uri("/login").set_form([("username", "root"), ("password", "hunter2"), ("next", "/files/")]).to_request()).await;
+    let session = response.response().cookies().find(|cookie| cookie.name() == "session").unwrap().into_owned();
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/mkdir/shared/archive?parents=true").cookie(session.clone())), StatusCode::CREATED);
+    assert_eq!(status!(&app, TestRequest::put().uri("/api/v1/upload/private/secret.txt?parents=true").cookie(session.clone()).set_payload("secret")), StatusCode::CREATED);
+
+    // users share what they can read, and only take uploads where they can write
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/share/private").insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/share/shared/archive?access=upload").insert_header(alice.clone())), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, TestRequest::post().uri("/api/v1/share/shared?access=upload").insert_header(bob.clone())), StatusCode::FORBIDDEN);
+    let share = json!(&app, TestRequest::post().uri("/api/v1/share/shared?access=upload").insert_header(alice.clone()));
+    let url = share["url"].as_str().unwrap().to_string();
+
+    // the link works without logging in
+    assert_eq!(status!(&app, TestRequest::get().uri(&url)), StatusCode::OK);
+    assert_eq!(status!(&app, upload(&url, "e.txt", b"e")), StatusCode::FOUND);
+
+    // only admins and whoever made a link can revoke it
+    let revoke = || TestRequest::post().uri("/api/v1/revoke").set_json(serde_json::json!({ "token": share["token"] }));
+    assert_eq!(status!(&app, revoke().insert_header(bob.clone())), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, revoke().insert_header(alice.clone())), StatusCode::NO_CONTENT);
+    assert_eq!(status!(&app, upload(&url, "f.txt", b"f")), StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn damaged_revocations_stop_the_service() {
+    let folder = env::temp_dir().join("chunkdrive_share_damaged");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::write(folder.join("shares.dat"), b"\xc1not msgpack").unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(1, 400, &format!("root_path: {}\n", folder.join("root.dat").display()))).unwrap();
+    let config = from_str::<HttpService>(&format!("port: 0\nsecret: test\ntus_path: {}\n", folder.join("tus.dat").display())).unwrap();
+    assert!(ServerData::new(Arc::new(global), config).is_err());
+}
@@ -0,0 +1,212 @@
+use std::{env, sync::Arc};
+use actix_web::{App, web, test::{TestRequest, init_service, call_service}, http::{header, StatusCode}};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures::StreamExt;
+use serde_yaml::from_str;
+
+use crate::{blocks::block::{Block, BlockType}, gc::collect, global::Global, inodes::{file::File, inode::InodeType}, services::http::{service::{HttpService, ServerData}, tus::{self, TusState, parse_metadata}}};
+use super::utils::make_temp_config_multi;
+
+fn server_data(name: &str, expiration: u64) -> Arc<ServerData> {
+    server_data_with(name, expiration, "")
+}
+
+// Same as server_data, with extra global options appended
+fn server_data_with(name: &str, expiration: u64, extra: &str) -> Arc<ServerData> {
+    let folder = env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(2, 400, &format!("direct_block_count: 3\nroot_path: {}\n{}", folder.join("root.dat").display(), extra))).unwrap();
+    let config = from_str::<HttpService>(&format!("port: 0\nsee_root: false\ntus_path: {}\ntus_expiration: {}\n", folder.join("tus.dat").display(), expiration)).unwrap();
+    Arc::new(ServerData::new(Arc::new(global), config).unwrap())
+}
+
+#[test]
+fn parses_metadata() {
+    let metadata = parse_metadata(&format!("filename {},is_confidential", STANDARD.encode("zażółć.txt")));
+    assert_eq!(metadata.get("filename").map(|name| name.as_str()), Some("zażółć.txt"));
+    assert_eq!(metadata.get("is_confidential").map(|name| name.as_str()), Some(""));
+}
+
+#[actix_web::test]
+async fn resumes_uploads() {
+    let data = server_data("chunkdrive_tus_resume", 3600);
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(tus::configure)).await;
+    let content = (0..2_500u32).map(|x| (x % 239) as u8).collect::<Vec<u8>>();
+
+    let request = TestRequest::post().uri("/tus/files/")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", content.len().to_string()))
+        .insert_header(("Upload-Metadata", format!("filename {}", STANDARD.encode("data.bin"))))
+        .to_request();
+    let response = call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+
+    // requests without the protocol version are refused
+    let response = call_service(&app, TestRequest::default().method("HEAD".parse().unwrap()).uri(&location).to_request()).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let patch = |offset: usize, body: Vec<u8>| TestRequest::patch().uri(&location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Offset", offset.to_string()))
+        .insert_header((header::CONTENT_TYPE, "application/offset+octet-stream"))
+        .set_payload(body)
+        .to_request();
+
+    // the first part spans a few chunks and ends in the middle of one
+    let response = call_service(&app, patch(0, content[..1_000].to_vec())).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers().get("Upload-Offset").unwrap(), "1000");
+    assert_eq!(call_service(&app, patch(500, content[500..].to_vec())).await.status(), StatusCode::CONFLICT);
+
+    // the progress survives a restart
+    let restarted = TusState::load(&data.config.tus_path).unwrap();
+    assert_eq!(restarted.get(location.rsplit('/').next().unwrap()).unwrap().offset, 1_000);
+    let request = TestRequest::default().method("HEAD".parse().unwrap()).uri(&location).insert_header(("Tus-Resumable", "1.0.0")).to_request();
+    let response = call_service(&app, request).await;
+    assert_eq!(response.headers().get("Upload-Offset").unwrap(), "1000");
+    assert_eq!(response.headers().get("Upload-Length").unwrap(), "2500");
+
+    let response = call_service(&app, patch(1_000, content[1_000..].to_vec())).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers().get("Upload-Offset").unwrap(), "2500");
+
+    // the finished upload is a file in the target directory and is forgotten by tus
+    let request = TestRequest::default().method("HEAD".parse().unwrap()).uri(&location).insert_header(("Tus-Resumable", "1.0.0")).to_request();
+    assert_eq!(call_service(&app, request).await.status(), StatusCode::NOT_FOUND);
+    let root = data.global.get_root().unwrap();
+    let stored = root.get(&"data.bin".to_string()).unwrap();
+    let file = match stored.get::<InodeType>(data.global.clone()).await.unwrap() {
+        InodeType::File(file) => file,
+        _ => panic!("not a file"),
+    };
+    let mut got = Vec::new();
+    let mut stream = file.get(data.global.clone());
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    assert_eq!(got, content);
+    file.data.delete(data.global.clone()).await.unwrap();
+    stored.delete(data.global.clone()).await.unwrap();
+}
+
+#[actix_web::test]
+async fn expired_uploads_are_deleted() {
+    let data = server_data("chunkdrive_tus_expire", 1);
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(tus::configure)).await;
+
+    let request = TestRequest::post().uri("/tus/files/")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", "1000"))
+        .insert_header(("Upload-Metadata", format!("filename {}", STANDARD.encode("data.bin"))))
+        .to_request();
+    let location = call_service(&app, request).await.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    let id = location.rsplit('/').next().unwrap().to_string();
+    let request = TestRequest::patch().uri(&location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Offset", "0"))
+        .insert_header((header::CONTENT_TYPE, "application/offset+octet-stream"))
+        .set_payload(vec![1u8; 100])
+        .to_request();
+    assert_eq!(call_service(&app, request).await.status(), StatusCode::NO_CONTENT);
+    let block = data.tus.get(&id).unwrap().blocks[0].clone();
+
+    tokio::time::sleep(std::time::Duration::from_millis(2_100)).await;
+    assert!(data.tus.expire(data.global.clone()).await.is_empty());
+    assert!(TusState::load(&data.config.tus_path).unwrap().get(&id).is_none());
+    assert!(block.get(data.global.clone(), 0..100).next().await.unwrap().is_err());
+}
+
+#[actix_web::test]
+async fn unfinished_uploads_survive_gc_and_failed_completion() {
+    // gc deletes everything it does not know about, so the bucket is not in the shared temp dir
+    let folder = env::temp_dir().join("chunkdrive_tus_gc");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let service = format!("port: 0\nsee_root: false\ntus_path: {}\n", folder.join("tus.dat").display());
+    let global = from_str::<Global>(&format!(
+        "buckets:\n  gc:\n    source:\n      type: local\n      folder: {0}\n      max_size: 400\n      descriptor_length: 3\ndirect_block_count: 3\nroot_path: {0}/root.dat\nservices:\n  - type: http\n{1}",
+        folder.display(), service.lines().map(|line| format!("    {}\n", line)).collect::<String>()
+    )).unwrap();
+    let data = Arc::new(ServerData::new(Arc::new(global), from_str::<HttpService>(&service).unwrap()).unwrap());
+    let global = data.global.clone();
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(tus::configure)).await;
+    let content = (0..1_500u32).map(|x| (x % 239) as u8).collect::<Vec<u8>>();
+
+    let request = TestRequest::post().uri("/tus/files/")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", content.len().to_string()))
+        .insert_header(("Upload-Metadata", format!("filename {}", STANDARD.encode("data.bin"))))
+        .to_request();
+    let location = call_service(&app, request).await.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    let id = location.rsplit('/').next().unwrap().to_string();
+    let patch = |offset: usize, body: Vec<u8>| TestRequest::patch().uri(&location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Offset", offset.to_string()))
+        .insert_header((header::CONTENT_TYPE, "application/offset+octet-stream"))
+        .set_payload(body)
+        .to_request();
+    assert_eq!(call_service(&app, patch(0, content[..1_000].to_vec())).await.status(), StatusCode::NO_CONTENT);
+
+    // the chunks of the unfinished upload are not orphans
+    assert!(collect(global.clone(), true).await.unwrap().orphans.is_empty());
+
+    // a name that is taken makes linking fail, the upload is kept so it can be finished later
+    let mut root = global.get_root().unwrap();
+    root.add(global.clone(), &"data.bin".to_string(), File::create(global.clone(), b"taken".to_vec()).await.unwrap().to_enum()).await.unwrap();
+    global.save_root(&root).unwrap();
+    assert_eq!(call_service(&app, patch(1_000, content[1_000..].to_vec())).await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(data.tus.get(&id).unwrap().offset, 1_500);
+    assert!(collect(global.clone(), true).await.unwrap().orphans.is_empty());
+
+    root.remove(global.clone(), &"data.bin".to_string()).await.unwrap();
+    global.save_root(&root).unwrap();
+    assert_eq!(call_service(&app, patch(1_500, Vec::new())).await.status(), StatusCode::NO_CONTENT);
+    assert!(data.tus.get(&id).is_none());
+    assert!(collect(global.clone(), false).await.unwrap().orphans.is_empty());
+    let file = match global.get_root().unwrap().get(&"data.bin".to_string()).unwrap().get::<InodeType>(global.clone()).await.unwrap() {
+        InodeType::File(file) => file,
+        _ => panic!("not a file"),
+    };
+    let mut got = Vec::new();
+    let mut stream = file.get(global.clone());
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    assert_eq!(got, content);
+}
+
+#[actix_web::test]
+async fn uploads_use_the_configured_redundancy() {
+    let data = server_data_with("chunkdrive_tus_redundancy", 3600, "redundancy:\n    type: replicate\n    copies: 2\n");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(tus::configure)).await;
+    let content = (0..1_000u32).map(|x| (x % 233) as u8).collect::<Vec<u8>>();
+
+    let request = TestRequest::post().uri("/tus/files/")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", content.len().to_string()))
+        .insert_header(("Upload-Metadata", format!("filename {}", STANDARD.encode("copies.bin"))))
+        .to_request();
+    let response = call_service(&app, request).await;
+    let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    let request = TestRequest::patch().uri(&location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Offset", "0"))
+        .insert_header((header::CONTENT_TYPE, "application/offset+octet-stream"))
+        .set_payload(content.clone())
+        .to_request();
+    assert_eq!(call_service(&app, request).await.status(), StatusCode::NO_CONTENT);
+
+    let root = data.global.get_root().unwrap();
+    let stored = root.get(&"copies.bin".to_string()).unwrap();
+    let file = match stored.get::<InodeType>(data.global.clone()).await.unwrap() {
+        InodeType::File(file) => file,
+        _ => panic!("not a file"),
+    };
+    let blocks = file.data.clone().into_blocks();
+    assert!(!blocks.is_empty());
+    assert!(blocks.iter().all(|block| matches!(block, BlockType::Replicated(_))));
+    file.data.delete(data.global.clone()).await.unwrap();
+    stored.delete(data.global.clone()).await.unwrap();
+}
@@ -0,0 +1,213 @@
+use std::{env, pin::Pin, sync::Arc, task::{Context, Poll}};
+use futures::StreamExt;
+use serde_yaml::from_str;
+use tokio::io::{AsyncRead, BufReader, ReadBuf};
+use actix_multipart::Multipart;
+use actix_web::{web, error::PayloadError, http::header::{self, HeaderMap, HeaderValue}};
+
+use crate::{blocks::block::{Block, BlockType}, global::Global, inodes::{file::File, metadata::{Metadata, Size}}, services::http::upload::{read_form, upload_stream}};
+use super::utils::make_temp_config_multi;
+
+#[tokio::test]
+async fn parallel_uploads_stay_in_order() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(2, 30, "parallel_uploads: 8\ndirect_block_count: 60\n")).unwrap());
+    assert_eq!(global.parallel_uploads, 8);
+
+    let data = (0..1_500u32).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+    let block = BlockType::create(global.clone(), data.clone(), 0).await.unwrap();
+    let mut got = Vec::new();
+    let mut stream = block.get(global.clone(), 0..data.len());
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    assert_eq!(got, data);
+    block.delete(global).await.unwrap();
+}
+
+#[tokio::test]
+async fn failed_upload_cleans_up() {
+    // one bucket points to a folder that does not exist, so every chunk sent there fails
+    let folder = env::temp_dir().join("chunkdrive_upload_cleanup");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let config = format!(r#"
+buckets:
+    working:
+        source:
+            type: local
+            folder: {}
+            max_size: 30
+            descriptor_length: 3
+    broken:
+        source:
+            type: local
+            folder: {}
+            max_size: 30
+            descriptor_length: 3
+direct_block_count: 30
+"#, folder.display(), folder.join("missing").display());
+    let global = Arc::new(from_str::<Global>(&config).unwrap());
+
+    // 20 chunks, so it is practically impossible that all of them go to the working bucket
+    assert!(BlockType::create(global.clone(), vec![7u8; 600], 0).await.is_err());
+    assert_eq!(std::fs::read_dir(&folder).unwrap().count(), 0);
+
+    let _ = std::fs::remove_dir_all(folder);
+}
+
+// gives out some data and then fails, like a dropped connection
+struct FailingReader(usize);
+
+impl AsyncRead for FailingReader {
+    fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        if self.0 == 0 {
+            return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset")));
+        }
+        let len = std::cmp::min(self.0, buf.remaining());
+        buf.put_slice(&vec![3u8; len]);
+        self.0 -= len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn streamed_file_matches() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(2, 700, "direct_block_count: 3\nparallel_uploads: 2\n")).unwrap());
+    let data = (0..10_000u32).map(|x| (x % 253) as u8).collect::<Vec<u8>>();
+
+    // small reads make sure chunks are cut across read boundaries
+    let mut reader = BufReader::with_capacity(100, data.as_slice());
+    let file = File::create_from_reader(global.clone(), &mut reader, global.redundancy.clone()).await.unwrap();
+    assert!(matches!(file.metadata.size, Size::Bytes(10_000)));
+    let mut got = Vec::new();
+    let mut stream = file.get(global.clone());
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    assert_eq!(got, data);
+
+    let empty = File::create_from_reader(global.clone(), &mut [].as_slice(), global.redundancy.clone()).await.unwrap();
+    assert!(matches!(empty.metadata.size, Size::Bytes(0)));
+}
+
+#[tokio::test]
+async fn failed_read_cleans_up() {
+    let folder = env::temp_dir().join("chunkdrive_upload_read");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let config = format!(r#"
+buckets:
+    stream1:
+        source:
+            type: local
+            folder: {}
+            max_size: 200
+            descriptor_length: 3
+direct_block_count: 3
+"#, folder.display());
+    let global = Arc::new(from_str::<Global>(&config).unwrap());
+
+    // the error comes after a few full indirect blocks were already uploaded
+    let mut reader = BufReader::new(FailingReader(2_000));
+    assert!(File::create_from_reader(global.clone(), &mut reader, global.redundancy.clone()).await.is_err());
+    assert_eq!(std::fs::read_dir(&folder).unwrap().count(), 0);
+
+    let _ = std::fs::remove_dir_all(folder);
+}
+
+#[tokio::test]
+async fn failed_storage_ends_the_upload_stream() {
+    // chunks sent to the broken bucket fail while the body still has a lot to give
+    let folder = env::temp_dir().join("chunkdrive_upload_stream_storage");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let config = format!(r#"
+buckets:
+    working:
+        source:
+            type: local
+            folder: {}
+            max_size: 200
+            descriptor_length: 3
+    broken:
+        source:
+            type: local
+            folder: {}
+            max_size: 200
+            descriptor_length: 3
+direct_block_count: 3
+"#, folder.display(), folder.join("missing").display());
+    let global = Arc::new(from_str::<Global>(&config).unwrap());
+
+    // the body never ends, so only the failed upload can end the stream
+    let mut body = futures::stream::repeat_with(|| Ok::<_, String>(web::Bytes::from(vec![5u8; 1_000])));
+    let upload = upload_stream(global.clone(), "endless.bin", &mut body, global.redundancy.clone(), None);
+    let result = tokio::time::timeout(std::time::Duration::from_secs(10), upload).await.expect("the upload did not end");
+    assert!(result.is_err());
+    assert_eq!(std::fs::read_dir(&folder).unwrap().count(), 0);
+
+    let _ = std::fs::remove_dir_all(folder);
+}
+
+#[tokio::test]
+async fn detects_mime_types() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(1, 700, "")).unwrap());
+    let png = [&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A][..], &[0u8; 50]].concat();
+
+    // the magic bytes win over a wrong extension, text has no magic so the extension is used
+    let file = File::create_named(global.clone(), "picture.txt", &mut png.as_slice(), global.redundancy.clone()).await.unwrap();
+    assert_eq!(file.metadata.mime.as_deref(), Some("image/png"));
+    let file = File::create_named(global.clone(), "notes.txt", &mut b"hello".as_slice(), global.redundancy.clone()).await.unwrap();
+    assert_eq!(file.metadata.mime.as_deref(), Some("text/plain"));
+    let file = File::create_named(global.clone(), "blob", &mut b"hello".as_slice(), global.redundancy.clone()).await.unwrap();
+    assert_eq!(file.metadata.mime, None);
+
+    // older files without a stored type are guessed from the name
+    assert_eq!(Metadata::new().content_type("movie.mp4"), "video/mp4");
+    assert_eq!(Metadata::new().content_type("blob"), "application/octet-stream");
+}
+
+fn multipart(fields: &[(&str, Option<&str>, &[u8])]) -> Multipart {
+    let mut body = Vec::new();
+    for (name, filename, data) in fields {
+        body.extend(format!("--XYZ\r\nContent-Disposition: form-data; name=\"{}\"", name).as_bytes());
+        if let Some(filename) = filename {
+            body.extend(format!("; filename=\"{}\"", filename).as_bytes());
+        }
+        body.extend(b"\r\n\r\n");
+        body.extend(*data);
+        body.extend(b"\r\n");
+    }
+    body.extend(b"--XYZ--\r\n");
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("multipart/form-data; boundary=XYZ"));
+    // the body arrives in small pieces, like it would over the network
+    let pieces = body.chunks(100).map(|piece| Ok(web::Bytes::copy_from_slice(piece))).collect::<Vec<Result<web::Bytes, PayloadError>>>();
+    Multipart::new(&headers, futures::stream::iter(pieces))
+}
+
+#[actix_web::test]
+async fn streams_multipart_uploads() {
+    let global = Arc::new(from_str::<Global>(&make_temp_config_multi(2, 100, "direct_block_count: 60\n")).unwrap());
+    let data = (0..2_000u32).map(|x| (x % 241) as u8).collect::<Vec<u8>>();
+
+    let form = read_form(global.clone(), multipart(&[("redundancy", None, b""), ("file", Some("data.bin"), &data), ("paste_name", None, b"x")]), None).await.unwrap();
+    assert_eq!(form.paste_name.as_deref(), Some("x"));
+    let (name, file) = form.file.unwrap();
+    assert_eq!(name, "data.bin");
+    let mut got = Vec::new();
+    let mut stream = file.get(global.clone());
+    while let Some(chunk) = stream.next().await {
+        got.extend(chunk.unwrap());
+    }
+    assert_eq!(got, data);
+    file.data.delete(global.clone()).await.unwrap();
+
+    // an empty file input means no file was picked
+    let form = read_form(global.clone(), multipart(&[("file", Some(""), b""), ("directory_name", None, b"dir")]), None).await.unwrap();
+    assert!(form.file.is_none());
+    assert_eq!(form.directory_name.as_deref(), Some("dir"));
+
+    // uploads over the limit are refused
+    assert!(read_form(global.clone(), multipart(&[("file", Some("data.bin"), &data)]), Some(1_000)).await.is_err());
+}
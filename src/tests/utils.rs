@@ -26,4 +26,21 @@ buckets:
             descriptor_length: 3  # just in case we set extremely small block size for testing
         "#, env::temp_dir().display(), size);
     }
-}
\ No newline at end of file
+}
+
+// Same as make_temp_config, but with multiple unencrypted buckets and extra global options appended
+pub fn make_temp_config_multi(buckets: usize, size: usize, extra: &str) -> String {
+    let mut config = String::from("buckets:\n");
+    for i in 0..buckets {
+        config.push_str(&format!(r#"
+    multi{}:
+        source:
+            type: local
+            folder: {}
+            max_size: {}
+            descriptor_length: 3  # just in case we set extremely small block size for testing
+"#, i, env::temp_dir().display(), size));
+    }
+    config.push_str(extra);
+    config
+}
@@ -0,0 +1,140 @@
+use std::{env, sync::Arc};
+use actix_web::{App, web, body::to_bytes, dev::ServiceResponse, test::{TestRequest, init_service, call_service}, http::{header, Method, StatusCode}};
+use serde_yaml::from_str;
+
+use crate::{global::Global, services::{http::{api, service::{HttpService, ServerData}}, webdav::service::{WebdavData, WebdavService, configure}}};
+use super::utils::make_temp_config_multi;
+
+fn webdav_data(name: &str, extra: &str) -> Arc<WebdavData> {
+    let folder = env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let global = from_str::<Global>(&make_temp_config_multi(2, 400, &format!("direct_block_count: 3\nroot_path: {}\n", folder.join("root.dat").display()))).unwrap();
+    let config = from_str::<WebdavService>(&format!("port: 0\npath: /dav/\n{}", extra)).unwrap();
+    Arc::new(WebdavData { global: Arc::new(global), config })
+}
+
+fn request(method: &str, uri: &str) -> TestRequest {
+    TestRequest::default().method(Method::from_bytes(method.as_bytes()).unwrap()).uri(uri)
+}
+
+async fn body(response: ServiceResponse) -> String {
+    String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+}
+
+macro_rules! status {
+    ($app:expr, $request:expr) => {
+        call_service($app, $request.to_request()).await.status()
+    };
+}
+
+#[actix_web::test]
+async fn webdav_operations() {
+    let data = webdav_data("chunkdrive_webdav", "");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(configure)).await;
+    let content = (0..1_500u32).map(|x| (x % 233) as u8).collect::<Vec<u8>>();
+
+    assert_eq!(status!(&app, request("MKCOL", "/dav/docs")), StatusCode::CREATED);
+    assert_eq!(status!(&app, request("MKCOL", "/dav/docs")), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(status!(&app, request("MKCOL", "/dav/missing/docs")), StatusCode::CONFLICT);
+    assert_eq!(status!(&app, request("PUT", "/dav/docs/a%20file.bin").set_payload(content.clone())), StatusCode::CREATED);
+    assert_eq!(status!(&app, request("PUT", "/dav/docs/a%20file.bin").set_payload(content.clone())), StatusCode::NO_CONTENT);
+    assert_eq!(status!(&app, request("PUT", "/dav/missing/file").set_payload("x")), StatusCode::CONFLICT);
+
+    // listing
+    let response = call_service(&app, request("PROPFIND", "/dav/docs").insert_header(("Depth", "1")).to_request()).await;
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let listing = body(response).await;
+    assert!(listing.contains("<D:href>/dav/docs/</D:href>"));
+    assert!(listing.contains("<D:href>/dav/docs/a%20file.bin</D:href>"));
+    assert!(listing.contains("<D:getcontentlength>1500</D:getcontentlength>"));
+    let response = call_service(&app, request("PROPFIND", "/dav/").insert_header(("Depth", "0")).to_request()).await;
+    assert!(!body(response).await.contains("docs"));
+    assert_eq!(status!(&app, request("PROPFIND", "/dav/").insert_header(("Depth", "infinity"))), StatusCode::FORBIDDEN);
+
+    // reading, with and without a range
+    let response = call_service(&app, request("GET", "/dav/docs/a%20file.bin").to_request()).await;
+    assert_eq!(to_bytes(response.into_body()).await.unwrap().to_vec(), content);
+    let response = call_service(&app, request("GET", "/dav/docs/a%20file.bin").insert_header((header::RANGE, "bytes=100-199")).to_request()).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(to_bytes(response.into_body()).await.unwrap().to_vec(), content[100..200].to_vec());
+
+    // copies get their own chunks, moves only relink
+    assert_eq!(status!(&app, request("COPY", "/dav/docs").insert_header(("Destination", "http://localhost/dav/backup"))), StatusCode::CREATED);
+    assert_eq!(status!(&app, request("COPY", "/dav/docs").insert_header(("Destination", "/dav/backup")).insert_header(("Overwrite", "F"))), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(status!(&app, request("COPY", "/dav/docs").insert_header(("Destination", "/dav/docs/inner"))), StatusCode::CONFLICT);
+    assert_eq!(status!(&app, request("DELETE", "/dav/docs/a%20file.bin")), StatusCode::NO_CONTENT);
+    let response = call_service(&app, request("GET", "/dav/backup/a%20file.bin").to_request()).await;
+    assert_eq!(to_bytes(response.into_body()).await.unwrap().to_vec(), content);
+    assert_eq!(status!(&app, request("MOVE", "/dav/backup/a%20file.bin").insert_header(("Destination", "/dav/moved.bin"))), StatusCode::CREATED);
+    assert_eq!(status!(&app, request("GET", "/dav/backup/a%20file.bin")), StatusCode::NOT_FOUND);
+    assert_eq!(status!(&app, request("MOVE", "/dav/moved.bin").insert_header(("Destination", "/dav/renamed.bin"))), StatusCode::CREATED);
+    let response = call_service(&app, request("GET", "/dav/renamed.bin").to_request()).await;
+    assert_eq!(to_bytes(response.into_body()).await.unwrap().to_vec(), content);
+
+    for path in ["/dav/renamed.bin", "/dav/backup", "/dav/docs"] {
+        assert_eq!(status!(&app, request("DELETE", path)), StatusCode::NO_CONTENT);
+    }
+    assert!(data.global.get_root().unwrap().list().is_empty());
+}
+
+#[actix_web::test]
+async fn webdav_readonly() {
+    let data = webdav_data("chunkdrive_webdav_readonly", "readonly: true\n");
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(configure)).await;
+
+    assert_eq!(status!(&app, request("MKCOL", "/dav/docs")), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, request("PUT", "/dav/file").set_payload("x")), StatusCode::FORBIDDEN);
+    assert_eq!(status!(&app, request("PROPFIND", "/dav/")), StatusCode::MULTI_STATUS);
+    assert_eq!(status!(&app, request("PROPFIND", "/elsewhere/")), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn services_share_the_tree_lock() {
+    let data = webdav_data("chunkdrive_webdav_tree_lock", "");
+    let http = Arc::new(ServerData::new(data.global.clone(), from_str::<HttpService>(&format!("port: 0\ntus_path: {}\n", env::temp_dir().join("chunkdrive_webdav_tree_lock").join("tus.dat").display())).unwrap()).unwrap());
+    let dav = init_service(App::new().app_data(web::Data::new(data.clone())).configure(configure)).await;
+    let app = init_service(App::new().app_data(web::Data::new(http)).configure(api::configure)).await;
+
+    // both services change the root at the same time, none of the changes may be lost
+    let requests = (0..10).map(|i| call_service(&dav, request("MKCOL", &format!("/dav/dav{}", i)).to_request()))
+        .chain((0..10).map(|i| call_service(&app, TestRequest::post().uri(&format!("/api/v1/mkdir/api{}", i)).to_request())))
+        .collect::<Vec<_>>();
+    for response in futures::future::join_all(requests).await {
+        assert!(response.status().is_success());
+    }
+    assert_eq!(data.global.get_root().unwrap().list().len(), 20);
+}
+
+#[actix_web::test]
+async fn copies_that_are_not_linked_are_deleted() {
+    // the buckets get their own folder, so the chunks of other tests are not counted
+    let folder = env::temp_dir().join("chunkdrive_webdav_copy");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(folder.join("chunks")).unwrap();
+    let global = from_str::<Global>(&format!(r#"
+buckets:
+    copy1:
+        source:
+            type: local
+            folder: {}
+            max_size: 400
+            descriptor_length: 3
+root_path: {}
+"#, folder.join("chunks").display(), folder.join("root.dat").display())).unwrap();
+    let config = from_str::<WebdavService>("port: 0\npath: /dav/\n").unwrap();
+    let data = Arc::new(WebdavData { global: Arc::new(global), config });
+    let app = init_service(App::new().app_data(web::Data::new(data.clone())).configure(configure)).await;
+    let chunks = || std::fs::read_dir(folder.join("chunks")).unwrap().count();
+
+    assert_eq!(status!(&app, request("PUT", "/dav/a.bin").set_payload(vec![1u8; 1_000])), StatusCode::CREATED);
+    assert_eq!(status!(&app, request("PUT", "/dav/b.bin").set_payload(vec![2u8; 1_000])), StatusCode::CREATED);
+    let before = chunks();
+    let copy = request("COPY", "/dav/a.bin").insert_header(("Destination", "/dav/b.bin")).insert_header(("Overwrite", "F"));
+    assert_eq!(status!(&app, copy), StatusCode::PRECONDITION_FAILED);
+    let copy = request("COPY", "/dav/a.bin").insert_header(("Destination", "/dav/missing/c.bin"));
+    assert_eq!(status!(&app, copy), StatusCode::CONFLICT);
+    assert_eq!(chunks(), before);
+
+    let _ = std::fs::remove_dir_all(folder);
+}